@@ -0,0 +1,60 @@
+//! Pinning threads to CPU cores so that high-rate measurements are not
+//! disturbed by threads moving between cores. The first core given is used
+//! by the measuring thread and per-connection threads are spread over the
+//! rest, or share it if only one core was given.
+
+use std::io;
+
+/// Parses a list of cores such as `2`, `2,3` or `0-3`.
+pub fn parse_cpus(value: &str) -> Option<Vec<usize>> {
+    let mut cpus = Vec::new();
+    for part in value.split(',') {
+        let part = part.trim();
+        match part.split_once('-') {
+            Some((first, last)) => {
+                let (first, last): (usize, usize) = (first.trim().parse().ok()?, last.trim().parse().ok()?);
+                if first > last { return None; }
+                cpus.extend(first..=last);
+            },
+            None => cpus.push(part.parse().ok()?),
+        }
+    }
+    Some(cpus)
+}
+
+/// Pins the calling thread to the given core. Threads it starts afterwards
+/// inherit the pinning.
+#[cfg(target_os = "linux")]
+pub fn pin_current_thread(cpu: usize) -> io::Result<()> {
+    if cpu >= libc::CPU_SETSIZE as usize {
+        return Err(io::Error::new(io::ErrorKind::InvalidInput, "CPU number is too large"));
+    }
+
+    unsafe {
+        let mut set: libc::cpu_set_t = std::mem::zeroed();
+        libc::CPU_SET(cpu, &mut set);
+        if libc::sched_setaffinity(0, std::mem::size_of::<libc::cpu_set_t>(), &set) != 0 {
+            return Err(io::Error::last_os_error());
+        }
+    }
+    Ok(())
+}
+
+#[cfg(not(target_os = "linux"))]
+pub fn pin_current_thread(_cpu: usize) -> io::Result<()> {
+    Err(io::Error::new(io::ErrorKind::Unsupported, "CPU pinning is only available on Linux"))
+}
+
+/// Pins the calling thread, which handles the connection with the given
+/// index, to its share of the cores. Does nothing if no cores were given.
+pub fn pin_connection_thread(cpus: &[usize], index: usize) {
+    let cpu = match cpus.len() {
+        0 => return,
+        1 => cpus[0],
+        len => cpus[1 + index % (len - 1)],
+    };
+
+    if let Err(err) = pin_current_thread(cpu) {
+        print_err!("Could not pin a connection thread to CPU {}: {}", cpu, err);
+    }
+}
@@ -0,0 +1,291 @@
+//! Collecting the measures that agents stream with `--stats-to` and showing
+//! them together, so that a test spread over many hosts can be followed from
+//! one screen.
+//!
+//! Agents send one line of JSON per measure, either over a TCP connection or
+//! as UDP datagrams, and a `done` message once they have finished. An agent
+//! that stops reporting without saying it is done is eventually given up on.
+
+use std::collections::BTreeMap;
+use std::io::{self, stdout, BufRead, BufReader, Read, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream, UdpSocket};
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError, Sender};
+use std::thread;
+use std::time::{Duration, Instant, SystemTime};
+
+use serde_json::Value;
+
+use console::StdStream;
+use output::{Reporter, TerminalReporter};
+use rate::RateEstimator;
+use {record_measure, DisplayMode, MeasureOptions, TransferInfo};
+
+/// How often the combined stats are measured.
+const MEASURE_INTERVAL: Duration = Duration::from_secs(1);
+
+/// How long an agent can go without reporting before it is given up on.
+const LOST_AFTER: Duration = Duration::from_secs(10);
+
+/// The longest line that will be accepted from an agent.
+const MAX_LINE_LENGTH: usize = 64 * 1024;
+
+/// Whether an agent is still taking part in the test.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum AgentState {
+    Reporting,
+    Done,
+
+    /// The agent stopped reporting without saying it was done.
+    Lost,
+}
+
+impl AgentState {
+    pub fn name(self) -> &'static str {
+        match self {
+            AgentState::Reporting => "reporting",
+            AgentState::Done => "done",
+            AgentState::Lost => "lost",
+        }
+    }
+}
+
+/// The latest measure reported by an agent.
+#[derive(Clone, Debug)]
+pub struct AgentStats {
+    pub name: String,
+    pub total_bytes: u64,
+    pub current_bps: f64,
+    pub average_bps: f64,
+    pub elapsed: Duration,
+    pub state: AgentState,
+}
+
+/// Something an agent sent.
+enum Event {
+    Stats(AgentStats),
+    Done(String),
+}
+
+impl Event {
+    fn parse(line: &[u8]) -> Option<Event> {
+        let message: Value = serde_json::from_slice(line).ok()?;
+        let name = message["agent"].as_str()?.to_string();
+        match message["type"].as_str()? {
+            "stats" => Some(Event::Stats(AgentStats {
+                name,
+                total_bytes: message["total_bytes"].as_u64()?,
+                current_bps: message["current_bps"].as_f64()?,
+                average_bps: message["average_bps"].as_f64()?,
+                elapsed: message["elapsed_secs"].as_f64()
+                    .filter(|secs| secs.is_finite() && *secs >= 0.0)
+                    .map(Duration::from_secs_f64)?,
+                state: AgentState::Reporting,
+            })),
+            "done" => Some(Event::Done(name)),
+            _ => None,
+        }
+    }
+}
+
+/// Receives stats on both TCP and UDP at the address and shows them
+/// combined until every agent that reported has finished.
+pub fn run(addr: SocketAddr, options: &MeasureOptions) -> io::Result<TransferInfo> {
+    let listener = TcpListener::bind(addr)?;
+    let local_addr = listener.local_addr()?;
+    let socket = UdpSocket::bind(local_addr)?;
+    println!("Collecting stats on {} over TCP and UDP", local_addr);
+
+    let (events, received) = mpsc::channel();
+    let tcp_events = events.clone();
+    thread::spawn(move || accept_agents(listener, tcp_events));
+    thread::spawn(move || receive_datagrams(socket, events));
+
+    let mut aggregator = Aggregator::new(received);
+    aggregator.wait_for_first_agent()?;
+    aggregator.measure_until_finished(options)
+}
+
+/// Reads every connection on its own thread. Closing a connection finishes
+/// the agents that reported over it.
+fn accept_agents(listener: TcpListener, events: Sender<Event>) {
+    for stream in listener.incoming() {
+        match stream {
+            Ok(stream) => {
+                let events = events.clone();
+                thread::spawn(move || read_agent(stream, events));
+            },
+            Err(err) => print_err!("There was an error accepting an agent: {}", err),
+        }
+    }
+}
+
+fn read_agent(stream: TcpStream, events: Sender<Event>) {
+    let mut reader = BufReader::new(stream);
+    let mut names = Vec::new();
+    let mut line = Vec::new();
+    loop {
+        line.clear();
+        match (&mut reader).take(MAX_LINE_LENGTH as u64).read_until(b'\n', &mut line) {
+            Ok(0) | Err(_) => break,
+            Ok(_) => {},
+        }
+        if let Some(event) = Event::parse(&line) {
+            if let Event::Stats(ref stats) = event {
+                if !names.contains(&stats.name) {
+                    names.push(stats.name.clone());
+                }
+            }
+            if events.send(event).is_err() { return; }
+        }
+    }
+
+    for name in names {
+        let _ = events.send(Event::Done(name));
+    }
+}
+
+fn receive_datagrams(socket: UdpSocket, events: Sender<Event>) {
+    let mut buffer = vec![0; MAX_LINE_LENGTH];
+    loop {
+        let len = match socket.recv_from(&mut buffer) {
+            Ok((len, _)) => len,
+            Err(err) => {
+                print_err!("Error while receiving stats: {}", err);
+                return;
+            }
+        };
+        for line in buffer[..len].split(|&byte| byte == b'\n') {
+            if let Some(event) = Event::parse(line) {
+                if events.send(event).is_err() { return; }
+            }
+        }
+    }
+}
+
+/// An agent and when it last reported.
+struct Agent {
+    stats: AgentStats,
+    last_seen: Instant,
+}
+
+/// Combines the stats of every agent into one transfer.
+struct Aggregator {
+    received: Receiver<Event>,
+    agents: BTreeMap<String, Agent>,
+
+    /// The bytes each agent had reported at the last measure.
+    measured_bytes: BTreeMap<String, u64>,
+}
+
+impl Aggregator {
+    fn new(received: Receiver<Event>) -> Aggregator {
+        Aggregator { received, agents: BTreeMap::new(), measured_bytes: BTreeMap::new() }
+    }
+
+    fn wait_for_first_agent(&mut self) -> io::Result<()> {
+        while self.agents.is_empty() {
+            let event = self.received.recv().map_err(|_| io::Error::other("stopped receiving stats"))?;
+            self.apply(event);
+        }
+        Ok(())
+    }
+
+    fn apply(&mut self, event: Event) {
+        match event {
+            Event::Stats(stats) => {
+                let name = stats.name.clone();
+                self.agents.insert(name, Agent { stats, last_seen: Instant::now() });
+            },
+            Event::Done(name) => {
+                if let Some(agent) = self.agents.get_mut(&name) {
+                    agent.stats.state = AgentState::Done;
+                }
+            },
+        }
+    }
+
+    /// Applies the events that arrive until the deadline.
+    fn receive_until(&mut self, deadline: Instant) {
+        loop {
+            let timeout = deadline.saturating_duration_since(Instant::now());
+            match self.received.recv_timeout(timeout) {
+                Ok(event) => self.apply(event),
+                Err(RecvTimeoutError::Timeout) => break,
+                Err(RecvTimeoutError::Disconnected) => {
+                    thread::sleep(timeout);
+                    break;
+                },
+            }
+        }
+
+        for agent in self.agents.values_mut() {
+            if agent.stats.state == AgentState::Reporting && agent.last_seen.elapsed() >= LOST_AFTER {
+                agent.stats.state = AgentState::Lost;
+            }
+        }
+    }
+
+    fn is_finished(&self) -> bool {
+        self.agents.values().all(|agent| agent.stats.state != AgentState::Reporting)
+    }
+
+    /// The bytes reported since the last measure across every agent. An
+    /// agent whose total went down started over, as it does between runs.
+    fn take_new_bytes(&mut self) -> u64 {
+        let mut new_bytes = 0;
+        for (name, agent) in &self.agents {
+            let total = agent.stats.total_bytes;
+            let measured = self.measured_bytes.insert(name.clone(), total).unwrap_or(0);
+            new_bytes += if total >= measured { total - measured } else { total };
+        }
+        new_bytes
+    }
+
+    fn measure_until_finished(&mut self, options: &MeasureOptions) -> io::Result<TransferInfo> {
+        let mut terminal = TerminalReporter::new(options, StdStream::Stdout);
+        // The current rate is the sum of the agents' own, not estimated here.
+        terminal.set_rate_description(None);
+
+        let mut rate_estimator = RateEstimator::new(options.rate_mode);
+        let transfer_start = Instant::now();
+        let mut last_measured = transfer_start;
+        let mut transfer_info = TransferInfo { start_time: Some(SystemTime::now()), ..TransferInfo::default() };
+        let mut printed_agents = 0;
+
+        loop {
+            self.receive_until(last_measured + MEASURE_INTERVAL);
+            let finished = self.is_finished();
+
+            let measure_end = Instant::now();
+            let bytes = self.take_new_bytes() as usize;
+            transfer_info.last_bytes_transferred = bytes;
+            transfer_info.total_bytes_transferred += bytes;
+            record_measure(&mut transfer_info, &mut rate_estimator, measure_end.duration_since(last_measured),
+                measure_end.duration_since(transfer_start), SystemTime::now(), options);
+            last_measured = measure_end;
+
+            // Agents measure on their own schedule, so the sum of their own
+            // rates is steadier than the bytes that happened to arrive.
+            transfer_info.current_bps = self.agents.values()
+                .filter(|agent| agent.stats.state == AgentState::Reporting)
+                .fold(0.0, |sum, agent| sum + agent.stats.current_bps);
+            transfer_info.agents = Some(self.agents.values().map(|agent| agent.stats.clone()).collect());
+
+            // A new agent adds a row, so the stats cannot be written over
+            // the ones printed before.
+            if printed_agents != self.agents.len() {
+                terminal.print_below();
+                printed_agents = self.agents.len();
+            }
+            terminal.measure(&transfer_info)?;
+
+            if finished {
+                terminal.finish(&transfer_info)?;
+                if options.display_mode != DisplayMode::Quiet {
+                    writeln!(stdout(), "Collected stats from {} agents", self.agents.len())?;
+                }
+                return Ok(transfer_info);
+            }
+        }
+    }
+}
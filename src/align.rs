@@ -0,0 +1,82 @@
+//! Taking measures on wall clock boundaries, such as every whole second or
+//! minute since the Unix epoch, so that the intervals measured on several
+//! machines line up when their output is merged.
+
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+/// Boundaries every `period` since the Unix epoch.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct Alignment {
+    period: Duration,
+}
+
+impl Alignment {
+    pub fn new(period: Duration) -> Alignment {
+        Alignment { period }
+    }
+
+    #[cfg_attr(not(feature = "tokio"), allow(dead_code))]
+    pub fn period(self) -> Duration {
+        self.period
+    }
+
+    /// The last boundary at or before the time.
+    pub fn previous(self, time: SystemTime) -> SystemTime {
+        let (since_epoch, period) = self.nanos(time);
+        from_nanos(since_epoch / period * period)
+    }
+
+    /// The first boundary after the time.
+    pub fn next_after(self, time: SystemTime) -> SystemTime {
+        let (since_epoch, period) = self.nanos(time);
+        from_nanos((since_epoch / period + 1) * period)
+    }
+
+    /// The boundary nearest to the time, which a measure that was scheduled
+    /// for a boundary but woke up a little early or late stands for.
+    #[cfg_attr(not(feature = "tokio"), allow(dead_code))]
+    pub fn nearest(self, time: SystemTime) -> SystemTime {
+        let (since_epoch, period) = self.nanos(time);
+        from_nanos((since_epoch + period / 2) / period * period)
+    }
+
+    /// When the next boundary comes, for waiting until it.
+    #[cfg_attr(not(feature = "tokio"), allow(dead_code))]
+    pub fn next_instant(self) -> Instant {
+        let now = SystemTime::now();
+        Instant::now() + self.next_after(now).duration_since(now).unwrap_or_default()
+    }
+
+    fn nanos(self, time: SystemTime) -> (u128, u128) {
+        let since_epoch = time.duration_since(UNIX_EPOCH).unwrap_or_default().as_nanos();
+        (since_epoch, self.period.as_nanos().max(1))
+    }
+}
+
+fn from_nanos(nanos: u128) -> SystemTime {
+    UNIX_EPOCH + Duration::new((nanos / 1_000_000_000) as u64, (nanos % 1_000_000_000) as u32)
+}
+
+/// Keeps track of the boundary the next measure is due at.
+pub struct Aligner {
+    alignment: Alignment,
+    next: SystemTime,
+}
+
+impl Aligner {
+    pub fn new(alignment: Alignment) -> Aligner {
+        Aligner { alignment, next: alignment.next_after(SystemTime::now()) }
+    }
+
+    /// Returns the latest boundary that has passed, if the one that was due
+    /// has, and moves on to the first one still to come. Boundaries missed
+    /// while a read was blocked are folded into the next measure.
+    pub fn take_due(&mut self) -> Option<SystemTime> {
+        let now = SystemTime::now();
+        if now < self.next {
+            return None;
+        }
+        self.next = self.alignment.next_after(now);
+        Some(self.alignment.previous(now))
+    }
+}
@@ -0,0 +1,358 @@
+//! Working out how a transfer went after the fact, either from a chunk log or
+//! a recording, by looking at when each chunk arrived and how large it was,
+//! or from interval data logged as CSV.
+
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, Write};
+use std::time::Duration;
+
+use display::{format_distribution, format_rate};
+use histogram::Histogram;
+use recording::ChunkReader;
+use tui::{self, GRAPH_WIDTH};
+use units::{byte_to_mem_units, format_elapsed, format_latency};
+
+/// Column width of the labels.
+const LABEL_WIDTH: usize = 24;
+
+/// Statistics about the chunks of a stream and the bursts they arrived in.
+pub struct BurstAnalysis {
+    /// Chunks that arrived at most this long after the one before them are
+    /// part of the same burst.
+    burst_gap: Duration,
+
+    /// The length of the intervals the peak rate is measured over.
+    interval: Duration,
+
+    chunks: u64,
+    bytes: u64,
+
+    /// When the last chunk arrived, relative to the first.
+    duration: Duration,
+
+    sizes: Histogram,
+
+    /// The nanoseconds between each chunk and the one before it.
+    gaps: Histogram,
+
+    /// The time spent in gaps that ended a burst.
+    idle: Duration,
+
+    /// When the longest gap started and how long it was.
+    longest_gap: (Duration, Duration),
+
+    /// The bytes and nanoseconds of every burst that has ended.
+    burst_sizes: Histogram,
+    burst_durations: Histogram,
+
+    /// When the current burst started and how many bytes it has had.
+    burst_start: Duration,
+    burst_bytes: u64,
+
+    /// The bytes that arrived during each interval.
+    interval_bytes: Vec<u64>,
+}
+
+impl BurstAnalysis {
+    pub fn new(burst_gap: Duration, interval: Duration) -> BurstAnalysis {
+        BurstAnalysis {
+            burst_gap,
+            interval,
+            chunks: 0,
+            bytes: 0,
+            duration: Duration::default(),
+            sizes: Histogram::default(),
+            gaps: Histogram::default(),
+            idle: Duration::default(),
+            longest_gap: (Duration::default(), Duration::default()),
+            burst_sizes: Histogram::default(),
+            burst_durations: Histogram::default(),
+            burst_start: Duration::default(),
+            burst_bytes: 0,
+            interval_bytes: Vec::new(),
+        }
+    }
+
+    /// Reads and analyzes every chunk in the file.
+    pub fn read(path: &str, burst_gap: Duration, interval: Duration) -> io::Result<BurstAnalysis> {
+        let mut reader = ChunkReader::open(path)?;
+        let mut analysis = BurstAnalysis::new(burst_gap, interval);
+        while let Some(chunk) = reader.next_chunk(None)? {
+            analysis.add(chunk.offset, chunk.len as u64);
+        }
+        analysis.finish();
+        Ok(analysis)
+    }
+
+    /// Adds a chunk that arrived at the offset, which must not be before
+    /// the offset of the chunk added before it.
+    pub fn add(&mut self, offset: Duration, len: u64) {
+        if self.chunks > 0 {
+            let gap = offset.saturating_sub(self.duration);
+            self.gaps.record(gap.as_nanos() as u64);
+            if gap > self.longest_gap.1 {
+                self.longest_gap = (self.duration, gap);
+            }
+            if gap > self.burst_gap {
+                self.end_burst();
+                self.idle += gap;
+                self.burst_start = offset;
+            }
+        } else {
+            self.burst_start = offset;
+        }
+
+        self.chunks += 1;
+        self.bytes += len;
+        self.burst_bytes += len;
+        self.duration = offset;
+        self.sizes.record(len);
+
+        let interval = (offset.as_nanos() / self.interval.as_nanos()) as usize;
+        if self.interval_bytes.len() <= interval {
+            self.interval_bytes.resize(interval + 1, 0);
+        }
+        self.interval_bytes[interval] += len;
+    }
+
+    /// Ends the burst that is in progress.
+    pub fn finish(&mut self) {
+        if self.burst_bytes > 0 {
+            self.end_burst();
+        }
+    }
+
+    fn end_burst(&mut self) {
+        self.burst_sizes.record(self.burst_bytes);
+        self.burst_durations.record((self.duration - self.burst_start).as_nanos() as u64);
+        self.burst_bytes = 0;
+    }
+
+    /// The rate over every interval up to the one the last chunk arrived in,
+    /// so that it can be compared with the peak.
+    pub fn average_bps(&self) -> f64 {
+        let secs = self.interval_bytes.len() as f64 * self.interval.as_secs_f64();
+        if secs > 0.0 { self.bytes as f64 / secs } else { 0.0 }
+    }
+
+    /// The highest rate over any whole interval.
+    pub fn peak_bps(&self) -> f64 {
+        let peak = self.interval_bytes.iter().cloned().max().unwrap_or(0);
+        peak as f64 / self.interval.as_secs_f64()
+    }
+
+    /// The rate during each interval.
+    pub fn series(&self) -> RateSeries {
+        let secs = self.interval.as_secs_f64();
+        RateSeries {
+            points: self.interval_bytes.iter().enumerate()
+                .map(|(index, &bytes)| (self.interval * (index as u32 + 1), bytes as f64 / secs))
+                .collect(),
+        }
+    }
+
+    /// The labelled values that make up the analysis.
+    pub fn rows(&self) -> Vec<(&'static str, String)> {
+        let (mem, unit) = byte_to_mem_units(self.bytes as f64);
+        let average = self.average_bps();
+        let peak = self.peak_bps();
+        let secs = self.duration.as_secs_f64();
+        let format_bytes = |bytes: u64| {
+            let (mem, unit) = byte_to_mem_units(bytes as f64);
+            format!("{:.3} {}", mem, unit)
+        };
+
+        vec![
+            ("Data Transferred:", format!("{:.3} {} in {} chunks over {}", mem, unit, self.chunks, format_elapsed(self.duration))),
+            ("Average Rate:", format_rate(average)),
+            ("Peak Rate:", format!("{} over {} intervals", format_rate(peak), format_latency(self.interval.as_nanos() as u64))),
+            ("Peak To Average:", if average > 0.0 { format!("{:.2}", peak / average) } else { "-".to_string() }),
+            self.series().rates_row(),
+            ("Chunk Sizes:", format_distribution(&self.sizes, "chunks", format_bytes)),
+            ("Gaps:", format_distribution(&self.gaps, "gaps", format_latency)),
+            ("Bursts:", format!("{} separated by gaps over {}",
+                self.burst_sizes.count(), format_latency(self.burst_gap.as_nanos() as u64))),
+            ("Burst Sizes:", format_distribution(&self.burst_sizes, "bursts", format_bytes)),
+            ("Burst Durations:", format_distribution(&self.burst_durations, "bursts", format_latency)),
+            ("Idle Time:", format!("{:.3}s ({:.1}%)", self.idle.as_secs_f64(),
+                if secs > 0.0 { self.idle.as_secs_f64() / secs * 100.0 } else { 0.0 })),
+            ("Longest Stall:", format_stall(self.longest_gap)),
+        ]
+    }
+}
+
+/// Throughput over time, as the rate during each of a series of intervals.
+pub struct RateSeries {
+    /// When each interval ended, relative to the start, and the rate during
+    /// it.
+    points: Vec<(Duration, f64)>,
+}
+
+impl RateSeries {
+    /// Reads interval data from CSV with a header row. Time is read from an
+    /// `elapsed_secs`, `elapsed`, `seconds` or `time` column as the seconds
+    /// since the start at the end of each interval, and the rate from a
+    /// `bps`, `current_bps` or `rate` column in bytes per second, or worked
+    /// out from a `bytes` column holding the bytes of each interval.
+    pub fn read_csv(path: &str) -> io::Result<RateSeries> {
+        let invalid = |line: usize, message: &str| {
+            io::Error::new(io::ErrorKind::InvalidData, format!("line {}: {}", line, message))
+        };
+
+        let mut lines = BufReader::new(File::open(path)?).lines();
+        let header = lines.next().ok_or_else(|| invalid(1, "missing the header row"))??;
+        let columns: Vec<String> = header.split(',').map(|column| column.trim().to_lowercase()).collect();
+        let find = |names: &[&str]| columns.iter().position(|column| names.contains(&column.as_str()));
+        let time_column = find(&["elapsed_secs", "elapsed", "seconds", "time"])
+            .ok_or_else(|| invalid(1, "no elapsed_secs, elapsed, seconds or time column"))?;
+        let (value_column, is_rate) = match (find(&["bps", "current_bps", "rate"]), find(&["bytes"])) {
+            (Some(column), _) => (column, true),
+            (None, Some(column)) => (column, false),
+            (None, None) => return Err(invalid(1, "no bps, current_bps, rate or bytes column")),
+        };
+
+        let mut points = Vec::new();
+        let mut last_secs = 0.0;
+        for (index, line) in lines.enumerate() {
+            let line = line?;
+            if line.trim().is_empty() { continue; }
+            let number = index + 2;
+            let fields: Vec<&str> = line.split(',').map(str::trim).collect();
+            let field = |column: usize| -> io::Result<f64> {
+                fields.get(column).and_then(|field| field.parse::<f64>().ok())
+                    .filter(|value| value.is_finite() && *value >= 0.0)
+                    .ok_or_else(|| invalid(number, "expected a number that is not negative"))
+            };
+
+            let secs = field(time_column)?;
+            if secs <= last_secs && !points.is_empty() {
+                return Err(invalid(number, "times must increase from one row to the next"));
+            }
+            let rate = if is_rate {
+                field(value_column)?
+            } else if secs > last_secs {
+                field(value_column)? / (secs - last_secs)
+            } else {
+                0.0
+            };
+            points.push((Duration::from_secs_f64(secs), rate));
+            last_secs = secs;
+        }
+        Ok(RateSeries { points })
+    }
+
+    fn duration(&self) -> Duration {
+        self.points.last().map_or(Duration::default(), |&(end, _)| end)
+    }
+
+    /// The length of each interval along with its rate.
+    fn intervals(&self) -> impl Iterator<Item = (Duration, Duration, f64)> + '_ {
+        let starts = std::iter::once(Duration::default()).chain(self.points.iter().map(|&(end, _)| end));
+        starts.zip(self.points.iter()).map(|(start, &(end, rate))| (start, end.saturating_sub(start), rate))
+    }
+
+    /// The rate over the whole series.
+    pub fn average_bps(&self) -> f64 {
+        let secs = self.duration().as_secs_f64();
+        let bytes: f64 = self.intervals().map(|(_, length, rate)| length.as_secs_f64() * rate).sum();
+        if secs > 0.0 { bytes / secs } else { 0.0 }
+    }
+
+    /// When the longest run of intervals without any data started and how
+    /// long it lasted.
+    pub fn longest_stall(&self) -> (Duration, Duration) {
+        let mut longest = (Duration::default(), Duration::default());
+        let mut current: Option<(Duration, Duration)> = None;
+        for (start, length, rate) in self.intervals() {
+            if rate > 0.0 {
+                current = None;
+                continue;
+            }
+            let stall = current.map_or((start, length), |(stall_start, stall_length)| (stall_start, stall_length + length));
+            if stall.1 > longest.1 {
+                longest = stall;
+            }
+            current = Some(stall);
+        }
+        longest
+    }
+
+    /// The labelled values that summarize the series.
+    pub fn rows(&self) -> Vec<(&'static str, String)> {
+        let average = self.average_bps();
+        let peak = self.points.iter().map(|&(_, rate)| rate).fold(0.0, f64::max);
+        vec![
+            ("Intervals:", format!("{} over {}", self.points.len(), format_elapsed(self.duration()))),
+            ("Average Rate:", format_rate(average)),
+            ("Peak Rate:", format_rate(peak)),
+            ("Peak To Average:", if average > 0.0 { format!("{:.2}", peak / average) } else { "-".to_string() }),
+            self.rates_row(),
+            ("Longest Stall:", format_stall(self.longest_stall())),
+        ]
+    }
+
+    /// The percentiles of the rates of the intervals.
+    pub fn rates_row(&self) -> (&'static str, String) {
+        let mut rates = Histogram::default();
+        for &(_, rate) in &self.points {
+            rates.record(rate as u64);
+        }
+        ("Interval Rates:", format_distribution(&rates, "intervals", |bps| format_rate(bps as f64)))
+    }
+
+    /// Draws the rates over time, with each column of the graph showing the
+    /// average rate during its share of the time.
+    pub fn print_graph<W: Write>(&self, output: &mut W) -> io::Result<()> {
+        let duration = self.duration();
+        if duration == Duration::default() { return Ok(()); }
+
+        let column_secs = duration.as_secs_f64() / GRAPH_WIDTH as f64;
+        let mut columns = vec![0.0; GRAPH_WIDTH];
+        for (start, length, rate) in self.intervals() {
+            let (start, end) = (start.as_secs_f64(), (start + length).as_secs_f64());
+            let first = ((start / column_secs) as usize).min(GRAPH_WIDTH - 1);
+            for (column, column_rate) in columns.iter_mut().enumerate().skip(first) {
+                let column_start = column as f64 * column_secs;
+                if column_start >= end { break; }
+                let overlap = end.min(column_start + column_secs) - start.max(column_start);
+                *column_rate += rate * overlap.max(0.0) / column_secs;
+            }
+        }
+
+        writeln!(output)?;
+        tui::render_graph(output, &columns, "")?;
+        writeln!(output, "{:>width$}{:>graph_width$}", "0s", format_elapsed(duration),
+            width = LABEL_WIDTH + 1, graph_width = GRAPH_WIDTH - 2)
+    }
+}
+
+fn format_stall((start, length): (Duration, Duration)) -> String {
+    if length == Duration::default() {
+        return "none".to_string();
+    }
+    format!("{:.3}s starting at {}", length.as_secs_f64(), format_elapsed(start))
+}
+
+fn print_rows<W: Write>(output: &mut W, rows: &[(&str, String)]) -> io::Result<()> {
+    for &(label, ref value) in rows {
+        writeln!(output, "{:<width$}{}", label, value, width = LABEL_WIDTH)?;
+    }
+    Ok(())
+}
+
+/// Prints the statistics of a chunk log, a recording or interval data logged
+/// as CSV, telling them apart by whether the file starts like a chunk log.
+pub fn print_analysis<W: Write>(output: &mut W, path: &str, burst_gap: Duration, interval: Duration) -> io::Result<()> {
+    match BurstAnalysis::read(path, burst_gap, interval) {
+        Ok(analysis) => {
+            print_rows(output, &analysis.rows())?;
+            analysis.series().print_graph(output)
+        },
+        Err(ref err) if err.kind() == io::ErrorKind::InvalidData => {
+            let series = RateSeries::read_csv(path)?;
+            print_rows(output, &series.rows())?;
+            series.print_graph(output)
+        },
+        Err(err) => Err(err),
+    }
+}
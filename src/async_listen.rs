@@ -0,0 +1,356 @@
+//! Measuring any number of connections at once on a Tokio runtime. Every
+//! connection is read by its own task and a timer drives the measures,
+//! instead of a thread blocking in `read()` for each connection. A client
+//! can start with a line such as `throughput-label web-1` to be told apart
+//! from the others in the stats, which otherwise go by its address.
+
+use std::collections::VecDeque;
+use std::future::{self, Future};
+use std::io::{self, stdout, Write};
+use std::mem;
+use std::net::{SocketAddr, TcpListener};
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::task::{Context, Poll};
+use std::time::{Duration, Instant, SystemTime};
+
+use tokio::io::{AsyncRead, ReadBuf};
+use tokio::net::{TcpListener as AsyncTcpListener, TcpStream as AsyncTcpStream};
+use tokio::runtime::Builder;
+use tokio::sync::mpsc::{self, UnboundedReceiver, UnboundedSender};
+use tokio::time::{self, Interval};
+
+use throughput::ThroughputMeter;
+
+use console::StdStream;
+use display;
+use net::{ConnectionStats, SocketOptions, LABEL_PREAMBLE, MAX_LABEL_LENGTH};
+use output::{Reporter, TerminalReporter};
+use rate::RateEstimator;
+use usage::Usage;
+use {record_measure, DisplayMode, MeasureOptions, TransferInfo};
+
+/// How often a measure is taken.
+const MEASURE_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Counters shared between the connection tasks and the measuring task.
+struct Shared {
+    /// Counts the bytes read by every connection.
+    meter: ThroughputMeter,
+
+    /// The number of connections that have been accepted.
+    accepted: AtomicUsize,
+
+    /// The number of connections that are still open.
+    open: AtomicUsize,
+
+    closed: Mutex<Closed>,
+}
+
+/// The connections that have closed, the oldest of which are left out once
+/// there are more than `--max-samples` of them.
+struct Closed {
+    connections: VecDeque<ConnectionStats>,
+    limit: usize,
+
+    /// How many of the latest connections have not been printed yet.
+    unprinted: usize,
+
+    /// How many connections were left out.
+    omitted: usize,
+}
+
+impl Closed {
+    fn new(limit: usize) -> Closed {
+        Closed { connections: VecDeque::new(), limit, unprinted: 0, omitted: 0 }
+    }
+
+    fn push(&mut self, connection: ConnectionStats) {
+        if self.connections.len() == self.limit {
+            self.connections.pop_front();
+            self.omitted += 1;
+        }
+        self.connections.push_back(connection);
+        self.unprinted = (self.unprinted + 1).min(self.connections.len());
+    }
+
+    /// The connections that closed since this was last called.
+    fn take_unprinted(&mut self) -> impl Iterator<Item = &ConnectionStats> {
+        let start = self.connections.len() - mem::take(&mut self.unprinted);
+        self.connections.range(start..)
+    }
+}
+
+/// Accepts every connection that arrives on the listeners and measures them
+/// together until the last open connection closes.
+pub fn measure_listeners(listeners: Vec<TcpListener>, socket_options: &SocketOptions, options: &MeasureOptions) -> io::Result<TransferInfo> {
+    let runtime = Builder::new_multi_thread().enable_all().build()?;
+    let _guard = runtime.enter();
+
+    let shared = Arc::new(Shared {
+        meter: ThroughputMeter::default(),
+        accepted: AtomicUsize::new(0),
+        open: AtomicUsize::new(0),
+        closed: Mutex::new(Closed::new(options.max_samples)),
+    });
+    let (changes, changed) = mpsc::unbounded_channel();
+    for listener in listeners {
+        listener.set_nonblocking(true)?;
+        let listener = AsyncTcpListener::from_std(listener)?;
+        runtime.spawn(accept_connections(listener, shared.clone(), changes.clone(), socket_options.clone(), options.buffer_size));
+    }
+
+    runtime.block_on(Measurer { shared, changed, options, measuring: None })
+}
+
+/// Accepts connections forever, starting a task to read each one.
+fn accept_connections(listener: AsyncTcpListener, shared: Arc<Shared>, changes: UnboundedSender<()>,
+    socket_options: SocketOptions, buffer_size: usize) -> impl Future<Output = ()>
+{
+    future::poll_fn(move |cx| loop {
+        let accepted = match listener.poll_accept(cx) {
+            Poll::Pending => return Poll::Pending,
+            Poll::Ready(accepted) => accepted,
+        };
+
+        match accepted.and_then(|(stream, peer)| Ok((configure(stream, &socket_options)?, peer))) {
+            Ok((stream, peer)) => {
+                shared.open.fetch_add(1, Ordering::SeqCst);
+                shared.accepted.fetch_add(1, Ordering::SeqCst);
+                let _ = changes.send(());
+                tokio::spawn(read_connection(stream, peer, shared.clone(), changes.clone(), buffer_size));
+            },
+            Err(err) => print_err!("There was an error accepting a connection: {}", err),
+        }
+    })
+}
+
+fn configure(stream: AsyncTcpStream, socket_options: &SocketOptions) -> io::Result<AsyncTcpStream> {
+    let stream = stream.into_std()?;
+    socket_options.apply(&stream)?;
+    AsyncTcpStream::from_std(stream)
+}
+
+/// Reads a connection until it closes, counting the bytes read apart from
+/// the label the client may have started with.
+fn read_connection(mut stream: AsyncTcpStream, peer: SocketAddr, shared: Arc<Shared>, changes: UnboundedSender<()>,
+    buffer_size: usize) -> impl Future<Output = ()>
+{
+    let (opened, opened_at) = (SystemTime::now(), Instant::now());
+    let mut buffer = vec![0; buffer_size];
+    let mut label = None;
+    let mut bytes = 0;
+
+    // The start of the connection while it could still be a label.
+    let mut head = Some(Vec::new());
+    future::poll_fn(move |cx| {
+        let mut count = |read: usize| {
+            shared.meter.record(read);
+            bytes += read as u64;
+        };
+        loop {
+            let mut read_buffer = ReadBuf::new(&mut buffer);
+            match Pin::new(&mut stream).poll_read(cx, &mut read_buffer) {
+                Poll::Pending => return Poll::Pending,
+                Poll::Ready(Ok(())) if read_buffer.filled().is_empty() => break,
+                Poll::Ready(Ok(())) => match head.take() {
+                    Some(mut start) => {
+                        start.extend_from_slice(read_buffer.filled());
+                        match parse_label(&start) {
+                            LabelPreamble::Incomplete => head = Some(start),
+                            LabelPreamble::Label(text, length) => {
+                                label = Some(text);
+                                count(start.len() - length);
+                            },
+                            LabelPreamble::None => count(start.len()),
+                        }
+                    },
+                    None => count(read_buffer.filled().len()),
+                },
+                Poll::Ready(Err(err)) => {
+                    print_err!("Error while reading from {}: {}", label.as_deref().unwrap_or(&peer.to_string()), err);
+                    break;
+                }
+            }
+        }
+        // The connection closed before it could be told whether it started
+        // with a label.
+        if let Some(start) = head.take() {
+            count(start.len());
+        }
+
+        let connection = ConnectionStats {
+            label: label.take().unwrap_or_else(|| peer.to_string()),
+            peer,
+            bytes,
+            opened,
+            elapsed: opened_at.elapsed(),
+        };
+        shared.closed.lock().expect("connection stats lock was poisoned").push(connection);
+        shared.open.fetch_sub(1, Ordering::SeqCst);
+        let _ = changes.send(());
+        Poll::Ready(())
+    })
+}
+
+enum LabelPreamble {
+    /// Not enough has been read to tell.
+    Incomplete,
+
+    /// The label and the length of the line it was sent on.
+    Label(String, usize),
+
+    None,
+}
+
+fn parse_label(start: &[u8]) -> LabelPreamble {
+    let compared = start.len().min(LABEL_PREAMBLE.len());
+    if start[..compared] != LABEL_PREAMBLE[..compared] {
+        return LabelPreamble::None;
+    }
+    let rest = &start[compared..];
+    match rest.iter().position(|&byte| byte == b'\n') {
+        Some(end) if compared == LABEL_PREAMBLE.len() => {
+            let label = String::from_utf8_lossy(&rest[..end]).trim().to_string();
+            if label.is_empty() {
+                return LabelPreamble::None;
+            }
+            LabelPreamble::Label(label, compared + end + 1)
+        },
+        None if rest.len() <= MAX_LABEL_LENGTH => LabelPreamble::Incomplete,
+        _ => LabelPreamble::None,
+    }
+}
+
+/// Takes a measure every interval from the first connection until there are
+/// no open connections left.
+struct Measurer<'a> {
+    shared: Arc<Shared>,
+
+    /// Receives a message whenever a connection is accepted or closed.
+    changed: UnboundedReceiver<()>,
+
+    options: &'a MeasureOptions,
+
+    /// Set once the first connection has been accepted.
+    measuring: Option<Measuring>,
+}
+
+struct Measuring {
+    terminal: TerminalReporter,
+    rate_estimator: RateEstimator,
+    ticks: Interval,
+    transfer_start: Instant,
+    last_measured: Instant,
+
+    /// The meter's total at the last measure.
+    last_total: u64,
+
+    usage_start: Option<Usage>,
+    transfer_info: TransferInfo,
+}
+
+impl<'a> Future for Measurer<'a> {
+    type Output = io::Result<TransferInfo>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context) -> Poll<io::Result<TransferInfo>> {
+        let Measurer { ref shared, ref mut changed, options, ref mut measuring } = *self.get_mut();
+        loop {
+            let mut any_changed = false;
+            while let Poll::Ready(Some(())) = changed.poll_recv(cx) {
+                any_changed = true;
+            }
+
+            if measuring.is_none() {
+                if shared.accepted.load(Ordering::SeqCst) == 0 {
+                    return Poll::Pending;
+                }
+                *measuring = Some(Measuring::new(options));
+            }
+            let state = measuring.as_mut().expect("measuring should have started");
+
+            let finished = any_changed && shared.open.load(Ordering::SeqCst) == 0;
+            if !finished && state.ticks.poll_tick(cx).is_pending() {
+                return Poll::Pending;
+            }
+
+            if let Err(err) = state.measure(shared, options, finished) {
+                return Poll::Ready(Err(err));
+            }
+            if finished {
+                return Poll::Ready(Ok(mem::take(&mut state.transfer_info)));
+            }
+        }
+    }
+}
+
+impl Measuring {
+    fn new(options: &MeasureOptions) -> Measuring {
+        let transfer_start = Instant::now();
+        Measuring {
+            terminal: TerminalReporter::new(options, StdStream::Stdout),
+            rate_estimator: RateEstimator::new(options.rate_mode),
+            ticks: match options.align {
+                Some(align) => time::interval_at(align.next_instant().into(), align.period()),
+                None => time::interval_at((transfer_start + MEASURE_INTERVAL).into(), MEASURE_INTERVAL),
+            },
+            transfer_start,
+            last_measured: transfer_start,
+            last_total: 0,
+            usage_start: Usage::now(),
+            transfer_info: TransferInfo { start_time: Some(SystemTime::now()), ..TransferInfo::default() },
+        }
+    }
+
+    /// Measures the bytes read since the last measure and prints the stats.
+    fn measure(&mut self, shared: &Shared, options: &MeasureOptions, finished: bool) -> io::Result<()> {
+        let measure_end = Instant::now();
+        let total = shared.meter.total_bytes();
+        let bytes = (total - self.last_total) as usize;
+        self.last_total = total;
+        self.transfer_info.last_bytes_transferred = bytes;
+        self.transfer_info.total_bytes_transferred += bytes;
+        // A tick can fire a little either side of the boundary it was set for.
+        let now = SystemTime::now();
+        let time = match options.align {
+            Some(align) if !finished => align.nearest(now),
+            _ => now,
+        };
+        record_measure(&mut self.transfer_info, &mut self.rate_estimator, measure_end.duration_since(self.last_measured),
+            measure_end.duration_since(self.transfer_start), time, options);
+        self.last_measured = measure_end;
+
+        if finished {
+            if let (Some(start), Some(end)) = (self.usage_start, Usage::now()) {
+                self.transfer_info.usage = Some(end.since(&start));
+            }
+            let closed = shared.closed.lock().expect("connection stats lock was poisoned");
+            self.transfer_info.connections = Some(closed.connections.iter().cloned().collect());
+            self.transfer_info.connections_omitted = closed.omitted;
+        }
+
+        let output = stdout();
+        let mut locked_output = output.lock();
+        if options.display_mode == DisplayMode::Live {
+            let mut closed = shared.closed.lock().expect("connection stats lock was poisoned");
+            for connection in closed.take_unprinted() {
+                writeln!(locked_output, "Closed {}", display::format_connection(connection))?;
+                // The stats are printed again below the line rather than over it.
+                self.terminal.print_below();
+            }
+        }
+        self.terminal.measure(&self.transfer_info)?;
+
+        if finished {
+            self.terminal.finish(&self.transfer_info)?;
+            if options.display_mode != DisplayMode::Quiet {
+                writeln!(locked_output, "Measured {} connections", shared.accepted.load(Ordering::SeqCst))?;
+            }
+        }
+
+        self.transfer_info.last_bps = 0.0;
+        self.transfer_info.last_bytes_transferred = 0;
+        Ok(())
+    }
+}
@@ -0,0 +1,83 @@
+//! Picks the read buffer size while a stream is being measured. The buffer
+//! grows while reads keep filling it at a high rate, since larger reads then
+//! mean fewer system calls, and shrinks when reads only use a small part of
+//! it.
+
+use std::time::{Duration, Instant};
+
+/// The size the buffer starts at.
+pub const START_SIZE: usize = 64 * 1024;
+
+const MIN_SIZE: usize = 4 * 1024;
+const MAX_SIZE: usize = 16 * 1024 * 1024;
+
+/// How often the size is reconsidered.
+const ADJUST_INTERVAL: Duration = Duration::from_millis(250);
+
+/// The fewest reads the size is reconsidered after, so that a few slow reads
+/// don't decide it.
+const MIN_READS: u64 = 16;
+
+/// Reads per second above which full reads grow the buffer.
+const GROW_READ_RATE: f64 = 1000.0;
+
+/// The fraction of reads that must fill the buffer for it to grow.
+const GROW_FULL_FRACTION: f64 = 0.9;
+
+pub struct BufferTuner {
+    size: usize,
+    reads: u64,
+    full_reads: u64,
+    bytes: u64,
+    since: Instant,
+}
+
+impl BufferTuner {
+    pub fn new() -> BufferTuner {
+        BufferTuner { size: START_SIZE, reads: 0, full_reads: 0, bytes: 0, since: Instant::now() }
+    }
+
+    /// The current buffer size in bytes.
+    pub fn size(&self) -> usize {
+        self.size
+    }
+
+    /// Records a read into a buffer of `capacity` bytes and returns the new
+    /// size if it should change.
+    pub fn record(&mut self, bytes_read: usize, capacity: usize) -> Option<usize> {
+        self.reads += 1;
+        self.bytes += bytes_read as u64;
+        if bytes_read >= capacity {
+            self.full_reads += 1;
+        }
+
+        let elapsed = self.since.elapsed();
+        if self.reads < MIN_READS || elapsed < ADJUST_INTERVAL {
+            return None;
+        }
+
+        let read_rate = self.reads as f64 / elapsed.as_secs_f64();
+        let full_fraction = self.full_reads as f64 / self.reads as f64;
+        let average = self.bytes / self.reads;
+
+        let size = if full_fraction >= GROW_FULL_FRACTION && read_rate >= GROW_READ_RATE {
+            (self.size * 2).min(MAX_SIZE)
+        } else if average < capacity as u64 / 4 {
+            (self.size / 2).max(MIN_SIZE)
+        } else {
+            self.size
+        };
+
+        self.reads = 0;
+        self.full_reads = 0;
+        self.bytes = 0;
+        self.since = Instant::now();
+
+        if size == self.size {
+            None
+        } else {
+            self.size = size;
+            Some(size)
+        }
+    }
+}
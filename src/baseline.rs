@@ -0,0 +1,168 @@
+//! Comparing a transfer against the report of an earlier one, so that the
+//! effect of a change such as kernel or NIC tuning can be seen at a glance
+//! and a regression can fail a script.
+
+use std::fs;
+use std::io;
+
+use serde_json::Value;
+
+use display::format_rate;
+use runs::RunStats;
+use units::byte_to_mem_units;
+use TransferInfo;
+
+/// The width that the labels of the comparison are padded to.
+const LABEL_WIDTH: usize = 24;
+
+/// The results of an earlier transfer, read from its `--report`.
+pub struct Baseline {
+    pub path: String,
+
+    /// The mean across the runs if the report has several, otherwise the
+    /// average of the transfer.
+    pub average_bps: f64,
+
+    pub peak_bps: Option<f64>,
+    pub total_bytes: Option<u64>,
+}
+
+impl Baseline {
+    pub fn load(path: &str) -> io::Result<Baseline> {
+        let content = fs::read_to_string(path)?;
+        let report: Value = serde_json::from_str(&content)
+            .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))?;
+        let average_bps = report["runs"]["mean_bps"].as_f64()
+            .or_else(|| report["average_bps"].as_f64())
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "not a throughput report"))?;
+
+        Ok(Baseline {
+            path: path.to_string(),
+            average_bps,
+            peak_bps: report["peak_bps"].as_f64(),
+            total_bytes: report["total_bytes"].as_u64(),
+        })
+    }
+}
+
+/// How a measured value compares to the one in the baseline.
+pub struct Delta {
+    pub label: &'static str,
+    pub key: &'static str,
+    pub baseline: f64,
+    pub current: f64,
+    format: fn(f64) -> String,
+}
+
+impl Delta {
+    pub fn change(&self) -> f64 {
+        self.current - self.baseline
+    }
+
+    /// The change as a percentage of the baseline, if there is one.
+    pub fn change_percent(&self) -> Option<f64> {
+        if self.baseline > 0.0 { Some(self.change() / self.baseline * 100.0) } else { None }
+    }
+
+    fn describe(&self) -> String {
+        let change = self.change();
+        let sign = if change < 0.0 { "-" } else { "+" };
+        let percent = match self.change_percent() {
+            Some(percent) => format!(" ({:+.1}%)", percent),
+            None => String::new(),
+        };
+        format!("{}  vs  {}  {}{}{}", (self.format)(self.current), (self.format)(self.baseline),
+            sign, (self.format)(change.abs()), percent)
+    }
+}
+
+/// The results of a transfer next to those of the baseline.
+pub struct Comparison<'a> {
+    baseline: &'a Baseline,
+    deltas: Vec<Delta>,
+    max_regression: Option<f64>,
+}
+
+impl<'a> Comparison<'a> {
+    /// Compares the mean of the runs, or the only run, to the baseline. The
+    /// peak and total are those of the last run, as they are in the report.
+    pub fn new(baseline: &'a Baseline, results: &[TransferInfo], run_stats: Option<&RunStats>,
+            max_regression: Option<f64>) -> Comparison<'a> {
+        let last = results.last().expect("there should be at least one run");
+        let mut deltas = vec![Delta {
+            label: "Average Transfer Speed:",
+            key: "average_bps",
+            baseline: baseline.average_bps,
+            current: run_stats.map_or_else(|| last.average_bps(), |run_stats| run_stats.mean_bps),
+            format: format_rate,
+        }];
+        if let Some(peak_bps) = baseline.peak_bps {
+            deltas.push(Delta {
+                label: "Peak Transfer Speed:",
+                key: "peak_bps",
+                baseline: peak_bps,
+                current: last.peak_bps,
+                format: format_rate,
+            });
+        }
+        if let Some(total_bytes) = baseline.total_bytes {
+            deltas.push(Delta {
+                label: "Data Transferred:",
+                key: "total_bytes",
+                baseline: total_bytes as f64,
+                current: last.total_bytes_transferred as f64,
+                format: format_size,
+            });
+        }
+        Comparison { baseline, deltas, max_regression }
+    }
+
+    /// Whether the average speed dropped by more than the maximum
+    /// regression.
+    pub fn regressed(&self) -> bool {
+        match (self.max_regression, self.deltas[0].change_percent()) {
+            (Some(max_regression), Some(percent)) => -percent > max_regression,
+            _ => false,
+        }
+    }
+
+    pub fn print(&self) {
+        println!();
+        println!("Compared to {}:", self.baseline.path);
+        for delta in &self.deltas {
+            println!("{:<width$}{}", delta.label, delta.describe(), width = LABEL_WIDTH);
+        }
+    }
+
+    pub fn to_json(&self) -> Value {
+        let mut comparison = json!({
+            "path": self.baseline.path,
+            "regressed": self.regressed(),
+        });
+        if let Some(max_regression) = self.max_regression {
+            comparison["max_regression_percent"] = json!(max_regression);
+        }
+        for delta in &self.deltas {
+            comparison[delta.key] = json!({
+                "baseline": delta.baseline,
+                "current": delta.current,
+                "change": delta.change(),
+                "change_percent": delta.change_percent(),
+            });
+        }
+        comparison
+    }
+
+    /// Reports that the average speed regressed.
+    pub fn print_regression(&self) {
+        let delta = &self.deltas[0];
+        print_err!("Average transfer speed {} is {:.1}% below the baseline of {}, more than the maximum \
+            regression of {}%.", format_rate(delta.current), -delta.change_percent().unwrap_or(0.0),
+            format_rate(delta.baseline), self.max_regression.unwrap_or(0.0));
+    }
+}
+
+fn format_size(bytes: f64) -> String {
+    let (mem, unit) = byte_to_mem_units(bytes);
+    format!("{:.3} {}", mem, unit)
+}
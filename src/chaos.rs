@@ -0,0 +1,130 @@
+//! Deliberately damaging passed through data, so that how a consumer copes
+//! with lost, repeated, late or corrupt data can be tested while the effect
+//! on throughput is measured.
+
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use pattern::splitmix64;
+use units;
+
+/// How long a buffer is held back when no delay is given.
+const DEFAULT_DELAY: Duration = Duration::from_millis(100);
+
+/// The fraction of buffers that each kind of fault is injected into.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct ChaosSpec {
+    pub drop: f64,
+    pub duplicate: f64,
+    pub delay: f64,
+    pub delay_for: Duration,
+    pub flip: f64,
+
+    /// Picks the buffers that get faults, so that a run can be repeated.
+    pub seed: u64,
+}
+
+impl ChaosSpec {
+    /// Parses a comma separated list such as
+    /// `drop=0.01,dup=0.01,delay=0.05:200ms,flip=0.001,seed=7`. Every buffer
+    /// gets at most one fault, so the fractions cannot add up to more than 1.
+    /// Without a seed one is made up from the current time.
+    pub fn parse(value: &str) -> Option<ChaosSpec> {
+        let seed = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_nanos() as u64;
+        let mut spec = ChaosSpec { drop: 0.0, duplicate: 0.0, delay: 0.0, delay_for: DEFAULT_DELAY, flip: 0.0, seed };
+        for part in value.split(',').map(str::trim).filter(|part| !part.is_empty()) {
+            let (key, value) = part.split_at(part.find('=')?);
+            let value = &value[1..];
+            match key {
+                "drop" => spec.drop = parse_fraction(value)?,
+                "dup" | "duplicate" => spec.duplicate = parse_fraction(value)?,
+                "delay" => match value.find(':') {
+                    Some(colon) => {
+                        spec.delay = parse_fraction(&value[..colon])?;
+                        spec.delay_for = units::parse_duration(&value[colon + 1..])?;
+                    },
+                    None => spec.delay = parse_fraction(value)?,
+                },
+                "flip" => spec.flip = parse_fraction(value)?,
+                "seed" => spec.seed = value.parse().ok()?,
+                _ => return None,
+            }
+        }
+
+        if spec.drop + spec.duplicate + spec.delay + spec.flip > 1.0 {
+            return None;
+        }
+        Some(spec)
+    }
+}
+
+fn parse_fraction(value: &str) -> Option<f64> {
+    value.parse().ok().filter(|fraction| (0.0..=1.0).contains(fraction))
+}
+
+/// A fault picked for a buffer.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Fault {
+    /// The buffer is not written at all.
+    Drop,
+
+    /// The buffer is written twice.
+    Duplicate,
+
+    /// The buffer is written after waiting.
+    Delay(Duration),
+
+    /// A bit of the buffer was flipped before it is written.
+    FlipBit,
+}
+
+/// Picks which buffers get faults and counts the faults injected.
+#[derive(Clone)]
+pub struct Chaos {
+    pub spec: ChaosSpec,
+    counter: u64,
+
+    pub dropped: u64,
+    pub duplicated: u64,
+    pub delayed: u64,
+    pub flipped: u64,
+}
+
+impl Chaos {
+    pub fn new(spec: ChaosSpec) -> Chaos {
+        Chaos { spec, counter: 0, dropped: 0, duplicated: 0, delayed: 0, flipped: 0 }
+    }
+
+    fn next_random(&mut self) -> u64 {
+        self.counter += 1;
+        splitmix64(self.spec.seed.wrapping_add(self.counter))
+    }
+
+    /// Picks the fault for a buffer that was read, if it gets one, flipping
+    /// one of its bits right away when that is the fault.
+    pub fn inject(&mut self, data: &mut [u8]) -> Option<Fault> {
+        if data.is_empty() {
+            return None;
+        }
+
+        // The top 53 bits make a uniformly distributed fraction.
+        let roll = (self.next_random() >> 11) as f64 / (1u64 << 53) as f64;
+        let spec = self.spec;
+        if roll < spec.drop {
+            self.dropped += 1;
+            Some(Fault::Drop)
+        } else if roll < spec.drop + spec.duplicate {
+            self.duplicated += 1;
+            Some(Fault::Duplicate)
+        } else if roll < spec.drop + spec.duplicate + spec.delay {
+            self.delayed += 1;
+            Some(Fault::Delay(spec.delay_for))
+        } else if roll < spec.drop + spec.duplicate + spec.delay + spec.flip {
+            let bit = self.next_random() % (data.len() as u64 * 8);
+            data[(bit / 8) as usize] ^= 1 << (bit % 8);
+            self.flipped += 1;
+            Some(Fault::FlipBit)
+        } else {
+            None
+        }
+    }
+}
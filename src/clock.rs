@@ -0,0 +1,185 @@
+//! Estimating how far the clock of a control protocol peer is from ours, and
+//! with that the one-way delay of the data path. Both sides sample how many
+//! bytes they have sent or received over wall clock time, and the delay is
+//! how long after the sender had sent some number of bytes the receiver had
+//! received as many, once the offset between their clocks is taken out.
+
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// How many probes are exchanged to estimate the clock offset. The one with
+/// the shortest round trip is used, as it was delayed the least.
+pub const CLOCK_PROBES: usize = 8;
+
+/// How often the bytes sent or received are sampled.
+const PROGRESS_INTERVAL: Duration = Duration::from_millis(5);
+
+/// The wall clock time in seconds since the Unix epoch.
+pub fn now_secs() -> f64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_secs_f64()
+}
+
+/// How far the clock of the peer is ahead of ours, from a probe that we sent
+/// at `sent`, the peer received at `peer_received` and answered at
+/// `peer_sent` by its clock, and whose answer we received at `received`.
+#[derive(Clone, Copy, Debug)]
+pub struct ClockOffset {
+    /// The clock of the peer minus ours, in seconds.
+    pub offset: f64,
+
+    /// The time the probe spent on the network both ways, in seconds.
+    pub round_trip: f64,
+}
+
+impl ClockOffset {
+    pub fn from_probe(sent: f64, peer_received: f64, peer_sent: f64, received: f64) -> ClockOffset {
+        ClockOffset {
+            offset: ((peer_received - sent) + (peer_sent - received)) / 2.0,
+            round_trip: ((received - sent) - (peer_sent - peer_received)).max(0.0),
+        }
+    }
+
+    /// The most the offset can be wrong by, which is when the probe spent
+    /// all of its round trip going one way.
+    pub fn error(&self) -> f64 {
+        self.round_trip / 2.0
+    }
+}
+
+/// Samples of the time in seconds since the Unix epoch and the bytes sent or
+/// received by then.
+pub type Progress = Vec<(f64, u64)>;
+
+/// Samples a byte count on the wall clock from a thread of its own while
+/// data is sent or received.
+pub struct ProgressSampler {
+    bytes: Arc<AtomicU64>,
+
+    /// The most samples that are kept, which keeps them within a control
+    /// message and the memory they take bounded however long the test ran.
+    max_samples: usize,
+    finished: Arc<AtomicBool>,
+    thread: JoinHandle<Progress>,
+}
+
+impl ProgressSampler {
+    pub fn start(max_samples: usize) -> ProgressSampler {
+        let bytes = Arc::new(AtomicU64::new(0));
+        let finished = Arc::new(AtomicBool::new(false));
+        let thread = {
+            let (bytes, finished) = (bytes.clone(), finished.clone());
+            thread::spawn(move || sample(&bytes, &finished, max_samples))
+        };
+        ProgressSampler { bytes, max_samples, finished, thread }
+    }
+
+    /// The count to add the bytes sent or received to.
+    pub fn counter(&self) -> Arc<AtomicU64> {
+        self.bytes.clone()
+    }
+
+    /// Stops sampling and returns the samples, thinned out if there are too
+    /// many.
+    pub fn finish(self) -> Progress {
+        self.finished.store(true, Ordering::Relaxed);
+        let mut progress = self.thread.join().expect("progress sampler thread panicked");
+        progress.push((now_secs(), self.bytes.load(Ordering::Relaxed)));
+        thin(progress, self.max_samples)
+    }
+}
+
+/// Records the count whenever it changed. The last time it was seen
+/// unchanged is recorded along with the change, so that the bytes can be
+/// spread over the time between them and not over an idle stretch. The
+/// samples are thinned out whenever there are twice as many as are kept.
+fn sample(bytes: &AtomicU64, finished: &AtomicBool, max_samples: usize) -> Progress {
+    let mut progress = vec![(now_secs(), bytes.load(Ordering::Relaxed))];
+    let mut unchanged = None;
+    while !finished.load(Ordering::Relaxed) {
+        thread::sleep(PROGRESS_INTERVAL);
+        let sample = (now_secs(), bytes.load(Ordering::Relaxed));
+        if sample.1 == progress[progress.len() - 1].1 {
+            unchanged = Some(sample);
+            continue;
+        }
+        if let Some(unchanged) = unchanged.take() {
+            progress.push(unchanged);
+        }
+        progress.push(sample);
+        if progress.len() > max_samples * 2 {
+            progress = thin(progress, max_samples);
+        }
+    }
+    progress
+}
+
+fn thin(progress: Progress, max_samples: usize) -> Progress {
+    if progress.len() <= max_samples {
+        return progress;
+    }
+    let step = progress.len().div_ceil(max_samples - 1);
+    let last = progress[progress.len() - 1];
+    let mut thinned: Progress = progress.into_iter().step_by(step).collect();
+    if thinned[thinned.len() - 1] != last {
+        thinned.push(last);
+    }
+    thinned
+}
+
+/// When the count first reached the bytes, assuming they came at an even
+/// rate between the samples around it.
+fn time_at(progress: &[(f64, u64)], bytes: u64) -> Option<f64> {
+    let index = progress.partition_point(|&(_, count)| count < bytes);
+    if index == progress.len() {
+        return None;
+    }
+    if index == 0 {
+        return Some(progress[0].0);
+    }
+    let (before_time, before_bytes) = progress[index - 1];
+    let (after_time, after_bytes) = progress[index];
+    let fraction = (bytes - before_bytes) as f64 / (after_bytes - before_bytes) as f64;
+    Some(before_time + (after_time - before_time) * fraction)
+}
+
+/// The one-way delay of the data during a test.
+#[derive(Clone, Copy, Debug)]
+pub struct OneWayDelay {
+    /// In seconds. Clock error can make these slightly negative on fast
+    /// links.
+    pub min: f64,
+    pub average: f64,
+    pub max: f64,
+
+    /// How many byte counts the delay was worked out at.
+    pub samples: usize,
+
+    /// The offset between the clocks that was taken out.
+    pub clock: ClockOffset,
+}
+
+impl OneWayDelay {
+    /// Works out the delay from the progress of the sender and receiver, each
+    /// by its own clock. The offset is the clock of the receiver minus the
+    /// clock of the sender. Returns None if no data arrived.
+    pub fn estimate(departures: &[(f64, u64)], arrivals: &[(f64, u64)], offset: f64, clock: ClockOffset) -> Option<OneWayDelay> {
+        let mut levels: Vec<u64> = departures.iter().map(|&(_, bytes)| bytes).filter(|&bytes| bytes > 0).collect();
+        levels.dedup();
+        let delays: Vec<f64> = levels.into_iter()
+            .filter_map(|bytes| Some(time_at(arrivals, bytes)? - offset - time_at(departures, bytes)?))
+            .collect();
+        if delays.is_empty() {
+            return None;
+        }
+
+        Some(OneWayDelay {
+            min: delays.iter().cloned().fold(f64::INFINITY, f64::min),
+            average: delays.iter().sum::<f64>() / delays.len() as f64,
+            max: delays.iter().cloned().fold(f64::NEG_INFINITY, f64::max),
+            samples: delays.len(),
+            clock,
+        })
+    }
+}
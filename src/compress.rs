@@ -0,0 +1,233 @@
+//! Decompressing data that is read and compressing data that is sent, so that
+//! both the bytes on the wire and the bytes they stand for can be measured.
+
+use std::io::{self, IoSlice, IoSliceMut, Read, Write};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+/// A compression format that streams can be decoded from or encoded into.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Codec {
+    Gzip,
+    Zstd,
+}
+
+impl Codec {
+    pub fn name(&self) -> &'static str {
+        match *self {
+            Codec::Gzip => "gzip",
+            Codec::Zstd => "zstd",
+        }
+    }
+
+    /// The lowest and highest levels the format can be compressed at.
+    pub fn levels(&self) -> (i32, i32) {
+        match *self {
+            Codec::Gzip => (0, 9),
+            Codec::Zstd => (1, 22),
+        }
+    }
+
+    /// Whether this build includes support for the format.
+    pub fn is_available(&self) -> bool {
+        match *self {
+            Codec::Gzip => cfg!(feature = "gzip"),
+            Codec::Zstd => cfg!(feature = "zstd"),
+        }
+    }
+}
+
+/// The format and level to compress data with before it is sent.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct Compression {
+    pub codec: Codec,
+
+    /// The level to compress at, or the default of the format if not given.
+    pub level: Option<i32>,
+}
+
+impl Compression {
+    /// Parses a format optionally followed by a level, such as `zstd` or
+    /// `gzip:9`.
+    pub fn parse(value: &str) -> Result<Compression, String> {
+        let (name, level) = match value.split_once(':') {
+            Some((name, level)) => (name, Some(level)),
+            None => (value, None),
+        };
+        let codec = match name {
+            "gzip" => Codec::Gzip,
+            "zstd" => Codec::Zstd,
+            _ => return Err(format!("unknown format \"{}\", expected gzip or zstd", name)),
+        };
+        let level = match level {
+            Some(level) => {
+                let (min, max) = codec.levels();
+                match level.parse() {
+                    Ok(level) if level >= min && level <= max => Some(level),
+                    _ => return Err(format!("{} levels go from {} to {} (got \"{}\")", codec.name(), min, max, level)),
+                }
+            },
+            None => None,
+        };
+        Ok(Compression { codec, level })
+    }
+}
+
+/// The bytes that crossed the wire while a stream was decoded or encoded.
+#[derive(Clone, Copy)]
+pub struct WireStats {
+    pub codec: Codec,
+    pub bytes: u64,
+}
+
+impl WireStats {
+    /// How many times smaller the data on the wire is than the data itself.
+    pub fn ratio(&self, data_bytes: u64) -> f64 {
+        if self.bytes == 0 { 0.0 } else { data_bytes as f64 / self.bytes as f64 }
+    }
+}
+
+/// Counts the bytes read or written through it, before decoding or after
+/// encoding.
+pub struct WireCounter<T> {
+    inner: T,
+    bytes: Arc<AtomicU64>,
+}
+
+impl<T> WireCounter<T> {
+    pub fn new(inner: T) -> (WireCounter<T>, Arc<AtomicU64>) {
+        let bytes = Arc::new(AtomicU64::new(0));
+        (WireCounter { inner, bytes: bytes.clone() }, bytes)
+    }
+
+    /// Adds to a count that is shared with other streams.
+    pub fn sharing(inner: T, bytes: Arc<AtomicU64>) -> WireCounter<T> {
+        WireCounter { inner, bytes }
+    }
+}
+
+impl<T: Read> Read for WireCounter<T> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let bytes_read = self.inner.read(buf)?;
+        self.bytes.fetch_add(bytes_read as u64, Ordering::Relaxed);
+        Ok(bytes_read)
+    }
+
+    fn read_vectored(&mut self, bufs: &mut [IoSliceMut]) -> io::Result<usize> {
+        let bytes_read = self.inner.read_vectored(bufs)?;
+        self.bytes.fetch_add(bytes_read as u64, Ordering::Relaxed);
+        Ok(bytes_read)
+    }
+}
+
+impl<T: Write> Write for WireCounter<T> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let written = self.inner.write(buf)?;
+        self.bytes.fetch_add(written as u64, Ordering::Relaxed);
+        Ok(written)
+    }
+
+    fn write_vectored(&mut self, bufs: &[IoSlice]) -> io::Result<usize> {
+        let written = self.inner.write_vectored(bufs)?;
+        self.bytes.fetch_add(written as u64, Ordering::Relaxed);
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+/// A writer that has to be told when there is nothing more to compress.
+pub trait Encoder: Write {
+    /// Writes out everything that is still buffered along with the end of
+    /// the stream.
+    fn finish(self: Box<Self>) -> io::Result<()>;
+}
+
+/// Decodes the stream read from the reader. Streams of several gzip members
+/// or zstd frames, such as files compressed separately and concatenated, are
+/// decoded as one.
+#[allow(unused_variables)]
+pub fn decoder<'a, R: Read + 'a>(codec: Codec, reader: R) -> io::Result<Box<dyn Read + 'a>> {
+    match codec {
+        #[cfg(feature = "gzip")]
+        Codec::Gzip => Ok(Box::new(flate2::read::MultiGzDecoder::new(reader))),
+        #[cfg(feature = "zstd")]
+        Codec::Zstd => Ok(Box::new(zstd::stream::read::Decoder::new(reader)?)),
+        #[allow(unreachable_patterns)]
+        _ => Err(unavailable(codec)),
+    }
+}
+
+/// Encodes everything written into the writer at the given level, or the
+/// default level of the format.
+#[allow(unused_variables)]
+pub fn encoder<'a, W: Write + Send + 'a>(codec: Codec, level: Option<i32>, writer: W) -> io::Result<Box<dyn Encoder + Send + 'a>> {
+    match codec {
+        #[cfg(feature = "gzip")]
+        Codec::Gzip => {
+            let level = level.map_or_else(flate2::Compression::default, |level| flate2::Compression::new(level as u32));
+            Ok(Box::new(flate2::write::GzEncoder::new(writer, level)))
+        },
+        #[cfg(feature = "zstd")]
+        Codec::Zstd => Ok(Box::new(zstd::stream::write::Encoder::new(writer, level.unwrap_or(0))?)),
+        #[allow(unreachable_patterns)]
+        _ => Err(unavailable(codec)),
+    }
+}
+
+/// An encoder that ends the stream once it is dropped, for handing to
+/// something that only ever writes and flushes, such as a pipeline.
+pub struct FinishOnDrop<'a> {
+    encoder: Option<Box<dyn Encoder + Send + 'a>>,
+}
+
+impl<'a> FinishOnDrop<'a> {
+    pub fn new(encoder: Box<dyn Encoder + Send + 'a>) -> FinishOnDrop<'a> {
+        FinishOnDrop { encoder: Some(encoder) }
+    }
+
+    fn encoder(&mut self) -> &mut Box<dyn Encoder + Send + 'a> {
+        self.encoder.as_mut().expect("the encoder is only taken when dropped")
+    }
+}
+
+impl<'a> Write for FinishOnDrop<'a> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.encoder().write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.encoder().flush()
+    }
+}
+
+impl<'a> Drop for FinishOnDrop<'a> {
+    fn drop(&mut self) {
+        if let Some(encoder) = self.encoder.take() {
+            if let Err(err) = encoder.finish() {
+                print_err!("Error while finishing the compressed stream: {}", err);
+            }
+        }
+    }
+}
+
+#[allow(dead_code)]
+fn unavailable(codec: Codec) -> io::Error {
+    io::Error::new(io::ErrorKind::Unsupported, format!("this build does not include {} support", codec.name()))
+}
+
+#[cfg(feature = "gzip")]
+impl<W: Write> Encoder for flate2::write::GzEncoder<W> {
+    fn finish(self: Box<Self>) -> io::Result<()> {
+        flate2::write::GzEncoder::finish(*self).map(drop)
+    }
+}
+
+#[cfg(feature = "zstd")]
+impl<'a, W: Write> Encoder for zstd::stream::write::Encoder<'a, W> {
+    fn finish(self: Box<Self>) -> io::Result<()> {
+        zstd::stream::write::Encoder::finish(*self).map(drop)
+    }
+}
@@ -0,0 +1,118 @@
+//! Reading several sources back to back as one stream, the way `cat` does,
+//! while keeping track of how quickly each one was read.
+
+use std::fs::File;
+use std::io::{self, stdin, Read};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+/// The path that stands for stdin.
+pub const STDIN_PATH: &str = "-";
+
+/// How much was read from one source and how long it took.
+#[derive(Clone)]
+pub struct SourceStats {
+    pub path: String,
+    pub bytes: u64,
+
+    /// The time from the first read of the source until its end.
+    pub elapsed: Duration,
+}
+
+/// Reads one of several sources that are read at the same time and records
+/// what it read into its entry in the stats shared by all of them.
+pub struct SourceReader<R> {
+    inner: R,
+    index: usize,
+    started: Instant,
+    stats: Arc<Mutex<Vec<SourceStats>>>,
+}
+
+impl<R> SourceReader<R> {
+    pub fn new(inner: R, index: usize, started: Instant, stats: Arc<Mutex<Vec<SourceStats>>>) -> SourceReader<R> {
+        SourceReader { inner, index, started, stats }
+    }
+}
+
+impl<R: Read> Read for SourceReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let bytes_read = self.inner.read(buf)?;
+
+        let mut stats = self.stats.lock().expect("source stats lock was poisoned");
+        let source_stats = &mut stats[self.index];
+        source_stats.bytes += bytes_read as u64;
+        source_stats.elapsed = self.started.elapsed();
+        Ok(bytes_read)
+    }
+}
+
+/// Reads each of the paths in turn, opening them as they are reached, and
+/// records their stats into a list shared with whoever created the reader.
+pub struct ConcatReader {
+    paths: Vec<String>,
+    next: usize,
+    current: Option<(Box<dyn Read>, Instant)>,
+    stats: Arc<Mutex<Vec<SourceStats>>>,
+}
+
+impl ConcatReader {
+    pub fn new(paths: Vec<String>) -> (ConcatReader, Arc<Mutex<Vec<SourceStats>>>) {
+        let stats = Arc::new(Mutex::new(Vec::with_capacity(paths.len())));
+        (ConcatReader { paths, next: 0, current: None, stats: stats.clone() }, stats)
+    }
+
+    /// Opens the next source, returning false once there are none left.
+    fn open_next(&mut self) -> io::Result<bool> {
+        let path = match self.paths.get(self.next) {
+            Some(path) => path.clone(),
+            None => return Ok(false),
+        };
+        self.next += 1;
+
+        let started = Instant::now();
+        let source: Box<dyn Read> = if path == STDIN_PATH {
+            Box::new(stdin())
+        } else {
+            Box::new(File::open(&path).map_err(|err| io::Error::new(err.kind(), format!("{}: {}", path, err)))?)
+        };
+        self.stats.lock().expect("source stats lock was poisoned")
+            .push(SourceStats { path, bytes: 0, elapsed: Duration::default() });
+        self.current = Some((source, started));
+        Ok(true)
+    }
+}
+
+impl Read for ConcatReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        loop {
+            if self.current.is_none() && !self.open_next()? {
+                return Ok(0);
+            }
+
+            let (ref mut source, started) = *self.current.as_mut().expect("a source should be open");
+            let bytes_read = source.read(buf)?;
+
+            let mut stats = self.stats.lock().expect("source stats lock was poisoned");
+            let source_stats = stats.last_mut().expect("the open source should have stats");
+            source_stats.bytes += bytes_read as u64;
+            source_stats.elapsed = started.elapsed();
+            drop(stats);
+
+            if bytes_read > 0 {
+                return Ok(bytes_read);
+            }
+            self.current = None;
+        }
+    }
+}
+
+/// The combined size of the sources, if every one of them has a known size.
+pub fn total_size<F: Fn() -> Option<u64>>(paths: &[String], stdin_size: F) -> Option<u64> {
+    paths.iter().map(|path| {
+        if path == STDIN_PATH {
+            stdin_size()
+        } else {
+            std::fs::metadata(path).ok().map(|metadata| metadata.len())
+        }
+    }).sum()
+}
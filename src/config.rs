@@ -0,0 +1,135 @@
+//! Settings that can be stored in a TOML config file or in `THROUGHPUT_*`
+//! environment variables instead of being given on the command line.
+//!
+//! Keys are the long names of the command line options, e.g. `port = 5000`,
+//! `bufsize = "64K"` or `min-throughput = "50MB/s"`, and flags are booleans.
+//! The command line takes precedence over the environment, which takes
+//! precedence over the config file, which takes precedence over a profile.
+
+use std::collections::HashMap;
+use std::env;
+use std::fs;
+
+use clap::ArgMatches;
+
+/// The prefix of environment variables holding settings, e.g.
+/// `THROUGHPUT_BUFSIZE`.
+const ENV_PREFIX: &str = "THROUGHPUT_";
+
+/// The environment variable that can name a config file instead of `--config`.
+const CONFIG_ENV: &str = "THROUGHPUT_CONFIG";
+
+/// Settings loaded from a config file and the environment, by key.
+#[derive(Default)]
+pub struct Config {
+    values: HashMap<String, Vec<String>>,
+}
+
+impl Config {
+    /// Loads the config file at the given path, or the one named by
+    /// `THROUGHPUT_CONFIG`, and then applies the environment on top of it.
+    pub fn load(path: Option<&str>) -> Result<Config, String> {
+        let mut config = Config::default();
+
+        let env_path = env::var(CONFIG_ENV).ok();
+        if let Some(path) = path.or(env_path.as_deref()) {
+            let content = fs::read_to_string(path)
+                .map_err(|err| format!("could not read config file {}: {}", path, err))?;
+            let table: toml::Table = content.parse()
+                .map_err(|err| format!("could not parse config file {}: {}", path, err))?;
+            for (key, value) in table {
+                let values = toml_values(&value)
+                    .ok_or_else(|| format!("unsupported value for {} in config file {}", key, path))?;
+                config.values.insert(normalize_key(&key), values);
+            }
+        }
+
+        for (name, value) in env::vars() {
+            if name == CONFIG_ENV || !name.starts_with(ENV_PREFIX) { continue; }
+            config.values.insert(normalize_key(&name[ENV_PREFIX.len()..]), vec![value]);
+        }
+
+        Ok(config)
+    }
+
+    /// Sets each of the options that is not set already, such as those of a
+    /// profile.
+    fn add_defaults(&mut self, options: &[(&str, &str)]) {
+        for &(key, value) in options {
+            self.values.entry(normalize_key(key)).or_insert_with(|| vec![value.to_string()]);
+        }
+    }
+
+    fn get(&self, name: &str) -> Option<&[String]> {
+        self.values.get(&config_key(name)).map(Vec::as_slice)
+    }
+}
+
+/// Turns a TOML value into the strings it would have been given as on the
+/// command line. Arrays are used for options that may be repeated.
+fn toml_values(value: &toml::Value) -> Option<Vec<String>> {
+    match *value {
+        toml::Value::String(ref value) => Some(vec![value.clone()]),
+        toml::Value::Integer(value) => Some(vec![value.to_string()]),
+        toml::Value::Float(value) => Some(vec![value.to_string()]),
+        toml::Value::Boolean(value) => Some(vec![value.to_string()]),
+        toml::Value::Array(ref values) => {
+            let mut strings = Vec::with_capacity(values.len());
+            for value in values {
+                strings.extend(toml_values(value)?);
+            }
+            Some(strings)
+        },
+        _ => None,
+    }
+}
+
+fn normalize_key(key: &str) -> String {
+    key.to_lowercase().replace('_', "-")
+}
+
+/// Returns the key used for an argument, which is its long option name.
+fn config_key(name: &str) -> String {
+    match name {
+        "address" => "addr".to_string(),
+        "buffer_size" => "bufsize".to_string(),
+        _ => normalize_key(name),
+    }
+}
+
+/// Looks up options on the command line first and in the config second.
+pub struct Settings<'a, 'b> {
+    matches: &'b ArgMatches<'a>,
+    config: Config,
+}
+
+impl<'a, 'b> Settings<'a, 'b> {
+    pub fn new(matches: &'b ArgMatches<'a>, config: Config) -> Settings<'a, 'b> {
+        Settings { matches, config }
+    }
+
+    /// Sets each of the options that is neither on the command line nor in
+    /// the config, keyed by their long names.
+    pub fn add_defaults(&mut self, options: &[(&str, &str)]) {
+        self.config.add_defaults(options);
+    }
+
+    pub fn value_of(&self, name: &str) -> Option<&str> {
+        self.matches.value_of(name)
+            .or_else(|| self.config.get(name).and_then(|values| values.last()).map(String::as_str))
+    }
+
+    pub fn values_of(&self, name: &str) -> Vec<&str> {
+        match self.matches.values_of(name) {
+            Some(values) => values.collect(),
+            None => self.config.get(name).map(|values| values.iter().map(String::as_str).collect()).unwrap_or_default(),
+        }
+    }
+
+    /// Whether a flag was given, or set to true in the config.
+    pub fn is_present(&self, name: &str) -> bool {
+        self.matches.is_present(name) || self.value_of(name).is_some_and(|value| {
+            !matches!(value.to_lowercase().as_str(), "" | "0" | "false" | "no" | "off")
+        })
+    }
+}
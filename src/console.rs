@@ -0,0 +1,134 @@
+//! Platform specific handling of the terminal the stats are written to.
+
+use std::env;
+use std::io::{stderr, stdout, IsTerminal};
+use std::sync::atomic::{AtomicBool, Ordering};
+
+/// Set when the terminal is resized, until the resize is taken.
+static RESIZED: AtomicBool = AtomicBool::new(false);
+
+/// One of the standard output streams.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum StdStream {
+    Stdout,
+    Stderr,
+}
+
+impl StdStream {
+    pub fn is_terminal(self) -> bool {
+        match self {
+            StdStream::Stdout => stdout().is_terminal(),
+            StdStream::Stderr => stderr().is_terminal(),
+        }
+    }
+
+    /// The number of columns of the terminal the stream writes to, falling
+    /// back to `COLUMNS` when the terminal cannot be asked.
+    pub fn width(self) -> Option<usize> {
+        terminal_width(self)
+            .or_else(|| env::var("COLUMNS").ok().and_then(|columns| columns.parse().ok()))
+            .filter(|&columns| columns > 0)
+    }
+}
+
+/// Starts noticing when the terminal is resized, which is only reported on
+/// Unix-like platforms where SIGWINCH is sent.
+pub fn watch_resize() {
+    #[cfg(unix)]
+    {
+        use std::sync::Once;
+
+        static WATCH: Once = Once::new();
+        extern "C" fn on_resize(_signal: libc::c_int) {
+            RESIZED.store(true, Ordering::Relaxed);
+        }
+        WATCH.call_once(|| unsafe {
+            libc::signal(libc::SIGWINCH, on_resize as *const () as libc::sighandler_t);
+        });
+    }
+}
+
+/// Whether the terminal was resized since the last time this was asked.
+pub fn take_resized() -> bool {
+    RESIZED.swap(false, Ordering::Relaxed)
+}
+
+#[cfg(unix)]
+fn terminal_width(stream: StdStream) -> Option<usize> {
+    let fd = match stream {
+        StdStream::Stdout => libc::STDOUT_FILENO,
+        StdStream::Stderr => libc::STDERR_FILENO,
+    };
+    let mut size: libc::winsize = unsafe { std::mem::zeroed() };
+    if unsafe { libc::ioctl(fd, libc::TIOCGWINSZ, &mut size) } == 0 {
+        Some(size.ws_col as usize)
+    } else {
+        None
+    }
+}
+
+#[cfg(windows)]
+fn terminal_width(stream: StdStream) -> Option<usize> {
+    use windows_sys::Win32::System::Console::{
+        GetConsoleScreenBufferInfo, GetStdHandle, CONSOLE_SCREEN_BUFFER_INFO, STD_ERROR_HANDLE, STD_OUTPUT_HANDLE,
+    };
+
+    let which = match stream {
+        StdStream::Stdout => STD_OUTPUT_HANDLE,
+        StdStream::Stderr => STD_ERROR_HANDLE,
+    };
+    unsafe {
+        let mut info: CONSOLE_SCREEN_BUFFER_INFO = std::mem::zeroed();
+        if GetConsoleScreenBufferInfo(GetStdHandle(which), &mut info) == 0 {
+            return None;
+        }
+        Some((info.srWindow.Right - info.srWindow.Left + 1).max(0) as usize)
+    }
+}
+
+#[cfg(not(any(unix, windows)))]
+fn terminal_width(_stream: StdStream) -> Option<usize> {
+    None
+}
+
+/// Makes sure the stream interprets ANSI escape sequences, returning false if
+/// that is not possible.
+#[cfg(not(windows))]
+pub fn enable_ansi(_stream: StdStream) -> bool {
+    true
+}
+
+/// Makes sure the stream interprets ANSI escape sequences, returning false if
+/// that is not possible.
+///
+/// Consoles on Windows 10 and later support ANSI escapes once virtual terminal
+/// processing has been enabled. Older consoles reject the mode.
+#[cfg(windows)]
+pub fn enable_ansi(stream: StdStream) -> bool {
+    use windows_sys::Win32::Foundation::INVALID_HANDLE_VALUE;
+    use windows_sys::Win32::System::Console::{
+        GetConsoleMode, GetStdHandle, SetConsoleMode, ENABLE_VIRTUAL_TERMINAL_PROCESSING, STD_ERROR_HANDLE, STD_OUTPUT_HANDLE,
+    };
+
+    let which = match stream {
+        StdStream::Stdout => STD_OUTPUT_HANDLE,
+        StdStream::Stderr => STD_ERROR_HANDLE,
+    };
+
+    unsafe {
+        let handle = GetStdHandle(which);
+        if handle == 0 || handle == INVALID_HANDLE_VALUE {
+            return false;
+        }
+
+        let mut mode = 0;
+        if GetConsoleMode(handle, &mut mode) == 0 {
+            // Not a console (e.g. a mintty pipe), which passes escapes through.
+            return true;
+        }
+        if mode & ENABLE_VIRTUAL_TERMINAL_PROCESSING != 0 {
+            return true;
+        }
+        SetConsoleMode(handle, mode | ENABLE_VIRTUAL_TERMINAL_PROCESSING) != 0
+    }
+}
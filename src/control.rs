@@ -0,0 +1,521 @@
+//! A small protocol that lets a throughput client and server agree on the
+//! parameters of a test and share its results, similar to iperf.
+//!
+//! Every connection starts with a single line of JSON. The client opens a
+//! control connection with a `test` message describing the test, the server
+//! answers with `accepted` and a test id, and the client then opens one data
+//! connection per parallel stream, each starting with a `data` message that
+//! names the test. Once every data connection has arrived the server sends
+//! `start`, data flows until the sender shuts down its side of the data
+//! connections, and whichever side received the data sends its measurements
+//! back as `results` so that both sides report the same numbers. In a
+//! bidirectional test both sides send and receive, and both send `results`.
+//!
+//! Before the data connections are opened the client sends a few `clock`
+//! probes that the server answers with its own time, which gives the offset
+//! between their clocks. The `results` carry when the bytes arrived, and the
+//! server follows them with `departures` saying when it sent bytes, if it
+//! sent any, so that the client can work out the one-way delay of the data.
+
+use std::io::{self, Read, Write};
+use std::net::{Shutdown, SocketAddr, TcpStream};
+use std::sync::atomic::AtomicU64;
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use serde_json::Value;
+
+use affinity;
+use clock::{self, ClockOffset, OneWayDelay, Progress, ProgressSampler, CLOCK_PROBES};
+use compress::WireCounter;
+use display::format_one_way_delay;
+use merge::MergedReader;
+use net::{self, Acceptor, SocketOptions};
+use pattern::Pattern;
+use report;
+use sender::{self, GenerationCost, Payload};
+use shape::{Schedule, Shaper};
+use {measure_reader, print_connect_retry, print_connected_family, print_summary, DisplayMode, MeasureOptions, TransferInfo};
+
+/// The version of the protocol, which both sides must agree on.
+pub const PROTOCOL_VERSION: u64 = 2;
+
+/// The longest control message that will be accepted.
+const MAX_MESSAGE_LENGTH: usize = 64 * 1024;
+
+/// How long a new connection has to identify itself.
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// The most parallel streams a server will agree to.
+const MAX_PARALLEL_STREAMS: usize = 128;
+
+/// The longest test a server will agree to.
+const MAX_TEST_DURATION: Duration = Duration::from_secs(24 * 60 * 60);
+
+/// The largest buffer a server will allocate for each stream.
+const MAX_BUFFER_SIZE: usize = 16 * 1024 * 1024;
+
+/// Which end of the control connection this is. The client knows the offset
+/// of the server's clock and works out the one-way delay, while the server
+/// only sends it what it needs for that.
+#[derive(Clone, Copy, Debug)]
+enum Role {
+    Client(ClockOffset),
+    Server,
+}
+
+/// Which way data flows during a test.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Direction {
+    /// The client sends and the server measures.
+    Upload,
+
+    /// The server sends and the client measures.
+    Download,
+
+    /// Both sides send and measure at the same time.
+    Bidirectional,
+}
+
+impl Direction {
+    pub fn name(self) -> &'static str {
+        match self {
+            Direction::Upload => "upload",
+            Direction::Download => "download",
+            Direction::Bidirectional => "bidirectional",
+        }
+    }
+
+    pub fn parse(value: &str) -> Option<Direction> {
+        match value {
+            "upload" => Some(Direction::Upload),
+            "download" => Some(Direction::Download),
+            "bidirectional" => Some(Direction::Bidirectional),
+            _ => None,
+        }
+    }
+}
+
+/// The parameters of a test, chosen by the client.
+#[derive(Clone, Debug)]
+pub struct TestParams {
+    /// How long the sender sends for.
+    pub duration: Duration,
+
+    /// The number of data connections used at once.
+    pub parallel: usize,
+
+    pub direction: Direction,
+
+    /// The size of the buffer used to read and write the data connections.
+    pub buffer_size: usize,
+}
+
+impl TestParams {
+    fn to_message(&self) -> Value {
+        json!({
+            "type": "test",
+            "version": PROTOCOL_VERSION,
+            "duration_secs": self.duration.as_secs_f64(),
+            "parallel": self.parallel,
+            "direction": self.direction.name(),
+            "buffer_size": self.buffer_size,
+        })
+    }
+
+    fn from_message(message: &Value) -> Result<TestParams, String> {
+        if message["version"].as_u64() != Some(PROTOCOL_VERSION) {
+            return Err(format!("unsupported protocol version {}, expected {}", message["version"], PROTOCOL_VERSION));
+        }
+
+        let duration = message["duration_secs"].as_f64()
+            .and_then(|secs| Duration::try_from_secs_f64(secs).ok())
+            .filter(|duration| !duration.is_zero() && *duration <= MAX_TEST_DURATION)
+            .ok_or(format!("the test duration must be more than 0 and at most {} seconds", MAX_TEST_DURATION.as_secs()))?;
+        let parallel = message["parallel"].as_u64()
+            .filter(|&parallel| parallel >= 1 && parallel <= MAX_PARALLEL_STREAMS as u64)
+            .ok_or(format!("the number of parallel streams must be from 1 to {}", MAX_PARALLEL_STREAMS))?;
+        let direction = message["direction"].as_str()
+            .and_then(Direction::parse)
+            .ok_or(format!("unsupported direction {}", message["direction"]))?;
+        let buffer_size = message["buffer_size"].as_u64()
+            .filter(|&size| size >= 1 && size <= MAX_BUFFER_SIZE as u64)
+            .ok_or(format!("the buffer size must be from 1 to {} bytes", MAX_BUFFER_SIZE))?;
+
+        Ok(TestParams {
+            duration,
+            parallel: parallel as usize,
+            direction,
+            buffer_size: buffer_size as usize,
+        })
+    }
+}
+
+/// Writes a message as a single line of JSON.
+fn write_message<W: Write>(writer: &mut W, message: &Value) -> io::Result<()> {
+    writeln!(writer, "{}", message)?;
+    writer.flush()
+}
+
+/// Reads a single line of JSON. Bytes are read one at a time so that none of
+/// the data following the message is consumed.
+fn read_message<R: Read>(reader: &mut R) -> io::Result<Value> {
+    let mut line = Vec::new();
+    let mut byte = [0u8];
+    loop {
+        if reader.read(&mut byte)? == 0 {
+            return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "connection closed during handshake"));
+        }
+        if byte[0] == b'\n' { break; }
+        if line.len() == MAX_MESSAGE_LENGTH {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "control message is too long"));
+        }
+        line.push(byte[0]);
+    }
+    serde_json::from_slice(&line).map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))
+}
+
+/// Reads a message and makes sure that it has the expected type, turning
+/// `error` messages from the peer into errors.
+fn expect_message<R: Read>(reader: &mut R, expected: &str) -> io::Result<Value> {
+    let message = read_message(reader)?;
+    match message["type"].as_str() {
+        Some(kind) if kind == expected => Ok(message),
+        Some("error") => Err(io::Error::other(format!("peer reported an error: {}",
+            message["message"].as_str().unwrap_or("unknown error")))),
+        _ => Err(io::Error::new(io::ErrorKind::InvalidData, format!("expected a {} message, got {}", expected, message))),
+    }
+}
+
+/// Sends the probes that estimate how far the clock of the server is ahead of
+/// ours, keeping the one with the shortest round trip.
+fn synchronize_clocks(control: &mut TcpStream) -> io::Result<ClockOffset> {
+    let mut best: Option<ClockOffset> = None;
+    for _ in 0..CLOCK_PROBES {
+        let sent = clock::now_secs();
+        write_message(control, &json!({ "type": "clock" }))?;
+        let reply = expect_message(control, "clock")?;
+        let received = clock::now_secs();
+        let (peer_received, peer_sent) = match (reply["received"].as_f64(), reply["sent"].as_f64()) {
+            (Some(peer_received), Some(peer_sent)) => (peer_received, peer_sent),
+            _ => return Err(io::Error::new(io::ErrorKind::InvalidData, "server sent an invalid clock reply")),
+        };
+        let probe = ClockOffset::from_probe(sent, peer_received, peer_sent, received);
+        if best.is_none_or(|best| probe.round_trip < best.round_trip) {
+            best = Some(probe);
+        }
+    }
+    Ok(best.expect("at least one clock probe is sent"))
+}
+
+/// Answers the clock probes of the client with the times they were received
+/// and answered.
+fn answer_clock_probes(control: &mut TcpStream) -> io::Result<()> {
+    for _ in 0..CLOCK_PROBES {
+        expect_message(control, "clock")?;
+        let received = clock::now_secs();
+        write_message(control, &json!({ "type": "clock", "received": received, "sent": clock::now_secs() }))?;
+    }
+    Ok(())
+}
+
+/// Accepts connections until one of them sends a message of the expected
+/// type, ignoring anything else.
+fn accept_message(acceptor: &mut Acceptor, matches: &dyn Fn(&Value) -> bool) -> io::Result<(TcpStream, SocketAddr, Value)> {
+    loop {
+        let (mut stream, peer, _) = acceptor.accept()?;
+        stream.set_read_timeout(Some(HANDSHAKE_TIMEOUT))?;
+        match read_message(&mut stream) {
+            Ok(message) => {
+                if matches(&message) {
+                    stream.set_read_timeout(None)?;
+                    return Ok((stream, peer, message));
+                }
+                print_err!("Ignoring unexpected connection from {}", peer);
+            },
+            Err(err) => print_err!("Ignoring connection from {}: {}", peer, err),
+        }
+    }
+}
+
+/// Waits for a client to start a test and runs it, measuring or sending data
+/// depending on the direction the client asked for.
+pub fn run_server(acceptor: &mut Acceptor, socket_options: &SocketOptions, options: &MeasureOptions) -> io::Result<TransferInfo> {
+    let (mut control, peer, request) = accept_message(acceptor, &|message| message["type"] == "test")?;
+    let params = match TestParams::from_message(&request) {
+        Ok(params) => params,
+        Err(message) => {
+            let _ = write_message(&mut control, &json!({ "type": "error", "message": message }));
+            return Err(io::Error::new(io::ErrorKind::InvalidData, message));
+        },
+    };
+
+    let test_id = SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_nanos() as u64).unwrap_or(0);
+    write_message(&mut control, &json!({ "type": "accepted", "test_id": test_id }))?;
+    // The probes are timed, so they must not wait to be coalesced.
+    control.set_nodelay(true)?;
+    answer_clock_probes(&mut control)?;
+    println!("Starting {} test from {} with {} stream(s) for {:.1} seconds",
+        params.direction.name(), peer, params.parallel, params.duration.as_secs_f64());
+
+    let mut streams = Vec::with_capacity(params.parallel);
+    while streams.len() < params.parallel {
+        let (stream, _, _) = accept_message(acceptor, &|message| {
+            message["type"] == "data" && message["test_id"].as_u64() == Some(test_id)
+        })?;
+        socket_options.apply(&stream)?;
+        streams.push(stream);
+    }
+
+    write_message(&mut control, &json!({ "type": "start" }))?;
+
+    match params.direction {
+        Direction::Upload => receive_and_share(&mut control, streams, &params, options, Role::Server),
+        Direction::Download => send_and_collect(&mut control, streams, &params, options, Role::Server),
+        Direction::Bidirectional => send_and_receive(&mut control, streams, &params, options, Role::Server),
+    }
+}
+
+/// Starts a test on a server and runs it, sending or measuring data depending
+/// on the direction of the test.
+pub fn run_client(addrs: &[SocketAddr], params: &TestParams, socket_options: &SocketOptions, options: &MeasureOptions) -> io::Result<TransferInfo> {
+    let (mut control, server) = net::connect_retrying(addrs, socket_options, |err, retry| print_connect_retry(err, retry, socket_options))?;
+    print_connected_family(addrs, &server);
+    write_message(&mut control, &params.to_message())?;
+    let accepted = expect_message(&mut control, "accepted")?;
+    let test_id = accepted["test_id"].as_u64()
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "server did not send a test id"))?;
+    control.set_nodelay(true)?;
+    let clock = synchronize_clocks(&mut control)?;
+
+    let mut streams = Vec::with_capacity(params.parallel);
+    for _ in 0..params.parallel {
+        let (mut stream, _) = net::connect_first(&[server], socket_options)?;
+        write_message(&mut stream, &json!({ "type": "data", "test_id": test_id }))?;
+        streams.push(stream);
+    }
+
+    expect_message(&mut control, "start")?;
+    println!("Starting {} test with {} with {} stream(s) for {:.1} seconds",
+        params.direction.name(), server, params.parallel, params.duration.as_secs_f64());
+
+    match params.direction {
+        Direction::Upload => send_and_collect(&mut control, streams, params, options, Role::Client(clock)),
+        Direction::Download => receive_and_share(&mut control, streams, params, options, Role::Client(clock)),
+        Direction::Bidirectional => send_and_receive(&mut control, streams, params, options, Role::Client(clock)),
+    }
+}
+
+/// Measures the data arriving on the streams and sends the results to the
+/// peer. The client then prints the one-way delay of the data the server
+/// sent.
+fn receive_and_share(control: &mut TcpStream, streams: Vec<TcpStream>, params: &TestParams, options: &MeasureOptions, role: Role) -> io::Result<TransferInfo> {
+    println!();
+    let (mut transfer_info, arrivals) = measure_streams(streams, params, options);
+    write_results(control, &transfer_info, &arrivals)?;
+
+    if let Role::Client(clock) = role {
+        let departures = expect_departures(control)?;
+        transfer_info.one_way_delay = OneWayDelay::estimate(&departures, &arrivals, -clock.offset, clock);
+        if let Some(ref delay) = transfer_info.one_way_delay {
+            print_one_way_delay(delay, options);
+        }
+    }
+    Ok(transfer_info)
+}
+
+/// Sends data over the streams for the duration of the test and prints the
+/// results measured by the peer, along with the one-way delay on the client.
+fn send_and_collect(control: &mut TcpStream, streams: Vec<TcpStream>, params: &TestParams, options: &MeasureOptions, role: Role) -> io::Result<TransferInfo> {
+    let sampler = ProgressSampler::start(options.max_samples);
+    let (bytes_sent, _) = send_streams(streams, params.buffer_size, options.vecs, &options.cpus, Payload::generated(Pattern::Counter),
+        params.duration, options.shape.as_ref(), Some(sampler.counter()))?;
+    let departures = sampler.finish();
+    println!("Sent {} bytes, waiting for results", bytes_sent);
+    println!();
+
+    let (mut transfer_info, arrivals) = expect_results(control)?;
+    match role {
+        Role::Client(clock) => transfer_info.one_way_delay = OneWayDelay::estimate(&departures, &arrivals, clock.offset, clock),
+        Role::Server => write_departures(control, &departures)?,
+    }
+    print_summary(&transfer_info, options);
+    Ok(transfer_info)
+}
+
+/// Sends data over the streams while measuring the data arriving on them,
+/// then exchanges results with the peer and prints both directions. Upstream
+/// is the data sent by the client. Returns what this side received.
+fn send_and_receive(control: &mut TcpStream, streams: Vec<TcpStream>, params: &TestParams, options: &MeasureOptions, role: Role) -> io::Result<TransferInfo> {
+    let sending = streams.iter().map(TcpStream::try_clone).collect::<io::Result<Vec<_>>>()?;
+    let sampler = ProgressSampler::start(options.max_samples);
+    let (buffer_size, vecs, cpus, duration, shape, sent) = (params.buffer_size, options.vecs, options.cpus.clone(), params.duration,
+        options.shape.clone(), sampler.counter());
+    let sender = thread::spawn(move || send_streams(sending, buffer_size, vecs, &cpus, Payload::generated(Pattern::Counter), duration,
+        shape.as_ref(), Some(sent)));
+
+    println!();
+    let (mut received, arrivals) = measure_streams(streams, params, options);
+    let (bytes_sent, _) = sender.join().expect("sender thread panicked")?;
+    let departures = sampler.finish();
+    println!("Sent {} bytes, waiting for results", bytes_sent);
+
+    write_results(control, &received, &arrivals)?;
+    let (mut peer_received, peer_arrivals) = expect_results(control)?;
+    match role {
+        Role::Client(clock) => {
+            let peer_departures = expect_departures(control)?;
+            peer_received.one_way_delay = OneWayDelay::estimate(&departures, &peer_arrivals, clock.offset, clock);
+            received.one_way_delay = OneWayDelay::estimate(&peer_departures, &arrivals, -clock.offset, clock);
+        },
+        Role::Server => write_departures(control, &departures)?,
+    }
+
+    let (upstream, downstream) = match role {
+        Role::Client(_) => (&peer_received, &received),
+        Role::Server => (&received, &peer_received),
+    };
+    println!();
+    println!("Upstream:");
+    print_summary(upstream, options);
+    println!("Downstream:");
+    print_summary(downstream, options);
+    Ok(received)
+}
+
+/// Measures the data arriving on all of the streams together. Returns the
+/// measurements and when the bytes arrived.
+fn measure_streams(streams: Vec<TcpStream>, params: &TestParams, options: &MeasureOptions) -> (TransferInfo, Progress) {
+    let options = MeasureOptions { buffer_size: params.buffer_size, ..options.clone() };
+    let sampler = ProgressSampler::start(options.max_samples);
+    let mut streams: Vec<_> = streams.into_iter().map(|stream| WireCounter::sharing(stream, sampler.counter())).collect();
+    let transfer_info = if streams.len() == 1 {
+        measure_reader(streams.pop().expect("there should be one stream"), &options)
+    } else {
+        measure_reader(MergedReader::new(streams, params.buffer_size, &options.cpus), &options)
+    };
+    (transfer_info, sampler.finish())
+}
+
+/// Sends the results measured by this side and when the bytes arrived.
+fn write_results(control: &mut TcpStream, transfer_info: &TransferInfo, arrivals: &Progress) -> io::Result<()> {
+    write_message(control, &json!({ "type": "results", "results": report::transfer_summary(transfer_info), "arrivals": arrivals }))
+}
+
+/// Prints the one-way delay of data that was measured before it could be
+/// worked out.
+fn print_one_way_delay(delay: &OneWayDelay, options: &MeasureOptions) {
+    if options.display_mode == DisplayMode::Quiet { return; }
+    println!("One-Way Delay: {}", format_one_way_delay(delay));
+}
+
+/// Reads the results measured by the peer and when the bytes arrived there.
+fn expect_results(control: &mut TcpStream) -> io::Result<(TransferInfo, Progress)> {
+    let results = expect_message(control, "results")?;
+    let transfer_info = report::transfer_info_from_summary(&results["results"])
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "peer sent invalid results"))?;
+    Ok((transfer_info, parse_progress(&results["arrivals"])?))
+}
+
+fn write_departures(control: &mut TcpStream, departures: &Progress) -> io::Result<()> {
+    write_message(control, &json!({ "type": "departures", "departures": departures }))
+}
+
+/// Reads when the server sent the bytes it sent.
+fn expect_departures(control: &mut TcpStream) -> io::Result<Progress> {
+    parse_progress(&expect_message(control, "departures")?["departures"])
+}
+
+fn parse_progress(value: &Value) -> io::Result<Progress> {
+    serde_json::from_value(value.clone()).map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))
+}
+
+/// Sends the payload over every stream at once for the given duration, then
+/// shuts down their sending side. Each stream's thread is pinned to its share
+/// of `cpus`. The bytes sent are added to `sent` as they go, if given.
+/// Returns the total number of bytes sent and what generating them cost.
+#[allow(clippy::too_many_arguments)]
+pub fn send_streams(streams: Vec<TcpStream>, buffer_size: usize, vecs: usize, cpus: &[usize], payload: Payload, duration: Duration, shape: Option<&Schedule>, sent: Option<Arc<AtomicU64>>) -> io::Result<(u64, GenerationCost)> {
+    // The streams share one shaper so that the schedule limits them together.
+    let shaper = shape.cloned().map(|schedule| Arc::new(Mutex::new(Shaper::new(schedule))));
+    let senders: Vec<_> = streams.into_iter().enumerate().map(|(index, mut stream)| {
+        let cpus = cpus.to_vec();
+        let shaper = shaper.clone();
+        let counter = sent.clone();
+        let payload = payload.clone();
+        thread::spawn(move || {
+            affinity::pin_connection_thread(&cpus, index);
+            let sent = match counter {
+                Some(counter) => sender::send_for(&mut WireCounter::sharing(&mut stream, counter), buffer_size, vecs, payload, duration,
+                    shaper.as_deref()),
+                None => sender::send_for(&mut stream, buffer_size, vecs, payload, duration, shaper.as_deref()),
+            };
+            stream.shutdown(Shutdown::Write)?;
+            sent
+        })
+    }).collect();
+
+    let mut bytes_sent = 0;
+    let mut cost = GenerationCost::default();
+    for handle in senders {
+        let (sent, sender_cost) = handle.join().expect("sender thread panicked")?;
+        bytes_sent += sent;
+        cost.add(&sender_cost);
+    }
+    Ok((bytes_sent, cost))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn test_message(duration_secs: Value, buffer_size: Value) -> Value {
+        json!({
+            "type": "test",
+            "version": PROTOCOL_VERSION,
+            "duration_secs": duration_secs,
+            "parallel": 1,
+            "direction": "upload",
+            "buffer_size": buffer_size,
+        })
+    }
+
+    #[test]
+    fn accepts_the_params_a_client_sends() {
+        let params = TestParams { duration: Duration::from_secs(10), parallel: 4, direction: Direction::Download, buffer_size: 65536 };
+        let parsed = TestParams::from_message(&params.to_message()).expect("the params should be accepted");
+        assert_eq!(parsed.duration, params.duration);
+        assert_eq!(parsed.parallel, params.parallel);
+        assert_eq!(parsed.direction, params.direction);
+        assert_eq!(parsed.buffer_size, params.buffer_size);
+    }
+
+    #[test]
+    fn rejects_a_duration_too_large_for_a_duration() {
+        assert!(TestParams::from_message(&test_message(json!(1e30), json!(4096))).is_err());
+    }
+
+    #[test]
+    fn rejects_a_duration_over_the_limit() {
+        let secs = MAX_TEST_DURATION.as_secs_f64() + 1.0;
+        assert!(TestParams::from_message(&test_message(json!(secs), json!(4096))).is_err());
+    }
+
+    #[test]
+    fn rejects_a_duration_that_is_not_positive() {
+        assert!(TestParams::from_message(&test_message(json!(0.0), json!(4096))).is_err());
+        assert!(TestParams::from_message(&test_message(json!(-1.0), json!(4096))).is_err());
+    }
+
+    #[test]
+    fn rejects_a_buffer_size_over_the_limit() {
+        assert!(TestParams::from_message(&test_message(json!(10.0), json!(MAX_BUFFER_SIZE as u64 + 1))).is_err());
+        assert!(TestParams::from_message(&test_message(json!(10.0), json!(u64::MAX))).is_err());
+    }
+
+    #[test]
+    fn rejects_an_empty_buffer() {
+        assert!(TestParams::from_message(&test_message(json!(10.0), json!(0))).is_err());
+    }
+}
@@ -0,0 +1,67 @@
+//! Controlling a measurement while it runs. Pausing, resetting the counters
+//! and taking a measure straight away are asked for from other threads, such
+//! as the one reading hotkeys, and acted on by the measuring loop between
+//! reads. Stopping goes through `daemon::request_stop` as SIGTERM does.
+
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use daemon;
+
+/// Something asked of the measurement in progress.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Command {
+    Pause,
+    Resume,
+
+    /// Pauses reading, or resumes it if it was paused.
+    TogglePause,
+
+    /// Starts the totals and averages over from zero.
+    Reset,
+
+    /// Takes a measure without waiting for the next one to be due.
+    Snapshot,
+
+    /// Finishes the transfer, printing the final stats.
+    Stop,
+}
+
+/// The requests made of the measurement that it has not acted on yet.
+#[derive(Default)]
+pub struct Controls {
+    paused: AtomicBool,
+    reset: AtomicBool,
+    snapshot: AtomicBool,
+}
+
+/// Controls shared between the measurement and whatever controls it.
+pub type SharedControls = Arc<Controls>;
+
+impl Controls {
+    pub fn send(&self, command: Command) {
+        match command {
+            Command::Pause => self.paused.store(true, Ordering::SeqCst),
+            Command::Resume => self.paused.store(false, Ordering::SeqCst),
+            Command::TogglePause => { self.paused.fetch_xor(true, Ordering::SeqCst); },
+            Command::Reset => self.reset.store(true, Ordering::SeqCst),
+            Command::Snapshot => self.snapshot.store(true, Ordering::SeqCst),
+            Command::Stop => daemon::request_stop(),
+        }
+    }
+
+    /// Whether reading should wait until resumed.
+    pub fn is_paused(&self) -> bool {
+        self.paused.load(Ordering::SeqCst)
+    }
+
+    /// Whether a reset was asked for since the last call.
+    pub fn take_reset(&self) -> bool {
+        self.reset.swap(false, Ordering::SeqCst)
+    }
+
+    /// Whether a measure was asked for since the last call.
+    pub fn take_snapshot(&self) -> bool {
+        self.snapshot.swap(false, Ordering::SeqCst)
+    }
+}
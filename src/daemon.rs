@@ -0,0 +1,159 @@
+//! Running a listening endpoint as a daemon started by an init script:
+//! detaching from the terminal with the output going to a log file, writing
+//! a PID file, and stopping on SIGTERM with the stats of the transfer in
+//! progress still printed, so that they end up in the log.
+
+use std::sync::atomic::{AtomicBool, Ordering};
+
+static STOP_REQUESTED: AtomicBool = AtomicBool::new(false);
+
+/// Whether SIGTERM was received, after which no further measures or runs
+/// should start.
+pub fn stop_requested() -> bool {
+    STOP_REQUESTED.load(Ordering::Relaxed)
+}
+
+/// Stops the transfer in progress as SIGTERM does, such as when asked to
+/// from the keyboard.
+#[cfg(unix)]
+pub fn request_stop() {
+    unix::stop_measuring();
+}
+
+#[cfg(not(unix))]
+pub fn request_stop() {
+    STOP_REQUESTED.store(true, Ordering::SeqCst);
+}
+
+#[cfg(unix)]
+pub use self::unix::{detach, remove_pidfile_at_exit, stop_on_terminate, write_pidfile};
+
+#[cfg(unix)]
+mod unix {
+    use std::ffi::CString;
+    use std::fs::{self, File, OpenOptions};
+    use std::io;
+    use std::os::unix::ffi::OsStrExt;
+    use std::os::unix::io::AsRawFd;
+    use std::path::Path;
+    use std::sync::{Once, OnceLock};
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::thread;
+    use std::time::Duration;
+
+    use super::STOP_REQUESTED;
+
+    /// How long the transfer in progress gets to finish after SIGTERM before
+    /// the process exits regardless, such as when it is still waiting for a
+    /// connection.
+    const SHUTDOWN_GRACE: Duration = Duration::from_secs(5);
+
+    /// The thread that measures, which SIGTERM is passed on to so that a read
+    /// it is blocked in returns.
+    static MEASURING_THREAD: AtomicUsize = AtomicUsize::new(0);
+
+    static PIDFILE: OnceLock<CString> = OnceLock::new();
+
+    static STOP_ON_TERMINATE: Once = Once::new();
+
+    /// Forks into the background. The parent writes the PID of the child to
+    /// the PID file, if there is one, and exits. The child starts a new
+    /// session with stdin read from /dev/null and stdout and stderr appended
+    /// to the log file, or discarded without one.
+    ///
+    /// This must be called before any threads are started, as only the
+    /// calling thread carries on in the child.
+    pub fn detach(log_path: Option<&str>, pidfile: Option<&str>) -> io::Result<()> {
+        // Opened before forking so that a bad path is reported on the terminal.
+        let log = match log_path {
+            Some(path) => OpenOptions::new().create(true).append(true).open(path)?,
+            None => OpenOptions::new().write(true).open("/dev/null")?,
+        };
+        let null = File::open("/dev/null")?;
+
+        match unsafe { libc::fork() } {
+            -1 => return Err(io::Error::last_os_error()),
+            0 => {},
+            child => {
+                if let Some(path) = pidfile {
+                    if let Err(err) = fs::write(path, format!("{}\n", child)) {
+                        unsafe { libc::kill(child, libc::SIGKILL) };
+                        return Err(err);
+                    }
+                }
+                std::process::exit(0);
+            },
+        }
+
+        if unsafe { libc::setsid() } == -1 {
+            return Err(io::Error::last_os_error());
+        }
+        for &(file, fd) in &[(&null, libc::STDIN_FILENO), (&log, libc::STDOUT_FILENO), (&log, libc::STDERR_FILENO)] {
+            if unsafe { libc::dup2(file.as_raw_fd(), fd) } == -1 {
+                return Err(io::Error::last_os_error());
+            }
+        }
+        Ok(())
+    }
+
+    pub fn write_pidfile(path: &str) -> io::Result<()> {
+        fs::write(path, format!("{}\n", std::process::id()))
+    }
+
+    /// Removes the PID file when the process exits, whether it returns from
+    /// main or calls `exit`.
+    pub fn remove_pidfile_at_exit(path: &str) {
+        extern "C" fn remove_pidfile() {
+            if let Some(path) = PIDFILE.get() {
+                unsafe { libc::unlink(path.as_ptr()) };
+            }
+        }
+
+        let path = CString::new(Path::new(path).as_os_str().as_bytes()).expect("paths from arguments have no NUL");
+        if PIDFILE.set(path).is_ok() {
+            unsafe { libc::atexit(remove_pidfile) };
+        }
+    }
+
+    /// Stops the measuring thread, passing SIGTERM on to it so that a read it
+    /// is blocked in returns. Does nothing more before `stop_on_terminate`.
+    pub fn stop_measuring() {
+        if !STOP_REQUESTED.swap(true, Ordering::SeqCst) {
+            let thread = MEASURING_THREAD.load(Ordering::SeqCst);
+            if thread != 0 {
+                unsafe { libc::pthread_kill(thread as libc::pthread_t, libc::SIGTERM) };
+            }
+        }
+    }
+
+    /// Makes SIGTERM stop the current thread from measuring. The handler is
+    /// installed without `SA_RESTART` so that a blocked read returns, and if
+    /// the signal was delivered to another thread it is passed on to this
+    /// one. Only the first call has any effect.
+    pub fn stop_on_terminate() {
+        STOP_ON_TERMINATE.call_once(install_stop_on_terminate);
+    }
+
+    fn install_stop_on_terminate() {
+        extern "C" fn on_terminate(_signal: libc::c_int) {
+            stop_measuring();
+        }
+
+        MEASURING_THREAD.store(unsafe { libc::pthread_self() } as usize, Ordering::SeqCst);
+        unsafe {
+            let mut action: libc::sigaction = std::mem::zeroed();
+            action.sa_sigaction = on_terminate as *const () as libc::sighandler_t;
+            libc::sigemptyset(&mut action.sa_mask);
+            libc::sigaction(libc::SIGTERM, &action, std::ptr::null_mut());
+        }
+
+        thread::spawn(|| {
+            while !super::stop_requested() {
+                thread::sleep(Duration::from_millis(100));
+            }
+            thread::sleep(SHUTDOWN_GRACE);
+            print_err!("Stopped without finishing the transfer.");
+            std::process::exit(0);
+        });
+    }
+}
@@ -0,0 +1,153 @@
+//! Direct I/O on Linux. Files are opened with `O_DIRECT` so that reads and
+//! writes go to the device instead of the page cache, which requires every
+//! transfer to use an aligned buffer and a multiple of the block size.
+
+use std::alloc::{self, Layout};
+use std::fs::{File, OpenOptions};
+use std::io::{self, Read, Write};
+use std::os::unix::fs::OpenOptionsExt;
+use std::os::unix::io::AsRawFd;
+use std::slice;
+
+/// The alignment of buffers, offsets and lengths. This is the page size, which
+/// satisfies the logical block size of practically every device.
+const ALIGNMENT: usize = 4096;
+
+/// A zeroed heap buffer aligned for direct I/O.
+struct AlignedBuffer {
+    pointer: *mut u8,
+    layout: Layout,
+}
+
+impl AlignedBuffer {
+    /// Allocates at least `size` bytes, rounded up to the alignment.
+    fn new(size: usize) -> AlignedBuffer {
+        let size = size.max(1).div_ceil(ALIGNMENT) * ALIGNMENT;
+        let layout = Layout::from_size_align(size, ALIGNMENT).expect("the buffer size should be valid");
+        let pointer = unsafe { alloc::alloc_zeroed(layout) };
+        if pointer.is_null() {
+            alloc::handle_alloc_error(layout);
+        }
+        AlignedBuffer { pointer, layout }
+    }
+
+    fn len(&self) -> usize {
+        self.layout.size()
+    }
+
+    fn as_slice(&self) -> &[u8] {
+        unsafe { slice::from_raw_parts(self.pointer, self.layout.size()) }
+    }
+
+    fn as_mut_slice(&mut self) -> &mut [u8] {
+        unsafe { slice::from_raw_parts_mut(self.pointer, self.layout.size()) }
+    }
+}
+
+impl Drop for AlignedBuffer {
+    fn drop(&mut self) {
+        unsafe { alloc::dealloc(self.pointer, self.layout) };
+    }
+}
+
+/// Opens a file for reading without the page cache.
+pub fn open(path: &str) -> io::Result<File> {
+    OpenOptions::new().read(true).custom_flags(libc::O_DIRECT).open(path)
+}
+
+/// Creates or truncates a file for writing without the page cache.
+pub fn create(path: &str) -> io::Result<File> {
+    OpenOptions::new().write(true).create(true).truncate(true).custom_flags(libc::O_DIRECT).open(path)
+}
+
+/// Reads a file opened with `O_DIRECT` into an aligned buffer of at least
+/// `buffer_size` bytes and hands the data out from there.
+pub struct DirectReader {
+    file: File,
+    buffer: AlignedBuffer,
+    position: usize,
+    filled: usize,
+}
+
+impl DirectReader {
+    pub fn new(file: File, buffer_size: usize) -> DirectReader {
+        DirectReader { file, buffer: AlignedBuffer::new(buffer_size), position: 0, filled: 0 }
+    }
+}
+
+impl Read for DirectReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.position == self.filled {
+            self.filled = self.file.read(self.buffer.as_mut_slice())?;
+            self.position = 0;
+        }
+
+        let available = &self.buffer.as_slice()[self.position..self.filled];
+        let count = available.len().min(buf.len());
+        buf[..count].copy_from_slice(&available[..count]);
+        self.position += count;
+        Ok(count)
+    }
+}
+
+/// Writes to a file opened with `O_DIRECT` in aligned blocks of at least
+/// `buffer_size` bytes. A partial block left over when flushed is written
+/// with `O_DIRECT` turned off, so the writer should only be flushed once the
+/// data has all been written.
+pub struct DirectWriter {
+    file: File,
+    buffer: AlignedBuffer,
+    filled: usize,
+}
+
+impl DirectWriter {
+    pub fn new(file: File, buffer_size: usize) -> DirectWriter {
+        DirectWriter { file, buffer: AlignedBuffer::new(buffer_size), filled: 0 }
+    }
+
+    fn write_buffer(&mut self) -> io::Result<()> {
+        let len = self.filled;
+        self.file.write_all(&self.buffer.as_slice()[..len])?;
+        self.filled = 0;
+        Ok(())
+    }
+}
+
+impl Write for DirectWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let count = (self.buffer.len() - self.filled).min(buf.len());
+        let filled = self.filled;
+        self.buffer.as_mut_slice()[filled..filled + count].copy_from_slice(&buf[..count]);
+        self.filled += count;
+
+        if self.filled == self.buffer.len() {
+            self.write_buffer()?;
+        }
+        Ok(count)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        if self.filled == 0 {
+            return Ok(());
+        }
+
+        if !self.filled.is_multiple_of(ALIGNMENT) {
+            clear_direct(&self.file)?;
+        }
+        self.write_buffer()
+    }
+}
+
+/// Turns `O_DIRECT` off for an open file.
+fn clear_direct(file: &File) -> io::Result<()> {
+    let fd = file.as_raw_fd();
+    let flags = unsafe { libc::fcntl(fd, libc::F_GETFL) };
+    if flags < 0 {
+        return Err(io::Error::last_os_error());
+    }
+
+    if unsafe { libc::fcntl(fd, libc::F_SETFL, flags & !libc::O_DIRECT) } < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
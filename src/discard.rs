@@ -0,0 +1,50 @@
+//! Discarding received data on Linux. Reads from a TCP socket are made with
+//! `MSG_TRUNC`, which makes the kernel drop the data instead of copying it
+//! into the buffer, so the measured rate is limited only by the sender and
+//! the network.
+
+use std::io::{self, Read};
+use std::os::unix::io::RawFd;
+
+/// A reader that counts the bytes received on a socket without copying them.
+/// The buffer passed to `read` is never written to.
+pub struct DiscardReader {
+    socket: RawFd,
+}
+
+impl DiscardReader {
+    /// Returns a reader if the descriptor is a TCP socket. The descriptor must
+    /// stay open for as long as the reader is used.
+    pub fn new(socket: RawFd) -> Option<DiscardReader> {
+        let mut protocol: libc::c_int = 0;
+        let mut len = std::mem::size_of::<libc::c_int>() as libc::socklen_t;
+        let result = unsafe {
+            libc::getsockopt(socket, libc::SOL_SOCKET, libc::SO_PROTOCOL,
+                &mut protocol as *mut libc::c_int as *mut libc::c_void, &mut len)
+        };
+
+        if result == 0 && protocol == libc::IPPROTO_TCP {
+            Some(DiscardReader { socket })
+        } else {
+            None
+        }
+    }
+}
+
+impl Read for DiscardReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        loop {
+            let received = unsafe {
+                libc::recv(self.socket, buf.as_mut_ptr() as *mut libc::c_void, buf.len(), libc::MSG_TRUNC)
+            };
+            if received >= 0 {
+                return Ok(received as usize);
+            }
+
+            let err = io::Error::last_os_error();
+            if err.kind() != io::ErrorKind::Interrupted {
+                return Err(err);
+            }
+        }
+    }
+}
@@ -0,0 +1,81 @@
+//! Measuring how quickly generated data can be written to a file, including
+//! the cost of flushing it to the device with `fsync(2)`.
+
+use std::fs::File;
+use std::io::{self, Read, Write};
+
+use pattern::Pattern;
+
+/// When written data is flushed to the device.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum FsyncMode {
+    /// After every buffer that is written.
+    Every,
+
+    /// Once, after everything has been written.
+    End,
+
+    /// Never, leaving the data in the page cache.
+    Never,
+}
+
+impl FsyncMode {
+    pub fn parse(value: &str) -> Option<FsyncMode> {
+        match value {
+            "every" => Some(FsyncMode::Every),
+            "end" => Some(FsyncMode::End),
+            "none" => Some(FsyncMode::Never),
+            _ => None,
+        }
+    }
+}
+
+/// A reader whose reads generate the pattern into the buffer and write it to
+/// a file, so that measuring the reader measures the writes. The final read
+/// waits for the data to be flushed before reporting the end.
+pub struct DiskWriter {
+    output: Box<dyn Write>,
+
+    /// The file being written, used to flush it to the device.
+    file: File,
+
+    pattern: Pattern,
+    fsync: FsyncMode,
+    offset: u64,
+    remaining: u64,
+    finished: bool,
+}
+
+impl DiskWriter {
+    /// Writes `bytes` bytes of the pattern through `output`, which must write
+    /// to `file`.
+    pub fn new(output: Box<dyn Write>, file: File, bytes: u64, pattern: Pattern, fsync: FsyncMode) -> DiskWriter {
+        DiskWriter { output, file, pattern, fsync, offset: 0, remaining: bytes, finished: false }
+    }
+}
+
+impl Read for DiskWriter {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.remaining == 0 {
+            if !self.finished {
+                self.finished = true;
+                self.output.flush()?;
+                if self.fsync != FsyncMode::Never {
+                    self.file.sync_data()?;
+                }
+            }
+            return Ok(0);
+        }
+
+        let len = (buf.len() as u64).min(self.remaining) as usize;
+        self.pattern.fill(self.offset, &mut buf[..len]);
+        self.output.write_all(&buf[..len])?;
+        if self.fsync == FsyncMode::Every {
+            self.file.sync_data()?;
+        }
+
+        self.offset += len as u64;
+        self.remaining -= len as u64;
+        Ok(len)
+    }
+}
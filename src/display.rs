@@ -0,0 +1,909 @@
+use std::cell::Cell;
+use std::io::Write;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use aggregate::AgentStats;
+use compress::WireStats;
+use concat::SourceStats;
+use console::{self, StdStream};
+use exec::CommandStats;
+use histogram::Histogram;
+use pattern::Verifier;
+use pipeline::QueueOccupancy;
+use records::RecordCounter;
+use relay::{self, DirectionStats};
+use frames::FrameCounter;
+use stalls::StallDetector;
+use template::Template;
+use sctp::SctpStats;
+use clock::OneWayDelay;
+use net::{CloseStats, ConnectionStats};
+use tcpinfo::TcpStats;
+use sender::GenerationCost;
+use state::Totals;
+use udp::UdpStats;
+use serial;
+use tui;
+use units::{byte_to_mem_units, format_elapsed, format_latency, NumberFormat};
+use usage::Usage;
+use {PassthroughTiming, ReadStats, TransferInfo};
+
+/// How stats are rendered.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Style {
+    /// Stats are updated in place using ANSI escape sequences.
+    Ansi,
+
+    /// Stats are appended as timestamped lines, for logs and pipes.
+    Plain,
+}
+
+impl Style {
+    /// Picks the style for the stream the stats are written to, using the
+    /// forced style if there is one. ANSI output is used for terminals that
+    /// support it and plain output for anything else.
+    pub fn resolve(forced: Option<Style>, stream: StdStream) -> Style {
+        match forced {
+            Some(Style::Plain) => Style::Plain,
+            Some(Style::Ansi) => {
+                console::enable_ansi(stream);
+                Style::Ansi
+            },
+            None if stream.is_terminal() && console::enable_ansi(stream) => Style::Ansi,
+            None => Style::Plain,
+        }
+    }
+}
+
+/// Whether stats should be colored.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum ColorChoice {
+    /// Colored when writing to a terminal and `NO_COLOR` is not set.
+    Auto,
+    Always,
+    Never,
+}
+
+impl ColorChoice {
+    pub fn parse(value: &str) -> Option<ColorChoice> {
+        match value {
+            "auto" => Some(ColorChoice::Auto),
+            "always" => Some(ColorChoice::Always),
+            "never" => Some(ColorChoice::Never),
+            _ => None,
+        }
+    }
+
+    fn enabled(self, style: Style) -> bool {
+        match self {
+            ColorChoice::Always => true,
+            ColorChoice::Never => false,
+            ColorChoice::Auto => {
+                let no_color = ::std::env::var_os("NO_COLOR").is_some_and(|v| !v.is_empty());
+                style == Style::Ansi && !no_color
+            },
+        }
+    }
+}
+
+/// Rates at which the transfer speed changes color. Speeds below `low` are
+/// red, speeds below `high` are yellow and anything else is green.
+#[derive(Clone, Copy, Debug)]
+pub struct RateThresholds {
+    pub low: f64,
+    pub high: f64,
+}
+
+const COLOR_RED: &str = "\x1b[31m";
+const COLOR_YELLOW: &str = "\x1b[33m";
+const COLOR_GREEN: &str = "\x1b[32m";
+const BOLD: &str = "\x1b[1m";
+const RESET: &str = "\x1b[0m";
+
+/// The width of the labels in front of the stats.
+const LABEL_WIDTH: usize = 24;
+
+/// Terminals narrower than this show the stats on a single line.
+const COMPACT_WIDTH: usize = 48;
+
+/// The share of the time sending spent generating the data above which the
+/// generator is pointed out as a likely bottleneck.
+const GENERATION_BOTTLENECK_SHARE: f64 = 0.5;
+
+/// Turns transfer info into labelled, optionally colored values and prints
+/// them in a particular style.
+pub struct Formatter {
+    style: Style,
+    color: bool,
+
+    /// Fixed thresholds for coloring speeds. Without them the transfer speed
+    /// is colored relative to the average speed.
+    thresholds: Option<RateThresholds>,
+
+    /// The number of bytes the transfer is expected to be, used to show
+    /// progress.
+    expected_bytes: Option<u64>,
+
+    /// Describes how the current transfer speed was estimated.
+    rate_description: Option<String>,
+
+    /// The layout of the line printed for every measure instead of the
+    /// labelled stats.
+    template: Option<Template>,
+
+    /// How the sizes and rates printed for every measure are written.
+    number_format: NumberFormat,
+
+    /// Whether the stats are shown on a single line however wide the
+    /// terminal is.
+    compact: bool,
+
+    /// The stream the stats are written to, whose terminal the live stats
+    /// are fit to.
+    terminal: Option<StdStream>,
+
+    /// The width of the terminal when the stats were last printed.
+    width: Cell<Option<usize>>,
+
+    /// How many lines the stats took up when they were last printed.
+    printed_lines: Cell<usize>,
+}
+
+impl Formatter {
+    pub fn new(style: Style, color: ColorChoice, thresholds: Option<RateThresholds>) -> Formatter {
+        Formatter {
+            style,
+            color: color.enabled(style),
+            thresholds,
+            expected_bytes: None,
+            rate_description: None,
+            template: None,
+            number_format: NumberFormat::default(),
+            compact: false,
+            terminal: None,
+            width: Cell::new(None),
+            printed_lines: Cell::new(0),
+        }
+    }
+
+    /// Notes how the current transfer speed is estimated next to it.
+    pub fn set_rate_description(&mut self, description: Option<String>) {
+        self.rate_description = description;
+    }
+
+    /// Prints every measure as a line laid out by the template instead.
+    pub fn set_template(&mut self, template: Option<Template>) {
+        self.template = template;
+    }
+
+    pub fn set_number_format(&mut self, number_format: NumberFormat) {
+        self.number_format = number_format;
+    }
+
+    pub fn set_compact(&mut self, compact: bool) {
+        self.compact = compact;
+    }
+
+    /// Fits the live stats to the width of the terminal the stream writes
+    /// to, following it as it is resized.
+    pub fn set_terminal(&mut self, stream: StdStream) {
+        if self.style == Style::Ansi {
+            console::watch_resize();
+        }
+        self.terminal = Some(stream);
+    }
+
+    /// Whether the stats are printed over the last ones rather than after
+    /// them, so that they can be redrawn between measures.
+    pub fn draws_in_place(&self) -> bool {
+        self.style == Style::Ansi && self.template.is_none()
+    }
+
+    /// Shows a progress bar and estimated time remaining for a transfer of
+    /// the given size.
+    pub fn set_expected_bytes(&mut self, expected_bytes: Option<u64>) {
+        self.expected_bytes = expected_bytes;
+    }
+
+    /// Prints the stats. When using ANSI escapes the previously printed stats
+    /// are overwritten if `rewrite` is set.
+    pub fn print_info<W: Write>(&self, output: &mut W, transfer_info: &TransferInfo, rewrite: bool) -> Result<(), std::io::Error> {
+        if let Some(ref template) = self.template {
+            return writeln!(output, "{}", template.render(transfer_info, &self.number_format));
+        }
+
+        match self.style {
+            Style::Ansi => {
+                let width = self.terminal.and_then(StdStream::width);
+                // A resize reflows what was printed, so the stats are printed
+                // anew instead of over lines that may have moved.
+                let resized = console::take_resized() | (self.width.replace(width) != width);
+                let move_up = if rewrite && !resized { self.printed_lines.get() } else { 0 };
+                let lines = match width {
+                    _ if self.compact || width.is_some_and(|width| width < COMPACT_WIDTH) => {
+                        let line = self.compact_line(transfer_info);
+                        term_move_up(output, move_up)?;
+                        match width {
+                            Some(width) => write!(output, "{}", truncate_to_width(&line, width - 1))?,
+                            None => write!(output, "{}", line)?,
+                        }
+                        term_clear_line(output)?;
+                        1
+                    },
+                    _ => {
+                        let rows = self.rows(transfer_info);
+                        print_rows_ansi(output, &rows, move_up, width)?;
+                        rows.len()
+                    },
+                };
+                self.printed_lines.set(lines);
+                Ok(())
+            },
+            Style::Plain => {
+                let time = transfer_info.last_measure_time.unwrap_or_else(SystemTime::now);
+                if self.compact {
+                    return writeln!(output, "{}  {}", format_timestamp(time), self.compact_line(transfer_info));
+                }
+                print_rows_plain(output, &self.rows(transfer_info), time)
+            },
+        }
+    }
+
+    /// Prints the stats that are only available once the transfer has
+    /// finished, such as read distributions and the digest of the stream.
+    pub fn print_final_stats<W: Write>(&self, output: &mut W, transfer_info: &TransferInfo) -> Result<(), std::io::Error> {
+        let mut rows = Vec::new();
+        if let Some(ref stats) = transfer_info.read_stats {
+            rows.extend(read_stats_rows(stats));
+        }
+        if let Some(size) = transfer_info.tuned_buffer_size {
+            let (mem, unit) = byte_to_mem_units(size as f64);
+            rows.push(("Buffer Size:", format!("{:.3} {} (auto)", mem, unit)));
+        }
+        if let Some(ref usage) = transfer_info.usage {
+            rows.extend(usage_rows(usage, transfer_info));
+        }
+        if let Some(ref stats) = transfer_info.sctp {
+            rows.extend(sctp_rows(stats, transfer_info.elapsed));
+        }
+        if let Some(ref stats) = transfer_info.udp {
+            rows.extend(udp_rows(stats));
+        }
+        if let Some(ref stats) = transfer_info.tcp {
+            rows.extend(tcp_rows(stats));
+        }
+        if let Some(ref totals) = transfer_info.totals {
+            rows.push(("Session Total:", format_totals(&totals.session)));
+            rows.push(("Lifetime Total:", format!("{}  since {}", format_totals(&totals.lifetime), format_timestamp(totals.since))));
+        }
+        if let Some(ref sources) = transfer_info.sources {
+            rows.extend(sources.iter().map(|source| ("Source:", format_source(source))));
+        }
+        if let Some(ref directions) = transfer_info.directions {
+            rows.extend(directions.iter().map(|direction| ("Direction:", format_direction(direction, transfer_info.elapsed))));
+            rows.push(("Dominant Direction:", format_dominant(directions)));
+        }
+        if let Some(ref command) = transfer_info.command {
+            rows.push(("Command:", format_command(command)));
+        }
+        if let Some(ref connections) = transfer_info.connections {
+            rows.extend(connections.iter().map(|connection| ("Connection:", format_connection(connection))));
+            if transfer_info.connections_omitted > 0 {
+                rows.push(("Connection:", format!("{} more not kept", transfer_info.connections_omitted)));
+            }
+        }
+        if let Some(reconnects) = transfer_info.reconnects {
+            rows.push(("Reconnects:", reconnects.to_string()));
+        }
+        if let Some(ref chaos) = transfer_info.chaos {
+            rows.push(("Faults Injected:", format!("{} dropped  {} duplicated  {} delayed  {} bit flipped  (seed {})",
+                chaos.dropped, chaos.duplicated, chaos.delayed, chaos.flipped, chaos.spec.seed)));
+        }
+        if let Some((algorithm, ref digest)) = transfer_info.digest {
+            rows.push(("Hash:", format!("{} {}", algorithm.name(), digest)));
+        }
+        if let Some(ref verifier) = transfer_info.verifier {
+            rows.push(("Verification:", self.verification(verifier)));
+        }
+
+        if !rows.is_empty() {
+            match self.style {
+                Style::Ansi => print_rows_ansi(output, &rows, 0, None)?,
+                Style::Plain => print_rows_plain(output, &rows, SystemTime::now())?,
+            }
+        }
+        if let Some(ref history) = transfer_info.rate_history {
+            tui::render_history(output, &history.samples(), transfer_info.elapsed)?;
+        }
+        Ok(())
+    }
+
+    /// The most important stats on one line, such as
+    /// `12.3 GB | cur 480 MB/s | avg 450 MB/s | 27s`, with the most important
+    /// first as the end of the line may be cut off on narrow terminals.
+    fn compact_line(&self, transfer_info: &TransferInfo) -> String {
+        let avg_bps = transfer_info.average_bps();
+        let mut line = format!("{} | cur {} | avg {}/s | {}",
+            self.format_size(transfer_info.total_bytes_transferred as f64),
+            self.rate(transfer_info.current_bps, avg_bps).replace("/sec", "/s"),
+            self.format_size(avg_bps), format_compact_elapsed(transfer_info.elapsed));
+        if let Some(expected) = self.expected_bytes {
+            let fraction = if expected == 0 { 1.0 } else { (transfer_info.total_bytes_transferred as f64 / expected as f64).min(1.0) };
+            line.push_str(&format!(" | {:.1}%", fraction * 100.0));
+        }
+        line
+    }
+
+    /// The labelled values that make up the stats.
+    fn rows(&self, transfer_info: &TransferInfo) -> Vec<(&'static str, String)> {
+        let avg_bps = transfer_info.average_bps();
+
+        let mut rows = vec![
+            ("Data Transferred:", format!("{} ({} cycles)",
+                self.bold(&self.format_size(transfer_info.total_bytes_transferred as f64)), transfer_info.total_measures)),
+            ("Transfer Speed:", self.current_rate(transfer_info.current_bps, avg_bps)),
+            ("Average Transfer Speed:", self.bold(&self.format_rate(avg_bps))),
+            ("Peak Transfer Speed:", self.format_rate(transfer_info.peak_bps)),
+        ];
+
+        if let Some(ref records) = transfer_info.records {
+            rows.push(("Records:", format_records(records, transfer_info.elapsed)));
+        }
+        if let Some(ref frames) = transfer_info.frames {
+            rows.push(("Messages:", format_frames(frames, transfer_info.elapsed)));
+            rows.push(("Message Size:", self.format_frame_sizes(frames)));
+        }
+        if let Some(ref stalls) = transfer_info.stalls {
+            rows.push(("Stalls:", format_stalls(stalls)));
+        }
+        let errors = transfer_info.read_errors;
+        if errors.fatal > 0 || errors.transient > 0 {
+            rows.push(("Read Errors:", format!("{} failed  {} retried", errors.fatal, errors.transient)));
+        }
+        if let Some(ref wire) = transfer_info.wire {
+            rows.push(("Wire Data:", format_wire(wire, transfer_info)));
+        }
+        if let Some(ref generation) = transfer_info.generation {
+            rows.push(("Payload Generation:", format_generation(generation, transfer_info.elapsed)));
+        }
+        if let Some(ref close) = transfer_info.close {
+            rows.push(("Shutdown:", format_close(close)));
+        }
+        if let Some(ref delay) = transfer_info.one_way_delay {
+            rows.push(("One-Way Delay:", format_one_way_delay(delay)));
+        }
+        if let Some(baud) = transfer_info.baud {
+            rows.push(("Line Utilization:", format_line_utilization(avg_bps, baud)));
+        }
+        if let Some(ref timing) = transfer_info.passthrough_timing {
+            rows.push(("Blocked On:", format_passthrough_timing(timing)));
+        }
+        if let Some(ref occupancy) = transfer_info.queue_occupancy {
+            rows.push(("Write Queue:", format_queue_occupancy(occupancy)));
+        }
+        if let Some(ref agents) = transfer_info.agents {
+            rows.extend(agents.iter().map(|agent| ("Agent:", format_agent(agent))));
+        }
+
+        let mut elapsed = format_elapsed(transfer_info.elapsed);
+        if let Some(start) = transfer_info.start_time {
+            elapsed.push_str(&format!(" (started {})", format_timestamp(start)));
+        }
+        rows.push(("Elapsed Time:", elapsed));
+
+        // Plain lines are already prefixed with the time of the measure.
+        if self.style == Style::Ansi {
+            if let Some(time) = transfer_info.last_measure_time {
+                rows.push(("Last Measure:", format_timestamp(time)));
+            }
+        }
+
+        if let Some(expected) = self.expected_bytes {
+            rows.push(("Progress:", format_progress(transfer_info.total_bytes_transferred as u64, expected, avg_bps)));
+        }
+
+        rows
+    }
+
+    /// Formats a rate, colored against the thresholds or the reference rate.
+    fn rate(&self, bps: f64, reference_bps: f64) -> String {
+        let text = self.format_rate(bps);
+        if !self.color { return text; }
+
+        let thresholds = self.thresholds.unwrap_or(RateThresholds {
+            low: reference_bps * 0.5,
+            high: reference_bps * 0.9,
+        });
+        let color = if bps < thresholds.low {
+            COLOR_RED
+        } else if bps < thresholds.high {
+            COLOR_YELLOW
+        } else {
+            COLOR_GREEN
+        };
+        format!("{}{}{}", color, text, RESET)
+    }
+
+    fn verification(&self, verifier: &Verifier) -> String {
+        if verifier.is_ok() {
+            let text = format!("OK ({} bytes checked)", verifier.bytes_checked());
+            return if self.color { format!("{}{}{}", COLOR_GREEN, text, RESET) } else { text };
+        }
+
+        let offsets: Vec<String> = verifier.first_corrupt_offsets.iter().map(|o| o.to_string()).collect();
+        let text = format!("{} corrupt bytes in {} regions of {} bytes checked, first at offsets {}",
+            verifier.corrupt_bytes, verifier.corrupt_regions, verifier.bytes_checked(), offsets.join(", "));
+        if self.color { format!("{}{}{}", COLOR_RED, text, RESET) } else { text }
+    }
+
+    fn current_rate(&self, bps: f64, reference_bps: f64) -> String {
+        let rate = self.rate(bps, reference_bps);
+        match self.rate_description {
+            Some(ref description) => format!("{} ({})", rate, description),
+            None => rate,
+        }
+    }
+
+    fn format_size(&self, bytes: f64) -> String {
+        let (mem, unit) = byte_to_mem_units(bytes);
+        format!("{} {}", self.number_format.format(mem), unit)
+    }
+
+    fn format_rate(&self, bps: f64) -> String {
+        format!("{}/sec", self.format_size(bps))
+    }
+
+    /// Formats the percentiles of the sizes of the framed messages.
+    fn format_frame_sizes(&self, frames: &FrameCounter) -> String {
+        let sizes = &frames.sizes;
+        format!("p50 {}  p90 {}  p99 {}  max {}", self.format_size(sizes.percentile(50.0) as f64),
+            self.format_size(sizes.percentile(90.0) as f64), self.format_size(sizes.percentile(99.0) as f64),
+            self.format_size(sizes.max() as f64))
+    }
+
+    fn bold(&self, text: &str) -> String {
+        if self.color { format!("{}{}{}", BOLD, text, RESET) } else { text.to_string() }
+    }
+}
+
+/// Formats a duration in the fewest characters, such as `27s`, `4m07s` or
+/// `2h05m`.
+fn format_compact_elapsed(duration: Duration) -> String {
+    let secs = duration.as_secs();
+    if secs < 60 {
+        format!("{}s", secs)
+    } else if secs < 3600 {
+        format!("{}m{:02}s", secs / 60, secs % 60)
+    } else {
+        format!("{}h{:02}m", secs / 3600, secs / 60 % 60)
+    }
+}
+
+/// Formats a rate in bytes per second using the largest fitting unit.
+pub fn format_rate(bps: f64) -> String {
+    let (mem, unit) = byte_to_mem_units(bps);
+    format!("{:.3} {}/sec", mem, unit)
+}
+
+fn read_stats_rows(stats: &ReadStats) -> Vec<(&'static str, String)> {
+    vec![
+        ("Read Sizes:", format_distribution(&stats.sizes, "reads", |bytes| {
+            let (mem, unit) = byte_to_mem_units(bytes as f64);
+            format!("{:.3} {}", mem, unit)
+        })),
+        ("Read Latency:", format_distribution(&stats.latencies, "reads", format_latency)),
+    ]
+}
+
+/// Formats the CPU time and system calls spent on the transfer relative to
+/// the amount of data and time.
+fn usage_rows(usage: &Usage, transfer_info: &TransferInfo) -> Vec<(&'static str, String)> {
+    let cpu_secs = usage.cpu_time().as_secs_f64();
+    let mut cpu = format!("user {:.3}s  sys {:.3}s", usage.user.as_secs_f64(), usage.system.as_secs_f64());
+    if cpu_secs > 0.0 {
+        let (mem, unit) = byte_to_mem_units(transfer_info.total_bytes_transferred as f64 / cpu_secs);
+        cpu.push_str(&format!("  ({:.3} {} per CPU-second)", mem, unit));
+    }
+
+    let mut rows = vec![("CPU Time:", cpu)];
+    if let Some(syscalls) = usage.syscalls {
+        let mut text = format!("{} reads  {} writes", syscalls.reads, syscalls.writes);
+        let elapsed = transfer_info.elapsed.as_secs_f64();
+        if elapsed > 0.0 {
+            text.push_str(&format!("  ({:.0}/sec)", (syscalls.reads + syscalls.writes) as f64 / elapsed));
+        }
+        rows.push(("Syscalls:", text));
+    }
+    rows
+}
+
+/// Formats the datagrams received over UDP and how they were spread over the
+/// receiving threads.
+fn udp_rows(stats: &UdpStats) -> Vec<(&'static str, String)> {
+    let (mem, unit) = byte_to_mem_units(stats.average_datagram_size());
+    let mut rows = vec![("Datagrams:", format!("{} (avg {:.3} {})  {:.0} datagrams/sec", stats.total_datagrams(),
+        mem, unit, stats.datagrams_per_second()))];
+
+    // Shows how evenly the kernel spread the flows over the threads.
+    if stats.datagrams.len() > 1 {
+        let counts: Vec<String> = stats.datagrams.iter().map(u64::to_string).collect();
+        rows.push(("Datagrams per Thread:", counts.join(" / ")));
+    }
+    rows
+}
+
+fn tcp_rows(stats: &TcpStats) -> Vec<(&'static str, String)> {
+    let millis = |rtt: Duration| rtt.as_secs_f64() * 1000.0;
+    let (mem, unit) = byte_to_mem_units(stats.cwnd as f64 * stats.mss as f64);
+    let mut rows = vec![
+        ("TCP Round Trip:", format!("avg {:.3} ms  min {:.3} ms  max {:.3} ms", millis(stats.average_rtt()),
+            millis(stats.min_rtt), millis(stats.max_rtt))),
+        ("Congestion Window:", format!("{} segments ({:.3} {})  max {} segments", stats.cwnd, mem, unit, stats.max_cwnd)),
+    ];
+    if let (Some(rate), Some(max_rate)) = (stats.pacing_rate, stats.max_pacing_rate) {
+        rows.push(("Pacing Rate:", format!("{}  max {}", format_rate(rate as f64), format_rate(max_rate as f64))));
+    }
+    rows.push(("Retransmits:", stats.retransmits.to_string()));
+    rows
+}
+
+/// Formats the messages received over SCTP and how quickly each stream
+/// carried data.
+fn sctp_rows(stats: &SctpStats, elapsed: Duration) -> Vec<(&'static str, String)> {
+    let (mem, unit) = byte_to_mem_units(stats.average_message_size());
+    let mut rows = vec![("SCTP Messages:", format!("{} (avg {:.3} {})", stats.messages(), mem, unit))];
+
+    let secs = elapsed.as_secs_f64();
+    for (stream, stream_stats) in &stats.streams {
+        let (mem, unit) = byte_to_mem_units(stream_stats.bytes as f64);
+        let rate = if secs > 0.0 { stream_stats.bytes as f64 / secs } else { 0.0 };
+        rows.push(("SCTP Stream:", format!("{}: {:.3} {} in {} messages  {}",
+            stream, mem, unit, stream_stats.messages, format_rate(rate))));
+    }
+    rows
+}
+
+/// Formats how much was read from one of several sources and how quickly.
+fn format_source(source: &SourceStats) -> String {
+    let (mem, unit) = byte_to_mem_units(source.bytes as f64);
+    let secs = source.elapsed.as_secs_f64();
+    let rate = if secs > 0.0 { source.bytes as f64 / secs } else { 0.0 };
+    format!("{}: {:.3} {} in {:.3}s  {}", source.path, mem, unit, secs, format_rate(rate))
+}
+
+/// Formats how the command whose output was measured exited, and how much it
+/// was fed.
+fn format_command(command: &CommandStats) -> String {
+    let status = match command.status.map(|status| status.code()) {
+        Some(Some(code)) => format!("exited with {}", code),
+        Some(None) => "killed by a signal".to_string(),
+        None => "still running".to_string(),
+    };
+    match command.fed {
+        Some(fed) => {
+            let (mem, unit) = byte_to_mem_units(fed as f64);
+            format!("{}: {}  fed {:.3} {}", command.command, status, mem, unit)
+        },
+        None => format!("{}: {}", command.command, status),
+    }
+}
+
+/// Formats how much was read from one of several connections and how
+/// quickly, by its label and with its address if that is not the label.
+pub fn format_connection(connection: &ConnectionStats) -> String {
+    let (mem, unit) = byte_to_mem_units(connection.bytes as f64);
+    let secs = connection.elapsed.as_secs_f64();
+    let rate = if secs > 0.0 { connection.bytes as f64 / secs } else { 0.0 };
+    let peer = connection.peer.to_string();
+    let name = if connection.label == peer { peer } else { format!("{} ({})", connection.label, peer) };
+    format!("{}: {:.3} {} in {:.3}s  {}", name, mem, unit, secs, format_rate(rate))
+}
+
+/// Formats the commonly interesting percentiles of a distribution of values
+/// that each describe one of `what`.
+pub fn format_distribution<F: Fn(u64) -> String>(histogram: &Histogram, what: &str, format: F) -> String {
+    format!("p50 {}  p90 {}  p99 {}  max {}  ({} {})",
+        format(histogram.percentile(50.0)),
+        format(histogram.percentile(90.0)),
+        format(histogram.percentile(99.0)),
+        format(histogram.max()),
+        histogram.count(), what)
+}
+
+/// Formats how long was spent blocked reading and writing and which side of
+/// the pipeline that makes the bottleneck.
+fn format_passthrough_timing(timing: &PassthroughTiming) -> String {
+    let read = timing.read_time.as_secs_f64();
+    let write = timing.write_time.as_secs_f64();
+    let total = read + write;
+    if total <= 0.0 { return "-".to_string(); }
+
+    let verdict = if read >= write { "input is the bottleneck" } else { "output is the bottleneck" };
+    format!("reading {:.3}s ({:.0}%)  writing {:.3}s ({:.0}%)  {}",
+        read, read / total * 100.0, write, write / total * 100.0, verdict)
+}
+
+/// Formats the number of records and how quickly they arrive.
+fn format_records(records: &RecordCounter, elapsed: Duration) -> String {
+    format!("{} ({:.0}/sec  avg {:.0}/sec  peak {:.0}/sec)",
+        records.total, records.last_rate, records.average_rate(elapsed.as_secs_f64()), records.peak_rate)
+}
+
+/// Formats the number of framed messages and how quickly they arrive.
+fn format_frames(frames: &FrameCounter, elapsed: Duration) -> String {
+    format!("{} ({:.0}/sec  avg {:.0}/sec  peak {:.0}/sec)",
+        frames.total, frames.last_rate, frames.average_rate(elapsed.as_secs_f64()), frames.peak_rate)
+}
+
+/// Formats how much has gone one way through a forwarded connection, how
+/// quickly, and how long it has waited for data.
+fn format_direction(direction: &DirectionStats, elapsed: Duration) -> String {
+    let (mem, unit) = byte_to_mem_units(direction.bytes as f64);
+    let secs = elapsed.as_secs_f64();
+    let average = if secs > 0.0 { direction.bytes as f64 / secs } else { 0.0 };
+    format!("{}: {:.3} {}  {}  avg {}  idle {:.1}s", direction.name, mem, unit, format_rate(direction.last_rate),
+        format_rate(average), direction.idle().as_secs_f64())
+}
+
+/// Formats which way most of the data went, over the whole transfer and over
+/// the last interval.
+fn format_dominant(directions: &[DirectionStats]) -> String {
+    let overall = match relay::dominant(directions, |direction| direction.bytes) {
+        Some((direction, share)) => format!("{} ({:.0}% of all data)", direction.name, share * 100.0),
+        None => return "none, as nothing has been sent".to_string(),
+    };
+    match relay::dominant(directions, |direction| direction.last_bytes) {
+        Some((direction, share)) => format!("{}  last interval {} ({:.0}%)", overall, direction.name, share * 100.0),
+        None => format!("{}  idle over the last interval", overall),
+    }
+}
+
+fn format_agent(agent: &AgentStats) -> String {
+    let (mem, unit) = byte_to_mem_units(agent.total_bytes as f64);
+    format!("{}: {:.3} {}  {}  avg {}  {}  ({})", agent.name, mem, unit, format_rate(agent.current_bps),
+        format_rate(agent.average_bps), format_elapsed(agent.elapsed), agent.state.name())
+}
+
+/// Formats the compressed bytes that crossed the wire and how much smaller
+/// they were than the data.
+fn format_wire(wire: &WireStats, transfer_info: &TransferInfo) -> String {
+    let (mem, unit) = byte_to_mem_units(wire.bytes as f64);
+    let secs = transfer_info.elapsed.as_secs_f64();
+    let rate = if secs > 0.0 { wire.bytes as f64 / secs } else { 0.0 };
+    format!("{:.3} {} {}  avg {}  ratio {:.2}:1",
+        mem, unit, wire.codec.name(), format_rate(rate), wire.ratio(transfer_info.total_bytes_transferred as u64))
+}
+
+/// Formats how long generating the data took out of the time spent sending
+/// it, which shows when the generator rather than the network limited the
+/// speed.
+fn format_generation(generation: &GenerationCost, elapsed: Duration) -> String {
+    let secs = generation.time.as_secs_f64();
+    let elapsed = elapsed.as_secs_f64();
+    let share = if elapsed > 0.0 { secs / elapsed } else { 0.0 };
+    let mut text = format!("{:.3}s ({:.1}% of the time sending)  {}", secs, share * 100.0, format_rate(generation.bytes_per_second()));
+    if generation.prefilled {
+        text.push_str("  prefilled");
+    } else if share >= GENERATION_BOTTLENECK_SHARE {
+        text.push_str("  may limit the speed, see --prefill");
+    }
+    text
+}
+
+/// Describes how a connection was closed after sending was shut down.
+pub fn format_close(close: &CloseStats) -> String {
+    let mut text = match close.in_flight {
+        Some(in_flight) => {
+            let (mem, unit) = byte_to_mem_units(in_flight as f64);
+            format!("{:.3} {} in flight, ", mem, unit)
+        },
+        None => String::new(),
+    };
+    let millis = close.wait.as_secs_f64() * 1000.0;
+    if close.closed_by_peer {
+        text.push_str(&format!("peer closed after {:.3} ms", millis));
+    } else {
+        text.push_str(&format!("peer did not close cleanly within {:.3} ms", millis));
+    }
+    if close.drained > 0 {
+        let (mem, unit) = byte_to_mem_units(close.drained as f64);
+        text.push_str(&format!("  ({:.3} {} received meanwhile)", mem, unit));
+    }
+    text
+}
+
+/// Formats the bytes transferred over several runs and how quickly.
+fn format_totals(totals: &Totals) -> String {
+    let (mem, unit) = byte_to_mem_units(totals.bytes as f64);
+    let secs = totals.elapsed.as_secs_f64();
+    let rate = if secs > 0.0 { totals.bytes as f64 / secs } else { 0.0 };
+    format!("{:.3} {} in {} runs ({} measuring)  avg {}", mem, unit, totals.runs, format_elapsed(totals.elapsed), format_rate(rate))
+}
+
+/// Formats the delay such as `avg 1.204 ms  min 0.830 ms  max 3.112 ms
+/// (clock offset +0.012 ms ± 0.041 ms)`.
+pub fn format_one_way_delay(delay: &OneWayDelay) -> String {
+    format!("avg {:.3} ms  min {:.3} ms  max {:.3} ms  (clock offset {:+.3} ms ± {:.3} ms)", delay.average * 1000.0,
+        delay.min * 1000.0, delay.max * 1000.0, delay.clock.offset * 1000.0, delay.clock.error() * 1000.0)
+}
+
+/// Formats how often reads waited longer than the stall threshold.
+fn format_stalls(stalls: &StallDetector) -> String {
+    format!("{} (last {}  peak {} per measure)  longest wait {:.3}s  over {:.3}s",
+        stalls.total, stalls.last, stalls.peak, stalls.longest.as_secs_f64(), stalls.threshold.as_secs_f64())
+}
+
+/// Formats how much of a serial link's theoretical rate the average rate
+/// reaches.
+fn format_line_utilization(avg_bps: f64, baud: u32) -> String {
+    let line_rate = serial::line_rate(baud);
+    format!("{:.1}% of {} ({} baud)", avg_bps / line_rate * 100.0, format_rate(line_rate), baud)
+}
+
+/// Formats how many buffers were usually waiting to be written. A queue that
+/// is mostly full means the output cannot keep up.
+fn format_queue_occupancy(occupancy: &QueueOccupancy) -> String {
+    format!("avg {:.1} of {} buffers  peak {}", occupancy.average(), occupancy.depth, occupancy.peak)
+}
+
+/// The number of characters inside of the progress bar.
+const PROGRESS_BAR_WIDTH: usize = 30;
+
+/// Formats a progress bar with the percent complete and, if the average rate
+/// is known, the estimated time remaining.
+fn format_progress(transferred: u64, expected: u64, avg_bps: f64) -> String {
+    let fraction = if expected == 0 { 1.0 } else { (transferred as f64 / expected as f64).min(1.0) };
+    let filled = (fraction * PROGRESS_BAR_WIDTH as f64) as usize;
+
+    let mut bar = "=".repeat(filled);
+    if filled < PROGRESS_BAR_WIDTH {
+        bar.push('>');
+        bar.push_str(&" ".repeat(PROGRESS_BAR_WIDTH - filled - 1));
+    }
+
+    let remaining = expected.saturating_sub(transferred);
+    let eta = if remaining == 0 {
+        format_elapsed(Duration::from_secs(0))
+    } else if avg_bps > 0.0 {
+        format_elapsed(Duration::from_secs_f64(remaining as f64 / avg_bps))
+    } else {
+        "--:--:--".to_string()
+    };
+
+    format!("[{}] {:5.1}%  ETA {}", bar, fraction * 100.0, eta)
+}
+
+/// Prints a row per line after moving up over the given number of lines.
+/// With a width, values are cut short so that no line wraps.
+fn print_rows_ansi<W: Write>(output: &mut W, rows: &[(&str, String)], move_up: usize, width: Option<usize>) -> Result<(), std::io::Error> {
+    term_move_up(output, move_up)?;
+
+    for &(label, ref value) in rows {
+        print_fixed_width(output, label, LABEL_WIDTH)?;
+        match width {
+            // The last column is left empty, as writing to it wraps on some
+            // terminals.
+            Some(width) => write!(output, "{}", truncate_to_width(value, width.saturating_sub(LABEL_WIDTH + 1)))?,
+            None => write!(output, "{}", value)?,
+        }
+        term_clear_line(output)?;
+    }
+
+    Ok(())
+}
+
+fn print_rows_plain<W: Write>(output: &mut W, rows: &[(&str, String)], time: SystemTime) -> Result<(), std::io::Error> {
+    write!(output, "{}", format_timestamp(time))?;
+    for &(label, ref value) in rows {
+        write!(output, "  {} {}", label, value)?;
+    }
+    writeln!(output)
+}
+
+/// Formats a point in time as an ISO 8601 UTC timestamp with second precision.
+pub fn format_timestamp(time: SystemTime) -> String {
+    let secs = time.duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0);
+    let (days, secs_of_day) = (secs / 86400, secs % 86400);
+
+    // Converts days since the epoch to a civil date (Howard Hinnant's algorithm).
+    let z = days as i64 + 719468;
+    let era = z.div_euclid(146097);
+    let doe = z - era * 146097;
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+
+    format!("{:04}-{:02}-{:02}T{:02}:{:02}:{:02}Z",
+        year, month, day, secs_of_day / 3600, secs_of_day / 60 % 60, secs_of_day % 60)
+}
+
+fn print_fixed_width<W: Write>(output: &mut W, text: &str, columns: usize) -> Result<(), std::io::Error> {
+    let width = display_width(text);
+    write!(output, "{}{:pad$}", text, "", pad = columns.saturating_sub(width))
+}
+
+/// Cuts the text short with an ellipsis so that it takes up at most the
+/// given number of columns, keeping escape sequences intact.
+fn truncate_to_width(text: &str, columns: usize) -> String {
+    if display_width(text) <= columns {
+        return text.to_string();
+    }
+
+    let mut truncated = String::new();
+    let mut width = 0;
+    let mut chars = text.chars();
+    while let Some(c) = chars.next() {
+        if c == '\x1b' {
+            truncated.push(c);
+            if let Some(next) = chars.next() {
+                truncated.push(next);
+                if next == '[' {
+                    for c in chars.by_ref() {
+                        truncated.push(c);
+                        if ('\x40'..='\x7e').contains(&c) { break; }
+                    }
+                }
+            }
+            continue;
+        }
+        let char_width = display_width(c.encode_utf8(&mut [0; 4]));
+        if width + char_width + 1 > columns { break; }
+        truncated.push(c);
+        width += char_width;
+    }
+    if columns > 0 {
+        truncated.push('…');
+    }
+    if truncated.contains('\x1b') {
+        truncated.push_str(RESET);
+    }
+    truncated
+}
+
+/// The number of terminal columns the text takes up. Escape sequences take up
+/// none, combining marks are drawn over the character before them and wide
+/// characters such as CJK ideographs and most emoji take up two.
+fn display_width(text: &str) -> usize {
+    let mut width = 0;
+    let mut chars = text.chars();
+    while let Some(c) = chars.next() {
+        if c == '\x1b' {
+            // Skips to the final byte of a CSI sequence such as a color.
+            if chars.next() == Some('[') {
+                for c in chars.by_ref() {
+                    if ('\x40'..='\x7e').contains(&c) { break; }
+                }
+            }
+            continue;
+        }
+        width += match c as u32 {
+            0x0300..=0x036F | 0x1AB0..=0x1AFF | 0x20D0..=0x20FF | 0xFE00..=0xFE0F | 0xFE20..=0xFE2F | 0x200B..=0x200D => 0,
+            0x1100..=0x115F | 0x2E80..=0x303E | 0x3041..=0x33FF | 0x3400..=0x4DBF | 0x4E00..=0x9FFF | 0xA000..=0xA4CF
+                | 0xAC00..=0xD7A3 | 0xF900..=0xFAFF | 0xFE30..=0xFE4F | 0xFF00..=0xFF60 | 0xFFE0..=0xFFE6
+                | 0x1F300..=0x1F64F | 0x1F900..=0x1F9FF | 0x20000..=0x3FFFD => 2,
+            code if code < 0x20 || (0x7F..0xA0).contains(&code) => 0,
+            _ => 1,
+        };
+    }
+    width
+}
+
+/// Clears to the end of the current line.
+#[inline]
+fn term_clear_line<W: Write>(output: &mut W) -> Result<(), std::io::Error> {
+    writeln!(output, "\x1b[K")?;
+    Ok(())
+}
+
+/// Moves the cursor up one line.
+#[inline]
+fn term_move_up<W: Write>(output: &mut W, lines: usize) -> Result<(), std::io::Error> {
+    // Moving up zero lines moves up one on most terminals.
+    if lines > 0 {
+        write!(output, "\x1b[{}A", lines)?;
+    }
+    Ok(())
+}
@@ -0,0 +1,147 @@
+//! Downloading a resource over plain HTTP, either with a single request or
+//! split into byte ranges that are fetched over several connections at once
+//! the way download managers do, which shows whether the server throttles
+//! each connection. Requests are made as HTTP/1.0 so that responses are never
+//! chunked. HTTPS is not supported.
+
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::net::TcpStream;
+use std::time::Duration;
+
+use push::{parse_http_url, resolve};
+
+/// How long connecting or waiting for the response to start may take.
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// The longest response head that will be read.
+const MAX_HEAD_LENGTH: u64 = 64 * 1024;
+
+/// A resource on an HTTP server.
+#[derive(Clone)]
+pub struct Resource {
+    /// The host and port to connect to.
+    host: String,
+    path: String,
+}
+
+impl Resource {
+    /// Parses an `http://` URL.
+    pub fn parse(url: &str) -> Option<Resource> {
+        parse_http_url(url, 80, "/").map(|(host, path)| Resource { host, path })
+    }
+
+    /// Asks for the size of the resource, and whether it can be fetched in
+    /// ranges.
+    pub fn size(&self) -> io::Result<(u64, bool)> {
+        let (_, head) = self.request("HEAD", None, 200)?;
+        let size = head.content_length
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "the server did not give the size of the resource"))?;
+        Ok((size, head.accepts_ranges))
+    }
+
+    /// Fetches the whole resource, returning its body and its size if the
+    /// server gave it.
+    pub fn get(&self) -> io::Result<(Body, Option<u64>)> {
+        let (reader, head) = self.request("GET", None, 200)?;
+        Ok((Body { reader, remaining: head.content_length }, head.content_length))
+    }
+
+    /// Fetches the bytes from `first` to `last` inclusive.
+    pub fn get_range(&self, first: u64, last: u64) -> io::Result<Body> {
+        let (reader, _) = self.request("GET", Some((first, last)), 206)?;
+        Ok(Body { reader, remaining: Some(last - first + 1) })
+    }
+
+    fn request(&self, method: &str, range: Option<(u64, u64)>, expected_status: u16) -> io::Result<(BufReader<TcpStream>, Head)> {
+        let addr = resolve(&self.host)?;
+        let mut stream = TcpStream::connect_timeout(&addr, CONNECT_TIMEOUT)?;
+        stream.set_read_timeout(Some(CONNECT_TIMEOUT))?;
+
+        let range = range.map_or_else(String::new, |(first, last)| format!("Range: bytes={}-{}\r\n", first, last));
+        write!(stream, "{} {} HTTP/1.0\r\nHost: {}\r\nUser-Agent: throughput\r\n{}\r\n", method, self.path, self.host, range)?;
+
+        let mut reader = BufReader::new(stream);
+        let head = Head::read(&mut reader)?;
+        if head.status != expected_status {
+            return Err(io::Error::other(format!("the server responded with {}", head.status_line)));
+        }
+        // The body is read for as long as it takes to arrive.
+        reader.get_ref().set_read_timeout(None)?;
+        Ok((reader, head))
+    }
+}
+
+/// The parts of a response head that are needed.
+struct Head {
+    status: u16,
+    status_line: String,
+    content_length: Option<u64>,
+    accepts_ranges: bool,
+}
+
+impl Head {
+    fn read(reader: &mut BufReader<TcpStream>) -> io::Result<Head> {
+        let mut limited = reader.take(MAX_HEAD_LENGTH);
+        let mut status_line = String::new();
+        limited.read_line(&mut status_line)?;
+        let status_line = status_line.trim_end().to_string();
+        let status = status_line.split_whitespace().nth(1).and_then(|status| status.parse().ok())
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, format!("the server sent a malformed response: {}", status_line)))?;
+
+        let mut head = Head { status, status_line, content_length: None, accepts_ranges: false };
+        loop {
+            let mut line = String::new();
+            if limited.read_line(&mut line)? == 0 {
+                return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "the response ended before its headers did"));
+            }
+            let line = line.trim_end();
+            if line.is_empty() {
+                return Ok(head);
+            }
+            if let Some((name, value)) = line.split_once(':') {
+                let value = value.trim();
+                if name.eq_ignore_ascii_case("Content-Length") {
+                    head.content_length = value.parse().ok();
+                } else if name.eq_ignore_ascii_case("Accept-Ranges") {
+                    head.accepts_ranges = value.eq_ignore_ascii_case("bytes");
+                }
+            }
+        }
+    }
+}
+
+/// The body of a response, which ends after the length the server gave or,
+/// if it gave none, once the connection closes.
+pub struct Body {
+    reader: BufReader<TcpStream>,
+    remaining: Option<u64>,
+}
+
+impl Read for Body {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let len = match self.remaining {
+            Some(0) => return Ok(0),
+            Some(remaining) => buf.len().min(remaining.min(usize::MAX as u64) as usize),
+            None => buf.len(),
+        };
+        let bytes_read = self.reader.read(&mut buf[..len])?;
+        if let Some(ref mut remaining) = self.remaining {
+            if bytes_read == 0 {
+                return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "the connection closed before the whole body arrived"));
+            }
+            *remaining -= bytes_read as u64;
+        }
+        Ok(bytes_read)
+    }
+}
+
+/// Splits `size` bytes into `count` ranges of nearly equal size, each given
+/// by its first and last byte. There are fewer ranges if there are fewer
+/// bytes than that.
+pub fn ranges(size: u64, count: u64) -> Vec<(u64, u64)> {
+    let count = count.min(size).max(1);
+    (0..count).map(|index| (size * index / count, size * (index + 1) / count))
+        .filter(|&(first, end)| end > first)
+        .map(|(first, end)| (first, end - 1))
+        .collect()
+}
@@ -0,0 +1,98 @@
+//! Running a command and reading what it writes to stdout, so that its output
+//! can be measured without a shell pipeline around it. Its stdin can be fed
+//! with a generated pattern for commands that transform their input.
+
+use std::io::{self, Read, Write};
+use std::process::{Child, ChildStdin, ChildStdout, Command, ExitStatus, Stdio};
+use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
+
+use pattern::Pattern;
+
+/// What happened to the command, filled in once its output ends.
+#[derive(Clone, Debug)]
+pub struct CommandStats {
+    /// The command line as it was given.
+    pub command: String,
+
+    /// How the command exited, once it has.
+    pub status: Option<ExitStatus>,
+
+    /// The bytes written to the command's stdin if it was fed.
+    pub fed: Option<u64>,
+}
+
+impl CommandStats {
+    /// Whether the command has exited with a status of zero.
+    pub fn succeeded(&self) -> bool {
+        self.status.is_some_and(|status| status.success())
+    }
+}
+
+/// The stats of a command, shared between its reader and the measurement.
+pub type SharedCommandStats = Arc<Mutex<CommandStats>>;
+
+/// Reads the stdout of a running command. Once it ends the command is waited
+/// for and its exit status recorded.
+pub struct CommandReader {
+    child: Child,
+    stdout: ChildStdout,
+    feeder: Option<JoinHandle<u64>>,
+    stats: SharedCommandStats,
+}
+
+impl CommandReader {
+    /// Starts the command with its stdout piped to the reader, and its stdin
+    /// fed with the pattern if there is one or otherwise inherited.
+    pub fn spawn(args: &[String], feed: Option<Pattern>, buffer_size: usize) -> io::Result<(CommandReader, SharedCommandStats)> {
+        let (program, rest) = args.split_first().ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "no command was given"))?;
+        let mut child = Command::new(program)
+            .args(rest)
+            .stdin(if feed.is_some() { Stdio::piped() } else { Stdio::inherit() })
+            .stdout(Stdio::piped())
+            .spawn()?;
+
+        let stdout = child.stdout.take().expect("the command's stdout should be piped");
+        let feeder = match (feed, child.stdin.take()) {
+            (Some(pattern), Some(stdin)) => Some(thread::spawn(move || feed_pattern(stdin, &pattern, buffer_size))),
+            _ => None,
+        };
+        let stats = Arc::new(Mutex::new(CommandStats { command: args.join(" "), status: None, fed: None }));
+        Ok((CommandReader { child, stdout, feeder, stats: stats.clone() }, stats))
+    }
+
+    /// Waits for the command to exit and for feeding it to stop.
+    fn finish(&mut self) -> io::Result<()> {
+        let status = self.child.wait()?;
+        let fed = self.feeder.take().map(|feeder| feeder.join().expect("command feeder thread panicked"));
+        let mut stats = self.stats.lock().expect("command stats lock was poisoned");
+        stats.status = Some(status);
+        stats.fed = fed;
+        Ok(())
+    }
+}
+
+impl Read for CommandReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let bytes_read = self.stdout.read(buf)?;
+        if bytes_read == 0 && self.stats.lock().expect("command stats lock was poisoned").status.is_none() {
+            self.finish()?;
+        }
+        Ok(bytes_read)
+    }
+}
+
+/// Writes the pattern to the command's stdin until it stops reading, and
+/// returns how much was written.
+fn feed_pattern(mut stdin: ChildStdin, pattern: &Pattern, buffer_size: usize) -> u64 {
+    let mut buffer = vec![0; buffer_size];
+    let mut offset = 0u64;
+    loop {
+        pattern.fill(offset, &mut buffer);
+        // The command closing its stdin, or exiting, ends the feed.
+        if stdin.write_all(&buffer).is_err() {
+            return offset;
+        }
+        offset += buffer.len() as u64;
+    }
+}
@@ -0,0 +1,87 @@
+//! Why the process failed. Each kind of failure exits with its own code, so
+//! that scripts can tell a refused connection from a slow transfer without
+//! reading the messages printed before it.
+
+use std::io;
+
+/// A reason for exiting without success.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Failure {
+    /// Anything without a code of its own, such as invalid arguments or a
+    /// file that could not be opened.
+    General,
+
+    /// `--idle-timeout` expired without any data arriving.
+    IdleTimeout,
+
+    /// `--min-throughput` or `--min-total` was not met.
+    BelowThreshold,
+
+    /// `--verify` found data that does not match the pattern.
+    VerifyFailed,
+
+    /// `--accept-timeout` expired without a connection.
+    AcceptTimeout,
+
+    /// A read waited longer than `--read-timeout`.
+    ReadTimeout,
+
+    /// The average speed fell further below `--baseline` than
+    /// `--max-regression` allows.
+    Regression,
+
+    /// The command run by `exec` did not exit successfully.
+    CommandFailed,
+
+    /// A port or address could not be listened on.
+    Bind,
+
+    /// The host refused the connection.
+    ConnectRefused,
+
+    /// The host could not be resolved or connected to for another reason.
+    Connect,
+
+    /// Reading the data being measured failed.
+    Read,
+
+    /// Sending data to a host or passing it through failed.
+    Write,
+}
+
+impl Failure {
+    /// The code the process exits with. The codes from 2 to 8 predate the
+    /// others and keep their values.
+    pub fn code(self) -> i32 {
+        match self {
+            Failure::General => 1,
+            Failure::IdleTimeout => 2,
+            Failure::BelowThreshold => 3,
+            Failure::VerifyFailed => 4,
+            Failure::AcceptTimeout => 5,
+            Failure::ReadTimeout => 6,
+            Failure::Regression => 7,
+            Failure::CommandFailed => 8,
+            Failure::Bind => 9,
+            Failure::ConnectRefused => 10,
+            Failure::Connect => 11,
+            Failure::Read => 12,
+            Failure::Write => 13,
+        }
+    }
+
+    /// The failure that an I/O error stands for, or `otherwise` if its kind
+    /// has no code of its own.
+    pub fn from_io(err: &io::Error, otherwise: Failure) -> Failure {
+        match err.kind() {
+            io::ErrorKind::ConnectionRefused => Failure::ConnectRefused,
+            io::ErrorKind::AddrInUse | io::ErrorKind::AddrNotAvailable => Failure::Bind,
+            _ => otherwise,
+        }
+    }
+
+    /// Exits the process with the code of the failure.
+    pub fn exit(self) -> ! {
+        std::process::exit(self.code())
+    }
+}
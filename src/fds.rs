@@ -0,0 +1,48 @@
+//! Reading from descriptors that a supervising process left open for
+//! throughput, such as the ends of pipes or sockets it already set up, all at
+//! once while keeping track of how much came through each one.
+
+use std::fs::File;
+use std::io;
+use std::sync::{Arc, Mutex};
+
+use concat::{SourceReader, SourceStats};
+
+/// How much was read from each of the descriptors, in the order they were
+/// given.
+pub type FdStats = Arc<Mutex<Vec<SourceStats>>>;
+
+/// Reads one of the descriptors and records what it read into its entry in
+/// the stats shared by all of them.
+pub type FdReader = SourceReader<File>;
+
+/// Takes ownership of the descriptors and returns a reader for each, along
+/// with their stats. None are taken unless every one of them is open.
+#[cfg(unix)]
+pub fn open(fds: &[i32]) -> io::Result<(Vec<FdReader>, FdStats)> {
+    use std::os::unix::io::FromRawFd;
+    use std::time::{Duration, Instant};
+
+    for &fd in fds {
+        if unsafe { libc::fcntl(fd, libc::F_GETFD) } == -1 {
+            let err = io::Error::last_os_error();
+            return Err(io::Error::new(err.kind(), format!("descriptor {}: {}", fd, err)));
+        }
+    }
+
+    let stats = Arc::new(Mutex::new(fds.iter()
+        .map(|fd| SourceStats { path: format!("fd {}", fd), bytes: 0, elapsed: Duration::default() })
+        .collect()));
+    let started = Instant::now();
+    // Each descriptor was handed over to be read until its end, so it is
+    // closed along with its file.
+    let readers = fds.iter().enumerate()
+        .map(|(index, &fd)| SourceReader::new(unsafe { File::from_raw_fd(fd) }, index, started, stats.clone()))
+        .collect();
+    Ok((readers, stats))
+}
+
+#[cfg(not(unix))]
+pub fn open(_fds: &[i32]) -> io::Result<(Vec<FdReader>, FdStats)> {
+    Err(io::Error::new(io::ErrorKind::Unsupported, "reading passed descriptors is only available on Unix"))
+}
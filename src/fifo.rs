@@ -0,0 +1,99 @@
+//! Reading from a named pipe at a well known path, so that producers can be
+//! pointed at it instead of piping into throughput. On Unix this is a FIFO
+//! in the file system and on Windows a pipe under `\\.\pipe\`.
+
+use std::fs::File;
+use std::io;
+
+/// A named pipe that throughput reads from. A FIFO created by throughput is
+/// removed again when this is dropped.
+pub struct Fifo {
+    path: String,
+
+    /// Whether the FIFO was created here rather than already existing.
+    #[cfg_attr(not(unix), allow(dead_code))]
+    created: bool,
+}
+
+impl Fifo {
+    /// Creates the FIFO, or reuses one that already exists at the path.
+    #[cfg(unix)]
+    pub fn create(path: &str) -> io::Result<Fifo> {
+        use std::ffi::CString;
+        use std::os::unix::fs::FileTypeExt;
+
+        let c_path = CString::new(path).map_err(|err| io::Error::new(io::ErrorKind::InvalidInput, err))?;
+        if unsafe { libc::mkfifo(c_path.as_ptr(), 0o600) } == 0 {
+            return Ok(Fifo { path: path.to_string(), created: true });
+        }
+
+        let err = io::Error::last_os_error();
+        match std::fs::metadata(path) {
+            Ok(metadata) if metadata.file_type().is_fifo() => Ok(Fifo { path: path.to_string(), created: false }),
+            Ok(_) => Err(io::Error::new(io::ErrorKind::AlreadyExists, "a file that is not a FIFO already exists at the path")),
+            Err(_) => Err(err),
+        }
+    }
+
+    /// Names the pipe, which is only created when it is opened. A bare name
+    /// is placed under `\\.\pipe\`.
+    #[cfg(windows)]
+    pub fn create(path: &str) -> io::Result<Fifo> {
+        let path = if path.starts_with(r"\\.\pipe\") { path.to_string() } else { format!(r"\\.\pipe\{}", path) };
+        Ok(Fifo { path, created: false })
+    }
+
+    pub fn path(&self) -> &str {
+        &self.path
+    }
+
+    /// Waits for a writer to open the pipe and returns the end to read from.
+    #[cfg(unix)]
+    pub fn open(&self) -> io::Result<File> {
+        File::open(&self.path)
+    }
+
+    #[cfg(windows)]
+    pub fn open(&self) -> io::Result<File> {
+        use std::os::windows::ffi::OsStrExt;
+        use std::os::windows::io::{FromRawHandle, RawHandle};
+        use std::ptr;
+        use windows_sys::Win32::Foundation::{CloseHandle, GetLastError, ERROR_PIPE_CONNECTED, INVALID_HANDLE_VALUE};
+        use windows_sys::Win32::Storage::FileSystem::PIPE_ACCESS_INBOUND;
+        use windows_sys::Win32::System::Pipes::{
+            ConnectNamedPipe, CreateNamedPipeW, PIPE_READMODE_BYTE, PIPE_TYPE_BYTE, PIPE_UNLIMITED_INSTANCES, PIPE_WAIT,
+        };
+
+        /// The size of the pipe's buffer in bytes.
+        const PIPE_BUFFER_SIZE: u32 = 64 * 1024;
+
+        let name: Vec<u16> = std::ffi::OsStr::new(&self.path).encode_wide().chain(Some(0)).collect();
+        let handle = unsafe {
+            CreateNamedPipeW(name.as_ptr(), PIPE_ACCESS_INBOUND, PIPE_TYPE_BYTE | PIPE_READMODE_BYTE | PIPE_WAIT,
+                PIPE_UNLIMITED_INSTANCES, PIPE_BUFFER_SIZE, PIPE_BUFFER_SIZE, 0, ptr::null())
+        };
+        if handle == INVALID_HANDLE_VALUE {
+            return Err(io::Error::last_os_error());
+        }
+
+        // A writer that connected between creating and waiting is reported
+        // as an error, but is connected all the same.
+        if unsafe { ConnectNamedPipe(handle, ptr::null_mut()) } == 0 && unsafe { GetLastError() } != ERROR_PIPE_CONNECTED {
+            let err = io::Error::last_os_error();
+            unsafe { CloseHandle(handle) };
+            return Err(err);
+        }
+        Ok(unsafe { File::from_raw_handle(handle as RawHandle) })
+    }
+}
+
+impl Drop for Fifo {
+    fn drop(&mut self) {
+        #[cfg(unix)]
+        {
+            if self.created {
+                let _ = std::fs::remove_file(&self.path);
+            }
+        }
+    }
+}
@@ -0,0 +1,41 @@
+//! Following a file as it grows, the way `tail -f` does, so that the rate at
+//! which a live log is written can be measured.
+
+use std::fs::File;
+use std::io::{self, Read, Seek, SeekFrom};
+use std::thread;
+use std::time::Duration;
+
+/// How long to wait before checking a file that has no new data again.
+const POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+/// A reader that never reaches the end of a file, waiting for more to be
+/// written instead. A file that is truncated is read again from the start.
+pub struct FollowReader {
+    file: File,
+    position: u64,
+}
+
+impl FollowReader {
+    pub fn new(file: File) -> FollowReader {
+        FollowReader { file, position: 0 }
+    }
+}
+
+impl Read for FollowReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        loop {
+            let bytes_read = self.file.read(buf)?;
+            if bytes_read > 0 || buf.is_empty() {
+                self.position += bytes_read as u64;
+                return Ok(bytes_read);
+            }
+
+            if self.file.metadata()?.len() < self.position {
+                self.position = self.file.seek(SeekFrom::Start(0))?;
+                continue;
+            }
+            thread::sleep(POLL_INTERVAL);
+        }
+    }
+}
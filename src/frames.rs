@@ -0,0 +1,157 @@
+//! Splitting a stream into the messages it is framed as, such as
+//! length-prefixed protobuf or newline delimited JSON, to report messages
+//! per second and how large they are. Frames may be split across reads, so
+//! the position in the current frame is kept between them.
+
+use histogram::Histogram;
+
+/// How the messages in a stream are delimited.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Framing {
+    /// Each message follows its length as a 32-bit big-endian integer.
+    LengthPrefix,
+
+    /// Each message ends with a newline, which is counted in its size.
+    Newline,
+}
+
+impl Framing {
+    pub fn parse(name: &str) -> Option<Framing> {
+        match name {
+            "length-prefix" => Some(Framing::LengthPrefix),
+            "newline" => Some(Framing::Newline),
+            _ => None,
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Framing::LengthPrefix => "length-prefix",
+            Framing::Newline => "newline",
+        }
+    }
+}
+
+/// The number of bytes of the length before each length-prefixed message.
+const LENGTH_PREFIX_BYTES: usize = 4;
+
+/// Counts the messages in the data read and records their sizes.
+#[derive(Clone)]
+pub struct FrameCounter {
+    pub framing: Framing,
+
+    /// Every complete message counted.
+    pub total: u64,
+
+    /// Messages counted since the last measure.
+    pub last: u64,
+
+    /// The messages per second during the last measure.
+    pub last_rate: f64,
+
+    /// The highest messages per second of any measure.
+    pub peak_rate: f64,
+
+    /// The sizes of the complete messages, without their length prefix.
+    pub sizes: Histogram,
+
+    /// The bytes of the length prefix read so far.
+    prefix: [u8; LENGTH_PREFIX_BYTES],
+    prefix_len: usize,
+
+    /// The bytes of the current message read so far, and how many are left
+    /// of it if its length is known.
+    current: u64,
+    remaining: u64,
+}
+
+impl FrameCounter {
+    pub fn new(framing: Framing) -> FrameCounter {
+        FrameCounter {
+            framing,
+            total: 0,
+            last: 0,
+            last_rate: 0.0,
+            peak_rate: 0.0,
+            sizes: Histogram::default(),
+            prefix: [0; LENGTH_PREFIX_BYTES],
+            prefix_len: 0,
+            current: 0,
+            remaining: 0,
+        }
+    }
+
+    pub fn count(&mut self, data: &[u8]) {
+        match self.framing {
+            Framing::LengthPrefix => self.count_length_prefixed(data),
+            Framing::Newline => self.count_newlines(data),
+        }
+    }
+
+    fn count_length_prefixed(&mut self, mut data: &[u8]) {
+        while !data.is_empty() {
+            if self.remaining > 0 {
+                let taken = (self.remaining.min(data.len() as u64)) as usize;
+                self.remaining -= taken as u64;
+                data = &data[taken..];
+                if self.remaining == 0 {
+                    self.finish(self.current);
+                }
+                continue;
+            }
+
+            let taken = (LENGTH_PREFIX_BYTES - self.prefix_len).min(data.len());
+            self.prefix[self.prefix_len..self.prefix_len + taken].copy_from_slice(&data[..taken]);
+            self.prefix_len += taken;
+            data = &data[taken..];
+            if self.prefix_len == LENGTH_PREFIX_BYTES {
+                self.prefix_len = 0;
+                self.current = u32::from_be_bytes(self.prefix) as u64;
+                self.remaining = self.current;
+                // An empty message is complete as soon as its length is.
+                if self.remaining == 0 {
+                    self.finish(0);
+                }
+            }
+        }
+    }
+
+    fn count_newlines(&mut self, data: &[u8]) {
+        let mut start = 0;
+        for (index, _) in data.iter().enumerate().filter(|&(_, &byte)| byte == b'\n') {
+            let size = self.current + (index + 1 - start) as u64;
+            self.finish(size);
+            self.current = 0;
+            start = index + 1;
+        }
+        self.current += (data.len() - start) as u64;
+    }
+
+    fn finish(&mut self, size: u64) {
+        self.sizes.record(size);
+        self.total += 1;
+        self.last += 1;
+    }
+
+    /// Starts the counts and sizes over from zero, keeping the place in the
+    /// current message so that the ones after it are still found.
+    pub fn reset(&mut self) {
+        self.total = 0;
+        self.last = 0;
+        self.last_rate = 0.0;
+        self.peak_rate = 0.0;
+        self.sizes = Histogram::default();
+    }
+
+    /// Takes a measure of the messages counted over the last `secs` seconds.
+    pub fn measure(&mut self, secs: f64) {
+        self.last_rate = if secs > 0.0 { self.last as f64 / secs } else { 0.0 };
+        self.peak_rate = self.peak_rate.max(self.last_rate);
+        self.last = 0;
+    }
+
+    /// The messages per second over the whole transfer.
+    pub fn average_rate(&self, elapsed_secs: f64) -> f64 {
+        if elapsed_secs > 0.0 { self.total as f64 / elapsed_secs } else { 0.0 }
+    }
+}
@@ -0,0 +1,68 @@
+use std::hash::Hasher;
+
+use crc32fast;
+use sha2::{Digest, Sha256};
+use twox_hash::XxHash64;
+
+/// A digest algorithm that can be computed over the measured stream.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum HashAlgorithm {
+    Sha256,
+    Crc32,
+    XxHash64,
+}
+
+impl HashAlgorithm {
+    pub fn parse(value: &str) -> Option<HashAlgorithm> {
+        match value {
+            "sha256" => Some(HashAlgorithm::Sha256),
+            "crc32" => Some(HashAlgorithm::Crc32),
+            "xxhash" | "xxhash64" | "xxh64" => Some(HashAlgorithm::XxHash64),
+            _ => None,
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            HashAlgorithm::Sha256 => "sha256",
+            HashAlgorithm::Crc32 => "crc32",
+            HashAlgorithm::XxHash64 => "xxhash64",
+        }
+    }
+}
+
+/// Incrementally computes a digest of every byte it is given.
+pub enum StreamHasher {
+    Sha256(Sha256),
+    Crc32(crc32fast::Hasher),
+    XxHash64(XxHash64),
+}
+
+impl StreamHasher {
+    pub fn new(algorithm: HashAlgorithm) -> StreamHasher {
+        match algorithm {
+            HashAlgorithm::Sha256 => StreamHasher::Sha256(Sha256::new()),
+            HashAlgorithm::Crc32 => StreamHasher::Crc32(crc32fast::Hasher::new()),
+            HashAlgorithm::XxHash64 => StreamHasher::XxHash64(XxHash64::with_seed(0)),
+        }
+    }
+
+    pub fn update(&mut self, data: &[u8]) {
+        match *self {
+            StreamHasher::Sha256(ref mut hasher) => hasher.update(data),
+            StreamHasher::Crc32(ref mut hasher) => hasher.update(data),
+            StreamHasher::XxHash64(ref mut hasher) => hasher.write(data),
+        }
+    }
+
+    /// Returns the digest as a lowercase hexadecimal string.
+    pub fn finish(self) -> String {
+        match self {
+            StreamHasher::Sha256(hasher) => {
+                hasher.finalize().iter().map(|byte| format!("{:02x}", byte)).collect()
+            },
+            StreamHasher::Crc32(hasher) => format!("{:08x}", hasher.finalize()),
+            StreamHasher::XxHash64(hasher) => format!("{:016x}", hasher.finish()),
+        }
+    }
+}
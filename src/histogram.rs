@@ -0,0 +1,80 @@
+/// The number of bits of precision kept below the highest set bit, giving
+/// eight buckets per power of two (a worst case error of 12.5%).
+const SUB_BUCKET_BITS: u32 = 3;
+const SUB_BUCKETS: u64 = 1 << SUB_BUCKET_BITS;
+
+/// The number of buckets needed to cover every `u64`.
+const BUCKET_COUNT: usize = ((64 - SUB_BUCKET_BITS as usize) + 1) * SUB_BUCKETS as usize;
+
+/// A histogram with logarithmically sized buckets, so its memory use is fixed
+/// no matter how many values are recorded or how large they are.
+#[derive(Clone)]
+pub struct Histogram {
+    counts: Vec<u64>,
+    count: u64,
+    min: u64,
+    max: u64,
+}
+
+impl Default for Histogram {
+    fn default() -> Histogram {
+        Histogram {
+            counts: vec![0; BUCKET_COUNT],
+            count: 0,
+            min: u64::MAX,
+            max: 0,
+        }
+    }
+}
+
+impl Histogram {
+    pub fn record(&mut self, value: u64) {
+        self.counts[bucket_index(value)] += 1;
+        self.count += 1;
+        self.min = self.min.min(value);
+        self.max = self.max.max(value);
+    }
+
+    /// The number of values recorded.
+    pub fn count(&self) -> u64 {
+        self.count
+    }
+
+    pub fn max(&self) -> u64 {
+        self.max
+    }
+
+    /// Returns an upper bound for the value at the given percentile (0-100).
+    pub fn percentile(&self, percentile: f64) -> u64 {
+        if self.count == 0 { return 0; }
+
+        let rank = ((percentile / 100.0) * self.count as f64).ceil().max(1.0) as u64;
+        let mut seen = 0;
+        for (index, &count) in self.counts.iter().enumerate() {
+            seen += count;
+            if seen >= rank {
+                return bucket_upper(index).max(self.min).min(self.max);
+            }
+        }
+        self.max
+    }
+}
+
+fn bucket_index(value: u64) -> usize {
+    if value < SUB_BUCKETS { return value as usize; }
+
+    let msb = 63 - value.leading_zeros();
+    let shift = msb - SUB_BUCKET_BITS;
+    let sub = (value >> shift) & (SUB_BUCKETS - 1);
+    ((msb - SUB_BUCKET_BITS + 1) as u64 * SUB_BUCKETS + sub) as usize
+}
+
+/// The largest value that falls into the bucket.
+fn bucket_upper(index: usize) -> u64 {
+    let index = index as u64;
+    if index < SUB_BUCKETS { return index; }
+
+    let shift = index / SUB_BUCKETS - 1;
+    let lower = (SUB_BUCKETS + index % SUB_BUCKETS) << shift;
+    lower + ((1u64 << shift) - 1)
+}
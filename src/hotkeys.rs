@@ -0,0 +1,89 @@
+//! Hotkeys read from the terminal while the live display is shown: p pauses
+//! and resumes reading, r resets the counters, s takes a measure straight
+//! away and q finishes the transfer as SIGTERM would.
+
+use std::fs::File;
+use std::io;
+use std::os::unix::io::{IntoRawFd, RawFd};
+use std::sync::OnceLock;
+use std::thread;
+
+use controls::{Command, SharedControls};
+use daemon;
+
+/// Printed when the hotkeys are set up, so that they can be found.
+pub const HINT: &str = "Press p to pause or resume, r to reset, s to measure now or q to finish.";
+
+/// The terminal and its settings from before the hotkeys were set up, which
+/// are put back when the process exits.
+static ORIGINAL: OnceLock<(RawFd, libc::termios)> = OnceLock::new();
+
+/// Reads keystrokes from the controlling terminal as they are typed, without
+/// echoing them, and sends the commands they stand for. Signals are still
+/// generated, so Ctrl-C works as usual, and the terminal is put back as it
+/// was however the process exits.
+///
+/// Must be called from the thread that measures, so that q can interrupt a
+/// read it is blocked in.
+pub fn listen(controls: SharedControls) -> io::Result<()> {
+    let tty = File::open("/dev/tty")?.into_raw_fd();
+    let mut original: libc::termios = unsafe { std::mem::zeroed() };
+    if unsafe { libc::tcgetattr(tty, &mut original) } != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    if ORIGINAL.set((tty, original)).is_err() {
+        return Ok(());
+    }
+
+    let mut keys = original;
+    keys.c_lflag &= !(libc::ICANON | libc::ECHO);
+    keys.c_cc[libc::VMIN] = 1;
+    keys.c_cc[libc::VTIME] = 0;
+    unsafe {
+        libc::atexit(restore_at_exit);
+        for &signal in &[libc::SIGINT, libc::SIGQUIT, libc::SIGHUP] {
+            libc::signal(signal, on_signal as *const () as libc::sighandler_t);
+        }
+        if libc::tcsetattr(tty, libc::TCSANOW, &keys) != 0 {
+            return Err(io::Error::last_os_error());
+        }
+    }
+    daemon::stop_on_terminate();
+
+    thread::spawn(move || {
+        let mut key = 0u8;
+        while unsafe { libc::read(tty, &mut key as *mut u8 as *mut libc::c_void, 1) } == 1 {
+            let command = match key.to_ascii_lowercase() {
+                b'p' => Command::TogglePause,
+                b'r' => Command::Reset,
+                b's' => Command::Snapshot,
+                b'q' => Command::Stop,
+                _ => continue,
+            };
+            controls.send(command);
+        }
+    });
+    Ok(())
+}
+
+/// Puts the terminal settings back. Only calls functions that are safe to
+/// call from a signal handler.
+fn restore() {
+    if let Some(&(tty, ref original)) = ORIGINAL.get() {
+        unsafe { libc::tcsetattr(tty, libc::TCSANOW, original) };
+    }
+}
+
+extern "C" fn restore_at_exit() {
+    restore();
+}
+
+/// Puts the terminal back before the signal does what it would have without
+/// the hotkeys.
+extern "C" fn on_signal(signal: libc::c_int) {
+    restore();
+    unsafe {
+        libc::signal(signal, libc::SIG_DFL);
+        libc::raise(signal);
+    }
+}
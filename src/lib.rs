@@ -0,0 +1,20 @@
+//! Measuring the throughput of streams from inside other programs. A
+//! `ThroughputMeter` counts the bytes that pass through any number of
+//! streams, which the `MeteredReader` and `MeteredWriter` adapters feed it
+//! from any reader or writer, and with the `tokio` feature the
+//! `MeteredAsyncReader` and `MeteredAsyncWriter` adapters from Tokio
+//! streams. The stats for every interval can be passed to a callback with
+//! `ThroughputMeter::observe` or received over a channel from
+//! `ThroughputMeter::subscribe`. A meter made `with_clock` a `ManualClock`
+//! is timed by however far a test moves the clock.
+
+#[cfg(feature = "tokio")]
+extern crate tokio;
+
+pub mod meter;
+pub mod metered;
+
+pub use meter::{Clock, IntervalStats, ManualClock, Observer, Snapshot, SystemClock, ThroughputMeter};
+pub use metered::{MeteredReader, MeteredWriter};
+#[cfg(feature = "tokio")]
+pub use metered::{MeteredAsyncReader, MeteredAsyncWriter};
@@ -1,16 +1,104 @@
 extern crate clap;
+extern crate socket2;
+#[macro_use]
+extern crate serde_json;
+extern crate crc32fast;
+#[cfg(feature = "gzip")]
+extern crate flate2;
+extern crate sha2;
+extern crate twox_hash;
+extern crate toml;
+#[cfg(unix)]
+extern crate libc;
+#[cfg(all(target_os = "linux", feature = "io-uring"))]
+extern crate io_uring;
+#[cfg(feature = "tokio")]
+extern crate tokio;
+#[cfg(windows)]
+extern crate windows_sys;
+#[cfg(feature = "zstd")]
+extern crate zstd;
+extern crate throughput;
 
-use clap::{App, Arg};
-use std::io::{stderr, stdin, stdout, Write, Read};
-use std::time::{Duration, Instant};
-use std::net::{SocketAddr, TcpListener, IpAddr};
+use std::fs::File;
+use std::io::{stderr, stdin, stdout, BufWriter, IsTerminal, Write, Read};
+use std::time::{Duration, Instant, SystemTime};
+use std::net::{Shutdown, SocketAddr, TcpListener, TcpStream};
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+
+use aggregate::AgentStats;
+use align::{Aligner, Alignment};
+use autotune::BufferTuner;
+use baseline::{Baseline, Comparison};
+use chaos::{Chaos, ChaosSpec, Fault};
+use net::{Acceptor, AddrPreference, CloseStats, ConnectionStats, PortRange, SocketOptions};
+use pattern::{Pattern, Verifier};
+use peek::Peek;
+use pipeline::{FlushPolicy, Pipeline, QueueOccupancy};
+use proxy::Proxy;
+use output::{CsvReporter, JsonLinesReporter, OutputSpec, Reporter, StatsdReporter, TerminalReporter};
+use push::{CollectorTarget, InfluxTarget, Pusher};
+use cli::Mode;
+use clock::OneWayDelay;
+use compress::{Codec, Compression, FinishOnDrop, WireCounter, WireStats};
+use concat::{SourceReader, SourceStats};
+use config::{Config, Settings};
+use console::StdStream;
+use control::{Direction, TestParams};
+use controls::SharedControls;
+use diskwrite::{DiskWriter, FsyncMode};
+use download::Resource;
+use failure::Failure;
+use exec::{CommandReader, CommandStats, SharedCommandStats};
+use hash::{HashAlgorithm, StreamHasher};
+use manifest::Manifest;
+use merge::MergedReader;
+use metrics::Metrics;
+use histogram::Histogram;
+use display::{format_rate, ColorChoice, RateThresholds, Style};
+use rate::{RateEstimator, RateMode};
+use recording::{Forwarder, Recorder, Replay};
+use profile::{Profile, PROFILE_NAMES};
+use records::RecordCounter;
+use relay::{DirectionStats, Directions};
+use frames::{FrameCounter, Framing};
+use report::ReportTarget;
+use runs::RunStats;
+use samples::{History, IntervalRates, DEFAULT_MAX_SAMPLES};
+use sctp::SctpStats;
+use sender::{GenerationCost, Payload};
+use shape::{Bursts, Schedule, Shaper};
+use stalls::StallDetector;
+use state::{StateFile, StateTotals};
+use sweep::{Sweep, SweepKind};
+use throughput::{Clock, SystemClock};
+use template::Template;
+use tcpinfo::{TcpInfoSampler, TcpStats};
+use udp::{UdpCounters, UdpReceiver, UdpStats};
+use units::{byte_to_mem_units, bytes_per_second, NumberFormat};
+use usage::Usage;
+use validate::Diagnostics;
+use watchdog::IdleWatchdog;
 
 const DEFAULT_BUFFER_SIZE: usize = 4096;
 const DEFAULT_ITERATION_COUNT: usize = 1;
-const DEFAULT_ADDRESS: &'static str = "127.0.0.1";
+const DEFAULT_MEASURE_INTERVAL_SECS: u64 = 1;
+const DEFAULT_REFRESH_MILLIS: u64 = 250;
+const DEFAULT_ADDRESS: &str = "127.0.0.1";
+const DEFAULT_TEST_DURATION_SECS: u64 = 10;
+const DEFAULT_BAUD_RATE: u32 = 115200;
+const DEFAULT_RETRY_DELAY_SECS: u64 = 1;
+const DEFAULT_BURST_GAP_MILLIS: u64 = 10;
+const DEFAULT_ANALYSIS_INTERVAL_MILLIS: u64 = 100;
+const DEFAULT_UDP_IDLE_TIMEOUT_SECS: u64 = 2;
+const DEFAULT_SELFTEST_DURATION_SECS: u64 = 3;
+const DEFAULT_PING_PONG_SIZE: usize = 64;
 
 macro_rules! print_err_into {
     ($err_write: expr, $fmt:expr) => ({
+        // Unused when the writer is generic over Write.
+        #[allow(unused_imports)]
         use std::io::Write;
         if let Err(e) = writeln!($err_write, $fmt) {
             panic!("Error while writing to stderr: {}", e);
@@ -18,6 +106,8 @@ macro_rules! print_err_into {
     });
 
     ($err_write: expr, $fmt:expr, $($arg:tt)*) => ({
+        // Unused when the writer is generic over Write.
+        #[allow(unused_imports)]
         use std::io::Write;
         if let Err(e) = writeln!($err_write, $fmt, $($arg)*) {
             panic!("Error while writing to stderr: {}", e);
@@ -42,6 +132,366 @@ macro_rules! print_err {
     });
 }
 
+mod affinity;
+mod aggregate;
+mod align;
+mod analyze;
+#[cfg(feature = "tokio")]
+mod async_listen;
+mod autotune;
+mod baseline;
+mod chaos;
+mod cli;
+mod clock;
+mod compress;
+mod concat;
+mod config;
+mod console;
+mod control;
+mod controls;
+mod daemon;
+#[cfg(target_os = "linux")]
+mod direct;
+mod diskwrite;
+mod download;
+#[cfg(target_os = "linux")]
+mod discard;
+mod display;
+mod follow;
+mod frames;
+#[cfg(any(unix, windows))]
+mod exec;
+mod failure;
+mod fds;
+mod fifo;
+mod hash;
+mod histogram;
+#[cfg(unix)]
+mod hotkeys;
+mod manifest;
+mod manpage;
+mod merge;
+mod metrics;
+#[cfg(unix)]
+mod mmap;
+mod net;
+mod output;
+#[cfg(feature = "otlp")]
+mod otlp;
+mod pattern;
+mod peek;
+mod pingpong;
+mod pipeline;
+#[cfg(target_os = "linux")]
+mod pipes;
+mod proxy;
+mod push;
+mod rate;
+mod reconnect;
+mod recording;
+#[cfg(unix)]
+mod remote;
+mod profile;
+mod records;
+mod relay;
+mod report;
+mod runs;
+mod samples;
+mod sctp;
+mod selftest;
+mod serial;
+mod shape;
+mod sender;
+#[cfg(target_os = "linux")]
+mod splice;
+mod stalls;
+mod state;
+mod sweep;
+#[cfg(unix)]
+mod syslog;
+mod tcpinfo;
+mod template;
+mod tui;
+mod udp;
+mod units;
+#[cfg(all(target_os = "linux", feature = "io-uring"))]
+mod uring;
+mod usage;
+mod validate;
+mod vectored;
+#[cfg(target_os = "linux")]
+mod vsock;
+mod watchdog;
+
+/// How long to wait before reading again from a non-blocking descriptor that
+/// had no data.
+const WOULD_BLOCK_RETRY_MILLIS: u64 = 10;
+
+/// How often a paused transfer checks whether it was resumed.
+const CONTROL_POLL_MILLIS: u64 = 50;
+
+/// The number of buffers that passed through data can be queued in while it
+/// waits to be written to stdout.
+const PASSTHROUGH_QUEUE_DEPTH: usize = 4;
+
+/// The number of reads kept in flight by the io_uring reader.
+#[cfg(all(target_os = "linux", feature = "io-uring"))]
+const IO_URING_QUEUE_DEPTH: u32 = 8;
+
+/// How measurements are shown while a stream is being measured.
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum DisplayMode {
+    /// Stats are rewritten in place after every measure.
+    Live,
+
+    /// Stats are printed once when the transfer has finished.
+    SummaryOnly,
+
+    /// No stats are printed.
+    Quiet,
+
+    /// Stats and a graph of recent measures fill the terminal.
+    Dashboard,
+}
+
+/// Where passed through data is written instead of stdout.
+#[derive(Clone)]
+enum PassTarget {
+    /// A connection that stdin is forwarded to.
+    Connection(Arc<TcpStream>),
+
+    /// Nowhere, to measure passing data through without any consumer.
+    Null,
+}
+
+/// Settings that control how a stream is measured.
+#[derive(Clone)]
+struct MeasureOptions {
+    /// The size of the buffer used to read from the stream in bytes.
+    buffer_size: usize,
+
+    /// Whether the buffer size is adjusted to the stream while reading,
+    /// starting from `buffer_size`.
+    auto_buffer_size: bool,
+
+    /// The number of times the buffer is filled before a measure is taken.
+    iterations: usize,
+
+    /// The number of buffers filled or written by each vectored read or
+    /// write.
+    vecs: usize,
+
+    /// The number of connections a test with a server runs in parallel.
+    parallel: usize,
+
+    /// Whether input is passed through to stdout.
+    passthrough: bool,
+
+    /// When passed through data is written to stdout.
+    flush: FlushPolicy,
+
+    /// Whether data is only counted and never looked at, which on Linux lets
+    /// the kernel drop data received on TCP sockets without copying it.
+    #[cfg_attr(not(target_os = "linux"), allow(dead_code))]
+    discard: bool,
+
+    /// Whether passthrough may move data inside the kernel instead of copying
+    /// it through the buffer, where the platform and streams allow it.
+    #[cfg_attr(not(target_os = "linux"), allow(dead_code))]
+    zero_copy: bool,
+
+    /// Set while the reader itself moves the data to stdout, so the measure
+    /// loop must not write it.
+    spliced: bool,
+
+    /// Where passed through data is written instead of stdout, such as when
+    /// stdin is forwarded to a host.
+    pass_to: Option<PassTarget>,
+
+    /// Whether reads are made with io_uring where nothing needs to see the
+    /// data.
+    #[cfg_attr(not(all(target_os = "linux", feature = "io-uring")), allow(dead_code))]
+    io_uring: bool,
+
+    /// Whether files are read and written with direct I/O, bypassing the
+    /// page cache.
+    #[cfg_attr(not(target_os = "linux"), allow(dead_code))]
+    direct: bool,
+
+    /// Whether files are read through a memory map instead of with reads.
+    #[cfg_attr(not(unix), allow(dead_code))]
+    mmap: bool,
+
+    /// The cores the measuring thread and per-connection threads are pinned
+    /// to, if any.
+    cpus: Vec<usize>,
+
+    /// How long to wait for data before giving up on the stream.
+    idle_timeout: Option<Duration>,
+
+    /// Whether reads on the socket time out, in which case a read that would
+    /// block has timed out rather than needing to be retried.
+    reads_time_out: bool,
+
+    /// The number of failed reads after which the transfer stops, rather
+    /// than retrying for as long as it takes.
+    max_errors: Option<u64>,
+
+    /// How long data is transferred before it starts being counted.
+    warmup: Option<Duration>,
+
+    /// How often a measure is taken.
+    interval: Duration,
+
+    /// How often the live stats are redrawn between measures, if at all.
+    refresh: Option<Duration>,
+
+    /// The wall clock boundaries measures are taken on instead of every
+    /// interval.
+    align: Option<Alignment>,
+
+    /// The number of bytes after which a measure is taken instead of every
+    /// interval.
+    measure_every_bytes: Option<u64>,
+
+    /// How measurements are shown.
+    display_mode: DisplayMode,
+
+    /// Forces the stats to be rendered in a particular style instead of
+    /// detecting whether they are written to a terminal.
+    style: Option<Style>,
+
+    /// Whether the stats are colored.
+    color: ColorChoice,
+
+    /// Fixed rates at which the transfer speed changes color.
+    color_thresholds: Option<RateThresholds>,
+
+    /// Whether the stats are shown on a single line.
+    compact: bool,
+
+    /// Whether a graph of the transfer speed over time is printed when
+    /// finished.
+    graph: bool,
+
+    /// The most samples kept of anything recorded over the whole transfer,
+    /// which bounds the memory used however long it runs.
+    max_samples: usize,
+
+    /// The layout of the line printed for every measure instead of the
+    /// usual stats.
+    template: Option<Template>,
+
+    /// How the sizes and rates printed for every measure are written.
+    number_format: NumberFormat,
+
+    /// The expected size of the transfer, used to show progress.
+    expected_bytes: Option<u64>,
+
+    /// How the current transfer speed is estimated.
+    rate_mode: RateMode,
+
+    /// Whether the size and duration of every read is recorded.
+    read_stats: bool,
+
+    /// A file that every byte read is also written to.
+    tee_path: Option<String>,
+
+    /// A file that every byte read is recorded to along with when it was
+    /// read.
+    record_path: Option<String>,
+
+    /// A file that the time and size of every read is logged to.
+    chunk_log_path: Option<String>,
+
+    /// The digest to compute over every byte read.
+    hash: Option<HashAlgorithm>,
+
+    /// The pattern the data read is expected to follow.
+    verify: Option<Pattern>,
+
+    /// The byte that ends each record, if records are counted.
+    delimiter: Option<u8>,
+
+    /// How the messages in the stream are framed, if they are counted.
+    framing: Option<Framing>,
+
+    /// How many bytes from the start of the stream to show as a hexdump.
+    peek: Option<usize>,
+
+    /// How many bytes at the start of the stream are read past before
+    /// anything is measured.
+    skip: Option<u64>,
+
+    /// How many bytes are measured, after which the transfer ends.
+    take: Option<u64>,
+
+    /// How long a read must wait for data to count as a stall.
+    stall_threshold: Option<Duration>,
+
+    /// The faults to inject into passed through data, if any.
+    chaos: Option<ChaosSpec>,
+
+    /// The rates that data is limited to over the transfer, if any.
+    shape: Option<Schedule>,
+
+    /// The format that data read is decoded from, and data sent is encoded
+    /// into, if it is compressed.
+    compression: Option<Codec>,
+
+    /// The format and level that data is compressed with before it is sent
+    /// or forwarded to a host.
+    compress: Option<Compression>,
+
+    /// Set while a compressed stream is read, for the bytes read before
+    /// decoding.
+    wire_bytes: Option<Arc<AtomicU64>>,
+
+    /// The baud rate of the serial port being read, if it is one.
+    baud: Option<u32>,
+
+    /// Set while an SCTP association is read, for the stats of its streams.
+    sctp: Option<Arc<Mutex<SctpStats>>>,
+
+    /// Set while UDP datagrams are received, for how many each thread got.
+    udp: Option<Arc<UdpCounters>>,
+
+    /// Set while a TCP connection is read on Linux, for what the kernel
+    /// reports about it.
+    tcp_info: Option<Arc<TcpInfoSampler>>,
+
+    /// Set while several sources are read as one, for the stats of each.
+    sources: Option<Arc<Mutex<Vec<SourceStats>>>>,
+
+    /// Set while a connection is forwarded, for the stats of each direction.
+    directions: Option<Directions>,
+
+    /// Set while the output of a command is read, for how it exited.
+    command: Option<SharedCommandStats>,
+
+    /// Set while a connection that is reconnected when it drops is read,
+    /// for how many times it was.
+    reconnects: Option<Arc<AtomicUsize>>,
+
+    /// Where measurements are published for the metrics endpoint.
+    metrics: Option<Arc<Metrics>>,
+
+    /// Where every measure is reported besides the terminal, such as files,
+    /// StatsD and the metrics endpoint.
+    outputs: Vec<Arc<dyn Reporter>>,
+
+    /// Where the totals are kept across restarts.
+    state: Option<Arc<StateFile>>,
+
+    /// Requests to pause, reset or measure made while the transfer runs,
+    /// such as with hotkeys.
+    controls: Option<SharedControls>,
+
+    /// The clock that the intervals and the elapsed time of a measurement
+    /// are timed by.
+    clock: Arc<dyn Clock>,
+}
+
 #[derive(Default)]
 struct TransferInfo {
     /// The total number of bytes transferred.
@@ -56,278 +506,3430 @@ struct TransferInfo {
     /// The Bytes Per Second during the last measure.
     last_bps: f64,
 
+    /// The highest Bytes Per Second of any measure.
+    peak_bps: f64,
+
+    /// The current Bytes Per Second as estimated by the selected rate mode.
+    current_bps: f64,
+
     /// The number of bytes transferred during the last measure.
     last_bytes_transferred: usize,
+
+    /// The time from the start of the transfer until the last measure.
+    elapsed: Duration,
+
+    /// The wall clock time at which the transfer started.
+    start_time: Option<SystemTime>,
+
+    /// The wall clock time at which the last measure was taken.
+    last_measure_time: Option<SystemTime>,
+
+    /// Distributions of individual reads, if they are being collected.
+    read_stats: Option<ReadStats>,
+
+    /// Time spent blocked on input and output when passing data through.
+    passthrough_timing: Option<PassthroughTiming>,
+
+    /// How many buffers were waiting to be written when passing data through.
+    queue_occupancy: Option<QueueOccupancy>,
+
+    /// The algorithm and hex digest of every byte transferred, once finished.
+    digest: Option<(HashAlgorithm, String)>,
+
+    /// Checks the transferred bytes against the expected pattern.
+    verifier: Option<Verifier>,
+
+    /// Counts the records transferred, if they are being counted.
+    records: Option<RecordCounter>,
+
+    /// Counts the framed messages transferred and their sizes, if they are
+    /// being counted.
+    frames: Option<FrameCounter>,
+
+    /// Counts the reads that waited too long for data, if they are being
+    /// counted.
+    stalls: Option<StallDetector>,
+
+    /// Injects faults into passed through data and counts them, if asked to.
+    chaos: Option<Chaos>,
+
+    /// The bytes that crossed the wire, if the data was compressed.
+    wire: Option<WireStats>,
+
+    /// The baud rate of the serial port the transfer was read from.
+    baud: Option<u32>,
+
+    /// The data and messages received on each stream of an SCTP
+    /// association, once finished.
+    sctp: Option<SctpStats>,
+
+    /// The datagrams received by each thread, once finished.
+    udp: Option<UdpStats>,
+
+    /// What the kernel reported about the TCP connection while it was
+    /// measured.
+    tcp: Option<TcpStats>,
+
+    /// How long generating the data that was sent took.
+    generation: Option<GenerationCost>,
+
+    /// How the connection was closed once everything was sent.
+    close: Option<CloseStats>,
+
+    /// The totals of this process and across restarts, once finished.
+    totals: Option<StateTotals>,
+
+    /// How long the data took to reach the peer, if it was worked out with
+    /// the control protocol.
+    one_way_delay: Option<OneWayDelay>,
+
+    /// How quickly each of several sources read as one stream was read,
+    /// once finished.
+    sources: Option<Vec<SourceStats>>,
+
+    /// How much went each way through a forwarded connection.
+    directions: Option<Vec<DirectionStats>>,
+
+    /// How the command whose output was read exited, once finished.
+    command: Option<CommandStats>,
+
+    /// How many times the connection was reconnected after it dropped.
+    reconnects: Option<usize>,
+
+    /// What was read from each of several connections measured together,
+    /// once finished.
+    connections: Option<Vec<ConnectionStats>>,
+
+    /// How many of the connections were left out of `connections` to keep
+    /// no more than `--max-samples` of them.
+    connections_omitted: usize,
+
+    /// The latest stats of every agent reporting to an aggregator.
+    agents: Option<Vec<AgentStats>>,
+
+    /// The transfer speed of the measures, oldest first, if it is graphed
+    /// when finished. Neighbouring measures are averaged together once there
+    /// are more than `--max-samples` of them.
+    rate_history: Option<History>,
+
+    /// The spread of the transfer speeds of the measures.
+    interval_rates: IntervalRates,
+
+    /// The buffer size that auto-tuning settled on, once finished.
+    tuned_buffer_size: Option<usize>,
+
+    /// The CPU time and system calls used during the transfer, once
+    /// finished.
+    usage: Option<Usage>,
+
+    /// Whether the transfer was cut short because a read timed out.
+    timed_out: bool,
+
+    /// The reads that returned an error instead of data.
+    read_errors: ReadErrors,
 }
 
-#[inline]
-fn exit_err() -> ! {
-    std::process::exit(1);
+/// Where time goes when passing data through, which shows whether the
+/// producer or the consumer of the data is the bottleneck.
+#[derive(Clone, Copy, Default)]
+struct PassthroughTiming {
+    /// Time spent waiting for data to be read from the input.
+    read_time: Duration,
+
+    /// Time spent waiting for queued data to be written to stdout so that a
+    /// buffer was free to read into.
+    write_time: Duration,
 }
 
-fn main() {
-    let matches = App::new("Throughput")
-        .version("1.1")
-        .author("Adolph C.")
-        .about("Measures the throughput of stdin or a socket.")
-        .arg(Arg::with_name("address")
-            .short("l")
-            .long("addr")
-            .value_name("IP Address")
-            .help("IP address to listen to. Defaults to 127.0.0.1. Must specify port.")
-            .takes_value(true))
-        .arg(Arg::with_name("buffer_size")
-            .short("b")
-            .long("bufsize")
-            .value_name("BYTES")
-            .help("The size of the buffer used to read from the stream in bytes. Defaults to 4096.")
-            .takes_value(true))
-        .arg(Arg::with_name("iterations")
-            .short("i")
-            .long("iterations")
-            .help("The number of times the buffer should be filled before a measure is taken. Defaults to 1.")
-            .takes_value(true))
-        .arg(Arg::with_name("port")
-            .short("p")
-            .long("port")
-            .value_name("PORT_NUMBER")
-            .help("Port to listen on. Must be specified if address is given.")
-            .takes_value(true))
-        .arg(Arg::with_name("pass")
-            .long("pass")
-            .help("If present, throughput will print to stderr and pass input to stdout.")
-            .takes_value(false))
-        .after_help("If a port/address is not specified, throughput will read from stdin.")
-        .get_matches();
-
-    let passthrough = matches.is_present("pass");
-    let buffer_size: usize;
-    let iterations: usize;
-
-    if let Some(buf_size_str) = matches.value_of("buffer_size") {
-        if let Ok(bsize) = buf_size_str.parse() {
-            buffer_size = bsize;
-        } else {
-            print_err!("Buffer size must be a valid number.");
-            exit_err();
-        }
-    } else {
-        buffer_size = DEFAULT_BUFFER_SIZE;
-    }
+/// The reads that returned an error rather than data or the end of the
+/// stream.
+#[derive(Clone, Copy, Default)]
+struct ReadErrors {
+    /// Reads that were interrupted by a signal or would have blocked, which
+    /// are retried.
+    transient: u64,
 
+    /// Reads that failed for any other reason.
+    fatal: u64,
+}
 
-    if let Some(iterations_str) = matches.value_of("iterations") {
-        if let Ok(it) = iterations_str.parse() {
-            iterations = it;
-        } else {
-            print_err!("Iterations must be a valid number.");
-            exit_err();
-        }
-    } else {
-        iterations = DEFAULT_ITERATION_COUNT;
-    }
+/// Distributions of the sizes and durations of individual `read()` calls.
+#[derive(Clone, Default)]
+struct ReadStats {
+    /// The number of bytes returned by each read.
+    sizes: Histogram,
 
-    let address_present = matches.is_present("address");
-    let port_present = matches.is_present("port");
-    if address_present || port_present {
-        if !port_present {
-            print_err!("A port must be speicified alongside a address.");
-            exit_err();
-        } else {
-            let address = matches.value_of("address").unwrap_or(DEFAULT_ADDRESS);
-            let port = matches.value_of("port").expect("Expected port arg to have value.");
+    /// The time spent in each read in nanoseconds.
+    latencies: Histogram,
+}
 
-            if let Ok(parsed_port) = port.parse() {
-                measure_tcp_stream(address, parsed_port, buffer_size, iterations, passthrough);
-            } else {
-                print_err!("Port must be a valid number from 0 to 65535");
-                exit_err();
-            }
+impl TransferInfo {
+    /// The average of all of the Bytes Per Second measures.
+    fn average_bps(&self) -> f64 {
+        if self.total_measures == 0 {
+            0.0
+        } else {
+            self.total_bps / self.total_measures as f64
         }
-    } else {
-        measure_stdin(buffer_size, iterations, passthrough);
     }
 }
 
-fn measure_tcp_stream(address: &str, port: u16, buffer_size: usize, iterations: usize, passthrough: bool) {
-    let parsed_addr: IpAddr = match address.parse() {
-        Ok(parsed) => parsed,
-        Err(_) => {
-            print_err!("Bad IP address {}", address);
-            exit_err();
-        }
-    };
-
-    let socket_addr = SocketAddr::new(parsed_addr, port);
-    match TcpListener::bind(socket_addr) {
-        Ok(listener) => {
-            println!("Listening at {}", socket_addr);
+#[inline]
+fn exit_err() -> ! {
+    Failure::General.exit();
+}
 
-            match listener.accept() {
-                Ok((stream, incoming_addr)) => {
-                    println!("Reading incoming data from {}", incoming_addr);
-                    println!();
-                    measure_reader(stream, buffer_size, iterations, passthrough);
-                },
+fn main() {
+    let app_matches = cli::app().get_matches();
+    let (mode, matches) = cli::mode(&app_matches);
 
-                Err(err) => {
-                    print_err!("There was an error accepting a connection.");
-                    print_err!("ERROR: {}", err);
-                    exit_err();
-                }
+    // Completions and the man page are printed without a config, so that
+    // packaging them never depends on the machine they are built on.
+    match mode {
+        Mode::Completions(shell) => {
+            cli::app().gen_completions_to("throughput", shell, &mut stdout());
+            return;
+        },
+        Mode::ManPage => {
+            if let Err(err) = manpage::write(&mut stdout().lock()) {
+                print_err!("Error while printing the man page: {}", err);
+                exit_err();
             }
+            return;
         },
+        _ => {},
+    }
 
+    let config = match Config::load(matches.value_of("config")) {
+        Ok(config) => config,
         Err(err) => {
-            print_err!("There was an error connecting to {}", socket_addr);
-            print_err!("ERROR: {}", err);
+            print_err!("Error while loading config: {}", err);
             exit_err();
         }
     };
-}
+    let mut settings = Settings::new(matches, config);
 
-fn measure_stdin(buffer_size: usize, iterations: usize, passthrough: bool) {
-    let input = stdin();
-    measure_reader(input.lock(), buffer_size, iterations, passthrough);
-}
+    if let Mode::Analyze(path) = mode {
+        analyze(path, &settings);
+        return;
+    }
+    if let Mode::PingPong = mode {
+        ping_pong(&settings);
+        return;
+    }
+    match settings.value_of("profile").map(|name| Profile::parse(name).ok_or_else(|| name.to_string())) {
+        Some(Ok(profile)) => settings.add_defaults(&profile.options()),
+        Some(Err(name)) => {
+            print_err!("Profile must be one of {} (got \"{}\").", PROFILE_NAMES.join(", "), name);
+            exit_err();
+        },
+        None => {},
+    }
 
-fn measure_reader<R: Read>(mut reader: R, buffer_size: usize, iterations: usize, passthrough: bool) {
-    let output = stdout();
-    let mut locked_output = output.lock();
+    // Every argument is checked before anything starts, so that all of the
+    // problems with them are reported together.
+    let diagnostics = Diagnostics::default();
 
-    let err_out = stderr();
-    let mut locked_error = err_out.lock();
-    
-    let mut buffer = Vec::with_capacity(buffer_size);
-    buffer.resize(buffer_size, 0);
+    let passthrough = settings.is_present("pass");
+    let auto_buffer_size = settings.value_of("buffer_size") == Some("auto");
+    let buffer_size = if auto_buffer_size {
+        autotune::START_SIZE
+    } else {
+        diagnostics.parse(settings.value_of("buffer_size"), |value| {
+            validate::number(value, "Buffer size", 1).map_err(|problem| format!("{} Use auto to have it tuned.", problem))
+        }).unwrap_or(DEFAULT_BUFFER_SIZE)
+    };
+    let iterations = diagnostics.parse(settings.value_of("iterations"), |value| validate::number(value, "Iterations", 1))
+        .unwrap_or(DEFAULT_ITERATION_COUNT);
 
-    let mut last_measured = Instant::now();
-    let mut transfer_info = TransferInfo::default();
+    let idle_timeout = diagnostics.parse(settings.value_of("idle_timeout"), |value| validate::timeout(value, "Idle timeout", "10s or 500ms"));
+    let warmup = diagnostics.parse(settings.value_of("warmup"), |value| validate::duration(value, "Warm-up", "5s or 500ms"));
+    let measure_interval = diagnostics.parse(settings.value_of("interval"), |value| validate::timeout(value, "Interval", "10s or 1m"))
+        .unwrap_or(Duration::from_secs(DEFAULT_MEASURE_INTERVAL_SECS));
+    let align = diagnostics.parse(settings.value_of("align"), |value| validate::timeout(value, "Align", "1s or 1m"))
+        .map(Alignment::new);
+    let measure_every_bytes = diagnostics.parse(settings.value_of("measure_every_bytes"), |value| {
+        validate::size(value, "Measure every bytes", "1G", 1)
+    });
+    // The stats are only redrawn between measures by default when those can
+    // be more than a second apart.
+    let refresh = diagnostics.parse(settings.value_of("refresh"), |value| validate::duration(value, "Refresh", "100ms or 0"))
+        .or_else(|| {
+            let sparse = measure_interval > Duration::from_secs(DEFAULT_MEASURE_INTERVAL_SECS) || align.is_some() || measure_every_bytes.is_some();
+            Some(Duration::from_millis(DEFAULT_REFRESH_MILLIS)).filter(|_| sparse)
+        })
+        .filter(|refresh| !refresh.is_zero());
+    let max_errors = diagnostics.parse(settings.value_of("max_errors"), |value| validate::number(value, "Maximum errors", 1));
+    let pipe_size = diagnostics.parse(settings.value_of("pipe_size"), |value| validate::size(value, "Pipe size", "1M", 1))
+        .map(|size| size as usize);
+    if pipe_size.is_some() && !cfg!(target_os = "linux") {
+        diagnostics.error("Pipe sizes can only be set on Linux.");
+    }
 
-    loop {
-        let mut end_loop = false;
-        for _ in 0..iterations {
-            match reader.read(&mut buffer) {
-                Ok(bytes_read) => {
-                    transfer_info.last_bytes_transferred += bytes_read;
-                    transfer_info.total_bytes_transferred += bytes_read;
-                    if bytes_read == 0 {
-                        end_loop = true;
-                        break;
-                    } else if passthrough {
-                        if let Err(err) = locked_output.write_all(&buffer[0..bytes_read]) {
-                            print_err_into!(locked_error, "Error while writing buffer into stdout: {}", err);
-                            exit_err();
-                        }
-                    }
-                }
+    let vecs = diagnostics.parse(settings.value_of("vecs"), |value| validate::number(value, "Vecs", 1)).unwrap_or(1);
+    let parallel = diagnostics.parse(settings.value_of("parallel"), |value| validate::number(value, "Parallel streams", 1)).unwrap_or(1);
+    let runs = diagnostics.parse(settings.value_of("runs"), |value| validate::number(value, "Runs", 1)).unwrap_or(1);
 
-                Err(err) => {
-                    print_err_into!(locked_error, "Error while reading into buffer: {}", err);
-                }
-            }
-        }
+    let sweep = match (settings.value_of("sweep_size"), settings.value_of("sweep_parallel")) {
+        (Some(value), _) => diagnostics.parse(Some(value), |value| Sweep::parse(SweepKind::BufferSize, value).ok_or_else(|| {
+            validate::invalid("Sweep must be a range of sizes and a step such as 1K:64K:x2 or 1K:8K:+1K", value)
+        })),
+        (None, Some(value)) => diagnostics.parse(Some(value), |value| Sweep::parse(SweepKind::Parallel, value).ok_or_else(|| {
+            validate::invalid("Parallel sweep must be a range of stream counts and a step such as 1:64:x2 or 1:8:+1", value)
+        })),
+        (None, None) => None,
+    };
+    // A sweep runs the transfer once for each value.
+    let runs = sweep.as_ref().map_or(runs, |sweep| sweep.values.len());
 
-        let measure_end = Instant::now();
-        let duration = measure_end.duration_since(last_measured);
-        if duration.as_secs() > 0 || end_loop {
-            transfer_info.last_bps = bytes_per_second(transfer_info.last_bytes_transferred, duration);
-            transfer_info.total_measures += 1;
-            transfer_info.total_bps += transfer_info.last_bps;
+    let min_throughput = diagnostics.parse(settings.value_of("min_throughput"), |value| validate::rate(value, "Minimum throughput", "50MB/s"));
+    let min_total = diagnostics.parse(settings.value_of("min_total"), |value| validate::size(value, "Minimum total", "1G", 0));
 
-            let _print_result = if passthrough {
-                print_info(&mut locked_error, &mut transfer_info)
-            } else {
-                print_info(&mut locked_output, &mut transfer_info)
-            };
+    let report_target = diagnostics.parse(settings.value_of("report"), ReportTarget::parse);
+    let mut manifest = settings.value_of("manifest").map(Manifest::new);
 
-            match _print_result {
-                Ok(_) => {},
-                Err(err) => {
-                    print_err_into!(locked_error, "Error while printing output: {}", err);
-                    exit_err();
-                }
-            }
+    let baseline = diagnostics.parse(settings.value_of("baseline"), |path| {
+        Baseline::load(path).map_err(|err| format!("Error while reading baseline {}: {}", path, err))
+    });
 
-            last_measured = measure_end;
-            transfer_info.last_bps = 0.0;
-            transfer_info.last_bytes_transferred = 0;
+    let max_regression = diagnostics.parse(settings.value_of("max_regression"), |value| {
+        match value.trim().trim_end_matches('%').trim().parse::<f64>() {
+            Ok(percent) if percent.is_finite() && percent >= 0.0 => Ok(percent),
+            _ => Err(validate::invalid("Maximum regression must be a percentage such as 10%", value)),
         }
+    });
 
-        if end_loop { return; }
-    }
-}
+    let display_mode = if settings.is_present("quiet") {
+        DisplayMode::Quiet
+    } else if settings.is_present("summary_only") {
+        DisplayMode::SummaryOnly
+    } else if settings.is_present("tui") {
+        DisplayMode::Dashboard
+    } else {
+        DisplayMode::Live
+    };
 
-fn print_info<W: Write>(output: &mut W, transfer_info: &mut TransferInfo) -> Result<(), std::io::Error> {
-    if transfer_info.total_measures > 1 { term_move_up(output, 3)?; }
+    let color_thresholds = diagnostics.parse(settings.value_of("color_thresholds"), |value| {
+        let mut rates = value.splitn(2, ',').map(units::parse_rate);
+        match (rates.next(), rates.next()) {
+            (Some(Some(low)), Some(Some(high))) if low <= high => Ok(RateThresholds { low, high }),
+            _ => Err(validate::invalid("Color thresholds must be two rates, the lower first, such as 10MB/s,100MB/s", value)),
+        }
+    });
 
-    let (mem_total_transfer, unit_total_transfer) = byte_to_mem_units(transfer_info.total_bytes_transferred as f64);
-    print_fixed_width(output, "Data Transferred:", 24);
-    write!(output, "{:.3} {} ({} cycles)", 
-        mem_total_transfer, unit_total_transfer, transfer_info.total_measures)?;
-    term_clear_line(output)?;
+    let rate_mode = if let Some(value) = settings.value_of("window") {
+        diagnostics.parse(Some(value), |value| validate::timeout(value, "Window", "10s")).map(RateMode::Window)
+    } else if let Some(value) = settings.value_of("ewma") {
+        diagnostics.parse(Some(value), |value| match value.parse::<f64>() {
+            Ok(alpha) if alpha > 0.0 && alpha <= 1.0 => Ok(RateMode::Ewma(alpha)),
+            _ => Err(validate::invalid("EWMA smoothing factor must be a number greater than 0 and at most 1", value)),
+        })
+    } else {
+        None
+    }.unwrap_or(RateMode::Last);
 
-    let (mem_single, unit_single) = byte_to_mem_units(transfer_info.last_bps);
-    print_fixed_width(output, "Transfer Speed:", 24);
-    write!(output, "{:.3} {}/sec", mem_single, unit_single)?;
-    term_clear_line(output)?;
+    let mut output_specs: Vec<OutputSpec> = settings.values_of("output").into_iter().filter_map(|value| {
+        diagnostics.parse(Some(value), |value| OutputSpec::parse(value).ok_or_else(|| {
+            validate::invalid("Output must be terminal, jsonl=PATH, csv=PATH, statsd=HOST:PORT or prometheus=ADDRESS:PORT", value)
+        }))
+    }).collect();
+    // Without --output the stats go to the terminal, and the shorthands add
+    // to that.
+    let terminal_output = output_specs.is_empty() || output_specs.contains(&OutputSpec::Terminal);
+    let display_mode = if terminal_output { display_mode } else { DisplayMode::Quiet };
+    output_specs.extend(diagnostics.parse(settings.value_of("statsd"), |value| {
+        match std::net::ToSocketAddrs::to_socket_addrs(value).ok().and_then(|mut addrs| addrs.next()) {
+            Some(addr) => Ok(OutputSpec::Statsd(addr)),
+            None => Err(validate::invalid("StatsD address must be a host and port such as localhost:8125", value)),
+        }
+    }));
+    output_specs.extend(diagnostics.parse(settings.value_of("metrics_addr"), |value| match value.parse() {
+        Ok(addr) => Ok(OutputSpec::Prometheus(addr)),
+        Err(_) => Err(validate::invalid("Metrics address must be an address and port such as 0.0.0.0:9100", value)),
+    }));
+    let metrics_addrs: Vec<SocketAddr> = output_specs.iter().filter_map(|spec| match *spec {
+        OutputSpec::Prometheus(addr) => Some(addr),
+        _ => None,
+    }).collect();
+    if metrics_addrs.is_empty() && (settings.is_present("buckets") || settings.is_present("size_buckets")) {
+        diagnostics.error("Buckets are only used by the Prometheus metrics, which need --metrics-addr or --output prometheus=ADDRESS:PORT.");
+    }
+    let latency_buckets = parse_buckets(&settings, &diagnostics, "buckets", |bound| units::parse_duration(bound).map(|duration| duration.as_secs_f64()),
+        "Buckets must be durations in increasing order such as 1ms,5ms,10ms,50ms")
+        .unwrap_or_else(|| metrics::DEFAULT_LATENCY_BUCKETS.to_vec());
+    let size_buckets = parse_buckets(&settings, &diagnostics, "size_buckets", |bound| units::parse_size(bound).map(|bytes| bytes as f64),
+        "Size buckets must be sizes in increasing order such as 4K,64K,1M")
+        .unwrap_or_else(|| metrics::DEFAULT_SIZE_BUCKETS.to_vec());
+    let influx = diagnostics.parse(settings.value_of("influx"), |value| {
+        InfluxTarget::parse(value, settings.value_of("influx_token").map(String::from)).ok_or_else(|| {
+            validate::invalid("InfluxDB URL must be an http:// URL such as http://localhost:8086/write?db=throughput", value)
+        })
+    });
+    let collector = diagnostics.parse(settings.value_of("stats_to"), |value| {
+        let agent = settings.value_of("stats_name").map_or_else(push::hostname, String::from);
+        CollectorTarget::parse(value, agent).ok_or_else(|| {
+            validate::invalid("Stats collector must be a host and port such as collector:9200, optionally after tcp:// or udp://", value)
+        })
+    });
+    #[cfg(feature = "otlp")]
+    let otlp = diagnostics.parse(settings.value_of("otlp"), |url| otlp::OtlpTarget::parse(url).ok_or_else(|| {
+        validate::invalid("OpenTelemetry collector URL must be an http:// URL such as http://localhost:4318", url)
+    }));
+    #[cfg(not(feature = "otlp"))]
+    {
+        if settings.is_present("otlp") {
+            diagnostics.error("OpenTelemetry export is only available when built with the otlp feature.");
+        }
+    }
+    let journald = settings.is_present("journald");
+    let system_log = journald || settings.is_present("syslog");
+    if journald && !cfg!(target_os = "linux") {
+        diagnostics.error("journald is only available on Linux.");
+    } else if system_log && !cfg!(unix) {
+        diagnostics.error("Syslog is only available on Unix.");
+    }
+    let daemonize = settings.is_present("daemon");
+    let pidfile = settings.value_of("pidfile");
+    if (daemonize || pidfile.is_some()) && !cfg!(unix) {
+        diagnostics.error("Daemon mode is only available on Unix.");
+    }
 
-    let avg_bps = transfer_info.total_bps / transfer_info.total_measures as f64;
-    let (mem_avg, unit_avg) = byte_to_mem_units(avg_bps);
-    print_fixed_width(output, "Average Transfer Speed:", 24);
-    write!(output, "{:.3} {}/sec", mem_avg, unit_avg)?;
-    term_clear_line(output)?;
+    let cpus = diagnostics.parse(settings.value_of("cpu"), |value| affinity::parse_cpus(value).ok_or_else(|| {
+        validate::invalid("CPU must be a core number or a list such as 2,3 or 0-3", value)
+    })).unwrap_or_default();
 
-    Ok(())
-}
+    let discard = settings.is_present("discard");
+    if discard && ["pass", "tee", "record", "hash", "verify", "count_lines", "delimiter", "frame", "peek", "gzip", "zstd"].iter().any(|name| settings.is_present(name)) {
+        diagnostics.error("Discard cannot be used with --pass, --tee, --record, --hash, --verify, --count-lines, --delimiter, --frame, --peek, --gzip or --zstd.");
+    }
+    if settings.is_present("udp") && ["pass", "tee", "record", "hash", "verify", "count_lines", "delimiter", "frame", "peek", "gzip", "zstd"].iter().any(|name| settings.is_present(name)) {
+        diagnostics.error("UDP cannot be used with --pass, --tee, --record, --hash, --verify, --count-lines, --delimiter, --frame, --peek, --gzip or --zstd, as the datagrams are not kept.");
+    }
 
-fn print_fixed_width<W: Write>(output: &mut W, text: &str, columns: usize) {
-    if let Err(err) = output.write(text.as_bytes()) {
-        panic!("[print_fixed_width] Error while writing to stream: {}", err);
+    let flush = if settings.is_present("line_buffered") {
+        FlushPolicy::Lines
+    } else {
+        diagnostics.parse(settings.value_of("flush_every"), |value| FlushPolicy::parse_every(value).ok_or_else(|| {
+            validate::invalid("Flush every must be a size such as 64K or a duration such as 100ms", value)
+        })).unwrap_or(FlushPolicy::Immediate)
+    };
+    if settings.is_present("io_uring") && !cfg!(all(target_os = "linux", feature = "io-uring")) {
+        diagnostics.error("io_uring is only available on Linux when built with the io-uring feature.");
+    }
+    if settings.is_present("direct") && !cfg!(target_os = "linux") {
+        diagnostics.error("Direct I/O is only available on Linux.");
+    }
+    if settings.is_present("mmap") && !cfg!(unix) {
+        diagnostics.error("Memory mapped files are only available on Unix.");
+    }
+    let max_samples = diagnostics.parse(settings.value_of("max_samples"), |value| validate::number(value, "Max samples", 2))
+        .unwrap_or(DEFAULT_MAX_SAMPLES);
+    let template = diagnostics.parse(settings.value_of("format"), |value| {
+        Template::parse(value).map_err(|err| format!("Invalid format: {}.", err))
+    });
+    let expected_bytes = diagnostics.parse(settings.value_of("expect_bytes"), |value| validate::size(value, "Expected bytes", "4G", 0));
+    let delimiter = if settings.is_present("count_lines") {
+        Some(b'\n')
+    } else {
+        diagnostics.parse(settings.value_of("delimiter"), |value| records::parse_delimiter(value).ok_or_else(|| {
+            validate::invalid("Delimiter must be a single character, an escape such as \\n or a byte such as 0x1e", value)
+        }))
+    };
+    let peek = diagnostics.parse(settings.value_of("peek"), |value| validate::size(value, "Peek", "256 or 4K", 1))
+        .map(|size| size as usize);
+    let skip = diagnostics.parse(settings.value_of("skip"), |value| validate::size(value, "Skip", "1G", 0));
+    let take = diagnostics.parse(settings.value_of("take"), |value| validate::size(value, "Take", "4G", 1));
+    if (skip.is_some() || take.is_some()) && passthrough {
+        diagnostics.error("Skip and take cannot be used with --pass, as the data outside of the range is not passed through.");
     }
+    let chaos = diagnostics.parse(settings.value_of("chaos"), |value| ChaosSpec::parse(value).ok_or_else(|| {
+        validate::invalid("Chaos must be a list such as drop=0.01,dup=0.01,delay=0.05:100ms,flip=0.001 whose fractions \
+                           add up to at most 1", value)
+    }));
+    let shape = diagnostics.parse(settings.value_of("shape"), |value| Schedule::parse(value).ok_or_else(|| {
+        validate::invalid("Shape must be a list of rates such as 0-30s:1MB/s,30-60s:10MB/s,60s-:5MB/s", value)
+    }));
+    // Bursts are kept to by the same shaper as the rates, with no rates of
+    // their own unless --shape gives some.
+    let shape = match parse_bursts(&settings, &diagnostics) {
+        Some(bursts) => Some(Schedule { bursts: Some(bursts), ..shape.unwrap_or(Schedule { segments: Vec::new(), bursts: None }) }),
+        None => shape,
+    };
 
-    if text.len() < columns {
-        let remaining = columns - text.len();
+    let preference = if settings.is_present("ipv4") {
+        AddrPreference::OnlyV4
+    } else if settings.is_present("ipv6") {
+        AddrPreference::OnlyV6
+    } else {
+        settings.value_of("prefer")
+            .and_then(AddrPreference::parse)
+            .unwrap_or(AddrPreference::Any)
+    };
 
-        let pad = [b' '];
-        for _ in 0..remaining {
-            if let Err(err) = output.write(&pad) {
-                panic!("[print_fixed_width] Error while padding output: {}", err);
-            }
-        }
+    if settings.is_present("congestion") && !cfg!(target_os = "linux") {
+        diagnostics.error("The congestion control algorithm can only be chosen on Linux.");
     }
-}
+    if settings.is_present("bind_device") && !cfg!(target_os = "linux") {
+        diagnostics.error("Binding to an interface is only possible on Linux.");
+    }
+    let socket_options = SocketOptions {
+        recv_buffer_size: diagnostics.parse(settings.value_of("rcvbuf"), |value| validate::number(value, "Receive buffer size", 0)),
+        send_buffer_size: diagnostics.parse(settings.value_of("sndbuf"), |value| validate::number(value, "Send buffer size", 0)),
+        nodelay: settings.is_present("nodelay"),
+        keepalive: diagnostics.parse(settings.value_of("keepalive"), |value| validate::number(value, "Keepalive seconds", 0))
+            .map(Duration::from_secs),
+        congestion: settings.value_of("congestion").map(String::from),
+        // The standard library sets SO_REUSEADDR on Unix, but on Windows it
+        // allows other processes to steal the port so it must be asked for.
+        reuse_address: settings.is_present("reuseaddr") || (cfg!(unix) && !settings.is_present("no_reuseaddr")),
+        reuse_port: settings.is_present("reuseport"),
+        accept_timeout: diagnostics.parse(settings.value_of("accept_timeout"), |value| validate::timeout(value, "Accept timeout", "30s")),
+        read_timeout: diagnostics.parse(settings.value_of("read_timeout"), |value| validate::timeout(value, "Read timeout", "10s")),
+        connect_retries: diagnostics.parse(settings.value_of("retry"), |value| validate::number(value, "Retry attempts", 0)).unwrap_or(0),
+        retry_delay: diagnostics.parse(settings.value_of("retry_delay"), |value| validate::timeout(value, "Retry delay", "2s"))
+            .unwrap_or(Duration::from_secs(DEFAULT_RETRY_DELAY_SECS)),
+        bind_device: settings.value_of("bind_device").map(String::from),
+        source_addr: diagnostics.parse(settings.value_of("source_addr"), |value| {
+            value.trim_start_matches('[').trim_end_matches(']').parse().map_err(|_| {
+                validate::invalid("Source address must be an IP address such as 192.0.2.10 or 2001:db8::10", value)
+            })
+        }),
+        tos: parse_tos(&settings, &diagnostics),
+        proxy: diagnostics.parse(settings.value_of("proxy"), |url| Proxy::parse(url, preference).map_err(|err| {
+            format!("{} {}.", validate::invalid("Proxy must be a URL such as socks5://localhost:1080 or http://proxy:3128", url), err)
+        })),
+        ttl: diagnostics.parse(settings.value_of("ttl"), |value| match value.parse() {
+            Ok(ttl) if (1..=255).contains(&ttl) => Ok(ttl),
+            _ => Err(validate::invalid("TTL must be a number of hops from 1 to 255", value)),
+        }),
+    };
 
-/// Clears to the end of the current line.
-#[inline]
-fn term_clear_line<W: Write>(output: &mut W) -> Result<(), std::io::Error> {
-    writeln!(output, "\x1b[K")?;
-    Ok(())
-}
+    let reverse = settings.is_present("reverse");
+    let duration = diagnostics.parse(settings.value_of("time"), |value| validate::timeout(value, "Time", "10s"))
+        .unwrap_or(Duration::from_secs(DEFAULT_TEST_DURATION_SECS));
 
-/// Moves the cursor up one line.
-#[inline]
-fn term_move_up<W: Write>(output: &mut W, lines: usize) -> Result<(), std::io::Error> {
-    write!(output, "\x1b[{}A", lines)?;
-    Ok(())
-}
+    let number_format = parse_number_format(&settings, &diagnostics);
+    let verify = pattern_setting(&settings, &diagnostics, "verify", "Verify pattern");
+    let stall_threshold = diagnostics.parse(settings.value_of("stall_threshold"), |value| validate::timeout(value, "Stall threshold", "100ms"));
+    let compression = parse_compression(&settings, &diagnostics);
+    let compress = parse_compress(&settings, &diagnostics, &mode, passthrough);
 
-fn byte_to_mem_units(bytes: f64) -> (f64, &'static str) {
-    const KB: f64 = 1024.0;
-    const MB: f64 = KB * 1024.0;
-    const GB: f64 = MB * 1024.0;
-    const TB: f64 = GB * 1024.0;
+    // The arguments of the other modes, which are only given in their own.
+    let baud = diagnostics.parse(settings.value_of("baud"), |value| validate::number(value, "Baud rate", 1)).unwrap_or(DEFAULT_BAUD_RATE);
+    let vsock_cid = diagnostics.parse(settings.value_of("vsock_cid"), |value| validate::number::<u32>(value, "vsock CID", 0));
+    let vsock_port = diagnostics.parse(settings.value_of("vsock_port"), |value| validate::number::<u32>(value, "vsock port", 0));
+    let recv_threads = diagnostics.parse(settings.value_of("recv_threads"), |value| validate::number(value, "Receive threads", 1));
+    let batch = diagnostics.parse(settings.value_of("batch"), |value| validate::number(value, "Batch", 1));
+    let speed = diagnostics.parse(settings.value_of("speed"), parse_speed).unwrap_or(Some(1.0));
+    let disk_bytes = diagnostics.parse(settings.value_of("bytes"), |value| validate::size(value, "Bytes", "10G", 1));
+    let fsync = diagnostics.parse(settings.value_of("fsync"), |value| {
+        FsyncMode::parse(value).ok_or_else(|| validate::invalid("Fsync must be every, end or none", value))
+    }).unwrap_or(FsyncMode::End);
+    let pattern = parse_pattern(&settings, &diagnostics);
+    let feed = pattern_setting(&settings, &diagnostics, "feed", "Feed");
+    let label = diagnostics.parse(settings.value_of("label"), |label| {
+        if label.trim().is_empty() || label.contains('\n') || label.len() > net::MAX_LABEL_LENGTH {
+            Err(validate::invalid(&format!("Label must be a single line of at most {} bytes", net::MAX_LABEL_LENGTH), label))
+        } else {
+            Ok(label.to_string())
+        }
+    });
+    let fds = match mode {
+        Mode::Fds(ref values) => parse_fds(values, &diagnostics),
+        _ => Vec::new(),
+    };
+    let gso = match mode {
+        Mode::Send(_) if settings.is_present("udp") => {
+            let buffer_sizes = sweep.as_ref().map_or_else(|| vec![buffer_size], |sweep| sweep.values.clone());
+            if buffer_sizes.iter().any(|&size| size > MAX_UDP_PAYLOAD) {
+                diagnostics.error(format!("UDP datagrams can be at most {} bytes, so the buffer size must be no larger.", MAX_UDP_PAYLOAD));
+            }
+            parse_gso(&settings, &diagnostics, &buffer_sizes)
+        },
+        _ => None,
+    };
+    match mode {
+        Mode::VsockListen | Mode::VsockConnect if vsock_port.is_none() && settings.value_of("vsock_port").is_none() => {
+            diagnostics.error("A vsock port must be given with --vsock-port.");
+        },
+        Mode::DiskWrite(_) if settings.value_of("bytes").is_none() => {
+            diagnostics.error("The number of bytes to write must be given, such as --bytes 10G.");
+        },
+        Mode::Listen if recv_threads.is_some_and(|threads| threads > 1) && !cfg!(unix) => {
+            diagnostics.error("Several receive threads are only available on Unix.");
+        },
+        Mode::Listen if batch.is_some_and(|batch| batch > 1) && !cfg!(target_os = "linux") => {
+            diagnostics.error("Batching is only available on Linux.");
+        },
+        _ => {},
+    }
+    if settings.value_of("control").is_some() && !cfg!(unix) {
+        diagnostics.error("The control socket is only available on Unix.");
+    }
+    let forward = diagnostics.parse(settings.value_of("forward"), validate::host_port);
+    let resource = match mode {
+        Mode::Download(url) => diagnostics.parse(Some(url), |url| {
+            Resource::parse(url).ok_or_else(|| validate::invalid("URL must be an http:// URL such as http://example.com/file.iso", url))
+        }),
+        _ => None,
+    };
+    let segments = diagnostics.parse(settings.value_of("segments"), |value| validate::number(value, "Segments", 1u64));
+    check_port(&mode, &settings, &diagnostics);
+    check_arguments(&diagnostics);
 
-    if bytes >= TB { (bytes / TB, "TB") }
-    else if bytes >= GB { (bytes / GB, "GB") }
-    else if bytes >= MB { (bytes / MB, "MB") }
-    else if bytes >= KB { (bytes / KB, "KB") }
-    else { (bytes, "Bytes") }
-}
+    if let Some(ref spec) = chaos {
+        print_err!("Injecting faults into the passed through data with seed {}.", spec.seed);
+    }
 
-fn bytes_per_second(bytes_read: usize, duration: Duration) -> f64 {
-    let duration_seconds = 
-        duration.as_secs() as f64 + 
-        duration.subsec_nanos() as f64 / 1000000000.0;
-    return bytes_read as f64 / duration_seconds;
-}
\ No newline at end of file
+    // Detaching has to happen before the pusher starts its threads.
+    #[cfg(unix)]
+    {
+        if daemonize {
+            if let Err(err) = daemon::detach(settings.value_of("log_file"), pidfile) {
+                print_err!("There was an error starting the daemon.");
+                print_err!("ERROR: {}", err);
+                exit_err();
+            }
+        } else if let Some(path) = pidfile {
+            if let Err(err) = daemon::write_pidfile(path) {
+                print_err!("Error while writing PID file {}: {}", path, err);
+                exit_err();
+            }
+        }
+        if let Some(path) = pidfile {
+            daemon::remove_pidfile_at_exit(path);
+        }
+        if daemonize || pidfile.is_some() {
+            daemon::stop_on_terminate();
+        }
+    }
+    let pusher = if influx.is_some() || collector.is_some() || system_log || settings.is_present("otlp") {
+        #[cfg_attr(not(any(unix, feature = "otlp")), allow(unused_mut))]
+        let mut pusher = Pusher::new(influx, collector);
+        #[cfg(feature = "otlp")]
+        {
+            if let Some(target) = otlp {
+                pusher.set_otlp(otlp::OtlpExporter::new(target, settings.is_present("otlp_spans")));
+            }
+        }
+        #[cfg(unix)]
+        {
+            if system_log {
+                let kind = if journald { syslog::LogKind::Journald } else { syslog::LogKind::Syslog };
+                match syslog::SystemLog::open(kind) {
+                    Ok(log) => pusher.set_system_log(log),
+                    Err(err) => {
+                        print_err!("There was an error opening the system log.");
+                        print_err!("ERROR: {}", err);
+                        exit_err();
+                    }
+                }
+            }
+        }
+        Some(Arc::new(pusher))
+    } else {
+        None
+    };
+
+    if let Some(&cpu) = cpus.first() {
+        if let Err(err) = affinity::pin_current_thread(cpu) {
+            print_err!("Could not pin to CPU {}: {}", cpu, err);
+            exit_err();
+        }
+    }
+
+    let metrics = if metrics_addrs.is_empty() {
+        None
+    } else {
+        let metrics = Arc::new(Metrics::new(latency_buckets, size_buckets));
+        for addr in metrics_addrs {
+            match metrics::serve(addr, metrics.clone()) {
+                Ok(local_addr) => println!("Serving metrics at http://{}/metrics", local_addr),
+                Err(err) => {
+                    print_err!("There was an error serving metrics at {}", addr);
+                    print_err!("ERROR: {}", err);
+                    Failure::Bind.exit();
+                }
+            }
+        }
+        Some(metrics)
+    };
+
+    let mut outputs: Vec<Arc<dyn Reporter>> = Vec::new();
+    for spec in &output_specs {
+        let output: std::io::Result<Arc<dyn Reporter>> = match *spec {
+            OutputSpec::JsonLines(ref path) => JsonLinesReporter::create(path).map(|output| Arc::new(output) as Arc<dyn Reporter>),
+            OutputSpec::Csv(ref path) => CsvReporter::create(path).map(|output| Arc::new(output) as Arc<dyn Reporter>),
+            OutputSpec::Statsd(addr) => StatsdReporter::new(addr).map(|output| Arc::new(output) as Arc<dyn Reporter>),
+            // The terminal is set up for each transfer, and the metrics
+            // endpoint above.
+            OutputSpec::Terminal | OutputSpec::Prometheus(_) => continue,
+        };
+        match output {
+            Ok(output) => outputs.push(output),
+            Err(err) => {
+                print_err!("There was an error setting up the output {}", spec);
+                print_err!("ERROR: {}", err);
+                exit_err();
+            }
+        }
+    }
+    if let Some(ref metrics) = metrics {
+        outputs.push(metrics.clone());
+    }
+    if let Some(ref pusher) = pusher {
+        outputs.push(pusher.clone());
+    }
+
+    let mut options = MeasureOptions {
+        buffer_size,
+        auto_buffer_size,
+        iterations,
+        vecs,
+        parallel,
+        passthrough,
+        flush,
+        discard,
+        zero_copy: !settings.is_present("no_splice"),
+        spliced: false,
+        pass_to: None,
+        io_uring: settings.is_present("io_uring"),
+        direct: settings.is_present("direct"),
+        mmap: settings.is_present("mmap"),
+        cpus,
+        idle_timeout,
+        reads_time_out: socket_options.read_timeout.is_some(),
+        max_errors,
+        warmup,
+        interval: measure_interval,
+        refresh,
+        align,
+        measure_every_bytes,
+        display_mode,
+        style: if settings.is_present("no_ansi") {
+            Some(Style::Plain)
+        } else if settings.is_present("force_ansi") {
+            Some(Style::Ansi)
+        } else {
+            None
+        },
+        color: settings.value_of("color").and_then(ColorChoice::parse).unwrap_or(ColorChoice::Auto),
+        color_thresholds,
+        compact: settings.is_present("compact"),
+        graph: settings.is_present("graph"),
+        max_samples,
+        number_format,
+        template,
+        expected_bytes,
+        rate_mode,
+        read_stats: settings.is_present("read_stats"),
+        tee_path: settings.value_of("tee").map(String::from),
+        record_path: settings.value_of("record").map(String::from),
+        chunk_log_path: settings.value_of("chunk_log").map(String::from),
+        hash: settings.value_of("hash").and_then(HashAlgorithm::parse),
+        verify,
+        delimiter,
+        framing: settings.value_of("frame").and_then(Framing::parse),
+        peek,
+        skip,
+        take,
+        stall_threshold,
+        chaos,
+        shape,
+        compression,
+        compress,
+        wire_bytes: None,
+        baud: None,
+        sctp: None,
+        udp: None,
+        tcp_info: None,
+        sources: None,
+        directions: None,
+        command: None,
+        reconnects: None,
+        metrics: metrics.clone(),
+        outputs,
+        state: settings.value_of("state").map(|path| match StateFile::load(path) {
+            Ok(state) => {
+                if state.resumed.runs > 0 {
+                    let (mem, unit) = byte_to_mem_units(state.resumed.bytes as f64);
+                    println!("Resuming totals from {}: {:.3} {} in {} runs since {}", path, mem, unit, state.resumed.runs,
+                        display::format_timestamp(state.since));
+                } else {
+                    println!("Keeping totals in {}", path);
+                }
+                Arc::new(state)
+            },
+            Err(err) => {
+                print_err!("There was an error loading the state file {}", path);
+                print_err!("ERROR: {}", err);
+                exit_err();
+            }
+        }),
+        controls: None,
+        clock: Arc::new(SystemClock::new()),
+    };
+
+    if let Some(size) = pipe_size {
+        resize_pipes(size, &options);
+    }
+    options.controls = start_controls(&mode, &settings, &options);
+
+    let mut measure: Box<dyn FnMut(&MeasureOptions) -> TransferInfo> = match mode {
+        Mode::Stdin => {
+            if runs > 1 && stdin_file().is_none() {
+                print_err!("Repeated runs can only reread stdin when it is redirected from a file.");
+                exit_err();
+            }
+            let mut run = 0;
+            Box::new(move |options| {
+                if run > 0 {
+                    if let Err(err) = rewind_stdin() {
+                        print_err!("Error while rewinding stdin: {}", err);
+                        exit_err();
+                    }
+                }
+                run += 1;
+                measure_stdin(options)
+            })
+        },
+
+        Mode::File(ref paths) if settings.is_present("follow") => {
+            if paths.len() > 1 || paths[0] == concat::STDIN_PATH {
+                print_err!("Follow can only be used with a single file.");
+                exit_err();
+            }
+            let path = paths[0].to_string();
+            Box::new(move |options| measure_followed_file(&path, options))
+        },
+
+        Mode::File(ref paths) if paths.len() == 1 && paths[0] != concat::STDIN_PATH => {
+            let path = paths[0].to_string();
+            Box::new(move |options| measure_file(&path, options))
+        },
+
+        Mode::File(paths) => {
+            if options.mmap || options.direct {
+                print_err!("Memory mapped and direct reads can only be used with a single file.");
+                exit_err();
+            }
+            if runs > 1 && paths.contains(&concat::STDIN_PATH) {
+                print_err!("Repeated runs cannot reread stdin when it is one of several sources.");
+                exit_err();
+            }
+            let paths: Vec<String> = paths.into_iter().map(String::from).collect();
+            Box::new(move |options| measure_concatenated(&paths, options))
+        },
+
+        Mode::Download(_) => {
+            let resource = resource.expect("the URL should have been checked");
+            match segments {
+                Some(segments) if segments > 1 => Box::new(move |options| download_segments(&resource, segments, options)),
+                _ => Box::new(move |options| download(&resource, options)),
+            }
+        },
+
+        Mode::Exec(args) => {
+            let args: Vec<String> = args.into_iter().map(String::from).collect();
+            Box::new(move |options| measure_command(&args, feed.clone(), options))
+        },
+
+        Mode::Fds(_) => {
+            if runs > 1 {
+                print_err!("Repeated runs cannot reread passed descriptors.");
+                exit_err();
+            }
+            let mut readers = match fds::open(&fds) {
+                Ok(opened) => Some(opened),
+                Err(err) => {
+                    print_err!("Error while opening {}", err);
+                    exit_err();
+                }
+            };
+            Box::new(move |options| {
+                let (readers, stats) = readers.take().expect("passed descriptors should only be read once");
+                measure_fds(readers, stats, options)
+            })
+        },
+
+        Mode::Fifo(path) => measure_fifo(path, &options),
+
+        Mode::Serial(path) => {
+            let path = path.to_string();
+            options.baud = Some(baud);
+            Box::new(move |options| match serial::open(&path, baud) {
+                Ok(port) => measure_fd(port, options),
+                Err(err) => {
+                    print_err!("Error while opening the serial port {}: {}", path, err);
+                    exit_err();
+                }
+            })
+        },
+
+        Mode::Client(host) => {
+            let addrs = resolve_or_exit(host, parse_port(&settings), preference);
+            let mut params = TestParams {
+                duration,
+                parallel: options.parallel,
+                direction: if settings.is_present("bidir") {
+                    Direction::Bidirectional
+                } else if reverse {
+                    Direction::Download
+                } else {
+                    Direction::Upload
+                },
+                buffer_size: options.buffer_size,
+            };
+            Box::new(move |options| {
+                params.buffer_size = options.buffer_size;
+                params.parallel = options.parallel;
+                run_control(control::run_client(&addrs, &params, &socket_options, options))
+            })
+        },
+
+        Mode::VsockListen | Mode::VsockConnect if !cfg!(target_os = "linux") => {
+            print_err!("vsock is only available on Linux.");
+            exit_err();
+        },
+
+        Mode::VsockListen => {
+            let port = vsock_port.expect("the vsock port should have been checked");
+            measure_vsock_listener(vsock_cid, port)
+        },
+
+        Mode::VsockConnect => {
+            let cid = vsock_cid.expect("Expected vsock CID arg to have value.");
+            let port = vsock_port.expect("the vsock port should have been checked");
+            Box::new(move |options| measure_vsock_client(cid, port, options))
+        },
+
+        Mode::Connect(host) if passthrough && !settings.is_present("sctp") && !stdin().is_terminal() => {
+            if settings.is_present("reconnect") {
+                print_err!("Reconnect cannot be used when forwarding stdin to a host.");
+                exit_err();
+            }
+            if runs > 1 && stdin_file().is_none() {
+                print_err!("Repeated runs can only reread stdin when it is redirected from a file.");
+                exit_err();
+            }
+            let addrs = resolve_or_exit(host, parse_port(&settings), preference);
+            let mut run = 0;
+            Box::new(move |options| {
+                if run > 0 {
+                    if let Err(err) = rewind_stdin() {
+                        print_err!("Error while rewinding stdin: {}", err);
+                        exit_err();
+                    }
+                }
+                run += 1;
+                forward_stdin_to_host(&addrs, &socket_options, options)
+            })
+        },
+
+        Mode::Connect(host) => {
+            let addrs = resolve_or_exit(host, parse_port(&settings), preference);
+            if settings.is_present("sctp") {
+                check_sctp_support();
+                Box::new(move |options| measure_sctp_client(&addrs, &socket_options, options))
+            } else {
+                let reconnect = settings.is_present("reconnect");
+                Box::new(move |options| measure_tcp_client(&addrs, &socket_options, options, reconnect))
+            }
+        },
+
+        Mode::Send(host) => {
+            let addrs = resolve_or_exit(host, parse_port(&settings), preference);
+            let payload = Payload { pattern: pattern.clone(), prefill: settings.is_present("prefill") };
+            if settings.is_present("udp") {
+                Box::new(move |options| send_udp_client(&addrs, &socket_options, options, duration, payload.clone(), gso))
+            } else {
+                Box::new(move |options| send_tcp_client(&addrs, &socket_options, options, duration, payload.clone(), label.as_deref()))
+            }
+        },
+
+        Mode::Analyze(_) | Mode::PingPong => unreachable!("analyzing and ping-pong return before anything is measured"),
+        Mode::Completions(_) | Mode::ManPage => unreachable!("completions and the man page are printed before anything is measured"),
+
+        Mode::SelfTest => {
+            let duration = if settings.is_present("time") { duration } else { Duration::from_secs(DEFAULT_SELFTEST_DURATION_SECS) };
+            selftest(&options, duration);
+            return;
+        },
+
+        Mode::Replay(path) => {
+            let path = path.to_string();
+            match settings.value_of("to") {
+                Some(host) => {
+                    let addrs = resolve_or_exit(host, parse_port(&settings), preference);
+                    Box::new(move |options| replay_to_host(&path, speed, &addrs, &socket_options, options))
+                },
+                None => {
+                    options.passthrough = true;
+                    Box::new(move |options| measure_reader(open_replay(&path, speed), options))
+                },
+            }
+        },
+
+        Mode::Aggregate(addr) => {
+            let addr = match std::net::ToSocketAddrs::to_socket_addrs(addr).ok().and_then(|mut addrs| addrs.next()) {
+                Some(addr) => addr,
+                None => {
+                    print_err!("Listen address must be an address and port such as 0.0.0.0:9200.");
+                    exit_err();
+                }
+            };
+            Box::new(move |options| run_control(aggregate::run(addr, options)))
+        },
+
+        Mode::DiskWrite(path) => {
+            let path = path.to_string();
+            let bytes = disk_bytes.expect("the bytes to write should have been checked");
+            Box::new(move |options| measure_disk_write(&path, bytes, fsync, pattern.clone(), options))
+        },
+
+        Mode::Listen if settings.is_present("sctp") => {
+            check_sctp_support();
+            let ports = parse_port_range(&settings);
+            let addrs: Vec<SocketAddr> = listen_addrs(&settings, ports.first, preference).into_iter().flatten().collect();
+            measure_sctp_listener(&addrs, ports, &socket_options)
+        },
+
+        Mode::Listen if settings.is_present("udp") => {
+            let recv_threads = recv_threads.unwrap_or(1);
+            let batch = batch.unwrap_or(if cfg!(target_os = "linux") { udp::DEFAULT_BATCH_SIZE } else { 1 });
+            let ports = parse_port_range(&settings);
+            let addrs: Vec<SocketAddr> = listen_addrs(&settings, ports.first, preference).into_iter().flatten().collect();
+            measure_udp_listener(&addrs, ports, recv_threads, batch, &socket_options)
+        },
+
+        Mode::Listen if settings.values_of("port").len() > 1 => {
+            let mut acceptors: Vec<Acceptor> = parse_port_ranges(&settings).into_iter().map(|ports| {
+                let addr_groups = listen_addrs(&settings, ports.first, preference);
+                bind_listeners(&addr_groups, ports, &socket_options)
+            }).collect();
+            Box::new(move |options| measure_tcp_ports(&mut acceptors, &socket_options, options))
+        },
+
+        Mode::Listen if forward.is_some() => {
+            let (host, port) = forward.expect("the forward address was just checked");
+            let target = resolve_or_exit(&host, port, preference);
+            let mut acceptor = activated_acceptor(&socket_options).unwrap_or_else(|| {
+                let ports = parse_port_range(&settings);
+                let addr_groups = listen_addrs(&settings, ports.first, preference);
+                bind_listeners(&addr_groups, ports, &socket_options)
+            });
+            Box::new(move |options| forward_connection(&mut acceptor, &target, &socket_options, options))
+        },
+
+        Mode::Listen => {
+            let mut acceptor = activated_acceptor(&socket_options).unwrap_or_else(|| {
+                let ports = parse_port_range(&settings);
+                let addr_groups = listen_addrs(&settings, ports.first, preference);
+                bind_listeners(&addr_groups, ports, &socket_options)
+            });
+            if settings.is_present("async") {
+                if !cfg!(feature = "tokio") {
+                    print_err!("Async listening is only available when built with the tokio feature.");
+                    exit_err();
+                }
+                Box::new(move |options| measure_listeners_async(&acceptor, &socket_options, options))
+            } else if settings.is_present("server") {
+                Box::new(move |options| run_control(control::run_server(&mut acceptor, &socket_options, options)))
+            } else if reverse {
+                Box::new(move |options| send_tcp_stream(&mut acceptor, &socket_options, options, duration))
+            } else {
+                Box::new(move |options| measure_tcp_stream(&mut acceptor, &socket_options, options))
+            }
+        },
+    };
+
+    let mut results = Vec::with_capacity(runs);
+    for run in 0..runs {
+        if run > 0 && daemon::stop_requested() { break; }
+        let swept;
+        let run_options = match sweep {
+            Some(ref sweep) => {
+                swept = sweep.options_for(run, &options);
+                &swept
+            },
+            None => &options,
+        };
+        if runs > 1 {
+            if run > 0 { println!(); }
+            match sweep {
+                Some(ref sweep) => println!("Run {} of {} with {}", run + 1, runs, sweep.describe(run)),
+                None => println!("Run {} of {}", run + 1, runs),
+            }
+        }
+        if let Some(ref pusher) = pusher { pusher.start_run(run + 1); }
+        let result = measure(run_options);
+        for output in &run_options.outputs {
+            if let Err(err) = output.finish(&result) {
+                print_err!("Error while writing output: {}", err);
+                exit_err();
+            }
+        }
+        if let Some(ref mut manifest) = manifest {
+            if let Err(err) = manifest.record(&result) {
+                print_err!("Error while writing manifest to {}: {}", manifest.path(), err);
+                exit_err();
+            }
+        }
+        results.push(result);
+    }
+
+    if let Some(ref sweep) = sweep {
+        sweep.print(&results);
+    }
+    let run_stats = if runs > 1 && sweep.is_none() {
+        let run_stats = RunStats::new(&results);
+        run_stats.print(&results);
+        Some(run_stats)
+    } else {
+        None
+    };
+
+    let comparison = baseline.as_ref().map(|baseline| {
+        let comparison = Comparison::new(baseline, &results, run_stats.as_ref(), max_regression);
+        comparison.print();
+        comparison
+    });
+
+    if let Some(ref pusher) = pusher {
+        let mut summary = report::transfer_summary(results.last().expect("there should be at least one run"));
+        if let Some(ref run_stats) = run_stats {
+            summary["runs"] = run_stats.to_json(&results);
+        }
+        if let Some(ref sweep) = sweep {
+            summary["sweep"] = sweep.to_json(&results);
+        }
+        pusher.push_summary(&summary);
+        pusher.finish();
+    }
+
+    let mut thresholds_met = true;
+    for transfer_info in &results {
+        thresholds_met &= check_thresholds(transfer_info, min_throughput, min_total);
+    }
+    let verified = results.iter().all(|transfer_info| {
+        transfer_info.verifier.as_ref().is_none_or(Verifier::is_ok)
+    });
+
+    if let Some(ref target) = report_target {
+        // With several runs the top level describes the last one.
+        let last = results.last().expect("there should be at least one run");
+        let mut summary = report::summary(last, thresholds_met);
+        if let Some(ref run_stats) = run_stats {
+            summary["runs"] = run_stats.to_json(&results);
+        }
+        if let Some(ref sweep) = sweep {
+            summary["sweep"] = sweep.to_json(&results);
+        }
+        if let Some(ref comparison) = comparison {
+            summary["baseline"] = comparison.to_json();
+        }
+        if let Err(err) = report::write_report(target, &summary) {
+            print_err!("Error while writing report: {}", err);
+            exit_err();
+        }
+    }
+
+    if results.iter().any(|transfer_info| transfer_info.timed_out) {
+        Failure::ReadTimeout.exit();
+    }
+
+    if results.iter().any(|transfer_info| transfer_info.read_errors.fatal > 0) {
+        Failure::Read.exit();
+    }
+
+    if !thresholds_met {
+        Failure::BelowThreshold.exit();
+    }
+
+    if !verified {
+        Failure::VerifyFailed.exit();
+    }
+
+    if let Some(ref comparison) = comparison {
+        if comparison.regressed() {
+            comparison.print_regression();
+            Failure::Regression.exit();
+        }
+    }
+    if results.iter().any(|transfer_info| transfer_info.command.as_ref().is_some_and(|command| !command.succeeded())) {
+        Failure::CommandFailed.exit();
+    }
+}
+
+/// Prints the statistics of a chunk log, a recording or interval data.
+fn analyze(path: &str, settings: &Settings) {
+    let diagnostics = Diagnostics::default();
+    let burst_gap = diagnostics.parse(settings.value_of("burst_gap"), |value| validate::timeout(value, "Burst gap", "10ms"))
+        .unwrap_or(Duration::from_millis(DEFAULT_BURST_GAP_MILLIS));
+    let interval = diagnostics.parse(settings.value_of("interval"), |value| validate::timeout(value, "Interval", "100ms"))
+        .unwrap_or(Duration::from_millis(DEFAULT_ANALYSIS_INTERVAL_MILLIS));
+    check_arguments(&diagnostics);
+
+    if let Err(err) = analyze::print_analysis(&mut stdout().lock(), path, burst_gap, interval) {
+        print_err!("Error while analyzing {}: {}", path, err);
+        exit_err();
+    }
+}
+
+/// Bounces messages off the host given with --connect and prints the round
+/// trips, or echoes them back on the address given with --listen.
+fn ping_pong(settings: &Settings) {
+    let diagnostics = Diagnostics::default();
+    let size = diagnostics.parse(settings.value_of("size"), |value| validate::size(value, "Size", "64 or 4K", 1))
+        .map_or(DEFAULT_PING_PONG_SIZE, |size| size as usize);
+    let duration = diagnostics.parse(settings.value_of("time"), |value| validate::timeout(value, "Time", "10s"))
+        .unwrap_or(Duration::from_secs(DEFAULT_TEST_DURATION_SECS));
+    check_arguments(&diagnostics);
+
+    if let Some(addr) = settings.value_of("listen") {
+        let listener = match TcpListener::bind(addr) {
+            Ok(listener) => listener,
+            Err(err) => {
+                print_err!("Error while listening on {}: {}", addr, err);
+                Failure::Bind.exit();
+            }
+        };
+        println!("Echoing messages sent to {}", listener.local_addr().map_or_else(|_| addr.to_string(), |addr| addr.to_string()));
+        pingpong::echo(listener);
+        return;
+    }
+
+    let host = settings.value_of("connect").expect("Expected connect or listen arg to have value.");
+    let mut stream = match TcpStream::connect(host) {
+        Ok(stream) => stream,
+        Err(err) => {
+            print_err!("There was an error connecting to {}", host);
+            print_err!("ERROR: {}", err);
+            Failure::from_io(&err, Failure::Connect).exit();
+        }
+    };
+    println!("Bouncing messages of {} bytes off {} for {:.1} seconds", size, host, duration.as_secs_f64());
+    let result = pingpong::PingPong::run(&mut stream, size, duration)
+        .and_then(|ping_pong| ping_pong.print(&mut stdout().lock()));
+    if let Err(err) = result {
+        print_err!("Error while bouncing messages off {}: {}", host, err);
+        Failure::Read.exit();
+    }
+}
+
+/// Reports and returns false if the transfer did not meet the minimums.
+fn check_thresholds(transfer_info: &TransferInfo, min_throughput: Option<f64>, min_total: Option<u64>) -> bool {
+    let mut failed = false;
+
+    if let Some(min_bps) = min_throughput {
+        let avg_bps = transfer_info.average_bps();
+        if avg_bps < min_bps {
+            let (mem_avg, unit_avg) = byte_to_mem_units(avg_bps);
+            let (mem_min, unit_min) = byte_to_mem_units(min_bps);
+            print_err!("Average transfer speed {:.3} {}/sec is below the minimum of {:.3} {}/sec.",
+                mem_avg, unit_avg, mem_min, unit_min);
+            failed = true;
+        }
+    }
+
+    if let Some(min_bytes) = min_total {
+        if (transfer_info.total_bytes_transferred as u64) < min_bytes {
+            let (mem_total, unit_total) = byte_to_mem_units(transfer_info.total_bytes_transferred as f64);
+            let (mem_min, unit_min) = byte_to_mem_units(min_bytes as f64);
+            print_err!("Data transferred {:.3} {} is below the minimum of {:.3} {}.",
+                mem_total, unit_total, mem_min, unit_min);
+            failed = true;
+        }
+    }
+
+    !failed
+}
+
+/// Prints every problem found with the arguments and exits if there were
+/// any.
+fn check_arguments(diagnostics: &Diagnostics) {
+    let problems = diagnostics.take();
+    if problems.is_empty() {
+        return;
+    }
+    for problem in &problems {
+        print_err!("{}", problem);
+    }
+    if problems.len() > 1 {
+        print_err!("Found {} problems with the arguments.", problems.len());
+    }
+    exit_err();
+}
+
+/// Checks that the modes that need a port were given a valid one: a single
+/// port to connect or send to, or a port or range of ports to listen on.
+fn check_port(mode: &Mode, settings: &Settings, diagnostics: &Diagnostics) {
+    let single = match *mode {
+        Mode::Client(_) | Mode::Connect(_) | Mode::Send(_) => true,
+        Mode::Replay(_) if settings.value_of("to").is_some() => true,
+        Mode::Listen => false,
+        _ => return,
+    };
+    let ports = settings.values_of("port");
+    if ports.len() > 1 {
+        if single {
+            diagnostics.error("Only one port can be connected to.");
+        } else if let Some(flag) = ["udp", "sctp", "async", "server", "reverse", "forward"].iter().find(|&&flag| settings.is_present(flag)) {
+            diagnostics.error(format!("Listening on several ports cannot be combined with --{}.", flag));
+        }
+        for value in ports {
+            diagnostics.parse(Some(value), validate::port_range);
+        }
+        return;
+    }
+    match (settings.value_of("port"), settings.value_of("address")) {
+        (Some(value), _) if single => { diagnostics.parse(Some(value), validate::port); },
+        (Some(value), _) => { diagnostics.parse(Some(value), validate::port_range); },
+        (None, Some(address)) => diagnostics.error(format!("A port must be given alongside the address {}, such as --port 5000.", address)),
+        (None, None) if single => diagnostics.error("A port to connect to must be given, such as --port 5000."),
+        (None, None) => diagnostics.error("A port to listen on must be given, such as --port 5000 or --port 0 for any free port."),
+    }
+}
+
+/// Parses the replay speed, which is a multiplier or `max` for no delays.
+fn parse_speed(value: &str) -> Result<Option<f64>, String> {
+    match value {
+        "max" => Ok(None),
+        _ => match value.parse::<f64>() {
+            Ok(speed) if speed > 0.0 && speed.is_finite() => Ok(Some(speed)),
+            _ => Err(validate::invalid("Speed must be a multiplier greater than 0 such as 2 or 0.5, or max", value)),
+        },
+    }
+}
+
+/// Parses the descriptors to read, each of which can only be given once.
+fn parse_fds(values: &[&str], diagnostics: &Diagnostics) -> Vec<i32> {
+    let mut fds: Vec<i32> = Vec::with_capacity(values.len());
+    for value in values {
+        match diagnostics.parse(Some(value), |value| validate::number(value, "Descriptor", 0)) {
+            Some(fd) if fds.contains(&fd) => diagnostics.error(format!("Descriptor {} was given more than once.", fd)),
+            Some(fd) => fds.push(fd),
+            None => {},
+        }
+    }
+    fds
+}
+
+/// Parses the upper bounds of histogram buckets, which must be above zero
+/// and in increasing order.
+fn parse_buckets<F: Fn(&str) -> Option<f64>>(settings: &Settings, diagnostics: &Diagnostics, name: &str, parse: F, expected: &str) -> Option<Vec<f64>> {
+    diagnostics.parse(settings.value_of(name), |value| match value.split(',').map(parse).collect::<Option<Vec<f64>>>() {
+        Some(bounds) if bounds[0] > 0.0 && bounds.windows(2).all(|pair| pair[0] < pair[1]) => Ok(bounds),
+        _ => Err(validate::invalid(expected, value)),
+    })
+}
+
+/// Parses how numbers are written, where `--thousands locale` also takes the
+/// decimal point from the locale.
+fn parse_number_format(settings: &Settings, diagnostics: &Diagnostics) -> NumberFormat {
+    let mut number_format = NumberFormat::default();
+    if let Some(digits) = diagnostics.parse(settings.value_of("digits"), |value| validate::number(value, "Digits", 0)) {
+        number_format.digits = digits;
+    }
+    number_format.pad = diagnostics.parse(settings.value_of("pad"), |value| validate::number(value, "Pad", 0));
+    match settings.value_of("thousands") {
+        Some("locale") => {
+            let (thousands, decimal) = NumberFormat::locale_separators();
+            number_format.thousands = Some(thousands);
+            number_format.decimal = decimal;
+        },
+        Some(value) => {
+            let mut chars = value.chars();
+            match (chars.next(), chars.next()) {
+                (Some(separator), None) => number_format.thousands = Some(separator),
+                _ => diagnostics.error(validate::invalid("The thousands separator must be a single character or locale", value)),
+            }
+        },
+        None => {},
+    }
+    number_format
+}
+
+/// Parses the format of compressed data, checking that this build supports it.
+fn parse_compression(settings: &Settings, diagnostics: &Diagnostics) -> Option<Codec> {
+    let codec = if settings.is_present("gzip") {
+        Codec::Gzip
+    } else if settings.is_present("zstd") {
+        Codec::Zstd
+    } else {
+        return None;
+    };
+    if !codec.is_available() {
+        diagnostics.error(format!("{} is only available when built with the {} feature.", codec.name(), codec.name()));
+    }
+    Some(codec)
+}
+
+/// Parses the format and level to compress sent data with, which is only
+/// done by send and when forwarding stdin to a host.
+fn parse_compress(settings: &Settings, diagnostics: &Diagnostics, mode: &Mode, passthrough: bool) -> Option<Compression> {
+    let compress = diagnostics.parse(settings.value_of("compress"), |value| {
+        Compression::parse(value).map_err(|err| format!("Invalid compression: {}.", err))
+    })?;
+    match *mode {
+        Mode::Send(_) => {},
+        Mode::Connect(_) if passthrough => {},
+        _ => diagnostics.error("Compress can only be used with send or when forwarding stdin to a host with --pass."),
+    }
+    if !compress.codec.is_available() {
+        diagnostics.error(format!("{} is only available when built with the {} feature.", compress.codec.name(), compress.codec.name()));
+    }
+    Some(compress)
+}
+
+/// Parses the size of the bursts to send and how often they start, which
+/// are only given together.
+fn parse_bursts(settings: &Settings, diagnostics: &Diagnostics) -> Option<Bursts> {
+    let size = diagnostics.parse(settings.value_of("burst"), |value| validate::size(value, "Burst", "100MB", 1));
+    let interval = diagnostics.parse(settings.value_of("burst_interval"), |value| validate::timeout(value, "Burst interval", "5s"));
+    if settings.is_present("burst") != settings.is_present("burst_interval") {
+        diagnostics.error("Burst and burst interval must be given together.");
+    }
+    match (size, interval) {
+        (Some(size), Some(interval)) => Some(Bursts { size, interval }),
+        _ => None,
+    }
+}
+
+/// Parses the pattern of generated data, which defaults to a counter.
+fn parse_pattern(settings: &Settings, diagnostics: &Diagnostics) -> Pattern {
+    pattern_setting(settings, diagnostics, "pattern", "Pattern").unwrap_or(Pattern::Counter)
+}
+
+/// Parses a pattern option, reading the sample that `file:<path>` names.
+fn pattern_setting(settings: &Settings, diagnostics: &Diagnostics, name: &str, what: &str) -> Option<Pattern> {
+    diagnostics.parse(settings.value_of(name), |value| match Pattern::parse(value) {
+        Ok(Some(pattern)) => Ok(pattern),
+        Ok(None) => Err(validate::invalid(&format!("{} must be 'pattern', 'seed:<number>' or 'file:<path>'", what), value)),
+        Err(err) => Err(format!("Error while reading the sample {}: {}", value.trim_start_matches("file:"), err)),
+    })
+}
+
+/// Exits if a coordinated test failed.
+fn run_control(result: std::io::Result<TransferInfo>) -> TransferInfo {
+    match result {
+        Ok(transfer_info) => transfer_info,
+        Err(err) => {
+            print_err!("There was an error during the test.");
+            print_err!("ERROR: {}", err);
+            Failure::from_io(&err, Failure::General).exit();
+        }
+    }
+}
+
+/// The port that network modes connect or send to, which `check_port` has
+/// made sure is valid.
+fn parse_port(settings: &Settings) -> u16 {
+    settings.value_of("port").and_then(|value| validate::port(value).ok()).expect("the port should have been checked")
+}
+
+/// The port or range of ports to listen on, which `check_port` has made sure
+/// is valid.
+fn parse_port_range(settings: &Settings) -> PortRange {
+    settings.value_of("port").and_then(|value| validate::port_range(value).ok()).expect("the port should have been checked")
+}
+
+fn parse_port_ranges(settings: &Settings) -> Vec<PortRange> {
+    settings.values_of("port").into_iter()
+        .map(|value| validate::port_range(value).expect("the ports should have been checked"))
+        .collect()
+}
+
+fn resolve_or_exit(host: &str, port: u16, preference: AddrPreference) -> Vec<SocketAddr> {
+    match net::resolve(host, port, preference) {
+        Ok(addrs) => addrs,
+        Err(err) => {
+            print_err!("Could not resolve address {}", host);
+            print_err!("ERROR: {}", err);
+            Failure::Connect.exit();
+        }
+    }
+}
+
+/// Measures the next connection that arrives on any of the listeners.
+fn measure_tcp_stream(acceptor: &mut Acceptor, socket_options: &SocketOptions, options: &MeasureOptions) -> TransferInfo {
+    match acceptor.accept() {
+        Ok((stream, incoming_addr, _)) => {
+            println!("Reading incoming data from {}", incoming_addr);
+            configure_stream(&stream, socket_options);
+            println!();
+            let options = MeasureOptions { tcp_info: TcpInfoSampler::start(&stream), ..options.clone() };
+            measure_fd(stream, &options)
+        },
+
+        Err(err) => exit_accept_err(err, socket_options),
+    }
+}
+
+/// Measures the first connection to arrive on each of the ports together, as
+/// one transfer alongside how much came through every port.
+fn measure_tcp_ports(acceptors: &mut [Acceptor], socket_options: &SocketOptions, options: &MeasureOptions) -> TransferInfo {
+    let accepted: Vec<_> = std::thread::scope(|scope| {
+        let accepting: Vec<_> = acceptors.iter_mut().map(|acceptor| scope.spawn(move || acceptor.accept())).collect();
+        accepting.into_iter().map(|accepting| accepting.join().expect("accepting thread panicked")).collect()
+    });
+
+    let mut streams = Vec::with_capacity(accepted.len());
+    for result in accepted {
+        match result {
+            Ok((stream, incoming_addr, local_addr)) => {
+                println!("Reading incoming data from {} on port {}", incoming_addr, local_addr.port());
+                configure_stream(&stream, socket_options);
+                streams.push((stream, local_addr.port()));
+            },
+            Err(err) => exit_accept_err(err, socket_options),
+        }
+    }
+    println!();
+
+    let stats = Arc::new(Mutex::new(streams.iter()
+        .map(|&(_, port)| SourceStats { path: format!("port {}", port), bytes: 0, elapsed: Duration::default() })
+        .collect()));
+    let started = Instant::now();
+    let readers: Vec<_> = streams.into_iter().enumerate()
+        .map(|(index, (stream, _))| SourceReader::new(stream, index, started, stats.clone()))
+        .collect();
+    let options = MeasureOptions { sources: Some(stats), ..options.clone() };
+    measure_reader(MergedReader::new(readers, options.buffer_size, &options.cpus), &options)
+}
+
+/// Forwards the next connection on to the target and its replies back,
+/// measuring both directions together alongside the stats of each.
+fn forward_connection(acceptor: &mut Acceptor, target: &[SocketAddr], socket_options: &SocketOptions, options: &MeasureOptions) -> TransferInfo {
+    let client = match acceptor.accept() {
+        Ok((stream, incoming_addr, _)) => {
+            println!("Forwarding the connection from {}", incoming_addr);
+            configure_stream(&stream, socket_options);
+            stream
+        },
+        Err(err) => exit_accept_err(err, socket_options),
+    };
+    let (server, peer) = connect_or_exit(target, socket_options);
+    println!("Forwarding to {}", peer);
+    configure_stream(&server, socket_options);
+    println!();
+
+    let (readers, directions) = match relay::relay(client, server) {
+        Ok(relayed) => relayed,
+        Err(err) => {
+            print_err!("There was an error forwarding the connection.");
+            print_err!("ERROR: {}", err);
+            exit_err();
+        }
+    };
+    let options = MeasureOptions { directions: Some(directions), ..options.clone() };
+    measure_reader(MergedReader::new(readers, options.buffer_size, &options.cpus), &options)
+}
+
+/// Reports a failed accept, exiting with its own code if it timed out.
+fn exit_accept_err(err: std::io::Error, socket_options: &SocketOptions) -> ! {
+    if let (std::io::ErrorKind::TimedOut, Some(timeout)) = (err.kind(), socket_options.accept_timeout) {
+        print_err!("No connection arrived within {:.1} seconds.", timeout.as_secs_f64());
+        Failure::AcceptTimeout.exit();
+    }
+    print_err!("There was an error accepting a connection.");
+    print_err!("ERROR: {}", err);
+    exit_err();
+}
+
+/// Measures every connection that arrives on any of the listeners together,
+/// on a Tokio runtime, until none are left open.
+#[cfg(feature = "tokio")]
+fn measure_listeners_async(acceptor: &Acceptor, socket_options: &SocketOptions, options: &MeasureOptions) -> TransferInfo {
+    let result = acceptor.try_clone_listeners()
+        .and_then(|listeners| async_listen::measure_listeners(listeners, socket_options, options));
+    match result {
+        Ok(transfer_info) => transfer_info,
+        Err(err) => {
+            print_err!("There was an error measuring connections.");
+            print_err!("ERROR: {}", err);
+            exit_err();
+        }
+    }
+}
+
+#[cfg(not(feature = "tokio"))]
+fn measure_listeners_async(_acceptor: &Acceptor, _socket_options: &SocketOptions, _options: &MeasureOptions) -> TransferInfo {
+    unreachable!("async listening requires the tokio feature");
+}
+
+/// Sends data to the next connection that arrives on any of the listeners for
+/// the given duration.
+fn send_tcp_stream(acceptor: &mut Acceptor, socket_options: &SocketOptions, options: &MeasureOptions, duration: Duration) -> TransferInfo {
+    match acceptor.accept() {
+        Ok((stream, incoming_addr, _)) => {
+            configure_stream(&stream, socket_options);
+            send_stream(stream, incoming_addr, options, duration, Payload::generated(Pattern::Counter))
+        },
+
+        Err(err) => exit_accept_err(err, socket_options),
+    }
+}
+
+/// Connects to the first address that accepts and sends data to it for the
+/// given duration, after the label if there is one.
+fn send_tcp_client(addrs: &[SocketAddr], socket_options: &SocketOptions, options: &MeasureOptions, duration: Duration, payload: Payload,
+    label: Option<&str>) -> TransferInfo {
+    let (stream, socket_addr) = connect_or_exit(addrs, socket_options);
+    print_socket_buffers(&stream);
+    if let Some(label) = label {
+        if let Err(err) = net::send_label(&stream, label) {
+            print_err!("There was an error sending the label to {}", socket_addr);
+            print_err!("ERROR: {}", err);
+            Failure::Write.exit();
+        }
+    }
+    send_stream(stream, socket_addr, options, duration, payload)
+}
+
+/// Sends the payload over the stream for the given duration and prints how
+/// quickly it was sent.
+fn send_stream(stream: TcpStream, peer: SocketAddr, options: &MeasureOptions, duration: Duration, payload: Payload) -> TransferInfo {
+    println!("Sending data to {} for {:.1} seconds", peer, duration.as_secs_f64());
+
+    // Sending goes through a clone so that the connection stays open until
+    // it has been sampled for the last time.
+    let options = &MeasureOptions { tcp_info: TcpInfoSampler::start(&stream), ..options.clone() };
+    let sending = match stream.try_clone() {
+        Ok(sending) => sending,
+        Err(err) => {
+            print_err!("There was an error sending data to {}", peer);
+            print_err!("ERROR: {}", err);
+            Failure::Write.exit();
+        }
+    };
+
+    let start_time = SystemTime::now();
+    let compress = options.compress.or(options.compression.map(|codec| Compression { codec, level: None }));
+    let result = match compress {
+        Some(compress) => send_compressed(sending, compress, options, duration, payload)
+            .map(|(bytes_sent, wire_bytes, cost)| (bytes_sent, Some(WireStats { codec: compress.codec, bytes: wire_bytes }), cost)),
+        None => control::send_streams(vec![sending], options.buffer_size, options.vecs, &options.cpus, payload, duration, options.shape.as_ref(), None)
+            .map(|(bytes_sent, cost)| (bytes_sent, None, cost)),
+    };
+    let (bytes_sent, wire, cost) = match result {
+        Ok(sent) => sent,
+        Err(err) => {
+            print_err!("There was an error sending data to {}", peer);
+            print_err!("ERROR: {}", err);
+            Failure::Write.exit();
+        }
+    };
+
+    // Sending has been shut down, so the peer closing its side means that it
+    // has read everything.
+    let close = net::drain(&stream);
+    if !close.closed_by_peer {
+        print_err!("{} did not close the connection after everything was sent; it may not have received all of it.", peer);
+    }
+    finish_sending(bytes_sent, start_time, wire, cost, Some(close), options)
+}
+
+/// The type of service byte to mark sent packets with, given directly or as
+/// a DSCP that fills its upper six bits.
+fn parse_tos(settings: &Settings, diagnostics: &Diagnostics) -> Option<u8> {
+    if let Some(value) = settings.value_of("tos") {
+        return diagnostics.parse(Some(value), |value| {
+            let tos = match value.strip_prefix("0x") {
+                Some(hex) => u8::from_str_radix(hex, 16).ok(),
+                None => value.parse().ok(),
+            };
+            tos.ok_or_else(|| validate::invalid("TOS must be a number from 0 to 255, such as 184 or 0xb8", value))
+        });
+    }
+    diagnostics.parse(settings.value_of("dscp"), |value| match net::parse_dscp(value) {
+        Some(dscp) => Ok(dscp << 2),
+        None => Err(validate::invalid("DSCP must be a number from 0 to 63 or a class such as EF, AF41 or CS1", value)),
+    })
+}
+
+/// The largest payload of a UDP datagram over IPv4.
+const MAX_UDP_PAYLOAD: usize = 65507;
+
+/// The most datagrams one send can be split into with GSO.
+const MAX_GSO_SEGMENTS: usize = 64;
+
+/// Checks that every buffer size can be split into datagrams of the GSO size
+/// and returns that size if one was given.
+fn parse_gso(settings: &Settings, diagnostics: &Diagnostics, buffer_sizes: &[usize]) -> Option<u16> {
+    let segment = diagnostics.parse(settings.value_of("gso"), |value| validate::number::<u16>(value, "GSO size", 1))?;
+    if !cfg!(target_os = "linux") {
+        diagnostics.error("GSO is only available on Linux.");
+        return None;
+    }
+    let fits = |&buffer_size: &usize| segment as usize <= buffer_size && buffer_size.div_ceil(segment as usize) <= MAX_GSO_SEGMENTS;
+    if !buffer_sizes.iter().all(fits) {
+        diagnostics.error(format!("GSO size must be no larger than the buffer size, which can be split into at most {} datagrams (got \"{}\").",
+            MAX_GSO_SEGMENTS, segment));
+        return None;
+    }
+    Some(segment)
+}
+
+fn send_udp_client(addrs: &[SocketAddr], socket_options: &SocketOptions, options: &MeasureOptions, duration: Duration, payload: Payload, gso: Option<u16>) -> TransferInfo {
+    let (socket, peer) = match net::connect_udp(addrs, socket_options) {
+        Ok(connected) => connected,
+        Err(err) => {
+            print_err!("There was an error setting up a UDP socket for {}", format_addrs(addrs));
+            print_err!("ERROR: {}", err);
+            exit_err();
+        }
+    };
+    #[cfg(target_os = "linux")]
+    {
+        if let Err(err) = gso.map_or(Ok(()), |size| net::set_gso_segment(&socket, size)) {
+            print_err!("There was an error enabling GSO.");
+            print_err!("ERROR: {}", err);
+            exit_err();
+        }
+    }
+    match gso {
+        Some(size) => println!("Sending datagrams of {} bytes to {} for {:.1} seconds, {} bytes per send with GSO",
+            size, peer, duration.as_secs_f64(), options.buffer_size),
+        None => println!("Sending datagrams of {} bytes to {} for {:.1} seconds", options.buffer_size, peer, duration.as_secs_f64()),
+    }
+
+    let start_time = SystemTime::now();
+    let shaper = options.shape.clone().map(|schedule| Mutex::new(Shaper::new(schedule)));
+    let (bytes_sent, cost) = match sender::send_datagrams_for(&socket, options.buffer_size, payload, duration, shaper.as_ref()) {
+        Ok(sent) => sent,
+        Err(err) => {
+            print_err!("There was an error sending datagrams to {}", peer);
+            print_err!("ERROR: {}", err);
+            Failure::Write.exit();
+        }
+    };
+    finish_sending(bytes_sent, start_time, None, cost, None, options)
+}
+
+/// Prints the summary of data that was sent, which is measured as a whole
+/// once it is done.
+fn finish_sending(bytes_sent: u64, start_time: SystemTime, wire: Option<WireStats>, generation: GenerationCost, close: Option<CloseStats>, options: &MeasureOptions) -> TransferInfo {
+    // The rate is of the sending alone, not of waiting for the peer to close.
+    let waited = close.map(|close| close.wait).unwrap_or_default();
+    let elapsed = start_time.elapsed().unwrap_or_default().saturating_sub(waited);
+    let bps = units::bytes_per_second(bytes_sent as usize, elapsed);
+
+    let transfer_info = TransferInfo {
+        total_bytes_transferred: bytes_sent as usize,
+        total_measures: 1,
+        total_bps: bps,
+        peak_bps: bps,
+        current_bps: bps,
+        elapsed,
+        start_time: Some(start_time),
+        wire,
+        generation: Some(generation),
+        close,
+        tcp: options.tcp_info.as_ref().map(|sampler| sampler.finish()),
+        ..TransferInfo::default()
+    };
+    println!();
+    print_summary(&transfer_info, options);
+    transfer_info
+}
+
+/// Sends the payload through an encoder for the given duration and returns
+/// the bytes of data sent alongside the bytes that crossed the wire and what
+/// generating the data cost.
+fn send_compressed(stream: TcpStream, compress: Compression, options: &MeasureOptions, duration: Duration, payload: Payload) -> std::io::Result<(u64, u64, GenerationCost)> {
+    let (counted, wire_bytes) = WireCounter::new(&stream);
+    let mut encoder = compress::encoder(compress.codec, compress.level, counted)?;
+    let shaper = options.shape.clone().map(|schedule| Mutex::new(Shaper::new(schedule)));
+    let (bytes_sent, cost) = sender::send_for(&mut encoder, options.buffer_size, options.vecs, payload, duration, shaper.as_ref())?;
+    encoder.finish()?;
+    stream.shutdown(Shutdown::Write)?;
+    Ok((bytes_sent, wire_bytes.load(Ordering::Relaxed), cost))
+}
+
+/// Listens on one address out of each group of candidate addresses, using
+/// the first of the ports that every group can be bound to.
+fn bind_listeners(addr_groups: &[Vec<SocketAddr>], ports: PortRange, socket_options: &SocketOptions) -> Acceptor {
+    let mut last_err = None;
+    for port in ports.ports() {
+        match net::bind_all(addr_groups, port, socket_options) {
+            Ok(bound) => {
+                for &(_, socket_addr) in &bound {
+                    println!("Listening at {}", socket_addr);
+                }
+                announce_port(ports, bound[0].1.port());
+
+                let mut acceptor = Acceptor::new(bound.into_iter().map(|(listener, _)| listener).collect());
+                acceptor.set_timeout(socket_options.accept_timeout);
+                return acceptor;
+            },
+
+            Err(err) => last_err = Some(err),
+        }
+    }
+
+    if ports.first == ports.last {
+        let addrs: Vec<SocketAddr> = addr_groups.iter().flatten().cloned().collect();
+        print_err!("There was an error listening on {}", format_addrs(&addrs));
+    } else {
+        print_err!("There was an error listening on any port from {} to {}", ports.first, ports.last);
+    }
+    print_err!("ERROR: {}", last_err.expect("a port range is never empty"));
+    Failure::Bind.exit();
+}
+
+/// Takes the listeners passed by systemd socket activation, if the process
+/// was started that way, in place of binding them.
+#[cfg(target_os = "linux")]
+fn activated_acceptor(socket_options: &SocketOptions) -> Option<Acceptor> {
+    match net::activated_listeners() {
+        Ok(Some(listeners)) => {
+            for listener in &listeners {
+                if let Ok(addr) = listener.local_addr() {
+                    println!("Listening at {} (socket activation)", addr);
+                }
+            }
+            let mut acceptor = Acceptor::new(listeners);
+            acceptor.set_timeout(socket_options.accept_timeout);
+            Some(acceptor)
+        },
+        Ok(None) => None,
+        Err(err) => {
+            print_err!("There was an error taking the sockets passed by systemd.");
+            print_err!("ERROR: {}", err);
+            exit_err();
+        }
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+fn activated_acceptor(_socket_options: &SocketOptions) -> Option<Acceptor> {
+    None
+}
+
+/// Prints the port that was picked, in a line that scripts can look for,
+/// if it was not given exactly.
+fn announce_port(ports: PortRange, port: u16) {
+    if ports.is_chosen() {
+        println!("THROUGHPUT_PORT={}", port);
+    }
+}
+
+/// Connects to the first address that accepts and measures the data it
+/// sends, connecting again whenever the connection drops if asked to.
+fn measure_tcp_client(addrs: &[SocketAddr], socket_options: &SocketOptions, options: &MeasureOptions, reconnect: bool) -> TransferInfo {
+    let (stream, socket_addr) = connect_or_exit(addrs, socket_options);
+    println!("Reading incoming data from {}", socket_addr);
+    print_socket_buffers(&stream);
+    println!();
+
+    if reconnect {
+        let (reader, reconnects) = reconnect::ReconnectReader::new(stream, socket_addr, addrs, socket_options);
+        let options = MeasureOptions { reconnects: Some(reconnects), ..options.clone() };
+        measure_reader(reader, &options)
+    } else {
+        let options = MeasureOptions { tcp_info: TcpInfoSampler::start(&stream), ..options.clone() };
+        measure_fd(stream, &options)
+    }
+}
+
+/// Connects to the first address that accepts, retrying as many times as
+/// the socket options allow, and exits if none ever does.
+fn connect_or_exit(addrs: &[SocketAddr], socket_options: &SocketOptions) -> (TcpStream, SocketAddr) {
+    let waiting = |err: &std::io::Error, retry| print_connect_retry(err, retry, socket_options);
+    match net::connect_retrying(addrs, socket_options, waiting) {
+        Ok(connected) => {
+            print_connected_family(addrs, &connected.1);
+            connected
+        },
+        Err(err) => {
+            print_err!("There was an error connecting to {}", format_addrs(addrs));
+            print_err!("ERROR: {}", err);
+            Failure::from_io(&err, Failure::Connect).exit();
+        }
+    }
+}
+
+/// Reports a failed attempt to connect before waiting to try again.
+fn print_connect_retry(err: &std::io::Error, retry: u32, socket_options: &SocketOptions) {
+    print_err!("Could not connect ({}), retrying in {:.1} seconds ({}/{})",
+        err, socket_options.retry_delay.as_secs_f64(), retry, socket_options.connect_retries);
+}
+
+/// Reports which family won when addresses of both were raced.
+fn print_connected_family(addrs: &[SocketAddr], addr: &SocketAddr) {
+    if net::has_both_families(addrs) {
+        let other = if addr.is_ipv4() { "IPv6" } else { "IPv4" };
+        print_err!("Connected to {} over {}, which answered before {}", addr, net::family_name(addr), other);
+    }
+}
+
+/// Applies the socket options to an accepted connection.
+fn configure_stream(stream: &TcpStream, socket_options: &SocketOptions) {
+    if let Err(err) = socket_options.apply(stream) {
+        print_err!("There was an error configuring the socket.");
+        print_err!("ERROR: {}", err);
+        exit_err();
+    }
+    print_socket_buffers(stream);
+}
+
+/// Starts listening on the control socket if there is one, and reading
+/// hotkeys while the live stats are shown on a terminal, unless the data
+/// being measured is typed on that same terminal.
+#[cfg(unix)]
+fn start_controls(mode: &Mode, settings: &Settings, options: &MeasureOptions) -> Option<SharedControls> {
+    let controls = SharedControls::default();
+    let mut controlled = false;
+
+    if let Some(path) = settings.value_of("control") {
+        if let Err(err) = remote::listen(path, controls.clone()) {
+            print_err!("There was an error listening for commands at {}", path);
+            print_err!("ERROR: {}", err);
+            Failure::Bind.exit();
+        }
+        // Lets stop interrupt a read that is waiting for data.
+        daemon::stop_on_terminate();
+        controlled = true;
+    }
+
+    let live = matches!(options.display_mode, DisplayMode::Live | DisplayMode::Dashboard);
+    let shown = if options.passthrough { stderr().is_terminal() } else { stdout().is_terminal() };
+    let typed = stdin().is_terminal() && match *mode {
+        Mode::Stdin => true,
+        Mode::File(ref paths) => paths.contains(&concat::STDIN_PATH),
+        _ => false,
+    };
+    if live && shown && !typed && !settings.is_present("no_hotkeys") && hotkeys::listen(controls.clone()).is_ok() {
+        if options.passthrough {
+            print_err!("{}", hotkeys::HINT);
+        } else {
+            println!("{}", hotkeys::HINT);
+        }
+        controlled = true;
+    }
+
+    if controlled { Some(controls) } else { None }
+}
+
+#[cfg(not(unix))]
+fn start_controls(_mode: &Mode, _settings: &Settings, _options: &MeasureOptions) -> Option<SharedControls> {
+    None
+}
+
+/// Resizes the pipes on stdin and stdout and prints the size each ended up
+/// with.
+#[cfg(target_os = "linux")]
+fn resize_pipes(size: usize, options: &MeasureOptions) {
+    let mut resized = false;
+    for (fd, name) in [(0, "stdin"), (1, "stdout")] {
+        let pipe = match pipes::resize(fd, size) {
+            Some(pipe) => pipe,
+            None => continue,
+        };
+        resized = true;
+        if let Some(err) = pipe.error {
+            print_err!("Could not resize the {} pipe to {} bytes: {}", name, pipe.requested, err);
+        }
+        // Passed through data goes to stdout, so it must not be mixed in there.
+        if options.passthrough {
+            print_err!("Pipe on {} using {} bytes", name, pipe.achieved);
+        } else {
+            println!("Pipe on {} using {} bytes", name, pipe.achieved);
+        }
+    }
+    if !resized {
+        print_err!("Neither stdin nor stdout is a pipe, so their size was left alone.");
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+fn resize_pipes(_size: usize, _options: &MeasureOptions) {
+    unreachable!("pipe sizes can only be set on Linux");
+}
+
+fn print_socket_buffers(stream: &TcpStream) {
+    match net::describe_buffers(stream) {
+        Ok(description) => println!("Socket using {}", description),
+        Err(err) => print_err!("Could not query socket buffer sizes: {}", err),
+    }
+}
+
+/// Resolves the addresses to listen at, grouped by the host they came from.
+fn listen_addrs(settings: &Settings, port: u16, preference: AddrPreference) -> Vec<Vec<SocketAddr>> {
+    let mut hosts: Vec<&str> = Vec::new();
+    for host in settings.values_of("address") {
+        if host == "any" {
+            hosts.extend_from_slice(&net::ANY_ADDRESSES);
+        } else {
+            hosts.push(host);
+        }
+    }
+    if hosts.is_empty() { hosts.push(DEFAULT_ADDRESS); }
+
+    hosts.iter()
+        .map(|host| resolve_or_exit(host, port, preference))
+        .collect()
+}
+
+/// Binds the UDP sockets and returns a run that measures the datagrams from
+/// the first one that arrives until none arrive for the read timeout.
+fn measure_udp_listener(addrs: &[SocketAddr], ports: PortRange, recv_threads: usize, batch: usize,
+        socket_options: &SocketOptions) -> Box<dyn FnMut(&MeasureOptions) -> TransferInfo> {
+    let mut bound = Err(std::io::Error::other("no ports to try"));
+    for port in ports.ports() {
+        let addrs: Vec<SocketAddr> = addrs.iter().map(|addr| SocketAddr::new(addr.ip(), port)).collect();
+        bound = net::bind_udp(&addrs, recv_threads, socket_options);
+        if bound.is_ok() { break; }
+    }
+
+    let sockets = match bound {
+        Ok((sockets, local_addr)) => {
+            if recv_threads > 1 {
+                println!("Listening for UDP at {} on {} sockets", local_addr, recv_threads);
+            } else {
+                println!("Listening for UDP at {}", local_addr);
+            }
+            announce_port(ports, local_addr.port());
+            sockets
+        },
+        Err(err) => {
+            if ports.first == ports.last {
+                print_err!("There was an error listening for UDP at {}", format_addrs(addrs));
+            } else {
+                print_err!("There was an error listening for UDP on any port from {} to {}", ports.first, ports.last);
+            }
+            print_err!("ERROR: {}", err);
+            Failure::Bind.exit();
+        }
+    };
+
+    let idle_timeout = socket_options.read_timeout.unwrap_or(Duration::from_secs(DEFAULT_UDP_IDLE_TIMEOUT_SECS));
+    let accept_timeout = socket_options.accept_timeout;
+    Box::new(move |options| {
+        let (mut receiver, counters) = match UdpReceiver::start(&sockets, idle_timeout, batch) {
+            Ok(started) => started,
+            Err(err) => {
+                print_err!("There was an error starting to receive datagrams.");
+                print_err!("ERROR: {}", err);
+                exit_err();
+            }
+        };
+        if !receiver.wait_for_first(accept_timeout) {
+            print_err!("No datagram arrived within {:.1} seconds.", accept_timeout.unwrap_or_default().as_secs_f64());
+            Failure::AcceptTimeout.exit();
+        }
+        println!("Receiving datagrams");
+        println!();
+        measure_reader(receiver, &MeasureOptions { udp: Some(counters), ..options.clone() })
+    })
+}
+
+fn check_sctp_support() {
+    if !cfg!(target_os = "linux") {
+        print_err!("SCTP is only available on Linux.");
+        exit_err();
+    }
+}
+
+/// Listens for SCTP associations and returns a run that measures the next
+/// one to arrive.
+#[cfg(target_os = "linux")]
+fn measure_sctp_listener(addrs: &[SocketAddr], ports: PortRange, socket_options: &SocketOptions) -> Box<dyn FnMut(&MeasureOptions) -> TransferInfo> {
+    let mut bound = Err(std::io::Error::other("no ports to try"));
+    for port in ports.ports() {
+        let addrs: Vec<SocketAddr> = addrs.iter().map(|addr| SocketAddr::new(addr.ip(), port)).collect();
+        bound = net::bind_first_sctp(&addrs, socket_options)
+            .and_then(|(listener, _)| Ok((listener.local_addr()?, listener)));
+        if bound.is_ok() { break; }
+    }
+
+    let listener = match bound {
+        Ok((local_addr, listener)) => {
+            match local_addr.as_socket() {
+                Some(socket_addr) => {
+                    println!("Listening for SCTP at {}", socket_addr);
+                    announce_port(ports, socket_addr.port());
+                },
+                None => println!("Listening for SCTP"),
+            }
+            listener
+        },
+        Err(err) => {
+            if ports.first == ports.last {
+                print_err!("There was an error listening for SCTP at {}", format_addrs(addrs));
+            } else {
+                print_err!("There was an error listening for SCTP on any port from {} to {}", ports.first, ports.last);
+            }
+            print_err!("ERROR: {}", err);
+            Failure::Bind.exit();
+        }
+    };
+
+    Box::new(move |options| match listener.accept() {
+        Ok((socket, incoming_addr)) => {
+            match incoming_addr.as_socket() {
+                Some(addr) => println!("Reading incoming data from {}", addr),
+                None => println!("Reading incoming data"),
+            }
+            println!();
+            measure_sctp(socket, options)
+        },
+
+        Err(err) => {
+            print_err!("There was an error accepting an association.");
+            print_err!("ERROR: {}", err);
+            exit_err();
+        }
+    })
+}
+
+#[cfg(not(target_os = "linux"))]
+fn measure_sctp_listener(_addrs: &[SocketAddr], _ports: PortRange, _socket_options: &SocketOptions) -> Box<dyn FnMut(&MeasureOptions) -> TransferInfo> {
+    unreachable!("SCTP requires Linux");
+}
+
+#[cfg(target_os = "linux")]
+fn measure_sctp_client(addrs: &[SocketAddr], socket_options: &SocketOptions, options: &MeasureOptions) -> TransferInfo {
+    match net::connect_first_sctp(addrs, socket_options) {
+        Ok((socket, socket_addr)) => {
+            println!("Reading incoming data from {}", socket_addr);
+            println!();
+            measure_sctp(socket, options)
+        },
+
+        Err(err) => {
+            print_err!("There was an error connecting to {}", format_addrs(addrs));
+            print_err!("ERROR: {}", err);
+            Failure::from_io(&err, Failure::Connect).exit();
+        }
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+fn measure_sctp_client(_addrs: &[SocketAddr], _socket_options: &SocketOptions, _options: &MeasureOptions) -> TransferInfo {
+    unreachable!("SCTP requires Linux");
+}
+
+/// Measures an association, recording which stream the data arrived on.
+#[cfg(target_os = "linux")]
+fn measure_sctp(socket: socket2::Socket, options: &MeasureOptions) -> TransferInfo {
+    match sctp::SctpReader::new(socket) {
+        Ok((reader, stats)) => {
+            let options = MeasureOptions { sctp: Some(stats), ..options.clone() };
+            measure_reader(reader, &options)
+        },
+        Err(err) => {
+            print_err!("There was an error configuring the association.");
+            print_err!("ERROR: {}", err);
+            exit_err();
+        }
+    }
+}
+
+/// Listens on the vsock port and returns a run that measures the next
+/// connection to arrive.
+#[cfg(target_os = "linux")]
+fn measure_vsock_listener(cid: Option<u32>, port: u32) -> Box<dyn FnMut(&MeasureOptions) -> TransferInfo> {
+    let listener = match vsock::listen(cid.unwrap_or(vsock::CID_ANY), port) {
+        Ok(listener) => listener,
+        Err(err) => {
+            print_err!("There was an error listening on vsock port {}.", port);
+            print_err!("ERROR: {}", err);
+            Failure::Bind.exit();
+        }
+    };
+    println!("Listening on vsock port {}", port);
+
+    Box::new(move |options| match listener.accept() {
+        Ok((socket, incoming_addr)) => {
+            println!("Reading incoming data from {}", vsock::describe(&incoming_addr));
+            println!();
+            measure_fd(socket, options)
+        },
+
+        Err(err) => {
+            print_err!("There was an error accepting a connection.");
+            print_err!("ERROR: {}", err);
+            exit_err();
+        }
+    })
+}
+
+#[cfg(not(target_os = "linux"))]
+fn measure_vsock_listener(_cid: Option<u32>, _port: u32) -> Box<dyn FnMut(&MeasureOptions) -> TransferInfo> {
+    unreachable!("vsock requires Linux");
+}
+
+#[cfg(target_os = "linux")]
+fn measure_vsock_client(cid: u32, port: u32, options: &MeasureOptions) -> TransferInfo {
+    match vsock::connect(cid, port) {
+        Ok(socket) => {
+            println!("Reading incoming data from vsock CID {} port {}", cid, port);
+            println!();
+            measure_fd(socket, options)
+        },
+
+        Err(err) => {
+            print_err!("There was an error connecting to vsock CID {} port {}", cid, port);
+            print_err!("ERROR: {}", err);
+            Failure::from_io(&err, Failure::Connect).exit();
+        }
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+fn measure_vsock_client(_cid: u32, _port: u32, _options: &MeasureOptions) -> TransferInfo {
+    unreachable!("vsock requires Linux");
+}
+
+fn format_addrs(addrs: &[SocketAddr]) -> String {
+    addrs.iter().map(|a| a.to_string()).collect::<Vec<_>>().join(", ")
+}
+
+fn open_replay(path: &str, speed: Option<f64>) -> Replay {
+    match Replay::open(path, speed) {
+        Ok(replay) => replay,
+        Err(err) => {
+            print_err!("Error while opening recording {}: {}", path, err);
+            exit_err();
+        }
+    }
+}
+
+/// Connects to the first address that accepts and measures the recording as
+/// it is sent to it.
+fn replay_to_host(path: &str, speed: Option<f64>, addrs: &[SocketAddr], socket_options: &SocketOptions, options: &MeasureOptions) -> TransferInfo {
+    let replay = open_replay(path, speed);
+    let (stream, peer) = connect_or_exit(addrs, socket_options);
+    print_socket_buffers(&stream);
+    println!("Replaying {} to {}", path, peer);
+    measure_reader(Forwarder::new(replay, stream), options)
+}
+
+/// Measures how fast the measuring loop itself goes on zeros read from memory,
+/// first only counting them and then also passing them through to nowhere,
+/// and prints both as the most that can be measured on this machine.
+fn selftest(options: &MeasureOptions, duration: Duration) {
+    println!("Counting zeros read from memory for {:.1} seconds", duration.as_secs_f64());
+    let counted = measure_reader(selftest::ZeroReader::new(duration),
+        &MeasureOptions { passthrough: false, ..options.clone() });
+
+    println!();
+    println!("Passing zeros read from memory through to nowhere for {:.1} seconds", duration.as_secs_f64());
+    let passed = measure_reader(selftest::ZeroReader::new(duration),
+        &MeasureOptions { passthrough: true, pass_to: Some(PassTarget::Null), ..options.clone() });
+
+    println!();
+    println!("Most this machine can measure with a buffer of {} bytes:", options.buffer_size);
+    println!("  Counting:         {}", format_rate(counted.average_bps()));
+    println!("  Passing through:  {}", format_rate(passed.average_bps()));
+}
+
+/// Prints the stats of a transfer that was measured elsewhere, such as by the
+/// peer of a coordinated test.
+fn print_summary(transfer_info: &TransferInfo, options: &MeasureOptions) {
+    let mut terminal = TerminalReporter::new(&MeasureOptions { expected_bytes: None, ..options.clone() }, StdStream::Stdout);
+    terminal.set_rate_description(None);
+    if let Err(err) = terminal.summarize(transfer_info) {
+        print_err!("Error while printing output: {}", err);
+        exit_err();
+    }
+}
+
+/// Forwards stdin to the host as `nc` would, measuring what is sent, and
+/// copies whatever the host sends back to stdout. The connection is shut
+/// down for writing once stdin ends, after which the host closing it ends
+/// the run.
+fn forward_stdin_to_host(addrs: &[SocketAddr], socket_options: &SocketOptions, options: &MeasureOptions) -> TransferInfo {
+    let (stream, peer) = connect_or_exit(addrs, socket_options);
+    // Stdout carries what the host sends back, so nothing else is printed there.
+    print_err!("Forwarding stdin to {}", peer);
+
+    let received = Arc::new(AtomicU64::new(0));
+    let replies = match stream.try_clone() {
+        Ok(reader) => {
+            let mut reader = WireCounter::sharing(reader, received.clone());
+            std::thread::spawn(move || {
+                let output = stdout();
+                let mut output = output.lock();
+                std::io::copy(&mut reader, &mut output).and_then(|_| output.flush())
+            })
+        },
+        Err(err) => {
+            print_err!("Error while reading from {}: {}", peer, err);
+            exit_err();
+        }
+    };
+
+    // What is forwarded is compressed as the pipeline writes it, counting the
+    // bytes that cross the wire.
+    let stream = Arc::new(stream);
+    let transfer_info = measure_stdin(&MeasureOptions {
+        pass_to: Some(PassTarget::Connection(stream.clone())),
+        wire_bytes: options.compress.map(|_| Arc::new(AtomicU64::new(0))),
+        ..options.clone()
+    });
+    if let Err(err) = stream.shutdown(Shutdown::Write) {
+        print_err!("Error while closing the connection to {}: {}", peer, err);
+    }
+
+    // The replies run until the host closes its side, which it does once it
+    // has read everything that was forwarded.
+    let in_flight = net::unacknowledged_bytes(&stream);
+    let received_before = received.load(Ordering::Relaxed);
+    let start = Instant::now();
+    let closed_by_peer = match replies.join() {
+        Ok(Ok(())) => true,
+        Ok(Err(err)) => {
+            print_err!("Error while copying data from {} to stdout: {}", peer, err);
+            false
+        },
+        Err(_) => false,
+    };
+    let close = CloseStats {
+        in_flight,
+        drained: received.load(Ordering::Relaxed) - received_before,
+        wait: start.elapsed(),
+        closed_by_peer,
+    };
+    print_err!("Shutdown: {}", display::format_close(&close));
+    TransferInfo { close: Some(close), ..transfer_info }
+}
+
+/// The writer that forwarded data goes to, which compresses it first when
+/// asked to, counting the compressed bytes in `options.wire_bytes`.
+fn forwarding_output(stream: TcpStream, options: &MeasureOptions) -> std::io::Result<Box<dyn Write + Send>> {
+    match (options.compress, options.wire_bytes.as_ref()) {
+        (Some(compress), Some(wire_bytes)) => {
+            let counted = WireCounter::sharing(stream, wire_bytes.clone());
+            let encoder = compress::encoder(compress.codec, compress.level, counted)?;
+            Ok(Box::new(FinishOnDrop::new(encoder)))
+        },
+        _ => Ok(Box::new(stream)),
+    }
+}
+
+fn measure_stdin(options: &MeasureOptions) -> TransferInfo {
+    let input = stdin();
+    // The size of compressed data says nothing about how much it decodes to.
+    if options.expected_bytes.is_none() && options.compression.is_none() {
+        if let Some(size) = stdin_file_size() {
+            let options = MeasureOptions { expected_bytes: Some(size), ..options.clone() };
+            return measure_fd(input.lock(), &options);
+        }
+    }
+    measure_fd(input.lock(), options)
+}
+
+/// Creates the named pipe and returns a run that waits for a writer to open
+/// it and measures what they write, so every run reads from a new writer.
+#[cfg(any(unix, windows))]
+fn measure_fifo(path: &str, options: &MeasureOptions) -> Box<dyn FnMut(&MeasureOptions) -> TransferInfo> {
+    let fifo = match fifo::Fifo::create(path) {
+        Ok(fifo) => fifo,
+        Err(err) => {
+            print_err!("Error while creating the named pipe {}: {}", path, err);
+            exit_err();
+        }
+    };
+    // Passed through data goes to stdout, so it must not be mixed in there.
+    if options.passthrough {
+        print_err!("Reading from {}", fifo.path());
+    } else {
+        println!("Reading from {}", fifo.path());
+    }
+
+    Box::new(move |options| match fifo.open() {
+        Ok(file) => measure_fd(file, options),
+        Err(err) => {
+            print_err!("Error while opening the named pipe {}: {}", fifo.path(), err);
+            exit_err();
+        }
+    })
+}
+
+#[cfg(not(any(unix, windows)))]
+fn measure_fifo(_path: &str, _options: &MeasureOptions) -> Box<dyn FnMut(&MeasureOptions) -> TransferInfo> {
+    print_err!("Named pipes are only available on Unix and Windows.");
+    exit_err();
+}
+
+/// Measures reading the file at the given path from start to finish.
+fn measure_file(path: &str, options: &MeasureOptions) -> TransferInfo {
+    #[cfg(target_os = "linux")]
+    let opened = if options.direct { direct::open(path) } else { File::open(path) };
+    #[cfg(not(target_os = "linux"))]
+    let opened = File::open(path);
+
+    let file = match opened {
+        Ok(file) => file,
+        Err(err) => {
+            print_err!("Error while opening {}: {}", path, err);
+            exit_err();
+        }
+    };
+
+    if options.expected_bytes.is_none() && options.compression.is_none() {
+        if let Ok(metadata) = file.metadata() {
+            let options = MeasureOptions { expected_bytes: Some(metadata.len()), ..options.clone() };
+            return measure_open_file(file, &options);
+        }
+    }
+    measure_open_file(file, options)
+}
+
+/// Measures reading a file as it grows, until the process is stopped or the
+/// idle timeout expires.
+fn measure_followed_file(path: &str, options: &MeasureOptions) -> TransferInfo {
+    match File::open(path) {
+        Ok(file) => measure_reader(follow::FollowReader::new(file), options),
+        Err(err) => {
+            print_err!("Error while opening {}: {}", path, err);
+            exit_err();
+        }
+    }
+}
+
+/// Measures reading several sources one after another as a single stream.
+fn measure_concatenated(paths: &[String], options: &MeasureOptions) -> TransferInfo {
+    let (reader, stats) = concat::ConcatReader::new(paths.to_vec());
+    let options = MeasureOptions {
+        expected_bytes: options.expected_bytes
+            .or_else(|| concat::total_size(paths, stdin_file_size).filter(|_| options.compression.is_none())),
+        sources: Some(stats),
+        ..options.clone()
+    };
+    measure_reader(reader, &options)
+}
+
+/// Measures downloading the whole resource with a single request.
+fn download(resource: &Resource, options: &MeasureOptions) -> TransferInfo {
+    match resource.get() {
+        Ok((body, size)) => {
+            let options = MeasureOptions {
+                expected_bytes: options.expected_bytes.or(size.filter(|_| options.compression.is_none())),
+                ..options.clone()
+            };
+            measure_reader(body, &options)
+        },
+        Err(err) => {
+            print_err!("There was an error downloading the resource.");
+            print_err!("ERROR: {}", err);
+            Failure::from_io(&err, Failure::Connect).exit();
+        }
+    }
+}
+
+/// Measures downloading the resource in ranges over separate connections at
+/// once, alongside how quickly each range arrived.
+fn download_segments(resource: &Resource, segments: u64, options: &MeasureOptions) -> TransferInfo {
+    let fetched = resource.size().and_then(|(size, accepts_ranges)| {
+        if !accepts_ranges {
+            return Err(std::io::Error::other("the server does not accept range requests"));
+        }
+        download::ranges(size, segments).into_iter()
+            .map(|(first, last)| resource.get_range(first, last).map(|body| (body, first, last)))
+            .collect::<std::io::Result<Vec<_>>>()
+            .map(|bodies| (bodies, size))
+    });
+    let (bodies, size) = match fetched {
+        Ok(fetched) => fetched,
+        Err(err) => {
+            print_err!("There was an error downloading the resource in segments.");
+            print_err!("ERROR: {}", err);
+            Failure::from_io(&err, Failure::Connect).exit();
+        }
+    };
+
+    let stats = Arc::new(Mutex::new(bodies.iter().enumerate()
+        .map(|(index, &(_, first, last))| SourceStats {
+            path: format!("segment {} (bytes {}-{})", index + 1, first, last),
+            bytes: 0,
+            elapsed: Duration::default(),
+        })
+        .collect()));
+    let started = Instant::now();
+    let readers: Vec<_> = bodies.into_iter().enumerate()
+        .map(|(index, (body, _, _))| SourceReader::new(body, index, started, stats.clone()))
+        .collect();
+    let options = MeasureOptions {
+        expected_bytes: options.expected_bytes.or(Some(size).filter(|_| options.compression.is_none())),
+        sources: Some(stats),
+        ..options.clone()
+    };
+    measure_reader(MergedReader::new(readers, options.buffer_size, &options.cpus), &options)
+}
+
+/// Runs the command and measures reading its stdout until it ends.
+fn measure_command(args: &[String], feed: Option<Pattern>, options: &MeasureOptions) -> TransferInfo {
+    match CommandReader::spawn(args, feed, options.buffer_size) {
+        Ok((reader, stats)) => measure_reader(reader, &MeasureOptions { command: Some(stats), ..options.clone() }),
+        Err(err) => {
+            print_err!("Error while running {}: {}", args[0], err);
+            exit_err();
+        }
+    }
+}
+
+/// Measures reading every passed descriptor at once as a single stream.
+fn measure_fds(readers: Vec<fds::FdReader>, stats: fds::FdStats, options: &MeasureOptions) -> TransferInfo {
+    // A single descriptor is read on this thread as stdin would be.
+    let options = MeasureOptions { sources: Some(stats), ..options.clone() };
+    if readers.len() == 1 {
+        return measure_reader(readers.into_iter().next().expect("there should be one descriptor"), &options);
+    }
+    measure_reader(MergedReader::new(readers, options.buffer_size, &options.cpus), &options)
+}
+
+/// Measures reading an open file, through a memory map or with direct I/O if
+/// asked to.
+fn measure_open_file(file: File, options: &MeasureOptions) -> TransferInfo {
+    #[cfg(target_os = "linux")]
+    {
+        if options.direct {
+            let buffer_size = options.buffer_size * options.vecs;
+            return measure_reader(direct::DirectReader::new(file, buffer_size), options);
+        }
+    }
+    #[cfg(unix)]
+    {
+        if options.mmap {
+            return match mmap::MappedFile::new(&file) {
+                Ok(mapped) => measure_reader(mapped, options),
+                Err(err) => {
+                    print_err!("Error while mapping the file: {}", err);
+                    exit_err();
+                }
+            };
+        }
+    }
+    measure_fd(file, options)
+}
+
+/// Writes `bytes` bytes of the pattern to the file at the given path and
+/// measures how quickly they were written.
+fn measure_disk_write(path: &str, bytes: u64, fsync: FsyncMode, pattern: Pattern, options: &MeasureOptions) -> TransferInfo {
+    let (output, file) = match create_disk_file(path, options) {
+        Ok(created) => created,
+        Err(err) => {
+            print_err!("Error while creating {}: {}", path, err);
+            exit_err();
+        }
+    };
+
+    let (mem, unit) = byte_to_mem_units(bytes as f64);
+    println!("Writing {:.3} {} to {}", mem, unit, path);
+    println!();
+
+    let options = MeasureOptions { expected_bytes: Some(options.expected_bytes.unwrap_or(bytes)), ..options.clone() };
+    measure_reader(DiskWriter::new(output, file, bytes, pattern, fsync), &options)
+}
+
+/// Creates the file written by `diskwrite`, returning the writer to write it
+/// through and the file itself for flushing it.
+fn create_disk_file(path: &str, options: &MeasureOptions) -> std::io::Result<(Box<dyn Write>, File)> {
+    #[cfg(target_os = "linux")]
+    {
+        if options.direct {
+            let file = direct::create(path)?;
+            let output = direct::DirectWriter::new(file.try_clone()?, options.buffer_size);
+            return Ok((Box::new(output), file));
+        }
+    }
+
+    let file = File::create(path)?;
+    Ok((Box::new(file.try_clone()?), file))
+}
+
+/// Returns the size of the file stdin was redirected from, if it was.
+fn stdin_file_size() -> Option<u64> {
+    stdin_file()?.metadata().ok().map(|metadata| metadata.len())
+}
+
+/// Returns a handle to the file stdin was redirected from, if it was. The
+/// handle shares its file position with stdin.
+fn stdin_file() -> Option<File> {
+    #[cfg(unix)]
+    let owned = {
+        use std::os::unix::io::AsFd;
+        stdin().as_fd().try_clone_to_owned().ok()?
+    };
+    #[cfg(windows)]
+    let owned = {
+        use std::os::windows::io::AsHandle;
+        stdin().as_handle().try_clone_to_owned().ok()?
+    };
+
+    let file = File::from(owned);
+    if file.metadata().ok()?.is_file() { Some(file) } else { None }
+}
+
+/// Creates the file that everything read is also written to.
+fn create_tee(path: &str, options: &MeasureOptions) -> std::io::Result<Box<dyn Write>> {
+    #[cfg(target_os = "linux")]
+    {
+        if options.direct {
+            let buffer_size = options.buffer_size * options.vecs;
+            return Ok(Box::new(direct::DirectWriter::new(direct::create(path)?, buffer_size)));
+        }
+    }
+    Ok(Box::new(BufWriter::new(File::create(path)?)))
+}
+
+/// Returns a handle to stdout that can be written to from another thread
+/// without holding the lock on stdout.
+fn stdout_file() -> std::io::Result<File> {
+    #[cfg(unix)]
+    let owned = {
+        use std::os::unix::io::AsFd;
+        stdout().as_fd().try_clone_to_owned()?
+    };
+    #[cfg(windows)]
+    let owned = {
+        use std::os::windows::io::AsHandle;
+        stdout().as_handle().try_clone_to_owned()?
+    };
+
+    Ok(File::from(owned))
+}
+
+/// Moves stdin back to the start of the file it was redirected from.
+fn rewind_stdin() -> std::io::Result<()> {
+    use std::io::{Seek, SeekFrom};
+    match stdin_file() {
+        Some(mut file) => file.seek(SeekFrom::Start(0)).map(|_| ()),
+        None => Err(std::io::Error::other("stdin is not a file")),
+    }
+}
+
+/// Measures a reader, dropping discarded socket data in the kernel, moving
+/// passed through data to stdout inside the kernel or reading with io_uring
+/// when possible. Falls back to plain reads when anything needs to see the
+/// data.
+#[cfg(target_os = "linux")]
+fn measure_fd<R: Read + std::os::unix::io::AsRawFd>(reader: R, options: &MeasureOptions) -> TransferInfo {
+    use std::os::unix::io::AsRawFd;
+
+    if options.discard {
+        if let Some(discarded) = discard::DiscardReader::new(reader.as_raw_fd()) {
+            let transfer_info = measure_reader(discarded, options);
+            drop(reader);
+            return transfer_info;
+        }
+    }
+
+    let needs_data = options.tee_path.is_some() || options.record_path.is_some() || options.hash.is_some() || options.verify.is_some()
+        || options.delimiter.is_some() || options.framing.is_some() || options.peek.is_some() || options.chaos.is_some()
+        || options.compression.is_some() || options.compress.is_some();
+
+    #[cfg(feature = "io-uring")]
+    {
+        if options.io_uring && !options.passthrough && !needs_data {
+            match uring::UringReader::new(reader.as_raw_fd(), options.buffer_size, IO_URING_QUEUE_DEPTH) {
+                Ok(uring_reader) => {
+                    let transfer_info = measure_reader(uring_reader, options);
+                    drop(reader);
+                    return transfer_info;
+                },
+                Err(err) => print_err!("Could not use io_uring, falling back to plain reads: {}", err),
+            }
+        }
+    }
+
+    // Data moved inside the kernel cannot be collected or split into lines.
+    if options.passthrough && options.zero_copy && !needs_data && options.flush == FlushPolicy::Immediate {
+        let output = match options.pass_to {
+            Some(PassTarget::Connection(ref stream)) => Some(stream.as_raw_fd()),
+            Some(PassTarget::Null) => None,
+            None => Some(stdout().as_raw_fd()),
+        };
+        if let Some(spliced) = output.and_then(|output| splice::SpliceReader::new(reader.as_raw_fd(), output)) {
+            let options = MeasureOptions { spliced: true, ..options.clone() };
+            let transfer_info = measure_reader(spliced, &options);
+            drop(reader);
+            return transfer_info;
+        }
+    }
+    measure_reader(reader, options)
+}
+
+#[cfg(not(target_os = "linux"))]
+fn measure_fd<R: Read>(reader: R, options: &MeasureOptions) -> TransferInfo {
+    measure_reader(reader, options)
+}
+
+/// Measures the data read, decoding it first if it is compressed.
+fn measure_reader<R: Read>(reader: R, options: &MeasureOptions) -> TransferInfo {
+    match options.compression {
+        Some(codec) => {
+            let (counted, wire_bytes) = WireCounter::new(reader);
+            let decoder = match compress::decoder(codec, counted) {
+                Ok(decoder) => decoder,
+                Err(err) => {
+                    print_err!("Error while starting to decode {}: {}", codec.name(), err);
+                    exit_err();
+                }
+            };
+            let options = MeasureOptions { wire_bytes: Some(wire_bytes), ..options.clone() };
+            measure_range(decoder, &options)
+        },
+        None => measure_range(reader, options),
+    }
+}
+
+/// Measures only the range of the stream given by --skip and --take, reading
+/// past the bytes before it without measuring them and ending the transfer
+/// after it. The bytes skipped are not timed.
+fn measure_range<R: Read>(mut reader: R, options: &MeasureOptions) -> TransferInfo {
+    let skip = options.skip.unwrap_or(0);
+    if skip > 0 {
+        match std::io::copy(&mut (&mut reader).take(skip), &mut std::io::sink()) {
+            Ok(skipped) if skipped < skip => print_err!("The stream ended after {} of the {} bytes to skip.", skipped, skip),
+            Ok(_) => {},
+            Err(err) => {
+                print_err!("Error while skipping the start of the stream: {}", err);
+                Failure::Read.exit();
+            }
+        }
+    }
+
+    match options.take {
+        Some(take) => {
+            let expected_bytes = options.expected_bytes.map(|bytes| bytes.saturating_sub(skip).min(take)).or(Some(take));
+            measure_watched(reader.take(take), &MeasureOptions { expected_bytes, ..options.clone() })
+        },
+        None if skip > 0 => {
+            let expected_bytes = options.expected_bytes.map(|bytes| bytes.saturating_sub(skip));
+            measure_watched(reader, &MeasureOptions { expected_bytes, ..options.clone() })
+        },
+        None => measure_watched(reader, options),
+    }
+}
+
+fn measure_watched<R: Read>(reader: R, options: &MeasureOptions) -> TransferInfo {
+    match options.idle_timeout {
+        Some(timeout) => {
+            let watched = IdleWatchdog::new(reader, timeout, |idle| {
+                print_err!("No data received for {:.1} seconds, aborting.", idle.as_secs_f64());
+                Failure::IdleTimeout.exit();
+            });
+            measure_reader_loop(watched, options)
+        },
+        None => measure_reader_loop(reader, options),
+    }
+}
+
+/// Queues the data that was read to be written, after the fault it was
+/// picked for, if any.
+fn pass_through(pipeline: &mut Pipeline, buffer: &mut Vec<u8>, len: usize, fault: Option<Fault>, occupancy: Option<&mut QueueOccupancy>) -> std::io::Result<()> {
+    match fault {
+        // The buffer is kept and read into again.
+        Some(Fault::Drop) => return Ok(()),
+        Some(Fault::Delay(delay)) => std::thread::sleep(delay),
+        Some(Fault::Duplicate) => {
+            let mut copy = pipeline.take_buffer()?;
+            if copy.len() < len { copy.resize(len, 0); }
+            copy[..len].copy_from_slice(&buffer[..len]);
+            pipeline.send(copy, len)?;
+        },
+        Some(Fault::FlipBit) | None => {},
+    }
+
+    let queued = pipeline.send(std::mem::take(buffer), len)?;
+    if let Some(occupancy) = occupancy {
+        occupancy.record(queued);
+    }
+    Ok(())
+}
+
+/// Prints the head of the stream that was collected for `--peek`.
+fn print_peek<W: Write>(output: &mut W, peek: Option<Peek>) {
+    if let Some(peek) = peek {
+        let _ = writeln!(output, "First {} bytes:", peek.data().len());
+        let _ = write!(output, "{}", peek::hexdump(peek.data()));
+    }
+}
+
+/// Records the bytes transferred over the last `duration` as a measure taken
+/// at `time`, and reports it to the outputs.
+fn record_measure(transfer_info: &mut TransferInfo, rate_estimator: &mut RateEstimator, duration: Duration, elapsed: Duration, time: SystemTime, options: &MeasureOptions) {
+    transfer_info.last_bps = bytes_per_second(transfer_info.last_bytes_transferred, duration);
+    transfer_info.total_measures += 1;
+    transfer_info.total_bps += transfer_info.last_bps;
+    transfer_info.peak_bps = transfer_info.peak_bps.max(transfer_info.last_bps);
+    transfer_info.interval_rates.record(transfer_info.last_bps);
+    transfer_info.current_bps = rate_estimator.update(transfer_info.last_bytes_transferred, duration);
+    transfer_info.elapsed = elapsed;
+    if let Some(ref mut records) = transfer_info.records {
+        records.measure(duration.as_secs_f64());
+    }
+    if let Some(ref mut frames) = transfer_info.frames {
+        frames.measure(duration.as_secs_f64());
+    }
+    if let Some(ref directions) = options.directions {
+        relay::measure(directions, duration.as_secs_f64());
+        transfer_info.directions = Some(directions.lock().expect("direction stats lock was poisoned").clone());
+    }
+    if let Some(ref mut stalls) = transfer_info.stalls {
+        stalls.measure();
+    }
+    if options.graph {
+        transfer_info.rate_history.get_or_insert_with(|| History::new(options.max_samples)).push(transfer_info.last_bps);
+    }
+    if let (Some(wire), Some(wire_bytes)) = (transfer_info.wire.as_mut(), options.wire_bytes.as_ref()) {
+        wire.bytes = wire_bytes.load(Ordering::Relaxed);
+    }
+    transfer_info.last_measure_time = Some(time);
+    if let Some(ref state) = options.state {
+        if let Err(err) = state.checkpoint(transfer_info.total_bytes_transferred as u64, elapsed) {
+            print_err!("Error while saving totals to {}: {}", state.path, err);
+        }
+    }
+    for output in &options.outputs {
+        if let Err(err) = output.measure(transfer_info) {
+            print_err!("Error while writing output: {}", err);
+            exit_err();
+        }
+    }
+}
+
+/// Starts the statistics of the transfer over from zero, at the end of the
+/// warm-up or when asked to while it runs. The digest and the pattern check
+/// still cover the whole stream.
+fn reset_counters(transfer_info: &mut TransferInfo, options: &MeasureOptions) {
+    transfer_info.total_bytes_transferred = 0;
+    transfer_info.last_bytes_transferred = 0;
+    transfer_info.total_measures = 0;
+    transfer_info.total_bps = 0.0;
+    transfer_info.peak_bps = 0.0;
+    transfer_info.current_bps = 0.0;
+    transfer_info.interval_rates = IntervalRates::default();
+    transfer_info.rate_history = None;
+    transfer_info.read_stats = transfer_info.read_stats.take().map(|_| ReadStats::default());
+    transfer_info.passthrough_timing = transfer_info.passthrough_timing.take().map(|_| PassthroughTiming::default());
+    transfer_info.queue_occupancy = transfer_info.queue_occupancy.take().map(|occupancy| QueueOccupancy::new(occupancy.depth));
+    transfer_info.records = transfer_info.records.take().map(|records| RecordCounter::new(records.delimiter));
+    if let Some(ref mut frames) = transfer_info.frames {
+        frames.reset();
+    }
+    transfer_info.stalls = transfer_info.stalls.take().map(|stalls| StallDetector::new(stalls.threshold));
+    if let Some(ref wire_bytes) = options.wire_bytes {
+        wire_bytes.store(0, Ordering::Relaxed);
+    }
+}
+
+/// Where the measuring loop writes. The stats go to `output` unless the data
+/// itself is passed through to stdout, in which case they go to `error` along
+/// with any problems.
+struct Sinks<O, E> {
+    output: O,
+    error: E,
+    passthrough: bool,
+}
+
+impl<O: Write, E: Write> Sinks<O, E> {
+    fn stats(&mut self) -> &mut dyn Write {
+        if self.passthrough { &mut self.error } else { &mut self.output }
+    }
+}
+
+fn measure_reader_loop<R: Read>(reader: R, options: &MeasureOptions) -> TransferInfo {
+    // Stderr is not locked for the whole run so that the watchdog thread can
+    // report.
+    measure_reader_loop_into(reader, options, stdout().lock(), stderr())
+}
+
+/// Measures the stream, writing the stats and any problems to the given
+/// sinks in place of stdout and stderr.
+fn measure_reader_loop_into<R: Read, O: Write, E: Write>(mut reader: R, options: &MeasureOptions, output: O, error: E) -> TransferInfo {
+    let MeasureOptions { buffer_size, iterations, vecs, passthrough, display_mode, .. } = *options;
+    let mut sinks = Sinks { output, error, passthrough };
+
+    let terminal = TerminalReporter::new(options, if passthrough { StdStream::Stderr } else { StdStream::Stdout });
+
+    let mut tee = options.tee_path.as_ref().map(|path| match create_tee(path, options) {
+        Ok(tee) => tee,
+        Err(err) => {
+            print_err!("Error while creating tee file {}: {}", path, err);
+            exit_err();
+        }
+    });
+
+    let mut recorder = options.record_path.as_ref().map(|path| match Recorder::create(path) {
+        Ok(recorder) => recorder,
+        Err(err) => {
+            print_err!("Error while creating recording {}: {}", path, err);
+            exit_err();
+        }
+    });
+
+    let mut chunk_log = options.chunk_log_path.as_ref().map(|path| match Recorder::create_chunk_log(path) {
+        Ok(chunk_log) => chunk_log,
+        Err(err) => {
+            print_err!("Error while creating chunk log {}: {}", path, err);
+            exit_err();
+        }
+    });
+
+    let mut hasher = options.hash.map(StreamHasher::new);
+    let mut peek = options.peek.map(Peek::new);
+    let mut shaper = options.shape.clone().map(Shaper::new);
+    let mut rate_estimator = RateEstimator::new(options.rate_mode);
+    let mut tuner = if options.auto_buffer_size { Some(BufferTuner::new()) } else { None };
+    let mut chunk_size = tuner.as_ref().map_or(buffer_size, BufferTuner::size);
+    let _connection = options.metrics.as_ref().map(|metrics| metrics.connection());
+
+    let clock = options.clock.clone();
+    let mut transfer_start = clock.now();
+    let mut last_measured = transfer_start;
+    let mut last_redrawn = transfer_start;
+    let redraw = options.refresh.filter(|_| terminal.redraws());
+    let mut warmup_end = options.warmup.map(|warmup| transfer_start + warmup);
+    let mut aligner = options.align.map(Aligner::new);
+    let mut usage_start = Usage::now();
+    // Counted here as well because the kernel does not count socket reads.
+    let mut reads_since_usage_start = 0u64;
+    if let (Some(warmup), true) = (options.warmup, display_mode != DisplayMode::Quiet) {
+        if let Err(err) = writeln!(sinks.stats(), "Warming up for {:.1} seconds", warmup.as_secs_f64()) {
+            print_err_into!(sinks.error, "Error while printing output: {}", err);
+            exit_err();
+        }
+    }
+
+    // Passed through data is written by another thread so that a slow
+    // consumer does not stall reads until every buffer is queued.
+    let mut pipeline = if passthrough && !options.spliced {
+        let output = match options.pass_to {
+            Some(PassTarget::Connection(ref stream)) => stream.try_clone().and_then(|stream| forwarding_output(stream, options)),
+            Some(PassTarget::Null) => Ok(Box::new(std::io::sink()) as Box<dyn Write + Send>),
+            None => stdout_file().map(|file| Box::new(file) as Box<dyn Write + Send>),
+        };
+        match output {
+            Ok(output) => Some(Pipeline::new(output, buffer_size * vecs, PASSTHROUGH_QUEUE_DEPTH, options.flush)),
+            Err(err) => {
+                print_err_into!(sinks.error, "Error while opening the output for passed through data: {}", err);
+                exit_err();
+            }
+        }
+    } else {
+        None
+    };
+    let mut buffer = if pipeline.is_some() { Vec::new() } else { vec![0; chunk_size * vecs] };
+
+    let mut transfer_info = TransferInfo {
+        start_time: Some(SystemTime::now()),
+        read_stats: if options.read_stats { Some(ReadStats::default()) } else { None },
+        passthrough_timing: if pipeline.is_some() { Some(PassthroughTiming::default()) } else { None },
+        queue_occupancy: if pipeline.is_some() { Some(QueueOccupancy::new(PASSTHROUGH_QUEUE_DEPTH)) } else { None },
+        verifier: options.verify.clone().map(Verifier::new),
+        records: options.delimiter.map(RecordCounter::new),
+        frames: options.framing.map(FrameCounter::new),
+        stalls: options.stall_threshold.map(StallDetector::new),
+        chaos: options.chaos.map(Chaos::new),
+        wire: options.compression.or(options.compress.map(|compress| compress.codec)).map(|codec| WireStats { codec, bytes: 0 }),
+        baud: options.baud,
+        ..TransferInfo::default()
+    };
+
+    loop {
+        let mut end_loop = false;
+        let mut snapshot = false;
+        for _ in 0..iterations {
+            if daemon::stop_requested() {
+                end_loop = true;
+                break;
+            }
+            if let Some(ref controls) = options.controls {
+                // Time spent paused is left out of the transfer, as if it
+                // had not passed.
+                if controls.is_paused() {
+                    print_err_into!(sinks.error, "Paused.");
+                    terminal.print_below();
+                    let paused_at = clock.now();
+                    while controls.is_paused() && !daemon::stop_requested() {
+                        std::thread::sleep(Duration::from_millis(CONTROL_POLL_MILLIS));
+                    }
+                    let paused = clock.now().saturating_sub(paused_at);
+                    transfer_start += paused;
+                    last_measured += paused;
+                }
+                if controls.take_reset() {
+                    reset_counters(&mut transfer_info, options);
+                    rate_estimator = RateEstimator::new(options.rate_mode);
+                    transfer_start = clock.now();
+                    last_measured = transfer_start;
+                    transfer_info.start_time = Some(SystemTime::now());
+                    usage_start = Usage::now();
+                    reads_since_usage_start = 0;
+                    print_err_into!(sinks.error, "Counters reset.");
+                    terminal.print_below();
+                }
+                if controls.take_snapshot() {
+                    snapshot = true;
+                    break;
+                }
+            }
+            if let (Some(pipeline), true) = (pipeline.as_mut(), buffer.is_empty()) {
+                let wait_start = Instant::now();
+                buffer = match pipeline.take_buffer() {
+                    Ok(buffer) => buffer,
+                    Err(err) => {
+                        print_err_into!(sinks.error, "Error while writing buffer into stdout: {}", err);
+                        Failure::Write.exit();
+                    }
+                };
+                buffer.resize(chunk_size * vecs, 0);
+                if let Some(timing) = transfer_info.passthrough_timing.as_mut() {
+                    timing.write_time += wait_start.elapsed();
+                }
+            }
+
+            let timed = transfer_info.read_stats.is_some() || transfer_info.stalls.is_some() || passthrough || options.metrics.is_some();
+            let read_start = if timed { Some(Instant::now()) } else { None };
+            let capacity = buffer.len();
+            let read_result = vectored::read_chunks(&mut reader, &mut buffer, chunk_size);
+            reads_since_usage_start += 1;
+            if let (Some(start), Some(timing)) = (read_start, transfer_info.passthrough_timing.as_mut()) {
+                timing.read_time += start.elapsed();
+            }
+
+            match read_result {
+                Ok(bytes_read) => {
+                    if let (Some(start), Some(stats)) = (read_start, transfer_info.read_stats.as_mut()) {
+                        if bytes_read > 0 {
+                            stats.sizes.record(bytes_read as u64);
+                            stats.latencies.record(start.elapsed().as_nanos() as u64);
+                        }
+                    }
+                    if let (Some(start), Some(stalls), true) = (read_start, transfer_info.stalls.as_mut(), bytes_read > 0) {
+                        stalls.record(start.elapsed());
+                    }
+                    if let (Some(start), Some(metrics), true) = (read_start, options.metrics.as_ref(), bytes_read > 0) {
+                        metrics.record_read(bytes_read, start.elapsed());
+                    }
+
+                    transfer_info.last_bytes_transferred += bytes_read;
+                    transfer_info.total_bytes_transferred += bytes_read;
+                    if bytes_read == 0 {
+                        end_loop = true;
+                        break;
+                    }
+
+                    if let Some(ref mut chunk_log) = chunk_log {
+                        if let Err(err) = chunk_log.record(&buffer[0..bytes_read]) {
+                            print_err_into!(sinks.error, "Error while writing into chunk log: {}", err);
+                            exit_err();
+                        }
+                    }
+
+                    if let Some(ref mut shaper) = shaper {
+                        std::thread::sleep(shaper.reserve(bytes_read));
+                    }
+
+                    if peek.as_mut().is_some_and(|peek| peek.add(&buffer[0..bytes_read])) {
+                        print_peek(&mut sinks.error, peek.take());
+                    }
+
+                    if let Some(ref mut hasher) = hasher {
+                        hasher.update(&buffer[0..bytes_read]);
+                    }
+
+                    if let Some(ref mut verifier) = transfer_info.verifier {
+                        verifier.check(&buffer[0..bytes_read]);
+                    }
+
+                    if let Some(ref mut records) = transfer_info.records {
+                        records.count(&buffer[0..bytes_read]);
+                    }
+
+                    if let Some(ref mut frames) = transfer_info.frames {
+                        frames.count(&buffer[0..bytes_read]);
+                    }
+
+                    if let Some(ref mut tee) = tee {
+                        if let Err(err) = tee.write_all(&buffer[0..bytes_read]) {
+                            print_err_into!(sinks.error, "Error while writing buffer into tee file: {}", err);
+                            exit_err();
+                        }
+                    }
+
+                    if let Some(ref mut recorder) = recorder {
+                        if let Err(err) = recorder.record(&buffer[0..bytes_read]) {
+                            print_err_into!(sinks.error, "Error while writing buffer into recording: {}", err);
+                            exit_err();
+                        }
+                    }
+
+                    if let Some(ref mut pipeline) = pipeline {
+                        let fault = transfer_info.chaos.as_mut().and_then(|chaos| chaos.inject(&mut buffer[0..bytes_read]));
+                        if let Err(err) = pass_through(pipeline, &mut buffer, bytes_read, fault, transfer_info.queue_occupancy.as_mut()) {
+                            print_err_into!(sinks.error, "Error while writing buffer into stdout: {}", err);
+                            Failure::Write.exit();
+                        }
+                    }
+
+                    if let Some(size) = tuner.as_mut().and_then(|tuner| tuner.record(bytes_read, capacity)) {
+                        chunk_size = size;
+                        if pipeline.is_none() {
+                            buffer.resize(chunk_size * vecs, 0);
+                        }
+                    }
+                }
+
+                // A read timeout set on the socket is reported as one of
+                // these.
+                Err(ref err) if err.kind() == std::io::ErrorKind::TimedOut
+                    || (err.kind() == std::io::ErrorKind::WouldBlock && options.reads_time_out) => {
+                    print_err_into!(sinks.error, "No data received before the read timeout, stopping.");
+                    transfer_info.timed_out = true;
+                    end_loop = true;
+                    break;
+                }
+
+                // A descriptor that was left non-blocking has no data yet.
+                Err(ref err) if err.kind() == std::io::ErrorKind::WouldBlock => {
+                    transfer_info.read_errors.transient += 1;
+                    std::thread::sleep(Duration::from_millis(WOULD_BLOCK_RETRY_MILLIS));
+                }
+
+                // A read interrupted by SIGTERM stops at the top of the
+                // next iteration, and any other signal is retried.
+                Err(ref err) if err.kind() == std::io::ErrorKind::Interrupted => {
+                    if !daemon::stop_requested() {
+                        transfer_info.read_errors.transient += 1;
+                    }
+                }
+
+                Err(err) => {
+                    print_err_into!(sinks.error, "Error while reading into buffer: {}", err);
+                    transfer_info.read_errors.fatal += 1;
+                    if options.max_errors.is_some_and(|max| transfer_info.read_errors.fatal >= max) {
+                        print_err_into!(sinks.error, "Stopping after {} failed reads.", transfer_info.read_errors.fatal);
+                        end_loop = true;
+                        break;
+                    }
+                }
+            }
+
+            // A measure by size is taken as soon as it is due rather than
+            // after the rest of the iterations.
+            if options.measure_every_bytes.is_some_and(|every| transfer_info.last_bytes_transferred as u64 >= every) {
+                break;
+            }
+        }
+
+        let measure_end = clock.now();
+        let duration = measure_end.saturating_sub(last_measured);
+
+        // Everything up to the first measure after the warm-up ends is
+        // discarded and the statistics start over from there.
+        if let Some(end) = warmup_end {
+            if end_loop {
+                print_err_into!(sinks.error, "The transfer ended during the warm-up, so it is included in the results.");
+                warmup_end = None;
+            } else if duration.as_secs() > 0 {
+                reset_counters(&mut transfer_info, options);
+                last_measured = measure_end;
+                if measure_end >= end {
+                    warmup_end = None;
+                    transfer_start = measure_end;
+                    transfer_info.start_time = Some(SystemTime::now());
+                    usage_start = Usage::now();
+                    reads_since_usage_start = 0;
+                }
+                continue;
+            }
+        }
+
+        // Measures are taken every interval, every `--measure-every-bytes`
+        // or on every boundary when aligned to the wall clock, and once more
+        // when the transfer ends.
+        let due = match options.measure_every_bytes {
+            Some(every) => transfer_info.last_bytes_transferred as u64 >= every,
+            None => duration >= options.interval,
+        };
+        let measure_time = match aligner {
+            _ if snapshot => Some(SystemTime::now()),
+            Some(ref mut aligner) => aligner.take_due(),
+            None if due => Some(SystemTime::now()),
+            None => None,
+        };
+        if measure_time.is_some() || end_loop {
+            record_measure(&mut transfer_info, &mut rate_estimator, duration,
+                measure_end.saturating_sub(transfer_start), measure_time.unwrap_or_else(SystemTime::now), options);
+
+            if let Err(err) = terminal.measure_into(&mut sinks.stats(), &transfer_info) {
+                print_err_into!(sinks.error, "Error while printing output: {}", err);
+                exit_err();
+            }
+
+            last_measured = measure_end;
+            last_redrawn = measure_end;
+            transfer_info.last_bps = 0.0;
+            transfer_info.last_bytes_transferred = 0;
+        } else if redraw.is_some_and(|redraw| measure_end.saturating_sub(last_redrawn) >= redraw) {
+            // Between measures the live stats show the rate of the measure
+            // taken so far, so long intervals still update smoothly.
+            let current_bps = transfer_info.current_bps;
+            transfer_info.current_bps = bytes_per_second(transfer_info.last_bytes_transferred, duration);
+            transfer_info.elapsed = measure_end.saturating_sub(transfer_start);
+            if let Err(err) = terminal.redraw(&mut sinks.stats(), &transfer_info) {
+                print_err_into!(sinks.error, "Error while printing output: {}", err);
+                exit_err();
+            }
+            transfer_info.current_bps = current_bps;
+            last_redrawn = measure_end;
+        }
+
+        if end_loop {
+            // The stream ended before the whole head was read.
+            print_peek(&mut sinks.error, peek.take());
+
+            if let Some(ref mut pipeline) = pipeline {
+                if let Err(err) = pipeline.finish() {
+                    print_err_into!(sinks.error, "Error while writing buffer into stdout: {}", err);
+                    Failure::Write.exit();
+                }
+            }
+
+            if let Some(ref mut tee) = tee {
+                if let Err(err) = tee.flush() {
+                    print_err_into!(sinks.error, "Error while writing buffer into tee file: {}", err);
+                    exit_err();
+                }
+            }
+
+            if let Some(ref mut recorder) = recorder {
+                if let Err(err) = recorder.flush() {
+                    print_err_into!(sinks.error, "Error while writing buffer into recording: {}", err);
+                    exit_err();
+                }
+            }
+
+            if let Some(ref mut chunk_log) = chunk_log {
+                if let Err(err) = chunk_log.flush() {
+                    print_err_into!(sinks.error, "Error while writing into chunk log: {}", err);
+                    exit_err();
+                }
+            }
+
+            if let (Some(algorithm), Some(hasher)) = (options.hash, hasher.take()) {
+                transfer_info.digest = Some((algorithm, hasher.finish()));
+            }
+            transfer_info.tuned_buffer_size = tuner.as_ref().map(BufferTuner::size);
+            transfer_info.sctp = options.sctp.as_ref()
+                .map(|stats| stats.lock().expect("SCTP stats lock was poisoned").clone());
+            transfer_info.udp = options.udp.as_ref().map(|counters| counters.stats());
+            transfer_info.tcp = options.tcp_info.as_ref().map(|sampler| sampler.finish());
+            if let Some(ref state) = options.state {
+                let (totals, saved) = state.finish_run(transfer_info.total_bytes_transferred as u64, transfer_info.elapsed);
+                if let Err(err) = saved {
+                    print_err_into!(sinks.error, "Error while saving totals to {}: {}", state.path, err);
+                }
+                transfer_info.totals = Some(totals);
+            }
+            transfer_info.sources = options.sources.as_ref()
+                .map(|stats| stats.lock().expect("source stats lock was poisoned").clone());
+            transfer_info.directions = options.directions.as_ref()
+                .map(|directions| directions.lock().expect("direction stats lock was poisoned").clone());
+            transfer_info.command = options.command.as_ref()
+                .map(|stats| stats.lock().expect("command stats lock was poisoned").clone());
+            transfer_info.reconnects = options.reconnects.as_ref()
+                .map(|reconnects| reconnects.load(Ordering::Relaxed));
+            if let (Some(wire), Some(wire_bytes)) = (transfer_info.wire.as_mut(), options.wire_bytes.as_ref()) {
+                wire.bytes = wire_bytes.load(Ordering::Relaxed);
+            }
+            if let (Some(start), Some(end)) = (usage_start, Usage::now()) {
+                let mut usage = end.since(&start);
+                usage.syscalls = usage.syscalls.map(|syscalls| syscalls.with_reads_made(reads_since_usage_start));
+                transfer_info.usage = Some(usage);
+            }
+
+            if let Err(err) = terminal.finish_into(&mut sinks.stats(), &transfer_info) {
+                print_err_into!(sinks.error, "Error while printing output: {}", err);
+                exit_err();
+            }
+            return transfer_info;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+    use throughput::ManualClock;
+
+    /// Reads the data in chunks of the given sizes, taking one second of the
+    /// clock for every read including the last one that ends the stream.
+    struct PacedReader {
+        data: Cursor<Vec<u8>>,
+        chunks: Vec<usize>,
+        clock: ManualClock,
+    }
+
+    impl PacedReader {
+        fn new(chunks: &[usize], clock: &ManualClock) -> PacedReader {
+            let data = vec![0; chunks.iter().sum()];
+            PacedReader { data: Cursor::new(data), chunks: chunks.iter().rev().cloned().collect(), clock: clock.clone() }
+        }
+    }
+
+    impl Read for PacedReader {
+        fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+            self.clock.advance(Duration::from_secs(1));
+            let len = self.chunks.pop().unwrap_or(0).min(buf.len());
+            self.data.read(&mut buf[..len])
+        }
+    }
+
+    /// Keeps the rate of every measure it is given.
+    #[derive(Default)]
+    struct RateRecorder {
+        rates: Mutex<Vec<f64>>,
+    }
+
+    impl Reporter for RateRecorder {
+        fn measure(&self, transfer_info: &TransferInfo) -> std::io::Result<()> {
+            self.rates.lock().unwrap().push(transfer_info.last_bps);
+            Ok(())
+        }
+    }
+
+    fn options(clock: &ManualClock, outputs: Vec<Arc<dyn Reporter>>) -> MeasureOptions {
+        MeasureOptions {
+            buffer_size: 4096,
+            auto_buffer_size: false,
+            iterations: 1,
+            vecs: 1,
+            parallel: 1,
+            passthrough: false,
+            flush: FlushPolicy::Immediate,
+            discard: false,
+            zero_copy: false,
+            spliced: false,
+            pass_to: None,
+            io_uring: false,
+            direct: false,
+            mmap: false,
+            cpus: Vec::new(),
+            idle_timeout: None,
+            reads_time_out: false,
+            max_errors: None,
+            warmup: None,
+            interval: Duration::from_secs(1),
+            refresh: None,
+            align: None,
+            measure_every_bytes: None,
+            display_mode: DisplayMode::SummaryOnly,
+            style: Some(Style::Plain),
+            color: ColorChoice::Never,
+            color_thresholds: None,
+            compact: false,
+            graph: false,
+            max_samples: DEFAULT_MAX_SAMPLES,
+            template: None,
+            number_format: NumberFormat::default(),
+            expected_bytes: None,
+            rate_mode: RateMode::Last,
+            read_stats: false,
+            tee_path: None,
+            record_path: None,
+            chunk_log_path: None,
+            hash: None,
+            verify: None,
+            delimiter: None,
+            framing: None,
+            peek: None,
+            skip: None,
+            take: None,
+            stall_threshold: None,
+            chaos: None,
+            shape: None,
+            compression: None,
+            compress: None,
+            wire_bytes: None,
+            baud: None,
+            sctp: None,
+            udp: None,
+            tcp_info: None,
+            sources: None,
+            directions: None,
+            command: None,
+            reconnects: None,
+            metrics: None,
+            outputs,
+            state: None,
+            controls: None,
+            clock: Arc::new(clock.clone()),
+        }
+    }
+
+    #[test]
+    fn measures_every_interval_by_the_clock() {
+        let clock = ManualClock::new();
+        let recorder = Arc::new(RateRecorder::default());
+        let options = options(&clock, vec![recorder.clone()]);
+        let mut output = Vec::new();
+        let mut error = Vec::new();
+
+        let transfer_info = measure_reader_loop_into(PacedReader::new(&[1000, 3000, 2000], &clock), &options,
+            &mut output, &mut error);
+
+        // The read that ends the stream is measured too, with nothing read.
+        assert_eq!(*recorder.rates.lock().unwrap(), vec![1000.0, 3000.0, 2000.0, 0.0]);
+        assert_eq!(transfer_info.total_bytes_transferred, 6000);
+        assert_eq!(transfer_info.total_measures, 4);
+        assert_eq!(transfer_info.peak_bps, 3000.0);
+        assert_eq!(transfer_info.average_bps(), 1500.0);
+        assert_eq!(transfer_info.elapsed, Duration::from_secs(4));
+        assert!(!output.is_empty());
+        assert!(error.is_empty());
+    }
+
+    #[test]
+    fn measures_once_the_interval_has_passed() {
+        let clock = ManualClock::new();
+        let recorder = Arc::new(RateRecorder::default());
+        let options = MeasureOptions { interval: Duration::from_secs(2), ..options(&clock, vec![recorder.clone()]) };
+
+        let transfer_info = measure_reader_loop_into(PacedReader::new(&[1000, 3000, 2000], &clock), &options,
+            Vec::new(), Vec::new());
+
+        assert_eq!(*recorder.rates.lock().unwrap(), vec![2000.0, 1000.0]);
+        assert_eq!(transfer_info.peak_bps, 2000.0);
+        assert_eq!(transfer_info.average_bps(), 1500.0);
+        assert_eq!(transfer_info.elapsed, Duration::from_secs(4));
+    }
+
+    #[test]
+    fn stats_go_to_stderr_when_passing_data_through() {
+        let clock = ManualClock::new();
+        let options = MeasureOptions { passthrough: true, pass_to: Some(PassTarget::Null), ..options(&clock, Vec::new()) };
+        let mut output = Vec::new();
+        let mut error = Vec::new();
+
+        let transfer_info = measure_reader_loop_into(PacedReader::new(&[512, 512], &clock), &options, &mut output, &mut error);
+
+        assert_eq!(transfer_info.total_bytes_transferred, 1024);
+        assert!(output.is_empty());
+        assert!(!error.is_empty());
+    }
+
+    #[test]
+    fn record_measure_works_out_the_rate_over_the_duration() {
+        let clock = ManualClock::new();
+        let options = options(&clock, Vec::new());
+        let mut transfer_info = TransferInfo { last_bytes_transferred: 1024 * 1024, ..TransferInfo::default() };
+        let mut rate_estimator = RateEstimator::new(options.rate_mode);
+
+        record_measure(&mut transfer_info, &mut rate_estimator, Duration::from_millis(500), Duration::from_secs(3),
+            SystemTime::now(), &options);
+
+        assert_eq!(transfer_info.last_bps, 2.0 * 1024.0 * 1024.0);
+        assert_eq!(transfer_info.peak_bps, transfer_info.last_bps);
+        assert_eq!(transfer_info.elapsed, Duration::from_secs(3));
+        assert_eq!(units::byte_to_mem_units(transfer_info.last_bps), (2.0, "MB"));
+    }
+}
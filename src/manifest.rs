@@ -0,0 +1,61 @@
+//! Writing a manifest of each hashed run, such as a backup piped through
+//! throughput, so that what was measured can be checked later against the
+//! digest of what was stored. The manifest is rewritten after every run with
+//! all the runs so far, and its keys do not change between versions of the
+//! same format.
+
+use std::fs;
+use std::io;
+use std::time::SystemTime;
+
+use serde_json::Value;
+
+use display::format_timestamp;
+use TransferInfo;
+
+/// The format of the manifest.
+const MANIFEST_VERSION: u64 = 1;
+
+pub struct Manifest {
+    path: String,
+    runs: Vec<Value>,
+}
+
+impl Manifest {
+    pub fn new(path: &str) -> Manifest {
+        Manifest { path: path.to_string(), runs: Vec::new() }
+    }
+
+    /// Adds the finished run and replaces the file, through a temporary file
+    /// so that a crash cannot leave it half written.
+    pub fn record(&mut self, transfer_info: &TransferInfo) -> io::Result<()> {
+        let finished = SystemTime::now();
+        let started = transfer_info.start_time.unwrap_or(finished);
+        let (algorithm, digest) = match transfer_info.digest {
+            Some((algorithm, ref digest)) => (Some(algorithm.name()), Some(digest.as_str())),
+            None => (None, None),
+        };
+        self.runs.push(json!({
+            "run": self.runs.len() + 1,
+            "size_bytes": transfer_info.total_bytes_transferred,
+            "duration_secs": transfer_info.elapsed.as_secs_f64(),
+            "hash_algorithm": algorithm,
+            "hash": digest,
+            "average_bps": transfer_info.average_bps(),
+            "started": format_timestamp(started),
+            "finished": format_timestamp(finished),
+        }));
+
+        let content = json!({
+            "version": MANIFEST_VERSION,
+            "runs": self.runs,
+        });
+        let temporary = format!("{}.tmp", self.path);
+        fs::write(&temporary, format!("{:#}\n", content))?;
+        fs::rename(&temporary, &self.path)
+    }
+
+    pub fn path(&self) -> &str {
+        &self.path
+    }
+}
@@ -0,0 +1,47 @@
+//! Writing a man page from the help of the subcommands and their arguments,
+//! so that it never falls behind them.
+
+use std::io::{self, Write};
+
+use clap::ErrorKind;
+
+use cli;
+
+/// Writes the man page in roff, with the help of throughput and then of each
+/// subcommand as it is printed by `--help`.
+pub fn write<W: Write>(out: &mut W) -> io::Result<()> {
+    writeln!(out, ".TH THROUGHPUT 1 \"\" \"throughput {}\" \"User Commands\"", env!("CARGO_PKG_VERSION"))?;
+    writeln!(out, ".SH NAME")?;
+    writeln!(out, "throughput \\- measures the throughput of stdin, files, commands or sockets")?;
+    writeln!(out, ".SH DESCRIPTION")?;
+    write_preformatted(out, &help(&[]))?;
+    for subcommand in cli::SUBCOMMANDS {
+        writeln!(out, ".SH \"THROUGHPUT {}\"", subcommand.to_uppercase())?;
+        write_preformatted(out, &help(&[subcommand]))?;
+    }
+    Ok(())
+}
+
+/// The help that `throughput <args> --help` prints.
+fn help(args: &[&str]) -> String {
+    let args = std::iter::once("throughput").chain(args.iter().cloned()).chain(std::iter::once("--help"));
+    match cli::app().get_matches_from_safe(args) {
+        Err(err) if err.kind == ErrorKind::HelpDisplayed => err.message,
+        _ => unreachable!("--help always displays the help"),
+    }
+}
+
+/// Writes text as it is, without the lines being filled or adjusted.
+fn write_preformatted<W: Write>(out: &mut W, text: &str) -> io::Result<()> {
+    writeln!(out, ".nf")?;
+    for line in text.lines() {
+        let line = line.replace('\\', "\\e");
+        // A line starting with a period or an apostrophe would be taken as a
+        // request.
+        if line.starts_with('.') || line.starts_with('\'') {
+            write!(out, "\\&")?;
+        }
+        writeln!(out, "{}", line)?;
+    }
+    writeln!(out, ".fi")
+}
@@ -0,0 +1,71 @@
+use std::io::{self, Read};
+use std::sync::mpsc::{self, Receiver};
+use std::thread;
+
+use affinity;
+
+/// The number of chunks each source may have waiting to be read.
+const CHUNKS_PER_SOURCE: usize = 4;
+
+/// Reads from several sources at once, each on its own thread, and returns
+/// their data in whatever order it arrives. Reaches the end once every source
+/// has.
+pub struct MergedReader {
+    chunks: Receiver<io::Result<Vec<u8>>>,
+
+    /// The chunk currently being handed out and how much of it has been read.
+    current: Vec<u8>,
+    position: usize,
+}
+
+impl MergedReader {
+    /// Starts a thread for each source, pinned to its share of `cpus`.
+    pub fn new<R: Read + Send + 'static>(sources: Vec<R>, buffer_size: usize, cpus: &[usize]) -> MergedReader {
+        let (sender, chunks) = mpsc::sync_channel(sources.len() * CHUNKS_PER_SOURCE);
+
+        for (index, mut source) in sources.into_iter().enumerate() {
+            let sender = sender.clone();
+            let cpus = cpus.to_vec();
+            thread::spawn(move || {
+                affinity::pin_connection_thread(&cpus, index);
+                loop {
+                    let mut chunk = vec![0; buffer_size];
+                    let result = match source.read(&mut chunk) {
+                        Ok(0) => return,
+                        Ok(count) => {
+                            chunk.truncate(count);
+                            Ok(chunk)
+                        },
+                        Err(ref err) if err.kind() == io::ErrorKind::Interrupted => continue,
+                        Err(err) => Err(err),
+                    };
+
+                    let failed = result.is_err();
+                    if sender.send(result).is_err() || failed { return; }
+                }
+            });
+        }
+
+        MergedReader { chunks, current: Vec::new(), position: 0 }
+    }
+}
+
+impl Read for MergedReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.position == self.current.len() {
+            match self.chunks.recv() {
+                Ok(chunk) => {
+                    self.current = chunk?;
+                    self.position = 0;
+                },
+                // Every source has finished.
+                Err(_) => return Ok(0),
+            }
+        }
+
+        let count = buf.len().min(self.current.len() - self.position);
+        buf[..count].copy_from_slice(&self.current[self.position..self.position + count]);
+        self.position += count;
+        Ok(count)
+    }
+}
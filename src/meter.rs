@@ -0,0 +1,276 @@
+//! A byte counter that can be shared between the streams being measured and
+//! whatever reports on them.
+
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError, Sender};
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// Where the time that rates are worked out over comes from. Meters use the
+/// system clock unless given another, such as a `ManualClock` that a test
+/// moves forward itself so that the rates it checks never depend on how
+/// fast the test ran.
+pub trait Clock: Send + Sync {
+    /// The time since a fixed starting point, which never goes backwards.
+    fn now(&self) -> Duration;
+}
+
+/// The monotonic system clock, counted from when it was created.
+#[derive(Clone, Copy, Debug)]
+pub struct SystemClock {
+    start: Instant,
+}
+
+impl SystemClock {
+    pub fn new() -> SystemClock {
+        SystemClock { start: Instant::now() }
+    }
+}
+
+impl Default for SystemClock {
+    fn default() -> SystemClock {
+        SystemClock::new()
+    }
+}
+
+impl Clock for SystemClock {
+    fn now(&self) -> Duration {
+        self.start.elapsed()
+    }
+}
+
+/// A clock that only moves when told to. Clones share the same time.
+#[derive(Clone, Debug, Default)]
+pub struct ManualClock {
+    nanos: Arc<AtomicU64>,
+}
+
+impl ManualClock {
+    pub fn new() -> ManualClock {
+        ManualClock::default()
+    }
+
+    /// Moves the clock forward by the given duration.
+    pub fn advance(&self, duration: Duration) {
+        self.nanos.fetch_add(duration.as_nanos() as u64, Ordering::SeqCst);
+    }
+}
+
+impl Clock for ManualClock {
+    fn now(&self) -> Duration {
+        Duration::from_nanos(self.nanos.load(Ordering::SeqCst))
+    }
+}
+
+/// Counts the bytes passing through one or more streams. Clones share the
+/// same count, so a clone can be given to every stream and another kept to
+/// take snapshots from.
+#[derive(Clone)]
+pub struct ThroughputMeter {
+    inner: Arc<Inner>,
+}
+
+struct Inner {
+    clock: Box<dyn Clock>,
+    start: Duration,
+    bytes: AtomicU64,
+}
+
+/// The bytes counted by a meter up to a point in time.
+#[derive(Clone, Copy, Debug)]
+pub struct Snapshot {
+    /// Every byte counted since the meter was created.
+    pub total_bytes: u64,
+
+    /// The time since the meter was created.
+    pub elapsed: Duration,
+}
+
+/// The bytes counted by a meter during one interval.
+#[derive(Clone, Copy, Debug)]
+pub struct IntervalStats {
+    /// Bytes counted during the interval.
+    pub bytes: u64,
+
+    /// How long the interval actually lasted.
+    pub interval: Duration,
+
+    /// Every byte counted since the meter was created.
+    pub total_bytes: u64,
+
+    /// The time since the meter was created, at the end of the interval.
+    pub elapsed: Duration,
+}
+
+/// Keeps an observer registered with `ThroughputMeter::observe` running.
+/// Dropping it stops the observer without calling it again.
+pub struct Observer {
+    _stop: Sender<()>,
+}
+
+impl ThroughputMeter {
+    pub fn new() -> ThroughputMeter {
+        ThroughputMeter::with_clock(SystemClock::new())
+    }
+
+    /// Creates a meter that takes the time of its snapshots from the clock.
+    /// Observers and subscribers still wait for their intervals in real
+    /// time, but the stats they are given are timed by the clock.
+    pub fn with_clock<C: Clock + 'static>(clock: C) -> ThroughputMeter {
+        let start = clock.now();
+        ThroughputMeter {
+            inner: Arc::new(Inner { clock: Box::new(clock), start, bytes: AtomicU64::new(0) }),
+        }
+    }
+
+    /// Counts bytes that passed through a stream.
+    pub fn record(&self, bytes: usize) {
+        self.inner.bytes.fetch_add(bytes as u64, Ordering::Relaxed);
+    }
+
+    /// Every byte counted so far.
+    pub fn total_bytes(&self) -> u64 {
+        self.inner.bytes.load(Ordering::Relaxed)
+    }
+
+    pub fn snapshot(&self) -> Snapshot {
+        Snapshot { total_bytes: self.total_bytes(), elapsed: self.inner.clock.now().saturating_sub(self.inner.start) }
+    }
+
+    /// Calls `observer` from a background thread at the end of every
+    /// interval, until the returned `Observer` is dropped.
+    pub fn observe<F>(&self, interval: Duration, mut observer: F) -> Observer
+        where F: FnMut(&IntervalStats) + Send + 'static
+    {
+        let (stop, stopped) = mpsc::channel();
+        let meter = self.clone();
+        thread::spawn(move || {
+            let mut last = meter.snapshot();
+            while let Err(RecvTimeoutError::Timeout) = stopped.recv_timeout(interval) {
+                let now = meter.snapshot();
+                observer(&now.since(&last));
+                last = now;
+            }
+        });
+        Observer { _stop: stop }
+    }
+
+    /// Sends the stats for every interval over the returned channel, until
+    /// the receiver is dropped.
+    pub fn subscribe(&self, interval: Duration) -> Receiver<IntervalStats> {
+        let (sender, receiver) = mpsc::channel();
+        let meter = self.clone();
+        thread::spawn(move || {
+            let mut last = meter.snapshot();
+            loop {
+                thread::sleep(interval);
+                let now = meter.snapshot();
+                if sender.send(now.since(&last)).is_err() {
+                    break;
+                }
+                last = now;
+            }
+        });
+        receiver
+    }
+}
+
+impl Default for ThroughputMeter {
+    fn default() -> ThroughputMeter {
+        ThroughputMeter::new()
+    }
+}
+
+impl Snapshot {
+    /// The average rate in bytes per second since the meter was created.
+    pub fn average_bps(&self) -> f64 {
+        let secs = self.elapsed.as_secs_f64();
+        if secs > 0.0 { self.total_bytes as f64 / secs } else { 0.0 }
+    }
+
+    /// The bytes counted between an earlier snapshot and this one.
+    pub fn since(&self, earlier: &Snapshot) -> IntervalStats {
+        IntervalStats {
+            bytes: self.total_bytes.saturating_sub(earlier.total_bytes),
+            interval: self.elapsed.saturating_sub(earlier.elapsed),
+            total_bytes: self.total_bytes,
+            elapsed: self.elapsed,
+        }
+    }
+}
+
+impl IntervalStats {
+    /// The rate in bytes per second during the interval.
+    pub fn bps(&self) -> f64 {
+        let secs = self.interval.as_secs_f64();
+        if secs > 0.0 { self.bytes as f64 / secs } else { 0.0 }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn meter() -> (ThroughputMeter, ManualClock) {
+        let clock = ManualClock::new();
+        (ThroughputMeter::with_clock(clock.clone()), clock)
+    }
+
+    #[test]
+    fn interval_rates_are_timed_by_the_clock() {
+        let (meter, clock) = meter();
+        let start = meter.snapshot();
+
+        meter.record(4096);
+        clock.advance(Duration::from_secs(2));
+        let first = meter.snapshot();
+        let interval = first.since(&start);
+        assert_eq!(interval.bytes, 4096);
+        assert_eq!(interval.interval, Duration::from_secs(2));
+        assert_eq!(interval.bps(), 2048.0);
+
+        meter.record(1000);
+        clock.advance(Duration::from_millis(500));
+        let interval = meter.snapshot().since(&first);
+        assert_eq!(interval.bytes, 1000);
+        assert_eq!(interval.total_bytes, 5096);
+        assert_eq!(interval.elapsed, Duration::from_millis(2500));
+        assert_eq!(interval.bps(), 2000.0);
+    }
+
+    #[test]
+    fn average_covers_every_interval() {
+        let (meter, clock) = meter();
+        for bytes in &[1000, 3000, 2000] {
+            meter.record(*bytes);
+            clock.advance(Duration::from_secs(1));
+        }
+
+        let snapshot = meter.snapshot();
+        assert_eq!(snapshot.total_bytes, 6000);
+        assert_eq!(snapshot.elapsed, Duration::from_secs(3));
+        assert_eq!(snapshot.average_bps(), 2000.0);
+    }
+
+    #[test]
+    fn no_time_means_no_rate() {
+        let (meter, _clock) = meter();
+        meter.record(1024);
+
+        let snapshot = meter.snapshot();
+        assert_eq!(snapshot.average_bps(), 0.0);
+        assert_eq!(snapshot.since(&snapshot).bps(), 0.0);
+    }
+
+    #[test]
+    fn clones_share_the_count_and_the_clock() {
+        let (meter, clock) = meter();
+        let counter = meter.clone();
+        counter.record(512);
+        clock.clone().advance(Duration::from_secs(1));
+
+        assert_eq!(meter.total_bytes(), 512);
+        assert_eq!(meter.snapshot().average_bps(), 512.0);
+    }
+}
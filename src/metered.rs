@@ -0,0 +1,209 @@
+//! Stream adapters that count the bytes read or written through them, for
+//! `std::io` streams and, with the `tokio` feature, Tokio streams. Any
+//! reader or writer can be metered, so tests can measure an in-memory
+//! source or sink against a `ManualClock`.
+
+use std::io::{self, IoSlice, IoSliceMut, Read, Write};
+#[cfg(feature = "tokio")]
+use std::pin::Pin;
+#[cfg(feature = "tokio")]
+use std::task::{Context, Poll};
+
+#[cfg(feature = "tokio")]
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+
+use meter::ThroughputMeter;
+
+/// Counts every byte read from the inner reader.
+pub struct MeteredReader<R> {
+    inner: R,
+    meter: ThroughputMeter,
+}
+
+/// Counts every byte written to the inner writer.
+pub struct MeteredWriter<W> {
+    inner: W,
+    meter: ThroughputMeter,
+}
+
+impl<R> MeteredReader<R> {
+    pub fn new(inner: R, meter: ThroughputMeter) -> MeteredReader<R> {
+        MeteredReader { inner, meter }
+    }
+
+    pub fn meter(&self) -> &ThroughputMeter {
+        &self.meter
+    }
+
+    pub fn get_ref(&self) -> &R {
+        &self.inner
+    }
+
+    pub fn get_mut(&mut self) -> &mut R {
+        &mut self.inner
+    }
+
+    pub fn into_inner(self) -> R {
+        self.inner
+    }
+}
+
+impl<R: Read> Read for MeteredReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let bytes_read = self.inner.read(buf)?;
+        self.meter.record(bytes_read);
+        Ok(bytes_read)
+    }
+
+    fn read_vectored(&mut self, bufs: &mut [IoSliceMut]) -> io::Result<usize> {
+        let bytes_read = self.inner.read_vectored(bufs)?;
+        self.meter.record(bytes_read);
+        Ok(bytes_read)
+    }
+}
+
+impl<W> MeteredWriter<W> {
+    pub fn new(inner: W, meter: ThroughputMeter) -> MeteredWriter<W> {
+        MeteredWriter { inner, meter }
+    }
+
+    pub fn meter(&self) -> &ThroughputMeter {
+        &self.meter
+    }
+
+    pub fn get_ref(&self) -> &W {
+        &self.inner
+    }
+
+    pub fn get_mut(&mut self) -> &mut W {
+        &mut self.inner
+    }
+
+    pub fn into_inner(self) -> W {
+        self.inner
+    }
+}
+
+impl<W: Write> Write for MeteredWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let written = self.inner.write(buf)?;
+        self.meter.record(written);
+        Ok(written)
+    }
+
+    fn write_vectored(&mut self, bufs: &[IoSlice]) -> io::Result<usize> {
+        let written = self.inner.write_vectored(bufs)?;
+        self.meter.record(written);
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+/// Counts every byte read from the inner stream.
+#[cfg(feature = "tokio")]
+pub struct MeteredAsyncReader<R> {
+    inner: R,
+    meter: ThroughputMeter,
+}
+
+/// Counts every byte written to the inner stream.
+#[cfg(feature = "tokio")]
+pub struct MeteredAsyncWriter<W> {
+    inner: W,
+    meter: ThroughputMeter,
+}
+
+#[cfg(feature = "tokio")]
+impl<R> MeteredAsyncReader<R> {
+    pub fn new(inner: R, meter: ThroughputMeter) -> MeteredAsyncReader<R> {
+        MeteredAsyncReader { inner, meter }
+    }
+
+    pub fn meter(&self) -> &ThroughputMeter {
+        &self.meter
+    }
+
+    pub fn get_ref(&self) -> &R {
+        &self.inner
+    }
+
+    pub fn get_mut(&mut self) -> &mut R {
+        &mut self.inner
+    }
+
+    pub fn into_inner(self) -> R {
+        self.inner
+    }
+}
+
+#[cfg(feature = "tokio")]
+impl<R: AsyncRead + Unpin> AsyncRead for MeteredAsyncReader<R> {
+    fn poll_read(self: Pin<&mut Self>, cx: &mut Context, buf: &mut ReadBuf) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        let filled = buf.filled().len();
+        let result = Pin::new(&mut this.inner).poll_read(cx, buf);
+        if let Poll::Ready(Ok(())) = result {
+            this.meter.record(buf.filled().len() - filled);
+        }
+        result
+    }
+}
+
+#[cfg(feature = "tokio")]
+impl<W> MeteredAsyncWriter<W> {
+    pub fn new(inner: W, meter: ThroughputMeter) -> MeteredAsyncWriter<W> {
+        MeteredAsyncWriter { inner, meter }
+    }
+
+    pub fn meter(&self) -> &ThroughputMeter {
+        &self.meter
+    }
+
+    pub fn get_ref(&self) -> &W {
+        &self.inner
+    }
+
+    pub fn get_mut(&mut self) -> &mut W {
+        &mut self.inner
+    }
+
+    pub fn into_inner(self) -> W {
+        self.inner
+    }
+}
+
+#[cfg(feature = "tokio")]
+impl<W: AsyncWrite + Unpin> AsyncWrite for MeteredAsyncWriter<W> {
+    fn poll_write(self: Pin<&mut Self>, cx: &mut Context, buf: &[u8]) -> Poll<io::Result<usize>> {
+        let this = self.get_mut();
+        let result = Pin::new(&mut this.inner).poll_write(cx, buf);
+        if let Poll::Ready(Ok(written)) = result {
+            this.meter.record(written);
+        }
+        result
+    }
+
+    fn poll_write_vectored(self: Pin<&mut Self>, cx: &mut Context, bufs: &[IoSlice]) -> Poll<io::Result<usize>> {
+        let this = self.get_mut();
+        let result = Pin::new(&mut this.inner).poll_write_vectored(cx, bufs);
+        if let Poll::Ready(Ok(written)) = result {
+            this.meter.record(written);
+        }
+        result
+    }
+
+    fn is_write_vectored(&self) -> bool {
+        self.inner.is_write_vectored()
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.get_mut().inner).poll_flush(cx)
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.get_mut().inner).poll_shutdown(cx)
+    }
+}
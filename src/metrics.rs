@@ -0,0 +1,177 @@
+//! A minimal HTTP endpoint that exposes measurements in the Prometheus text
+//! format so long running instances can be scraped. The latency and size of
+//! reads are exported as histograms whose buckets can be chosen to match the
+//! rest of a monitoring stack.
+
+use std::io::{self, BufRead, BufReader, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream};
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+/// How long a scraper has to send its request.
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// The upper bounds of the read latency buckets used unless others are
+/// given, in seconds.
+pub const DEFAULT_LATENCY_BUCKETS: [f64; 11] = [0.00001, 0.00005, 0.0001, 0.0005, 0.001, 0.005, 0.01, 0.05, 0.1, 0.5, 1.0];
+
+/// The upper bounds of the read size buckets used unless others are given,
+/// in bytes.
+pub const DEFAULT_SIZE_BUCKETS: [f64; 7] = [64.0, 512.0, 4096.0, 16384.0, 65536.0, 262144.0, 1048576.0];
+
+/// Measurements shared between the measuring thread and the endpoint.
+pub struct Metrics {
+    bytes_total: AtomicU64,
+    current_bps: AtomicU64,
+    average_bps: AtomicU64,
+    active_connections: AtomicUsize,
+    read_latencies: Mutex<Buckets>,
+    read_sizes: Mutex<Buckets>,
+}
+
+impl Metrics {
+    /// Creates the metrics with the upper bounds of the read latency buckets
+    /// in seconds and of the read size buckets in bytes, each in increasing
+    /// order.
+    pub fn new(latency_buckets: Vec<f64>, size_buckets: Vec<f64>) -> Metrics {
+        Metrics {
+            bytes_total: AtomicU64::new(0),
+            current_bps: AtomicU64::new(0),
+            average_bps: AtomicU64::new(0),
+            active_connections: AtomicUsize::new(0),
+            read_latencies: Mutex::new(Buckets::new(latency_buckets)),
+            read_sizes: Mutex::new(Buckets::new(size_buckets)),
+        }
+    }
+
+    /// Records a read that returned data.
+    pub fn record_read(&self, bytes: usize, latency: Duration) {
+        self.read_latencies.lock().expect("metrics lock was poisoned").record(latency.as_secs_f64());
+        self.read_sizes.lock().expect("metrics lock was poisoned").record(bytes as f64);
+    }
+
+    /// Records a measure of the transfer.
+    pub fn record(&self, bytes: usize, current_bps: f64, average_bps: f64) {
+        self.bytes_total.fetch_add(bytes as u64, Ordering::Relaxed);
+        self.current_bps.store(current_bps.to_bits(), Ordering::Relaxed);
+        self.average_bps.store(average_bps.to_bits(), Ordering::Relaxed);
+    }
+
+    /// Counts a stream as being measured until the returned guard is dropped.
+    pub fn connection(&self) -> ConnectionGuard<'_> {
+        self.active_connections.fetch_add(1, Ordering::Relaxed);
+        ConnectionGuard { metrics: self }
+    }
+
+    fn render(&self) -> String {
+        let mut output = String::new();
+        write_metric(&mut output, "throughput_bytes_total", "counter",
+            "Total number of bytes measured.",
+            self.bytes_total.load(Ordering::Relaxed) as f64);
+        write_metric(&mut output, "throughput_current_bps", "gauge",
+            "Current transfer speed in bytes per second.",
+            f64::from_bits(self.current_bps.load(Ordering::Relaxed)));
+        write_metric(&mut output, "throughput_average_bps", "gauge",
+            "Average transfer speed of the current transfer in bytes per second.",
+            f64::from_bits(self.average_bps.load(Ordering::Relaxed)));
+        write_metric(&mut output, "throughput_active_connections", "gauge",
+            "Number of streams currently being measured.",
+            self.active_connections.load(Ordering::Relaxed) as f64);
+        self.read_latencies.lock().expect("metrics lock was poisoned").write(&mut output,
+            "throughput_read_duration_seconds", "Time spent in reads that returned data.");
+        self.read_sizes.lock().expect("metrics lock was poisoned").write(&mut output,
+            "throughput_read_size_bytes", "Bytes returned by reads that returned data.");
+        output
+    }
+}
+
+/// A Prometheus histogram, counting the values that fall into each bucket.
+struct Buckets {
+    bounds: Vec<f64>,
+
+    /// The values in each bucket alone, one more than the bounds for the
+    /// values above the last.
+    counts: Vec<u64>,
+
+    sum: f64,
+}
+
+impl Buckets {
+    fn new(bounds: Vec<f64>) -> Buckets {
+        let counts = vec![0; bounds.len() + 1];
+        Buckets { bounds, counts, sum: 0.0 }
+    }
+
+    fn record(&mut self, value: f64) {
+        let index = self.bounds.partition_point(|&bound| bound < value);
+        self.counts[index] += 1;
+        self.sum += value;
+    }
+
+    /// Writes the buckets as the cumulative counts Prometheus expects.
+    fn write(&self, output: &mut String, name: &str, help: &str) {
+        output.push_str(&format!("# HELP {} {}\n# TYPE {} histogram\n", name, help, name));
+        let mut cumulative = 0;
+        for (bound, count) in self.bounds.iter().zip(&self.counts) {
+            cumulative += count;
+            output.push_str(&format!("{}_bucket{{le=\"{}\"}} {}\n", name, bound, cumulative));
+        }
+        cumulative += self.counts[self.bounds.len()];
+        output.push_str(&format!("{}_bucket{{le=\"+Inf\"}} {}\n", name, cumulative));
+        output.push_str(&format!("{}_sum {}\n{}_count {}\n", name, self.sum, name, cumulative));
+    }
+}
+
+/// Keeps a stream counted as active while it is alive.
+pub struct ConnectionGuard<'a> {
+    metrics: &'a Metrics,
+}
+
+impl<'a> Drop for ConnectionGuard<'a> {
+    fn drop(&mut self) {
+        self.metrics.active_connections.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+fn write_metric(output: &mut String, name: &str, kind: &str, help: &str, value: f64) {
+    output.push_str(&format!("# HELP {} {}\n# TYPE {} {}\n{} {}\n", name, help, name, kind, name, value));
+}
+
+/// Binds the endpoint and serves `/metrics` from a background thread.
+pub fn serve(addr: SocketAddr, metrics: Arc<Metrics>) -> io::Result<SocketAddr> {
+    let listener = TcpListener::bind(addr)?;
+    let local_addr = listener.local_addr()?;
+    thread::spawn(move || {
+        // A scraper that misbehaves should not stop the endpoint.
+        for stream in listener.incoming().flatten() {
+            let _ = respond(stream, &metrics);
+        }
+    });
+    Ok(local_addr)
+}
+
+fn respond(mut stream: TcpStream, metrics: &Metrics) -> io::Result<()> {
+    stream.set_read_timeout(Some(REQUEST_TIMEOUT))?;
+    let mut reader = BufReader::new(stream.try_clone()?);
+
+    let mut request_line = String::new();
+    reader.read_line(&mut request_line)?;
+    // The headers are not needed but are read so the client sees a clean close.
+    let mut header = String::new();
+    while reader.read_line(&mut header)? > 0 && header.trim_end() != "" {
+        header.clear();
+    }
+
+    let mut parts = request_line.split_whitespace();
+    let (status, content_type, body) = match (parts.next(), parts.next()) {
+        (Some("GET"), Some("/metrics")) => ("200 OK", "text/plain; version=0.0.4", metrics.render()),
+        (Some("GET"), _) => ("404 Not Found", "text/plain", "Not Found\n".to_string()),
+        _ => ("405 Method Not Allowed", "text/plain", "Method Not Allowed\n".to_string()),
+    };
+
+    write!(stream, "HTTP/1.1 {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        status, content_type, body.len(), body)?;
+    stream.flush()
+}
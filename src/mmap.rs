@@ -0,0 +1,61 @@
+//! Reading a file through a memory map on Unix. The file is mapped once and
+//! its pages are copied out of the page cache as they are faulted in, without
+//! a `read(2)` call per buffer.
+
+use std::fs::File;
+use std::io::{self, Read};
+use std::os::unix::io::AsRawFd;
+use std::ptr;
+use std::slice;
+
+/// A reader over the whole of a memory mapped file.
+pub struct MappedFile {
+    address: *mut libc::c_void,
+    len: usize,
+    position: usize,
+}
+
+impl MappedFile {
+    /// Maps the file, which may be closed afterwards.
+    pub fn new(file: &File) -> io::Result<MappedFile> {
+        let len = file.metadata()?.len() as usize;
+
+        // Empty files cannot be mapped, but there is nothing to read anyway.
+        if len == 0 {
+            return Ok(MappedFile { address: ptr::null_mut(), len, position: 0 });
+        }
+
+        let address = unsafe {
+            libc::mmap(ptr::null_mut(), len, libc::PROT_READ, libc::MAP_PRIVATE, file.as_raw_fd(), 0)
+        };
+        if address == libc::MAP_FAILED {
+            return Err(io::Error::last_os_error());
+        }
+
+        // Only a hint, so a failure is not worth reporting.
+        unsafe { libc::madvise(address, len, libc::MADV_SEQUENTIAL) };
+        Ok(MappedFile { address, len, position: 0 })
+    }
+
+    fn contents(&self) -> &[u8] {
+        if self.len == 0 { &[] } else { unsafe { slice::from_raw_parts(self.address as *const u8, self.len) } }
+    }
+}
+
+impl Read for MappedFile {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let remaining = &self.contents()[self.position..];
+        let count = remaining.len().min(buf.len());
+        buf[..count].copy_from_slice(&remaining[..count]);
+        self.position += count;
+        Ok(count)
+    }
+}
+
+impl Drop for MappedFile {
+    fn drop(&mut self) {
+        if self.len > 0 {
+            unsafe { libc::munmap(self.address, self.len) };
+        }
+    }
+}
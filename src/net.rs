@@ -0,0 +1,843 @@
+use std::fmt;
+use std::io::{self, Read, Write};
+use std::ops::RangeInclusive;
+use std::time::{Duration, Instant, SystemTime};
+use std::net::{IpAddr, SocketAddr, TcpListener, TcpStream, ToSocketAddrs, UdpSocket};
+use std::sync::mpsc;
+use std::thread;
+
+use socket2::{Domain, Protocol, SockRef, Socket, TcpKeepalive, Type};
+
+use proxy::Proxy;
+
+/// The number of pending connections a listener will queue.
+const LISTEN_BACKLOG: i32 = 128;
+
+/// How long a connection attempt gets before the next address is tried
+/// alongside it when a host has addresses of both families, as suggested by
+/// RFC 8305.
+const CONNECTION_ATTEMPT_DELAY: Duration = Duration::from_millis(250);
+
+/// How long the peer gets to close a connection that has been shut down for
+/// writing before it is closed regardless.
+const DRAIN_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Starts the line a client can send before its data to label its
+/// connection, such as `throughput-label web-1`.
+pub const LABEL_PREAMBLE: &[u8] = b"throughput-label ";
+
+/// The longest label a client can send.
+pub const MAX_LABEL_LENGTH: usize = 256;
+
+/// The addresses that `--addr any` expands to.
+pub const ANY_ADDRESSES: [&str; 2] = ["0.0.0.0", "::"];
+
+/// Which address family should be tried first when a host name resolves
+/// to more than one address.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum AddrPreference {
+    /// Keep the order returned by the resolver.
+    Any,
+    /// Try IPv4 addresses before IPv6 addresses.
+    V4,
+    /// Try IPv6 addresses before IPv4 addresses.
+    V6,
+    /// Only use IPv4 addresses.
+    OnlyV4,
+    /// Only use IPv6 addresses.
+    OnlyV6,
+}
+
+impl AddrPreference {
+    pub fn parse(value: &str) -> Option<AddrPreference> {
+        match value {
+            "any" => Some(AddrPreference::Any),
+            "ipv4" | "v4" | "4" => Some(AddrPreference::V4),
+            "ipv6" | "v6" | "6" => Some(AddrPreference::V6),
+            _ => None,
+        }
+    }
+}
+
+/// The ports a listener may use, tried in order until one of them binds.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct PortRange {
+    pub first: u16,
+    pub last: u16,
+}
+
+impl PortRange {
+    /// Parses a single port or an inclusive range such as `5000-5010`. Port 0
+    /// asks for an ephemeral port and cannot be part of a range.
+    pub fn parse(value: &str) -> Option<PortRange> {
+        let (first, last) = match value.find('-') {
+            Some(dash) => (value[..dash].trim().parse().ok()?, value[dash + 1..].trim().parse().ok()?),
+            None => {
+                let port = value.trim().parse().ok()?;
+                (port, port)
+            },
+        };
+        if first > last || (first == 0 && last != 0) {
+            return None;
+        }
+        Some(PortRange { first, last })
+    }
+
+    /// Whether the port is picked while binding rather than given exactly.
+    pub fn is_chosen(&self) -> bool {
+        self.first == 0 || self.first != self.last
+    }
+
+    pub fn ports(&self) -> RangeInclusive<u16> {
+        self.first..=self.last
+    }
+}
+
+impl fmt::Display for PortRange {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.first == self.last {
+            write!(f, "{}", self.first)
+        } else {
+            write!(f, "{}-{}", self.first, self.last)
+        }
+    }
+}
+
+/// Options applied to measured sockets before any data is transferred.
+#[derive(Clone, Default, Debug)]
+pub struct SocketOptions {
+    /// Size of the kernel receive buffer (SO_RCVBUF).
+    pub recv_buffer_size: Option<usize>,
+
+    /// Size of the kernel send buffer (SO_SNDBUF).
+    pub send_buffer_size: Option<usize>,
+
+    /// Disables Nagle's algorithm (TCP_NODELAY).
+    pub nodelay: bool,
+
+    /// Idle time before TCP keepalive probes are sent (SO_KEEPALIVE).
+    pub keepalive: Option<Duration>,
+
+    /// The congestion control algorithm, such as bbr or cubic
+    /// (TCP_CONGESTION). Only Linux lets it be chosen.
+    pub congestion: Option<String>,
+
+    /// Allows a listener to bind to a port that still has connections in
+    /// TIME_WAIT (SO_REUSEADDR).
+    pub reuse_address: bool,
+
+    /// Allows several sockets to bind to the same port, with the kernel
+    /// distributing incoming connections between them (SO_REUSEPORT).
+    pub reuse_port: bool,
+
+    /// How long to wait for a connection before giving up.
+    pub accept_timeout: Option<Duration>,
+
+    /// How long a read may wait for data before it fails (SO_RCVTIMEO).
+    pub read_timeout: Option<Duration>,
+
+    /// How many more times to try connecting when no address accepts.
+    pub connect_retries: u32,
+
+    /// How long to wait before each of those attempts.
+    pub retry_delay: Duration,
+
+    /// The network interface that outgoing connections are bound to
+    /// (SO_BINDTODEVICE). Only Linux lets it be chosen.
+    pub bind_device: Option<String>,
+
+    /// The local address that outgoing connections are made from.
+    pub source_addr: Option<IpAddr>,
+
+    /// The type of service byte that sent packets are marked with, holding
+    /// the DSCP in its upper six bits (IP_TOS or IPV6_TCLASS).
+    pub tos: Option<u8>,
+
+    /// How many hops sent packets may take (IP_TTL or IPV6_UNICAST_HOPS).
+    pub ttl: Option<u32>,
+
+    /// The proxy that outgoing TCP connections are made through.
+    pub proxy: Option<Proxy>,
+}
+
+impl SocketOptions {
+    /// Applies the buffer sizes, which must be set before a connection is
+    /// established for the TCP window to make use of them. Accepted sockets
+    /// inherit these from their listener.
+    fn apply_buffers(&self, socket: &Socket) -> io::Result<()> {
+        if let Some(size) = self.recv_buffer_size {
+            socket.set_recv_buffer_size(size)?;
+        }
+        if let Some(size) = self.send_buffer_size {
+            socket.set_send_buffer_size(size)?;
+        }
+        Ok(())
+    }
+
+    /// Applies the options that must be set on a listener before it is bound.
+    fn apply_reuse(&self, socket: &Socket) -> io::Result<()> {
+        if self.reuse_address {
+            socket.set_reuse_address(true)?;
+        }
+        if self.reuse_port {
+            set_reuse_port(socket)?;
+        }
+        Ok(())
+    }
+
+    /// Binds a socket that is about to connect to the address to the chosen
+    /// interface and source address.
+    fn apply_source(&self, socket: &Socket, addr: SocketAddr) -> io::Result<()> {
+        if let Some(ref device) = self.bind_device {
+            bind_device(socket, device)?;
+        }
+        if let Some(source) = self.source_addr {
+            if source.is_ipv4() != addr.is_ipv4() {
+                return Err(io::Error::new(io::ErrorKind::InvalidInput,
+                    format!("source address {} cannot reach {}, which is of another address family", source, addr)));
+            }
+            socket.bind(&SocketAddr::new(source, 0).into())?;
+        }
+        Ok(())
+    }
+
+    /// Marks the packets a socket sends over IPv4 or IPv6 with the type of
+    /// service and hop limit. A connecting socket is marked before it
+    /// connects so that its handshake is marked too.
+    fn apply_marking(&self, socket: &Socket, ipv6: bool) -> io::Result<()> {
+        if let Some(tos) = self.tos {
+            if ipv6 {
+                set_tclass_v6(socket, tos)?;
+            } else {
+                socket.set_tos(tos as u32)?;
+            }
+        }
+        if let Some(ttl) = self.ttl {
+            if ipv6 {
+                socket.set_unicast_hops_v6(ttl)?;
+            } else {
+                socket.set_ttl(ttl)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Applies every option to an established connection.
+    pub fn apply(&self, stream: &TcpStream) -> io::Result<()> {
+        let socket = SockRef::from(stream);
+        self.apply_buffers(&socket)?;
+        if self.tos.is_some() || self.ttl.is_some() {
+            self.apply_marking(&socket, stream.local_addr()?.is_ipv6())?;
+        }
+        if self.nodelay {
+            socket.set_nodelay(true)?;
+        }
+        if let Some(time) = self.keepalive {
+            socket.set_tcp_keepalive(&TcpKeepalive::new().with_time(time))?;
+        }
+        if let Some(ref algorithm) = self.congestion {
+            set_congestion(&socket, algorithm)?;
+        }
+        if let Some(timeout) = self.read_timeout {
+            stream.set_read_timeout(Some(timeout))?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(all(unix, not(any(target_os = "solaris", target_os = "illumos", target_os = "cygwin"))))]
+fn set_reuse_port(socket: &Socket) -> io::Result<()> {
+    socket.set_reuse_port(true)
+}
+
+#[cfg(not(all(unix, not(any(target_os = "solaris", target_os = "illumos", target_os = "cygwin")))))]
+fn set_reuse_port(_socket: &Socket) -> io::Result<()> {
+    Err(io::Error::new(io::ErrorKind::Unsupported, "SO_REUSEPORT is not supported on this platform"))
+}
+
+#[cfg(unix)]
+fn set_tclass_v6(socket: &Socket, tos: u8) -> io::Result<()> {
+    use std::os::unix::io::AsRawFd;
+
+    let tclass = tos as libc::c_int;
+    let result = unsafe {
+        libc::setsockopt(socket.as_raw_fd(), libc::IPPROTO_IPV6, libc::IPV6_TCLASS,
+            &tclass as *const libc::c_int as *const libc::c_void, std::mem::size_of::<libc::c_int>() as libc::socklen_t)
+    };
+    if result != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+#[cfg(not(unix))]
+fn set_tclass_v6(_socket: &Socket, _tos: u8) -> io::Result<()> {
+    Err(io::Error::new(io::ErrorKind::Unsupported, "marking IPv6 traffic is not supported on this platform"))
+}
+
+/// Parses a DSCP as a number from 0 to 63 or the name of a class, such as
+/// EF, AF41 or CS1.
+pub fn parse_dscp(value: &str) -> Option<u8> {
+    let value = value.trim().to_ascii_uppercase();
+    let dscp = if value == "EF" {
+        46
+    } else if let Some(class) = value.strip_prefix("CS") {
+        class.parse::<u8>().ok().filter(|&class| class <= 7)? * 8
+    } else if let Some(class) = value.strip_prefix("AF") {
+        let digits = class.as_bytes();
+        if digits.len() != 2 || !(b'1'..=b'4').contains(&digits[0]) || !(b'1'..=b'3').contains(&digits[1]) {
+            return None;
+        }
+        (digits[0] - b'0') * 8 + (digits[1] - b'0') * 2
+    } else {
+        value.parse().ok()?
+    };
+    if dscp < 64 { Some(dscp) } else { None }
+}
+
+#[cfg(target_os = "linux")]
+fn bind_device(socket: &Socket, device: &str) -> io::Result<()> {
+    socket.bind_device(Some(device.as_bytes()))
+        .map_err(|err| io::Error::new(err.kind(), format!("could not bind to interface {}: {}", device, err)))
+}
+
+#[cfg(not(target_os = "linux"))]
+fn bind_device(_socket: &Socket, _device: &str) -> io::Result<()> {
+    Err(io::Error::new(io::ErrorKind::Unsupported, "binding to an interface is only possible on Linux"))
+}
+
+#[cfg(target_os = "linux")]
+fn set_congestion(socket: &SockRef, algorithm: &str) -> io::Result<()> {
+    socket.set_tcp_congestion(algorithm.as_bytes()).map_err(|err| match err.kind() {
+        io::ErrorKind::NotFound => io::Error::new(io::ErrorKind::NotFound, format!(
+            "congestion control algorithm {} is not available, see /proc/sys/net/ipv4/tcp_available_congestion_control", algorithm)),
+        _ => err,
+    })
+}
+
+#[cfg(not(target_os = "linux"))]
+fn set_congestion(_socket: &SockRef, _algorithm: &str) -> io::Result<()> {
+    Err(io::Error::new(io::ErrorKind::Unsupported, "the congestion control algorithm can only be chosen on Linux"))
+}
+
+/// The congestion control algorithm a socket uses, where it can be told.
+#[cfg(target_os = "linux")]
+fn congestion(socket: &SockRef) -> Option<String> {
+    let name = socket.tcp_congestion().ok()?;
+    Some(String::from_utf8_lossy(&name).trim_end_matches('\0').to_string())
+}
+
+#[cfg(not(target_os = "linux"))]
+fn congestion(_socket: &SockRef) -> Option<String> {
+    None
+}
+
+/// Returns a human readable summary of the kernel buffer sizes actually in use
+/// by a socket, which may differ from what was requested, along with its
+/// congestion control algorithm on Linux.
+pub fn describe_buffers(stream: &TcpStream) -> io::Result<String> {
+    let socket = SockRef::from(stream);
+    let mut description = format!("receive buffer {} bytes, send buffer {} bytes",
+        socket.recv_buffer_size()?, socket.send_buffer_size()?);
+    if let Some(algorithm) = congestion(&socket) {
+        description.push_str(&format!(", congestion control {}", algorithm));
+    }
+    Ok(description)
+}
+
+/// Resolves a host name or IP address literal into a list of socket addresses,
+/// ordered according to the given address family preference.
+pub fn resolve(host: &str, port: u16, preference: AddrPreference) -> io::Result<Vec<SocketAddr>> {
+    // Allow IPv6 literals to be written in the bracketed form, e.g. [::1].
+    let host = host.trim_start_matches('[').trim_end_matches(']');
+    let mut addrs: Vec<SocketAddr> = (host, port).to_socket_addrs()?.collect();
+
+    // sort_by_key is stable so the resolver's order is kept within a family.
+    match preference {
+        AddrPreference::Any => {},
+        AddrPreference::V4 => addrs.sort_by_key(|a| !a.is_ipv4()),
+        AddrPreference::V6 => addrs.sort_by_key(|a| !a.is_ipv6()),
+        AddrPreference::OnlyV4 => addrs.retain(SocketAddr::is_ipv4),
+        AddrPreference::OnlyV6 => addrs.retain(SocketAddr::is_ipv6),
+    }
+
+    if addrs.is_empty() {
+        let family = match preference {
+            AddrPreference::OnlyV4 => " IPv4",
+            AddrPreference::OnlyV6 => " IPv6",
+            _ => "",
+        };
+        Err(io::Error::new(io::ErrorKind::NotFound, format!("{} did not resolve to any{} address", host, family)))
+    } else {
+        Ok(addrs)
+    }
+}
+
+/// Binds a listener to the first address in `addrs` that succeeds and returns
+/// it alongside the address that was actually used.
+///
+/// If `only_v6` is set IPv6 listeners will not also accept IPv4 connections,
+/// which allows an IPv4 listener to be bound to the same port alongside them.
+pub fn bind_first(addrs: &[SocketAddr], only_v6: bool, options: &SocketOptions) -> io::Result<(TcpListener, SocketAddr)> {
+    try_each(addrs, |addr| bind(addr, only_v6, options))
+}
+
+/// Binds one listener for each group of candidate addresses, all on the same
+/// port, and returns them alongside the addresses they are bound to. With
+/// port 0 the first listener is given an ephemeral port that the rest share.
+pub fn bind_all(addr_groups: &[Vec<SocketAddr>], port: u16, options: &SocketOptions) -> io::Result<Vec<(TcpListener, SocketAddr)>> {
+    // With several listeners an IPv6 wildcard must not claim the IPv4 port too.
+    let only_v6 = addr_groups.len() > 1;
+
+    let mut port = port;
+    let mut listeners = Vec::with_capacity(addr_groups.len());
+    for addrs in addr_groups {
+        let addrs: Vec<SocketAddr> = addrs.iter().map(|addr| SocketAddr::new(addr.ip(), port)).collect();
+        let (listener, _) = bind_first(&addrs, only_v6, options)?;
+        let local_addr = listener.local_addr()?;
+        port = local_addr.port();
+        listeners.push((listener, local_addr));
+    }
+    Ok(listeners)
+}
+
+fn bind(addr: SocketAddr, only_v6: bool, options: &SocketOptions) -> io::Result<TcpListener> {
+    let socket = Socket::new(Domain::for_address(addr), Type::STREAM, Some(Protocol::TCP))?;
+
+    options.apply_reuse(&socket)?;
+    if addr.is_ipv6() {
+        socket.set_only_v6(only_v6)?;
+    }
+    options.apply_buffers(&socket)?;
+
+    socket.bind(&addr.into())?;
+    socket.listen(LISTEN_BACKLOG)?;
+    Ok(socket.into())
+}
+
+/// Binds `count` UDP sockets to the first address in `addrs` that succeeds
+/// and returns them alongside the address they are bound to. Several sockets
+/// share the port through SO_REUSEPORT, with the kernel spreading datagrams
+/// over them by flow.
+pub fn bind_udp(addrs: &[SocketAddr], count: usize, options: &SocketOptions) -> io::Result<(Vec<UdpSocket>, SocketAddr)> {
+    let (mut sockets, _) = try_each(addrs, |addr| bind_datagram(addr, count > 1, options).map(|socket| vec![socket]))?;
+    let local_addr = sockets[0].local_addr()?;
+    // With port 0 the rest must share the port picked for the first.
+    for _ in 1..count {
+        sockets.push(bind_datagram(local_addr, true, options)?);
+    }
+    Ok((sockets, local_addr))
+}
+
+fn bind_datagram(addr: SocketAddr, shared: bool, options: &SocketOptions) -> io::Result<UdpSocket> {
+    let socket = Socket::new(Domain::for_address(addr), Type::DGRAM, Some(Protocol::UDP))?;
+
+    options.apply_reuse(&socket)?;
+    if shared && !options.reuse_port {
+        set_reuse_port(&socket)?;
+    }
+    options.apply_buffers(&socket)?;
+
+    socket.bind(&addr.into())?;
+    Ok(socket.into())
+}
+
+/// Binds a UDP socket to an ephemeral port and connects it to the first of
+/// the addresses, which only sets where datagrams are sent.
+pub fn connect_udp(addrs: &[SocketAddr], options: &SocketOptions) -> io::Result<(UdpSocket, SocketAddr)> {
+    try_each(addrs, |addr| {
+        let socket = Socket::new(Domain::for_address(addr), Type::DGRAM, Some(Protocol::UDP))?;
+        options.apply_buffers(&socket)?;
+        options.apply_source(&socket, addr)?;
+        options.apply_marking(&socket, addr.is_ipv6())?;
+        socket.connect(&addr.into())?;
+        Ok(socket.into())
+    })
+}
+
+/// Has the kernel split every send on the socket into datagrams of the given
+/// size (UDP_SEGMENT), leaving it to the NIC where it supports that.
+#[cfg(target_os = "linux")]
+pub fn set_gso_segment(socket: &UdpSocket, size: u16) -> io::Result<()> {
+    use std::os::unix::io::AsRawFd;
+
+    let size = size as libc::c_int;
+    let result = unsafe {
+        libc::setsockopt(socket.as_raw_fd(), libc::SOL_UDP, libc::UDP_SEGMENT,
+            &size as *const libc::c_int as *const libc::c_void, std::mem::size_of::<libc::c_int>() as libc::socklen_t)
+    };
+    if result != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+/// The first file descriptor that systemd passes sockets on.
+#[cfg(target_os = "linux")]
+const LISTEN_FDS_START: i32 = 3;
+
+/// Takes the listening sockets passed by systemd when the process was
+/// started through socket activation, or returns `None` if it was not. The
+/// variables describing them are removed so that processes started from here
+/// do not take them as well.
+#[cfg(target_os = "linux")]
+pub fn activated_listeners() -> io::Result<Option<Vec<TcpListener>>> {
+    use std::env;
+    use std::os::unix::io::FromRawFd;
+
+    let pid = env::var("LISTEN_PID");
+    let fds = env::var("LISTEN_FDS");
+    for name in &["LISTEN_PID", "LISTEN_FDS", "LISTEN_FDNAMES"] {
+        env::remove_var(name);
+    }
+    // The sockets are only meant for the process systemd started.
+    match pid.ok().and_then(|pid| pid.parse::<u32>().ok()) {
+        Some(pid) if pid == std::process::id() => {},
+        _ => return Ok(None),
+    }
+    let count = match fds.ok().and_then(|fds| fds.parse::<i32>().ok()) {
+        Some(count) if count > 0 => count,
+        _ => return Err(io::Error::new(io::ErrorKind::InvalidInput, "LISTEN_FDS is not a number of sockets")),
+    };
+
+    (LISTEN_FDS_START..LISTEN_FDS_START + count).map(|fd| {
+        let socket = unsafe { Socket::from_raw_fd(fd) };
+        let is_listener = |socket: &Socket| -> io::Result<bool> {
+            Ok(socket.domain()? != Domain::UNIX && socket.r#type()? == Type::STREAM && socket.is_listener()?)
+        };
+        match is_listener(&socket) {
+            Ok(true) => {
+                socket.set_cloexec(true)?;
+                Ok(socket.into())
+            },
+            result => {
+                // The descriptor may not even be open, so it is left alone.
+                std::mem::forget(socket);
+                Err(io::Error::new(io::ErrorKind::InvalidInput, match result {
+                    Err(err) => format!("file descriptor {} is not a socket: {}", fd, err),
+                    _ => format!("file descriptor {} is not a listening TCP socket", fd),
+                }))
+            },
+        }
+    }).collect::<io::Result<Vec<TcpListener>>>().map(Some)
+}
+
+/// A connection along with the peer's address and the local address it was
+/// accepted on.
+pub type Accepted = (TcpStream, SocketAddr, SocketAddr);
+
+/// Accepts connections from any of several listeners.
+pub struct Acceptor {
+    listeners: Vec<TcpListener>,
+
+    /// How long `accept` waits for a connection before failing.
+    timeout: Option<Duration>,
+
+    /// Connections accepted by the background threads used when there is more
+    /// than one listener. The threads are started by the first `accept`.
+    incoming: Option<mpsc::Receiver<io::Result<Accepted>>>,
+}
+
+impl Acceptor {
+    pub fn new(listeners: Vec<TcpListener>) -> Acceptor {
+        Acceptor { listeners, timeout: None, incoming: None }
+    }
+
+    /// Returns handles to every listener, such as for accepting on them
+    /// asynchronously.
+    #[cfg_attr(not(feature = "tokio"), allow(dead_code))]
+    pub fn try_clone_listeners(&self) -> io::Result<Vec<TcpListener>> {
+        self.listeners.iter().map(TcpListener::try_clone).collect()
+    }
+
+    /// Makes `accept` fail with `TimedOut` if no connection arrives within
+    /// the timeout.
+    pub fn set_timeout(&mut self, timeout: Option<Duration>) {
+        self.timeout = timeout;
+    }
+
+    /// Waits for a connection on any of the listeners and returns the first
+    /// one that arrives.
+    pub fn accept(&mut self) -> io::Result<Accepted> {
+        // Blocking accepts cannot time out, so with a timeout even a single
+        // listener is accepted on in the background.
+        if self.listeners.len() == 1 && self.timeout.is_none() {
+            return accept_one(&self.listeners[0]);
+        }
+
+        if self.incoming.is_none() {
+            self.incoming = Some(self.spawn_accept_threads()?);
+        }
+        let incoming = self.incoming.as_ref().expect("accept threads should have been started");
+        let received = match self.timeout {
+            Some(timeout) => incoming.recv_timeout(timeout).map_err(|err| match err {
+                mpsc::RecvTimeoutError::Timeout => io::Error::new(io::ErrorKind::TimedOut, "no connection arrived in time"),
+                mpsc::RecvTimeoutError::Disconnected => io::Error::other("all listeners failed"),
+            }),
+            None => incoming.recv().map_err(|_| io::Error::other("all listeners failed")),
+        };
+        received?
+    }
+
+    fn spawn_accept_threads(&self) -> io::Result<mpsc::Receiver<io::Result<Accepted>>> {
+        let (sender, receiver) = mpsc::channel();
+        for listener in &self.listeners {
+            let listener = listener.try_clone()?;
+            let sender = sender.clone();
+            thread::spawn(move || {
+                // Stops once the acceptor has been dropped.
+                while sender.send(accept_one(&listener)).is_ok() {}
+            });
+        }
+        Ok(receiver)
+    }
+}
+
+fn accept_one(listener: &TcpListener) -> io::Result<Accepted> {
+    let (stream, peer) = listener.accept()?;
+    let local = stream.local_addr()?;
+    Ok((stream, peer, local))
+}
+
+/// Connects to the first address in `addrs` that accepts the connection and
+/// returns the stream alongside the address that was actually used. When
+/// there are addresses of both families they are raced as in Happy Eyeballs
+/// (RFC 8305), alternating between the families and starting the next
+/// attempt whenever one fails or has not connected within a short delay.
+pub fn connect_first(addrs: &[SocketAddr], options: &SocketOptions) -> io::Result<(TcpStream, SocketAddr)> {
+    if has_both_families(addrs) && options.proxy.is_none() {
+        race(&interleave_families(addrs), options)
+    } else {
+        try_each(addrs, |addr| connect(addr, options))
+    }
+}
+
+/// Whether there are both IPv4 and IPv6 addresses to connect to.
+pub fn has_both_families(addrs: &[SocketAddr]) -> bool {
+    addrs.iter().any(SocketAddr::is_ipv4) && addrs.iter().any(SocketAddr::is_ipv6)
+}
+
+/// The name of the address's family.
+pub fn family_name(addr: &SocketAddr) -> &'static str {
+    if addr.is_ipv4() { "IPv4" } else { "IPv6" }
+}
+
+/// Orders the addresses so that the families take turns, starting with the
+/// family of the first.
+fn interleave_families(addrs: &[SocketAddr]) -> Vec<SocketAddr> {
+    let first_is_ipv4 = addrs[0].is_ipv4();
+    let (mut first, mut second): (Vec<SocketAddr>, Vec<SocketAddr>) = addrs.iter().partition(|addr| addr.is_ipv4() == first_is_ipv4);
+    let mut ordered = Vec::with_capacity(addrs.len());
+    first.reverse();
+    second.reverse();
+    while let Some(addr) = first.pop() {
+        ordered.push(addr);
+        ordered.extend(second.pop());
+    }
+    ordered.extend(second.into_iter().rev());
+    ordered
+}
+
+/// Starts connecting to each address in turn on its own thread and returns
+/// the first connection made. Attempts that connect after it are closed.
+fn race(addrs: &[SocketAddr], options: &SocketOptions) -> io::Result<(TcpStream, SocketAddr)> {
+    let (sender, receiver) = mpsc::channel();
+    let mut started = 0;
+    let mut failed = 0;
+    loop {
+        if started < addrs.len() {
+            let (addr, options, sender) = (addrs[started], options.clone(), sender.clone());
+            thread::spawn(move || {
+                let _ = sender.send((addr, connect(addr, &options)));
+            });
+            started += 1;
+        }
+
+        let received = if started < addrs.len() {
+            match receiver.recv_timeout(CONNECTION_ATTEMPT_DELAY) {
+                Ok(received) => received,
+                Err(_) => continue,
+            }
+        } else {
+            receiver.recv().expect("an attempt is still running")
+        };
+        match received {
+            (addr, Ok(stream)) => return Ok((stream, addr)),
+            (_, Err(err)) => {
+                failed += 1;
+                if failed == addrs.len() {
+                    return Err(err);
+                }
+            },
+        }
+    }
+}
+
+/// Connects like `connect_first`, trying again after the retry delay as many
+/// times as the options allow. `waiting` is called with the error and the
+/// number of the retry before each wait.
+pub fn connect_retrying<F>(addrs: &[SocketAddr], options: &SocketOptions, mut waiting: F) -> io::Result<(TcpStream, SocketAddr)>
+    where F: FnMut(&io::Error, u32)
+{
+    let mut retry = 0;
+    loop {
+        match connect_first(addrs, options) {
+            Err(ref err) if retry < options.connect_retries => {
+                retry += 1;
+                waiting(err, retry);
+                thread::sleep(options.retry_delay);
+            },
+            result => return result,
+        }
+    }
+}
+
+fn connect(addr: SocketAddr, options: &SocketOptions) -> io::Result<TcpStream> {
+    if let Some(ref proxy) = options.proxy {
+        return connect_through(proxy, addr, options);
+    }
+
+    let socket = Socket::new(Domain::for_address(addr), Type::STREAM, Some(Protocol::TCP))?;
+    options.apply_buffers(&socket)?;
+    options.apply_source(&socket, addr)?;
+    options.apply_marking(&socket, addr.is_ipv6())?;
+    socket.connect(&addr.into())?;
+
+    let stream: TcpStream = socket.into();
+    options.apply(&stream)?;
+    Ok(stream)
+}
+
+/// Connects to the first of the proxy's addresses that accepts and has the
+/// proxy connect on to the address. The socket options apply to the
+/// connection to the proxy.
+fn connect_through(proxy: &Proxy, addr: SocketAddr, options: &SocketOptions) -> io::Result<TcpStream> {
+    let direct = SocketOptions { proxy: None, ..options.clone() };
+    let (mut stream, proxy_addr) = connect_first(&proxy.addrs, &direct)
+        .map_err(|err| io::Error::new(err.kind(), format!("could not connect to the proxy: {}", err)))?;
+    proxy.handshake(&mut stream, addr)
+        .map_err(|err| io::Error::new(err.kind(), format!("proxy {}: {}", proxy_addr, err)))?;
+    Ok(stream)
+}
+
+/// How a connection that was shut down for writing came to be closed.
+#[derive(Clone, Copy, Debug)]
+pub struct CloseStats {
+    /// The bytes the peer had not acknowledged when sending was shut down,
+    /// if the platform can tell.
+    pub in_flight: Option<u64>,
+
+    /// The bytes the peer sent after that.
+    pub drained: u64,
+
+    /// How long it took the peer to close its side.
+    pub wait: Duration,
+
+    /// Whether the peer closed its side, rather than resetting the
+    /// connection or not closing it in time.
+    pub closed_by_peer: bool,
+}
+
+/// What was read from one of several connections measured together.
+#[derive(Clone, Debug)]
+pub struct ConnectionStats {
+    /// The label the client sent, or its address if it did not send one.
+    pub label: String,
+    pub peer: SocketAddr,
+    pub bytes: u64,
+
+    /// When the connection was accepted and how long it was open.
+    pub opened: SystemTime,
+    pub elapsed: Duration,
+}
+
+/// Labels the connection for a listener measuring several at once, before
+/// any data is sent.
+pub fn send_label(stream: &TcpStream, label: &str) -> io::Result<()> {
+    let mut preamble = LABEL_PREAMBLE.to_vec();
+    preamble.extend_from_slice(label.as_bytes());
+    preamble.push(b'\n');
+    (&*stream).write_all(&preamble)
+}
+
+/// Waits for the peer to close a connection that has been shut down for
+/// writing, discarding whatever it sends until then. Closing the connection
+/// with data left unread would reset it and could lose what the peer had yet
+/// to read.
+pub fn drain(stream: &TcpStream) -> CloseStats {
+    let in_flight = unacknowledged_bytes(stream);
+    let start = Instant::now();
+    let mut buffer = [0u8; 16 * 1024];
+    let mut drained = 0;
+    let closed_by_peer = loop {
+        let remaining = DRAIN_TIMEOUT.saturating_sub(start.elapsed());
+        if remaining.is_zero() || stream.set_read_timeout(Some(remaining)).is_err() {
+            break false;
+        }
+        match (&*stream).read(&mut buffer) {
+            Ok(0) => break true,
+            Ok(read) => drained += read as u64,
+            Err(ref err) if err.kind() == io::ErrorKind::Interrupted => {},
+            Err(_) => break false,
+        }
+    };
+    CloseStats { in_flight, drained, wait: start.elapsed(), closed_by_peer }
+}
+
+/// The bytes in the send queue that the peer has not acknowledged.
+#[cfg(target_os = "linux")]
+pub fn unacknowledged_bytes(stream: &TcpStream) -> Option<u64> {
+    use std::os::unix::io::AsRawFd;
+
+    let mut queued: libc::c_int = 0;
+    let result = unsafe { libc::ioctl(stream.as_raw_fd(), libc::TIOCOUTQ, &mut queued) };
+    if result != 0 {
+        return None;
+    }
+    Some(queued as u64)
+}
+
+#[cfg(not(target_os = "linux"))]
+pub fn unacknowledged_bytes(_stream: &TcpStream) -> Option<u64> {
+    None
+}
+
+/// Binds an SCTP one-to-one style listener to the first address in `addrs`
+/// that succeeds. Accepted associations are read like TCP connections.
+#[cfg(target_os = "linux")]
+pub fn bind_first_sctp(addrs: &[SocketAddr], options: &SocketOptions) -> io::Result<(Socket, SocketAddr)> {
+    try_each(addrs, |addr| {
+        let socket = Socket::new(Domain::for_address(addr), Type::STREAM, Some(Protocol::from(libc::IPPROTO_SCTP)))?;
+        options.apply_reuse(&socket)?;
+        options.apply_buffers(&socket)?;
+        socket.bind(&addr.into())?;
+        socket.listen(LISTEN_BACKLOG)?;
+        Ok(socket)
+    })
+}
+
+/// Opens an SCTP association to the first address in `addrs` that accepts.
+#[cfg(target_os = "linux")]
+pub fn connect_first_sctp(addrs: &[SocketAddr], options: &SocketOptions) -> io::Result<(Socket, SocketAddr)> {
+    try_each(addrs, |addr| {
+        let socket = Socket::new(Domain::for_address(addr), Type::STREAM, Some(Protocol::from(libc::IPPROTO_SCTP)))?;
+        options.apply_buffers(&socket)?;
+        options.apply_source(&socket, addr)?;
+        options.apply_marking(&socket, addr.is_ipv6())?;
+        socket.connect(&addr.into())?;
+        Ok(socket)
+    })
+}
+
+fn try_each<T, F>(addrs: &[SocketAddr], mut attempt: F) -> io::Result<(T, SocketAddr)>
+    where F: FnMut(SocketAddr) -> io::Result<T>
+{
+    let mut last_err = None;
+    for &addr in addrs {
+        match attempt(addr) {
+            Ok(value) => return Ok((value, addr)),
+            Err(err) => last_err = Some(err),
+        }
+    }
+    Err(last_err.unwrap_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "no addresses to try")))
+}
@@ -0,0 +1,229 @@
+//! Exporting measures to an OpenTelemetry collector over OTLP/HTTP with JSON
+//! bodies, so that runs show up in an existing OpenTelemetry pipeline. Every
+//! measure becomes a set of metrics, and every run can become a span with a
+//! child span for each connection measured together. The host and run are
+//! attached as attributes.
+
+use std::sync::Mutex;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use serde_json::Value;
+
+use pattern::splitmix64;
+use push::{self, Sample, Writer};
+use TransferInfo;
+
+/// The port OTLP/HTTP collectors listen on unless told otherwise.
+const DEFAULT_OTLP_PORT: u16 = 4318;
+
+/// What an OTLP request carries, which decides where it is sent.
+#[derive(Clone, Copy)]
+enum Signal {
+    Metrics,
+    Traces,
+}
+
+impl Signal {
+    fn path(self) -> &'static str {
+        match self {
+            Signal::Metrics => "/v1/metrics",
+            Signal::Traces => "/v1/traces",
+        }
+    }
+}
+
+/// An OTLP/HTTP collector, such as `http://localhost:4318`.
+pub struct OtlpTarget {
+    host: String,
+
+    /// Put in front of the path of each signal.
+    prefix: String,
+}
+
+impl OtlpTarget {
+    /// Parses an `http://` URL. HTTPS is not supported.
+    pub fn parse(url: &str) -> Option<OtlpTarget> {
+        let (host, path) = push::parse_http_url(url, DEFAULT_OTLP_PORT, "")?;
+        Some(OtlpTarget { host, prefix: path.trim_end_matches('/').to_string() })
+    }
+}
+
+/// The run that measures are being taken for.
+struct Run {
+    index: usize,
+
+    /// Shared by the spans of the run and attached to its metrics.
+    trace_id: String,
+}
+
+/// Sends measures, and spans if asked to, from a thread of its own.
+pub struct OtlpExporter {
+    /// Taken by `finish`.
+    writer: Mutex<Option<Writer<(Signal, String)>>>,
+
+    resource: Value,
+    spans: bool,
+    run: Mutex<Option<Run>>,
+
+    /// Counts the IDs made so that no two are the same.
+    ids: Mutex<u64>,
+}
+
+impl OtlpExporter {
+    pub fn new(target: OtlpTarget, spans: bool) -> OtlpExporter {
+        let writer = push::spawn_writer("the OpenTelemetry collector", move |(signal, body): (Signal, String)| {
+            let path = format!("{}{}", target.prefix, signal.path());
+            push::http_post("The OpenTelemetry collector", &target.host, &path, "application/json", "", &body)
+        });
+        let resource = json!({
+            "attributes": [
+                attribute("service.name", json!({ "stringValue": "throughput" })),
+                attribute("service.version", json!({ "stringValue": env!("CARGO_PKG_VERSION") })),
+                attribute("host.name", json!({ "stringValue": push::hostname() })),
+            ],
+        });
+        let seed = nanos(SystemTime::now()) as u64 ^ (std::process::id() as u64) << 32;
+        OtlpExporter { writer: Mutex::new(Some(writer)), resource, spans, run: Mutex::new(None), ids: Mutex::new(seed) }
+    }
+
+    pub fn start_run(&self, index: usize) {
+        let trace_id = format!("{:016x}{:016x}", self.next_id(), self.next_id());
+        *self.run.lock().expect("OTLP run lock was poisoned") = Some(Run { index, trace_id });
+    }
+
+    pub fn push(&self, sample: &Sample) {
+        let start = sample.time.checked_sub(sample.elapsed).unwrap_or(sample.time);
+        let attributes = self.run_attributes();
+        let point = |key: &str, value: Value| {
+            let mut point = json!({
+                "attributes": attributes,
+                "startTimeUnixNano": nanos(start).to_string(),
+                "timeUnixNano": nanos(sample.time).to_string(),
+            });
+            point[key] = value;
+            point
+        };
+        let metrics = json!([
+            {
+                "name": "throughput.bytes",
+                "unit": "By",
+                "description": "Bytes transferred since the start of the run.",
+                "sum": {
+                    "aggregationTemporality": 2,
+                    "isMonotonic": true,
+                    "dataPoints": [point("asInt", json!(sample.total_bytes.to_string()))],
+                },
+            },
+            {
+                "name": "throughput.rate",
+                "unit": "By/s",
+                "description": "Current transfer speed.",
+                "gauge": { "dataPoints": [point("asDouble", json!(sample.current_bps))] },
+            },
+            {
+                "name": "throughput.average_rate",
+                "unit": "By/s",
+                "description": "Average transfer speed of the run.",
+                "gauge": { "dataPoints": [point("asDouble", json!(sample.average_bps))] },
+            },
+        ]);
+        self.send(Signal::Metrics, json!({
+            "resourceMetrics": [{ "resource": self.resource, "scopeMetrics": [{ "scope": scope(), "metrics": metrics }] }],
+        }));
+    }
+
+    /// Sends the span of the run, with one for each connection if several
+    /// were measured together.
+    pub fn finish_run(&self, transfer_info: &TransferInfo) {
+        let run = self.run.lock().expect("OTLP run lock was poisoned").take();
+        let (run, start) = match (self.spans, run, transfer_info.start_time) {
+            (true, Some(run), Some(start)) => (run, start),
+            _ => return,
+        };
+
+        let run_span_id = format!("{:016x}", self.next_id());
+        let mut attributes = vec![
+            attribute("throughput.run", json!({ "intValue": run.index.to_string() })),
+            attribute("throughput.bytes", json!({ "intValue": transfer_info.total_bytes_transferred.to_string() })),
+            attribute("throughput.average_bps", json!({ "doubleValue": transfer_info.average_bps() })),
+            attribute("throughput.peak_bps", json!({ "doubleValue": transfer_info.peak_bps })),
+        ];
+        if let Some(ref connections) = transfer_info.connections {
+            attributes.push(attribute("throughput.connections", json!({ "intValue": connections.len().to_string() })));
+        }
+        let mut spans = vec![span(&run.trace_id, &run_span_id, "", "throughput run", start, transfer_info.elapsed, attributes)];
+
+        for connection in transfer_info.connections.iter().flatten() {
+            let attributes = vec![
+                attribute("throughput.label", json!({ "stringValue": connection.label })),
+                attribute("network.peer.address", json!({ "stringValue": connection.peer.ip().to_string() })),
+                attribute("network.peer.port", json!({ "intValue": connection.peer.port().to_string() })),
+                attribute("throughput.bytes", json!({ "intValue": connection.bytes.to_string() })),
+            ];
+            let span_id = format!("{:016x}", self.next_id());
+            spans.push(span(&run.trace_id, &span_id, &run_span_id, "throughput connection", connection.opened, connection.elapsed, attributes));
+        }
+
+        self.send(Signal::Traces, json!({
+            "resourceSpans": [{ "resource": self.resource, "scopeSpans": [{ "scope": scope(), "spans": spans }] }],
+        }));
+    }
+
+    /// Waits for everything that has been exported to be sent.
+    pub fn finish(&self) {
+        if let Some((sender, writer)) = self.writer.lock().expect("OTLP writer lock was poisoned").take() {
+            drop(sender);
+            let _ = writer.join();
+        }
+    }
+
+    fn send(&self, signal: Signal, body: Value) {
+        if let Some((ref sender, _)) = *self.writer.lock().expect("OTLP writer lock was poisoned") {
+            // The writer thread only stops if it panicked.
+            let _ = sender.send((signal, body.to_string()));
+        }
+    }
+
+    fn run_attributes(&self) -> Vec<Value> {
+        match *self.run.lock().expect("OTLP run lock was poisoned") {
+            Some(ref run) => vec![
+                attribute("throughput.run", json!({ "intValue": run.index.to_string() })),
+                attribute("throughput.run_id", json!({ "stringValue": run.trace_id })),
+            ],
+            None => Vec::new(),
+        }
+    }
+
+    fn next_id(&self) -> u64 {
+        let mut ids = self.ids.lock().expect("OTLP ID lock was poisoned");
+        *ids = ids.wrapping_add(1);
+        // An ID of zero is invalid.
+        splitmix64(*ids).max(1)
+    }
+}
+
+fn span(trace_id: &str, span_id: &str, parent_span_id: &str, name: &str, start: SystemTime, elapsed: Duration, attributes: Vec<Value>) -> Value {
+    json!({
+        "traceId": trace_id,
+        "spanId": span_id,
+        "parentSpanId": parent_span_id,
+        "name": name,
+        // A server span, as the connection was accepted or made to be measured.
+        "kind": 2,
+        "startTimeUnixNano": nanos(start).to_string(),
+        "endTimeUnixNano": nanos(start + elapsed).to_string(),
+        "attributes": attributes,
+    })
+}
+
+fn attribute(key: &str, value: Value) -> Value {
+    json!({ "key": key, "value": value })
+}
+
+fn scope() -> Value {
+    json!({ "name": "throughput", "version": env!("CARGO_PKG_VERSION") })
+}
+
+fn nanos(time: SystemTime) -> u128 {
+    time.duration_since(UNIX_EPOCH).map(|since| since.as_nanos()).unwrap_or(0)
+}
@@ -0,0 +1,344 @@
+//! Where the stats of a transfer go. Every measure is handed to each enabled
+//! `Reporter` as it is taken, and the transfer once it has ended, so that any
+//! number of outputs such as the terminal, a CSV file and StatsD can be used
+//! at once with repeated `--output` flags.
+
+use std::fmt;
+use std::fs::File;
+use std::io::{self, stderr, stdout, BufWriter, Write};
+use std::net::{SocketAddr, ToSocketAddrs, UdpSocket};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Mutex;
+use std::time::SystemTime;
+
+use console::StdStream;
+use display::{self, Formatter, Style};
+use metrics::Metrics;
+use push::{self, Pusher, Sample};
+use report;
+use tui::Dashboard;
+use {DisplayMode, MeasureOptions, TransferInfo};
+
+/// The name StatsD metrics are reported under.
+const STATSD_PREFIX: &str = "throughput";
+
+/// Receives the stats of a transfer as they are measured.
+pub trait Reporter: Send + Sync {
+    /// Reports the measure that was just taken.
+    fn measure(&self, transfer_info: &TransferInfo) -> io::Result<()>;
+
+    /// Reports the transfer once it has ended and everything about it is
+    /// known.
+    fn finish(&self, _transfer_info: &TransferInfo) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// An output given with `--output`, such as `csv=rates.csv`.
+#[derive(Clone, Debug, PartialEq)]
+pub enum OutputSpec {
+    Terminal,
+
+    /// A file to write a line of JSON to for every measure.
+    JsonLines(String),
+
+    /// A file to write a row to for every measure.
+    Csv(String),
+
+    Statsd(SocketAddr),
+
+    /// The address to serve Prometheus metrics at.
+    Prometheus(SocketAddr),
+}
+
+impl OutputSpec {
+    /// Parses `terminal`, `jsonl=PATH`, `csv=PATH`, `statsd=HOST:PORT` or
+    /// `prometheus=ADDRESS:PORT`.
+    pub fn parse(value: &str) -> Option<OutputSpec> {
+        let (kind, target) = match value.find('=') {
+            Some(index) => (&value[..index], Some(&value[index + 1..])),
+            None => (value, None),
+        };
+        match (kind.trim().to_lowercase().as_str(), target.filter(|target| !target.is_empty())) {
+            ("terminal", None) => Some(OutputSpec::Terminal),
+            ("jsonl", Some(path)) | ("json-lines", Some(path)) => Some(OutputSpec::JsonLines(path.to_string())),
+            ("csv", Some(path)) => Some(OutputSpec::Csv(path.to_string())),
+            ("statsd", Some(addr)) => addr.to_socket_addrs().ok().and_then(|mut addrs| addrs.next()).map(OutputSpec::Statsd),
+            ("prometheus", Some(addr)) => addr.parse().ok().map(OutputSpec::Prometheus),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for OutputSpec {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            OutputSpec::Terminal => write!(f, "terminal"),
+            OutputSpec::JsonLines(ref path) => write!(f, "jsonl={}", path),
+            OutputSpec::Csv(ref path) => write!(f, "csv={}", path),
+            OutputSpec::Statsd(addr) => write!(f, "statsd={}", addr),
+            OutputSpec::Prometheus(addr) => write!(f, "prometheus={}", addr),
+        }
+    }
+}
+
+/// Creates the file of an output. It is flushed after every measure so that
+/// it can be followed while the transfer is in progress.
+fn create_output(path: &str) -> io::Result<Mutex<BufWriter<File>>> {
+    Ok(Mutex::new(BufWriter::new(File::create(path)?)))
+}
+
+/// Prints the stats to the terminal as text, or as a dashboard with `--tui`.
+pub struct TerminalReporter {
+    display_mode: DisplayMode,
+    stream: StdStream,
+    state: Mutex<TerminalState>,
+}
+
+struct TerminalState {
+    formatter: Formatter,
+    dashboard: Dashboard,
+
+    /// Set when something else was printed after the stats, so that the
+    /// next stats cannot be written over them.
+    print_below: bool,
+
+    /// Set once the stats have been redrawn between measures, so that the
+    /// first measure is printed over them.
+    redrawn: bool,
+}
+
+impl TerminalReporter {
+    pub fn new(options: &MeasureOptions, stream: StdStream) -> TerminalReporter {
+        let style = Style::resolve(options.style, stream);
+        let mut formatter = Formatter::new(style, options.color, options.color_thresholds);
+        formatter.set_expected_bytes(options.expected_bytes);
+        formatter.set_rate_description(options.rate_mode.describe());
+        formatter.set_template(options.template.clone());
+        formatter.set_number_format(options.number_format);
+        formatter.set_compact(options.compact);
+        formatter.set_terminal(stream);
+        TerminalReporter {
+            display_mode: options.display_mode,
+            stream,
+            state: Mutex::new(TerminalState { formatter, dashboard: Dashboard::new(), print_below: false, redrawn: false }),
+        }
+    }
+
+    /// Describes the current transfer speed, for when it is not estimated
+    /// from the measures by the rate mode.
+    pub fn set_rate_description(&mut self, description: Option<String>) {
+        self.state.get_mut().expect("terminal lock was poisoned").formatter.set_rate_description(description);
+    }
+
+    /// Prints the next stats below the last ones rather than over them.
+    pub fn print_below(&self) {
+        self.state.lock().expect("terminal lock was poisoned").print_below = true;
+    }
+
+    /// Whether the stats are drawn in place, so that they can be redrawn
+    /// between measures.
+    pub fn redraws(&self) -> bool {
+        match self.display_mode {
+            DisplayMode::Live => self.state.lock().expect("terminal lock was poisoned").formatter.draws_in_place(),
+            DisplayMode::Dashboard => true,
+            DisplayMode::SummaryOnly | DisplayMode::Quiet => false,
+        }
+    }
+
+    /// Redraws the stats of a transfer that is between measures, with what
+    /// it has transferred so far.
+    pub fn redraw<W: Write>(&self, output: &mut W, transfer_info: &TransferInfo) -> io::Result<()> {
+        let mut state = self.state.lock().expect("terminal lock was poisoned");
+        let state = &mut *state;
+        let rewrite = (transfer_info.total_measures > 0 || state.redrawn) && !state.print_below;
+        state.print_below = false;
+        state.redrawn = true;
+        match self.display_mode {
+            DisplayMode::Live => state.formatter.print_info(output, transfer_info, rewrite),
+            DisplayMode::Dashboard => state.dashboard.redraw(output, transfer_info),
+            DisplayMode::SummaryOnly | DisplayMode::Quiet => Ok(()),
+        }
+    }
+
+    /// Prints the stats of a transfer that was measured elsewhere, such as
+    /// by the peer of a coordinated test.
+    pub fn summarize(&self, transfer_info: &TransferInfo) -> io::Result<()> {
+        if self.display_mode == DisplayMode::Quiet { return Ok(()); }
+
+        let state = self.state.lock().expect("terminal lock was poisoned");
+        let mut output = self.output();
+        state.formatter.print_info(&mut output, transfer_info, false)?;
+        state.formatter.print_final_stats(&mut output, transfer_info)
+    }
+
+    /// Prints the measure that was just taken to `output` rather than to the
+    /// stream the stats were set up for.
+    pub fn measure_into<W: Write>(&self, output: &mut W, transfer_info: &TransferInfo) -> io::Result<()> {
+        let mut state = self.state.lock().expect("terminal lock was poisoned");
+        let state = &mut *state;
+        let rewrite = self.display_mode == DisplayMode::Live && (transfer_info.total_measures > 1 || state.redrawn)
+            && !state.print_below;
+        state.print_below = false;
+        match self.display_mode {
+            DisplayMode::Live => state.formatter.print_info(output, transfer_info, rewrite),
+            DisplayMode::Dashboard => state.dashboard.render(output, transfer_info),
+            DisplayMode::SummaryOnly | DisplayMode::Quiet => Ok(()),
+        }
+    }
+
+    /// Prints the summary of the transfer to `output`.
+    pub fn finish_into<W: Write>(&self, output: &mut W, transfer_info: &TransferInfo) -> io::Result<()> {
+        let state = self.state.lock().expect("terminal lock was poisoned");
+        match self.display_mode {
+            DisplayMode::Quiet => return Ok(()),
+            DisplayMode::SummaryOnly => state.formatter.print_info(output, transfer_info, false)?,
+            DisplayMode::Live | DisplayMode::Dashboard => {},
+        }
+        state.formatter.print_final_stats(output, transfer_info)
+    }
+
+    fn output(&self) -> Box<dyn Write> {
+        match self.stream {
+            StdStream::Stdout => Box::new(stdout().lock()),
+            StdStream::Stderr => Box::new(stderr().lock()),
+        }
+    }
+}
+
+impl Reporter for TerminalReporter {
+    fn measure(&self, transfer_info: &TransferInfo) -> io::Result<()> {
+        self.measure_into(&mut self.output(), transfer_info)
+    }
+
+    fn finish(&self, transfer_info: &TransferInfo) -> io::Result<()> {
+        self.finish_into(&mut self.output(), transfer_info)
+    }
+}
+
+/// Writes a line of JSON for every measure, and the summary of the transfer
+/// as the last line.
+pub struct JsonLinesReporter {
+    output: Mutex<BufWriter<File>>,
+}
+
+impl JsonLinesReporter {
+    pub fn create(path: &str) -> io::Result<JsonLinesReporter> {
+        Ok(JsonLinesReporter { output: create_output(path)? })
+    }
+
+    fn write_line(&self, line: &serde_json::Value) -> io::Result<()> {
+        let mut output = self.output.lock().expect("JSON lines output lock was poisoned");
+        writeln!(output, "{}", line)?;
+        output.flush()
+    }
+}
+
+impl Reporter for JsonLinesReporter {
+    fn measure(&self, transfer_info: &TransferInfo) -> io::Result<()> {
+        let sample = sample(transfer_info);
+        self.write_line(&json!({
+            "event": "measure",
+            "timestamp": display::format_timestamp(sample.time),
+            "elapsed_secs": sample.elapsed.as_secs_f64(),
+            "bytes": sample.bytes,
+            "total_bytes": sample.total_bytes,
+            "current_bps": sample.current_bps,
+            "average_bps": sample.average_bps,
+        }))
+    }
+
+    fn finish(&self, transfer_info: &TransferInfo) -> io::Result<()> {
+        let mut summary = report::transfer_summary(transfer_info);
+        summary["event"] = json!("summary");
+        self.write_line(&summary)
+    }
+}
+
+/// Writes a row for every measure, which `throughput analyze` can read back.
+pub struct CsvReporter {
+    output: Mutex<BufWriter<File>>,
+}
+
+impl CsvReporter {
+    pub fn create(path: &str) -> io::Result<CsvReporter> {
+        let output = create_output(path)?;
+        writeln!(output.lock().expect("CSV output lock was poisoned"),
+            "timestamp,elapsed_secs,bytes,total_bytes,current_bps,average_bps")?;
+        Ok(CsvReporter { output })
+    }
+}
+
+impl Reporter for CsvReporter {
+    fn measure(&self, transfer_info: &TransferInfo) -> io::Result<()> {
+        let sample = sample(transfer_info);
+        let mut output = self.output.lock().expect("CSV output lock was poisoned");
+        writeln!(output, "{},{:.3},{},{},{:.3},{:.3}", display::format_timestamp(sample.time), sample.elapsed.as_secs_f64(),
+            sample.bytes, sample.total_bytes, sample.current_bps, sample.average_bps)?;
+        output.flush()
+    }
+}
+
+/// Sends every measure to StatsD as UDP datagrams.
+pub struct StatsdReporter {
+    socket: UdpSocket,
+    addr: SocketAddr,
+
+    /// Only the first failure is reported so that an unreachable server does
+    /// not flood the output.
+    warned: AtomicBool,
+}
+
+impl StatsdReporter {
+    pub fn new(addr: SocketAddr) -> io::Result<StatsdReporter> {
+        Ok(StatsdReporter { socket: UdpSocket::bind(push::unspecified_addr(addr))?, addr, warned: AtomicBool::new(false) })
+    }
+}
+
+impl Reporter for StatsdReporter {
+    fn measure(&self, transfer_info: &TransferInfo) -> io::Result<()> {
+        let sample = sample(transfer_info);
+        let datagram = format!("{0}.bytes:{1}|c\n{0}.total_bytes:{2}|g\n{0}.current_bps:{3}|g\n{0}.average_bps:{4}|g",
+            STATSD_PREFIX, sample.bytes, sample.total_bytes, sample.current_bps, sample.average_bps);
+        if let Err(err) = self.socket.send_to(datagram.as_bytes(), self.addr) {
+            if !self.warned.swap(true, Ordering::Relaxed) {
+                print_err!("Error while sending to StatsD: {}", err);
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Publishes every measure to the Prometheus metrics endpoint.
+impl Reporter for Metrics {
+    fn measure(&self, transfer_info: &TransferInfo) -> io::Result<()> {
+        self.record(transfer_info.last_bytes_transferred, transfer_info.current_bps, transfer_info.average_bps());
+        Ok(())
+    }
+}
+
+/// Pushes every measure to InfluxDB, a stats collector, the system log or
+/// OpenTelemetry, each of which reports its own failures.
+impl Reporter for Pusher {
+    fn measure(&self, transfer_info: &TransferInfo) -> io::Result<()> {
+        self.push(&sample(transfer_info));
+        Ok(())
+    }
+
+    fn finish(&self, transfer_info: &TransferInfo) -> io::Result<()> {
+        self.finish_run(transfer_info);
+        Ok(())
+    }
+}
+
+/// The measure that was just taken.
+fn sample(transfer_info: &TransferInfo) -> Sample {
+    Sample {
+        bytes: transfer_info.last_bytes_transferred,
+        total_bytes: transfer_info.total_bytes_transferred,
+        current_bps: transfer_info.current_bps,
+        average_bps: transfer_info.average_bps(),
+        elapsed: transfer_info.elapsed,
+        time: transfer_info.last_measure_time.unwrap_or_else(SystemTime::now),
+    }
+}
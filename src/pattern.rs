@@ -0,0 +1,157 @@
+use std::fs;
+use std::io;
+use std::sync::Arc;
+
+/// A deterministic payload that a sender generates and a receiver can check,
+/// where the expected byte at any offset of the stream is known up front.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub enum Pattern {
+    /// Every byte is its offset modulo 256.
+    Counter,
+
+    /// Pseudo random bytes derived from the seed.
+    Seeded(u64),
+
+    /// A sample of real data, such as a representative message, repeated
+    /// over and over. Unlike the other patterns it compresses and dedups
+    /// like the data it was taken from.
+    Sample(Arc<[u8]>),
+}
+
+impl Pattern {
+    /// Parses `pattern` or `seed:<number>`, or reads the sample of
+    /// `file:<path>`. Returns `Ok(None)` if the value is none of these.
+    pub fn parse(value: &str) -> io::Result<Option<Pattern>> {
+        if value == "pattern" || value == "counter" {
+            Ok(Some(Pattern::Counter))
+        } else if let Some(seed) = value.strip_prefix("seed:") {
+            Ok(seed.parse().ok().map(Pattern::Seeded))
+        } else if let Some(path) = value.strip_prefix("file:") {
+            let sample = fs::read(path)?;
+            if sample.is_empty() {
+                return Err(io::Error::new(io::ErrorKind::InvalidData, format!("{} is empty", path)));
+            }
+            Ok(Some(Pattern::Sample(sample.into())))
+        } else {
+            Ok(None)
+        }
+    }
+
+    /// Fills `buffer` with the bytes of the pattern starting at `offset`.
+    pub fn fill(&self, offset: u64, buffer: &mut [u8]) {
+        match *self {
+            Pattern::Counter => {
+                for (index, byte) in buffer.iter_mut().enumerate() {
+                    *byte = (offset + index as u64) as u8;
+                }
+            },
+
+            Pattern::Seeded(seed) => {
+                let mut position = offset;
+                let mut filled = 0;
+                while filled < buffer.len() {
+                    let word = splitmix64(seed.wrapping_add(position / 8)).to_le_bytes();
+                    let start = (position % 8) as usize;
+                    let count = (8 - start).min(buffer.len() - filled);
+                    buffer[filled..filled + count].copy_from_slice(&word[start..start + count]);
+                    filled += count;
+                    position += count as u64;
+                }
+            },
+
+            Pattern::Sample(ref sample) => {
+                let mut start = (offset % sample.len() as u64) as usize;
+                let mut filled = 0;
+                while filled < buffer.len() {
+                    let count = (sample.len() - start).min(buffer.len() - filled);
+                    buffer[filled..filled + count].copy_from_slice(&sample[start..start + count]);
+                    filled += count;
+                    start = 0;
+                }
+            },
+        }
+    }
+}
+
+/// A well distributed hash of a 64 bit value.
+pub fn splitmix64(value: u64) -> u64 {
+    let mut z = value.wrapping_add(0x9e3779b97f4a7c15);
+    z = (z ^ (z >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94d049bb133111eb);
+    z ^ (z >> 31)
+}
+
+/// The maximum number of corrupt regions whose offsets are remembered.
+const MAX_RECORDED_REGIONS: usize = 10;
+
+/// Checks a received stream against the pattern it is expected to contain.
+pub struct Verifier {
+    pattern: Pattern,
+
+    /// The offset in the stream of the next byte to be checked.
+    offset: u64,
+    expected: Vec<u8>,
+
+    /// The number of bytes that did not match.
+    pub corrupt_bytes: u64,
+
+    /// The number of runs of consecutive corrupt bytes.
+    pub corrupt_regions: u64,
+
+    /// The offsets at which the first corrupt regions start.
+    pub first_corrupt_offsets: Vec<u64>,
+
+    /// Whether the previous byte checked was corrupt.
+    in_corrupt_region: bool,
+}
+
+impl Verifier {
+    pub fn new(pattern: Pattern) -> Verifier {
+        Verifier {
+            pattern,
+            offset: 0,
+            expected: Vec::new(),
+            corrupt_bytes: 0,
+            corrupt_regions: 0,
+            first_corrupt_offsets: Vec::new(),
+            in_corrupt_region: false,
+        }
+    }
+
+    /// Checks the next bytes of the stream.
+    pub fn check(&mut self, data: &[u8]) {
+        self.expected.resize(data.len(), 0);
+        self.pattern.fill(self.offset, &mut self.expected);
+
+        if data == &self.expected[..] {
+            self.in_corrupt_region = false;
+        } else {
+            for (index, (&actual, &expected)) in data.iter().zip(self.expected.iter()).enumerate() {
+                if actual == expected {
+                    self.in_corrupt_region = false;
+                    continue;
+                }
+
+                self.corrupt_bytes += 1;
+                if !self.in_corrupt_region {
+                    self.in_corrupt_region = true;
+                    self.corrupt_regions += 1;
+                    if self.first_corrupt_offsets.len() < MAX_RECORDED_REGIONS {
+                        self.first_corrupt_offsets.push(self.offset + index as u64);
+                    }
+                }
+            }
+        }
+
+        self.offset += data.len() as u64;
+    }
+
+    /// The number of bytes checked so far.
+    pub fn bytes_checked(&self) -> u64 {
+        self.offset
+    }
+
+    pub fn is_ok(&self) -> bool {
+        self.corrupt_bytes == 0
+    }
+}
@@ -0,0 +1,58 @@
+//! Collecting the first bytes of a stream and formatting them as a hexdump,
+//! so that what is being measured can be checked at a glance.
+
+use std::fmt::Write;
+
+/// The number of bytes shown on each line of a hexdump.
+const BYTES_PER_LINE: usize = 16;
+
+/// Collects the head of a stream until it holds the number of bytes asked for.
+pub struct Peek {
+    limit: usize,
+    data: Vec<u8>,
+}
+
+impl Peek {
+    pub fn new(limit: usize) -> Peek {
+        Peek { limit, data: Vec::with_capacity(limit) }
+    }
+
+    /// Adds data that was read, returning true once the head is complete.
+    pub fn add(&mut self, data: &[u8]) -> bool {
+        let wanted = self.limit - self.data.len();
+        self.data.extend_from_slice(&data[..wanted.min(data.len())]);
+        self.is_complete()
+    }
+
+    pub fn is_complete(&self) -> bool {
+        self.data.len() >= self.limit
+    }
+
+    pub fn data(&self) -> &[u8] {
+        &self.data
+    }
+}
+
+/// Formats data the way `hexdump -C` does: an offset, the bytes in hex and
+/// the bytes that are printable ASCII.
+pub fn hexdump(data: &[u8]) -> String {
+    let mut dump = String::new();
+    for (line, chunk) in data.chunks(BYTES_PER_LINE).enumerate() {
+        let _ = write!(dump, "{:08x} ", line * BYTES_PER_LINE);
+        for column in 0..BYTES_PER_LINE {
+            if column % 8 == 0 {
+                dump.push(' ');
+            }
+            match chunk.get(column) {
+                Some(byte) => { let _ = write!(dump, "{:02x} ", byte); },
+                None => dump.push_str("   "),
+            }
+        }
+
+        dump.push_str(" |");
+        dump.extend(chunk.iter().map(|&byte| if byte.is_ascii_graphic() || byte == b' ' { byte as char } else { '.' }));
+        dump.push_str("|\n");
+    }
+    let _ = writeln!(dump, "{:08x}", data.len());
+    dump
+}
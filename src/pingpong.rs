@@ -0,0 +1,96 @@
+//! Bouncing small messages off a peer that echoes them, one at a time, to
+//! measure how many round trips a connection manages a second rather than
+//! how many bytes, which is what RPC-style workloads are limited by.
+
+use std::io::{self, Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use display::format_distribution;
+use histogram::Histogram;
+use units::{byte_to_mem_units, format_latency};
+
+/// Column width of the labels.
+const LABEL_WIDTH: usize = 24;
+
+/// The round trips made and how long each of them took.
+pub struct PingPong {
+    size: usize,
+    round_trips: u64,
+    elapsed: Duration,
+
+    /// The nanoseconds from sending each message to having it back.
+    rtts: Histogram,
+}
+
+impl PingPong {
+    /// Sends messages of `size` bytes over the stream, each once the one
+    /// before it has come back, until the duration has passed. Every message
+    /// is different, so a peer that sends back anything other than what it
+    /// was sent is caught.
+    pub fn run(stream: &mut TcpStream, size: usize, duration: Duration) -> io::Result<PingPong> {
+        stream.set_nodelay(true)?;
+        let mut message = vec![0u8; size];
+        let mut reply = vec![0u8; size];
+        let mut rtts = Histogram::default();
+        let mut round_trips = 0u64;
+
+        let start = Instant::now();
+        while start.elapsed() < duration {
+            for (byte, value) in message.iter_mut().zip(round_trips.to_le_bytes().iter().cycle()) {
+                *byte = *value;
+            }
+
+            let sent = Instant::now();
+            stream.write_all(&message)?;
+            stream.read_exact(&mut reply)?;
+            rtts.record(sent.elapsed().as_nanos() as u64);
+
+            if reply != message {
+                return Err(io::Error::new(io::ErrorKind::InvalidData, "the peer sent back something other than the message"));
+            }
+            round_trips += 1;
+        }
+        Ok(PingPong { size, round_trips, elapsed: start.elapsed(), rtts })
+    }
+
+    /// The round trips per second over the whole run.
+    pub fn rate(&self) -> f64 {
+        let secs = self.elapsed.as_secs_f64();
+        if secs > 0.0 { self.round_trips as f64 / secs } else { 0.0 }
+    }
+
+    /// The labelled values that make up the results.
+    pub fn rows(&self) -> Vec<(&'static str, String)> {
+        let (mem, unit) = byte_to_mem_units(self.rate() * self.size as f64);
+        vec![
+            ("Round Trips:", format!("{} in {:.3}s", self.round_trips, self.elapsed.as_secs_f64())),
+            ("Messages Per Second:", format!("{:.0}", self.rate())),
+            ("Data Rate:", format!("{:.3} {}/sec each way", mem, unit)),
+            ("Round Trip Time:", format_distribution(&self.rtts, "round trips", format_latency)),
+        ]
+    }
+
+    pub fn print<W: Write>(&self, output: &mut W) -> io::Result<()> {
+        for (label, value) in self.rows() {
+            writeln!(output, "{:<width$}{}", label, value, width = LABEL_WIDTH)?;
+        }
+        Ok(())
+    }
+}
+
+/// Sends back everything that every connection to the listener sends, for
+/// the other end of a ping-pong run. Runs until the process is stopped.
+pub fn echo(listener: TcpListener) {
+    for stream in listener.incoming().flatten() {
+        thread::spawn(move || {
+            // A peer going away is how every run ends.
+            let _ = stream.set_nodelay(true);
+            if let Ok(mut reader) = stream.try_clone() {
+                let mut writer = stream;
+                let _ = io::copy(&mut reader, &mut writer);
+            }
+        });
+    }
+}
@@ -0,0 +1,201 @@
+//! Passthrough with reading and writing on separate threads. Filled buffers
+//! are queued for a writer thread and handed back once they have been
+//! written, so a slow consumer only stalls reads once every buffer is queued
+//! and a slow producer never leaves the consumer without queued data.
+
+use std::io::{self, BufWriter, LineWriter, Write};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError, Sender, SyncSender};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+use units;
+
+/// How much data is collected before a timed flush when writing every
+/// interval.
+const INTERVAL_BUFFER_SIZE: usize = 1 << 20;
+
+/// When the writer thread hands what it has written over to the output.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum FlushPolicy {
+    /// Every buffer is written as soon as it has been read.
+    Immediate,
+
+    /// Data is collected and written once this many bytes have built up.
+    Bytes(usize),
+
+    /// Data is collected and written at least this often.
+    Interval(Duration),
+
+    /// Only whole lines are written, as soon as each one ends.
+    Lines,
+}
+
+impl FlushPolicy {
+    /// Parses the value of `--flush-every`, which is a size such as `64K` or
+    /// a duration such as `100ms`. A bare number is a number of bytes.
+    pub fn parse_every(value: &str) -> Option<FlushPolicy> {
+        match units::parse_size(value) {
+            Some(0) => None,
+            Some(bytes) => Some(FlushPolicy::Bytes(bytes as usize)),
+            None => units::parse_duration(value)
+                .filter(|interval| *interval > Duration::from_secs(0))
+                .map(FlushPolicy::Interval),
+        }
+    }
+}
+
+/// A ring of buffers shared between the reading thread and a writer thread.
+pub struct Pipeline {
+    /// Buffers waiting to be written, with the number of bytes filled.
+    filled: Option<SyncSender<(Vec<u8>, usize)>>,
+
+    /// Buffers that have been written and can be filled again.
+    empty: Receiver<Vec<u8>>,
+
+    /// The number of buffers queued or being written.
+    queued: Arc<AtomicUsize>,
+
+    writer: Option<JoinHandle<io::Result<()>>>,
+}
+
+/// How full the queue of buffers waiting to be written was, sampled every
+/// time a buffer is queued.
+#[derive(Clone, Copy, Default)]
+pub struct QueueOccupancy {
+    /// The number of buffers in the ring.
+    pub depth: usize,
+
+    /// The number of times the occupancy was sampled.
+    pub samples: u64,
+
+    /// The sum of every sampled occupancy.
+    pub total: u64,
+
+    /// The highest sampled occupancy.
+    pub peak: usize,
+}
+
+impl QueueOccupancy {
+    pub fn new(depth: usize) -> QueueOccupancy {
+        QueueOccupancy { depth, ..QueueOccupancy::default() }
+    }
+
+    pub fn record(&mut self, queued: usize) {
+        self.samples += 1;
+        self.total += queued as u64;
+        self.peak = self.peak.max(queued);
+    }
+
+    /// The average number of buffers that were queued.
+    pub fn average(&self) -> f64 {
+        if self.samples == 0 { 0.0 } else { self.total as f64 / self.samples as f64 }
+    }
+}
+
+impl Pipeline {
+    /// Starts a thread writing to the output with `depth` buffers of
+    /// `buffer_size` bytes, flushing it as the policy says.
+    pub fn new<W>(output: W, buffer_size: usize, depth: usize, flush: FlushPolicy) -> Pipeline
+        where W: Write + Send + 'static
+    {
+        let (filled_sender, filled_receiver) = mpsc::sync_channel::<(Vec<u8>, usize)>(depth);
+        let (empty_sender, empty_receiver) = mpsc::channel();
+        for _ in 0..depth {
+            empty_sender.send(vec![0; buffer_size]).expect("the receiver should still exist");
+        }
+
+        let queued = Arc::new(AtomicUsize::new(0));
+        let writer_queued = queued.clone();
+        let writer = thread::spawn(move || match flush {
+            FlushPolicy::Immediate => write_queued(output, filled_receiver, empty_sender, writer_queued, None),
+            FlushPolicy::Bytes(bytes) => {
+                let output = BufWriter::with_capacity(bytes, output);
+                write_queued(output, filled_receiver, empty_sender, writer_queued, None)
+            },
+            FlushPolicy::Interval(interval) => {
+                let output = BufWriter::with_capacity(INTERVAL_BUFFER_SIZE, output);
+                write_queued(output, filled_receiver, empty_sender, writer_queued, Some(interval))
+            },
+            FlushPolicy::Lines => write_queued(LineWriter::new(output), filled_receiver, empty_sender, writer_queued, None),
+        });
+
+        Pipeline { filled: Some(filled_sender), empty: empty_receiver, queued, writer: Some(writer) }
+    }
+
+    /// Returns a buffer to read into, waiting for one to be written if they
+    /// are all queued.
+    pub fn take_buffer(&mut self) -> io::Result<Vec<u8>> {
+        match self.empty.recv() {
+            Ok(buffer) => Ok(buffer),
+            Err(_) => Err(self.writer_error()),
+        }
+    }
+
+    /// Queues the first `len` bytes of the buffer to be written and returns
+    /// how many buffers are now queued.
+    pub fn send(&mut self, buffer: Vec<u8>, len: usize) -> io::Result<usize> {
+        let queued = self.queued.fetch_add(1, Ordering::Relaxed) + 1;
+        let filled = self.filled.as_ref().expect("the pipeline should not be finished");
+        match filled.send((buffer, len)) {
+            Ok(()) => Ok(queued),
+            Err(_) => Err(self.writer_error()),
+        }
+    }
+
+    /// Waits for every queued buffer to be written.
+    pub fn finish(&mut self) -> io::Result<()> {
+        self.filled = None;
+        match self.writer.take() {
+            Some(writer) => writer.join().unwrap_or_else(|_| Err(io::Error::other("the writer thread panicked"))),
+            None => Ok(()),
+        }
+    }
+
+    /// Collects the error that made the writer thread stop.
+    fn writer_error(&mut self) -> io::Error {
+        match self.finish() {
+            Err(err) => err,
+            Ok(()) => io::Error::other("the writer thread stopped"),
+        }
+    }
+}
+
+/// Writes queued buffers to the output until the reader finishes, handing
+/// each buffer back once written. With an interval the output is also
+/// flushed whenever that long has passed since it last was.
+fn write_queued<W: Write>(mut output: W, filled: Receiver<(Vec<u8>, usize)>, empty: Sender<Vec<u8>>,
+    queued: Arc<AtomicUsize>, interval: Option<Duration>) -> io::Result<()>
+{
+    let mut last_flush = Instant::now();
+    loop {
+        let received = match interval {
+            Some(interval) => match filled.recv_timeout(interval.saturating_sub(last_flush.elapsed())) {
+                Ok(received) => Some(received),
+                Err(RecvTimeoutError::Timeout) => None,
+                Err(RecvTimeoutError::Disconnected) => break,
+            },
+            None => match filled.recv() {
+                Ok(received) => Some(received),
+                Err(_) => break,
+            },
+        };
+
+        if let Some((buffer, len)) = received {
+            output.write_all(&buffer[0..len])?;
+            queued.fetch_sub(1, Ordering::Relaxed);
+
+            // The reader may have finished and stopped taking buffers.
+            let _ = empty.send(buffer);
+        }
+
+        if let Some(interval) = interval {
+            if last_flush.elapsed() >= interval {
+                output.flush()?;
+                last_flush = Instant::now();
+            }
+        }
+    }
+    output.flush()
+}
@@ -0,0 +1,36 @@
+//! Sizing the kernel buffers of the pipes on stdin and stdout on Linux. A
+//! pipe holds 64 KiB by default, which is often what limits a pipeline
+//! rather than the programs on either end of it.
+
+use std::io;
+use std::os::unix::io::RawFd;
+
+/// The size a pipe was asked to be and the size the kernel gave it.
+pub struct PipeSize {
+    pub requested: usize,
+    pub achieved: usize,
+
+    /// Why the pipe could not be resized, if it could not.
+    pub error: Option<io::Error>,
+}
+
+/// Resizes the buffer of the pipe, or returns None if the descriptor is not a
+/// pipe. The kernel rounds the size up to a whole number of pages, and only
+/// allows sizes above `/proc/sys/fs/pipe-max-size` to privileged processes.
+pub fn resize(fd: RawFd, size: usize) -> Option<PipeSize> {
+    let mut stat: libc::stat = unsafe { std::mem::zeroed() };
+    if unsafe { libc::fstat(fd, &mut stat) } != 0 || stat.st_mode & libc::S_IFMT != libc::S_IFIFO {
+        return None;
+    }
+
+    let requested = size.min(libc::c_int::MAX as usize);
+    let error = match unsafe { libc::fcntl(fd, libc::F_SETPIPE_SZ, requested as libc::c_int) } {
+        -1 => Some(io::Error::last_os_error()),
+        _ => None,
+    };
+    let achieved = match unsafe { libc::fcntl(fd, libc::F_GETPIPE_SZ) } {
+        -1 => 0,
+        achieved => achieved as usize,
+    };
+    Some(PipeSize { requested, achieved, error })
+}
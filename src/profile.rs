@@ -0,0 +1,71 @@
+//! Presets of options for common scenarios, so that a fast link is not
+//! measured with the small buffers that suit a slow pipe. A profile only sets
+//! defaults: options given on the command line, in the environment or in the
+//! config file take precedence over it.
+
+/// A scenario that options can be preset for.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Profile {
+    /// A fast local network with little latency.
+    Lan,
+
+    /// A long, fat path such as one between regions, whose rate varies
+    /// more from second to second.
+    Wan,
+
+    /// Reading or writing files on local storage.
+    Disk,
+
+    /// A stage of a shell pipeline.
+    Pipe,
+}
+
+pub const PROFILE_NAMES: &[&str] = &["lan", "wan", "disk", "pipe"];
+
+impl Profile {
+    pub fn parse(name: &str) -> Option<Profile> {
+        match name {
+            "lan" => Some(Profile::Lan),
+            "wan" => Some(Profile::Wan),
+            "disk" => Some(Profile::Disk),
+            "pipe" => Some(Profile::Pipe),
+            _ => None,
+        }
+    }
+
+    /// The options the profile sets, by their long names as in the config
+    /// file.
+    pub fn options(self) -> Vec<(&'static str, &'static str)> {
+        match self {
+            Profile::Lan => vec![
+                ("bufsize", "131072"),
+                ("rcvbuf", "4194304"),
+                ("sndbuf", "4194304"),
+            ],
+            // Enough buffering to keep a path of a few hundred milliseconds
+            // full, measured over longer intervals to smooth out its swings.
+            Profile::Wan => vec![
+                ("bufsize", "262144"),
+                ("rcvbuf", "16777216"),
+                ("sndbuf", "16777216"),
+                ("interval", "5s"),
+            ],
+            Profile::Disk => vec![
+                ("bufsize", "1048576"),
+                ("read-stats", "true"),
+            ],
+            // Pipes hold 64 KiB unless they are resized, which only Linux
+            // allows.
+            Profile::Pipe => {
+                let mut options = vec![
+                    ("bufsize", "65536"),
+                    ("compact", "true"),
+                ];
+                if cfg!(target_os = "linux") {
+                    options.push(("pipe-size", "1M"));
+                }
+                options
+            },
+        }
+    }
+}
@@ -0,0 +1,368 @@
+//! Pushes every measure to a metrics backend as it is taken, either as
+//! InfluxDB line protocol written over HTTP, as lines of JSON streamed to a
+//! collector such as `throughput aggregate`, as records in the system log, or
+//! as OpenTelemetry metrics and spans with the `otlp` feature.
+
+use std::io::{self, Read, Write};
+use std::net::{SocketAddr, TcpStream, ToSocketAddrs, UdpSocket};
+#[cfg(unix)]
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{mpsc, Mutex};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use serde_json::Value;
+
+#[cfg(feature = "otlp")]
+use otlp::OtlpExporter;
+#[cfg(unix)]
+use syslog::SystemLog;
+use TransferInfo;
+
+/// How long a write over HTTP may take before it is given up on.
+const HTTP_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// How long connecting or writing to a collector may take before the
+/// measure is given up on.
+const COLLECTOR_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// The name measurements are reported under.
+const MEASUREMENT: &str = "throughput";
+
+/// A single measure of a transfer.
+pub struct Sample {
+    /// Bytes transferred since the previous measure.
+    pub bytes: usize,
+    pub total_bytes: usize,
+    pub current_bps: f64,
+    pub average_bps: f64,
+    pub elapsed: Duration,
+    pub time: SystemTime,
+}
+
+/// An InfluxDB write endpoint, e.g.
+/// `http://localhost:8086/write?db=throughput` or
+/// `http://localhost:8086/api/v2/write?org=o&bucket=b`.
+pub struct InfluxTarget {
+    host: String,
+    path: String,
+
+    /// Sent as `Authorization: Token <token>`, which InfluxDB 2 requires.
+    token: Option<String>,
+}
+
+impl InfluxTarget {
+    /// Parses an `http://` URL. HTTPS is not supported.
+    pub fn parse(url: &str, token: Option<String>) -> Option<InfluxTarget> {
+        let (host, path) = parse_http_url(url, 80, "/write")?;
+        Some(InfluxTarget { host, path, token })
+    }
+
+    fn write(&self, body: &str) -> io::Result<()> {
+        let authorization = self.token.as_ref().map(|token| format!("Authorization: Token {}\r\n", token));
+        http_post("InfluxDB", &self.host, &self.path, "text/plain; charset=utf-8", authorization.as_deref().unwrap_or(""), body)
+    }
+}
+
+/// Parses the host and path of an `http://` URL. HTTPS is not supported.
+pub fn parse_http_url(url: &str, default_port: u16, default_path: &str) -> Option<(String, String)> {
+    let rest = url.strip_prefix("http://")?;
+    let (host, path) = match rest.find('/') {
+        Some(index) => (&rest[..index], &rest[index..]),
+        None => (rest, default_path),
+    };
+    if host.is_empty() { return None; }
+
+    let host = if host.contains(':') && !host.ends_with(']') { host.to_string() } else { format!("{}:{}", host, default_port) };
+    Some((host, path.to_string()))
+}
+
+/// Posts the body to the path on the host, which must respond with a 2xx
+/// status. The headers are complete lines, each ending with CRLF.
+pub fn http_post(backend: &str, host: &str, path: &str, content_type: &str, headers: &str, body: &str) -> io::Result<()> {
+    let addr = resolve(host)?;
+    let mut stream = TcpStream::connect_timeout(&addr, HTTP_TIMEOUT)?;
+    stream.set_read_timeout(Some(HTTP_TIMEOUT))?;
+    stream.set_write_timeout(Some(HTTP_TIMEOUT))?;
+
+    let request = format!("POST {} HTTP/1.1\r\nHost: {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n{}\r\n{}",
+        path, host, content_type, body.len(), headers, body);
+    stream.write_all(request.as_bytes())?;
+
+    let mut response = String::new();
+    stream.read_to_string(&mut response)?;
+    let status = response.split_whitespace().nth(1).unwrap_or("");
+    if status.starts_with('2') {
+        Ok(())
+    } else {
+        Err(io::Error::other(format!("{} responded with {}", backend, response.lines().next().unwrap_or(""))))
+    }
+}
+
+/// A collector that every measure is streamed to as a line of JSON, over a
+/// TCP connection or as UDP datagrams.
+pub struct CollectorTarget {
+    host: String,
+    udp: bool,
+
+    /// The name the measures are sent under, so that a collector can tell
+    /// several agents apart.
+    agent: String,
+}
+
+impl CollectorTarget {
+    /// Parses `host:port` or `tcp://host:port` for TCP and `udp://host:port`
+    /// for UDP, checking that the host resolves.
+    pub fn parse(value: &str, agent: String) -> Option<CollectorTarget> {
+        let (host, udp) = match value.strip_prefix("udp://") {
+            Some(host) => (host, true),
+            None => (value.strip_prefix("tcp://").unwrap_or(value), false),
+        };
+        resolve(host).ok()?;
+        Some(CollectorTarget { host: host.to_string(), udp, agent })
+    }
+
+    /// Returns a function that sends a line to the collector. TCP
+    /// connections are made when first needed and again after a failure.
+    fn sender(self) -> impl FnMut(&str) -> io::Result<()> {
+        let mut stream: Option<TcpStream> = None;
+        let mut socket: Option<UdpSocket> = None;
+        move |line| {
+            let addr = resolve(&self.host)?;
+            if self.udp {
+                if socket.is_none() {
+                    socket = Some(UdpSocket::bind(unspecified_addr(addr))?);
+                }
+                socket.as_ref().expect("the socket was just bound").send_to(line.as_bytes(), addr).map(drop)
+            } else {
+                if stream.is_none() {
+                    let connected = TcpStream::connect_timeout(&addr, COLLECTOR_TIMEOUT)?;
+                    connected.set_write_timeout(Some(COLLECTOR_TIMEOUT))?;
+                    stream = Some(connected);
+                }
+                let result = stream.as_mut().expect("the stream was just connected").write_all(line.as_bytes());
+                if result.is_err() {
+                    stream = None;
+                }
+                result
+            }
+        }
+    }
+}
+
+pub fn resolve(host: &str) -> io::Result<SocketAddr> {
+    host.to_socket_addrs()?.next()
+        .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, format!("{} did not resolve", host)))
+}
+
+/// The wildcard address of the same family, for binding a UDP socket that
+/// sends to `addr`.
+pub fn unspecified_addr(addr: SocketAddr) -> SocketAddr {
+    if addr.is_ipv4() { ([0, 0, 0, 0], 0).into() } else { ([0u16; 8], 0).into() }
+}
+
+/// The name of this host, which agents report under by default.
+pub fn hostname() -> String {
+    #[cfg(unix)]
+    {
+        let mut name = [0u8; 256];
+        if unsafe { libc::gethostname(name.as_mut_ptr() as *mut libc::c_char, name.len()) } == 0 {
+            let len = name.iter().position(|&byte| byte == 0).unwrap_or(name.len());
+            return String::from_utf8_lossy(&name[..len]).into_owned();
+        }
+    }
+    std::env::var("COMPUTERNAME").or_else(|_| std::env::var("HOSTNAME")).unwrap_or_else(|_| "throughput".to_string())
+}
+
+/// Messages for a thread that writes them to a backend, so that a slow
+/// backend does not hold up measuring.
+pub type Writer<T> = (mpsc::Sender<T>, JoinHandle<()>);
+
+type LineWriter = Writer<String>;
+
+/// Starts a thread that writes every line it is sent, reporting only the
+/// first failure so that an unreachable backend does not flood the output.
+fn spawn_line_writer<F>(backend: &'static str, mut write: F) -> LineWriter
+    where F: FnMut(&str) -> io::Result<()> + Send + 'static
+{
+    spawn_writer(backend, move |line: String| write(&line))
+}
+
+/// Starts a thread that writes every message it is sent, like
+/// `spawn_line_writer`.
+pub fn spawn_writer<T, F>(backend: &'static str, mut write: F) -> Writer<T>
+    where T: Send + 'static, F: FnMut(T) -> io::Result<()> + Send + 'static
+{
+    let (sender, receiver) = mpsc::channel::<T>();
+    let writer = thread::spawn(move || {
+        let mut warned = false;
+        for message in receiver {
+            if let Err(err) = write(message) {
+                if !warned {
+                    print_err!("Error while writing to {}: {}", backend, err);
+                    warned = true;
+                }
+            }
+        }
+    });
+    (sender, writer)
+}
+
+/// Sends samples to the configured backends.
+pub struct Pusher {
+    /// Taken by `finish`.
+    influx: Mutex<Option<LineWriter>>,
+
+    /// Taken by `finish`, along with the name of the agent.
+    collector: Mutex<Option<(LineWriter, String)>>,
+
+    #[cfg(unix)]
+    system_log: Option<SystemLog>,
+
+    #[cfg(feature = "otlp")]
+    otlp: Option<OtlpExporter>,
+
+    /// Only the first failure is reported so that an unavailable log does not
+    /// flood the output.
+    #[cfg(unix)]
+    warned: AtomicBool,
+}
+
+impl Pusher {
+    pub fn new(influx: Option<InfluxTarget>, collector: Option<CollectorTarget>) -> Pusher {
+        let influx = influx.map(|target| spawn_line_writer("InfluxDB", move |line| target.write(line)));
+        let collector = collector.map(|target| {
+            let agent = target.agent.clone();
+            (spawn_line_writer("the stats collector", target.sender()), agent)
+        });
+
+        Pusher {
+            influx: Mutex::new(influx),
+            collector: Mutex::new(collector),
+            #[cfg(unix)]
+            system_log: None,
+            #[cfg(feature = "otlp")]
+            otlp: None,
+            #[cfg(unix)]
+            warned: AtomicBool::new(false),
+        }
+    }
+
+    /// Also logs every measure and the summary to the system log.
+    #[cfg(unix)]
+    pub fn set_system_log(&mut self, system_log: SystemLog) {
+        self.system_log = Some(system_log);
+    }
+
+    /// Also exports every measure, and a span for every run if asked to, to
+    /// an OpenTelemetry collector.
+    #[cfg(feature = "otlp")]
+    pub fn set_otlp(&mut self, otlp: OtlpExporter) {
+        self.otlp = Some(otlp);
+    }
+
+    /// Marks the start of a run, counted from 1, which the measures that
+    /// follow belong to.
+    #[allow(unused_variables)]
+    pub fn start_run(&self, run: usize) {
+        #[cfg(feature = "otlp")]
+        {
+            if let Some(ref otlp) = self.otlp {
+                otlp.start_run(run);
+            }
+        }
+    }
+
+    /// Marks the end of the run, which only OpenTelemetry records.
+    #[allow(unused_variables)]
+    pub fn finish_run(&self, transfer_info: &TransferInfo) {
+        #[cfg(feature = "otlp")]
+        {
+            if let Some(ref otlp) = self.otlp {
+                otlp.finish_run(transfer_info);
+            }
+        }
+    }
+
+    pub fn push(&self, sample: &Sample) {
+        if let Some((ref influx, _)) = *self.influx.lock().expect("influx lock poisoned") {
+            let nanos = sample.time.duration_since(UNIX_EPOCH).map(|d| d.as_nanos()).unwrap_or(0);
+            let line = format!("{} bytes={}i,total_bytes={}i,current_bps={},average_bps={} {}\n",
+                MEASUREMENT, sample.bytes, sample.total_bytes, sample.current_bps, sample.average_bps, nanos);
+            // The writer thread only stops if it panicked.
+            let _ = influx.send(line);
+        }
+
+        if let Some(((ref collector, _), ref agent)) = *self.collector.lock().expect("collector lock poisoned") {
+            let time = sample.time.duration_since(UNIX_EPOCH).map(|d| d.as_secs_f64()).unwrap_or(0.0);
+            let message = json!({
+                "type": "stats",
+                "agent": agent,
+                "time": time,
+                "elapsed_secs": sample.elapsed.as_secs_f64(),
+                "bytes": sample.bytes,
+                "total_bytes": sample.total_bytes,
+                "current_bps": sample.current_bps,
+                "average_bps": sample.average_bps,
+            });
+            let _ = collector.send(format!("{}\n", message));
+        }
+
+        #[cfg(unix)]
+        {
+            if let Some(ref system_log) = self.system_log {
+                self.report(system_log.log_measure(sample));
+            }
+        }
+
+        #[cfg(feature = "otlp")]
+        {
+            if let Some(ref otlp) = self.otlp {
+                otlp.push(sample);
+            }
+        }
+    }
+
+    /// Logs the summary of a finished transfer, which only the system log
+    /// records.
+    #[allow(unused_variables)]
+    pub fn push_summary(&self, summary: &Value) {
+        #[cfg(unix)]
+        {
+            if let Some(ref system_log) = self.system_log {
+                self.report(system_log.log_summary(summary));
+            }
+        }
+    }
+
+    /// Reports the first failure to write to the system log. Records that
+    /// would have to wait for the log to catch up are dropped silently.
+    #[cfg(unix)]
+    fn report(&self, result: io::Result<()>) {
+        if let Err(err) = result {
+            if err.kind() != io::ErrorKind::WouldBlock && !self.warned.swap(true, Ordering::Relaxed) {
+                print_err!("Error while writing to the system log: {}", err);
+            }
+        }
+    }
+
+    /// Waits for the samples that have already been pushed to be written,
+    /// telling the collector that no more are coming.
+    pub fn finish(&self) {
+        if let Some((sender, writer)) = self.influx.lock().expect("influx lock poisoned").take() {
+            drop(sender);
+            let _ = writer.join();
+        }
+        if let Some(((sender, writer), agent)) = self.collector.lock().expect("collector lock poisoned").take() {
+            let _ = sender.send(format!("{}\n", json!({ "type": "done", "agent": agent })));
+            drop(sender);
+            let _ = writer.join();
+        }
+        #[cfg(feature = "otlp")]
+        {
+            if let Some(ref otlp) = self.otlp {
+                otlp.finish();
+            }
+        }
+    }
+}
@@ -0,0 +1,86 @@
+use std::collections::VecDeque;
+use std::time::Duration;
+
+use units::bytes_per_second;
+
+/// How the current transfer speed is estimated from recent measures.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum RateMode {
+    /// The speed during the last measure only.
+    Last,
+
+    /// The speed over all measures taken within the window.
+    Window(Duration),
+
+    /// An exponentially weighted moving average with the given smoothing
+    /// factor between 0 (ignore new measures) and 1 (only the last measure).
+    Ewma(f64),
+}
+
+impl RateMode {
+    /// A short description of the estimator shown next to the speed.
+    pub fn describe(&self) -> Option<String> {
+        match *self {
+            RateMode::Last => None,
+            RateMode::Window(window) => Some(format!("{}s window", window.as_secs_f64())),
+            RateMode::Ewma(alpha) => Some(format!("EWMA {}", alpha)),
+        }
+    }
+}
+
+/// Tracks recent measures to estimate the current transfer speed.
+pub struct RateEstimator {
+    mode: RateMode,
+
+    /// Measures within the window as (bytes, duration) pairs, oldest first.
+    samples: VecDeque<(usize, Duration)>,
+
+    /// The total duration of the measures in `samples`.
+    sampled_duration: Duration,
+
+    /// The smoothed speed so far, if there has been a measure.
+    ewma: Option<f64>,
+}
+
+impl RateEstimator {
+    pub fn new(mode: RateMode) -> RateEstimator {
+        RateEstimator {
+            mode,
+            samples: VecDeque::new(),
+            sampled_duration: Duration::from_secs(0),
+            ewma: None,
+        }
+    }
+
+    /// Records a measure and returns the estimated current speed.
+    pub fn update(&mut self, bytes: usize, duration: Duration) -> f64 {
+        match self.mode {
+            RateMode::Last => bytes_per_second(bytes, duration),
+
+            RateMode::Window(window) => {
+                self.samples.push_back((bytes, duration));
+                self.sampled_duration += duration;
+
+                // Always keep the latest measure even if it alone is longer
+                // than the window.
+                while self.samples.len() > 1 && self.sampled_duration - self.samples[0].1 >= window {
+                    let (_, oldest) = self.samples.pop_front().expect("samples should not be empty");
+                    self.sampled_duration -= oldest;
+                }
+
+                let total: usize = self.samples.iter().map(|&(bytes, _)| bytes).sum();
+                bytes_per_second(total, self.sampled_duration)
+            },
+
+            RateMode::Ewma(alpha) => {
+                let bps = bytes_per_second(bytes, duration);
+                let smoothed = match self.ewma {
+                    Some(previous) => alpha * bps + (1.0 - alpha) * previous,
+                    None => bps,
+                };
+                self.ewma = Some(smoothed);
+                smoothed
+            },
+        }
+    }
+}
@@ -0,0 +1,77 @@
+//! Reading from a server that may drop the connection partway through,
+//! connecting to it again so that one measurement covers every connection.
+
+use std::io::{self, Read};
+use std::net::{SocketAddr, TcpStream};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use net::{self, SocketOptions};
+use print_connect_retry;
+
+/// A reader that connects again whenever its connection ends or is reset,
+/// and only reaches its end once the server can no longer be reached.
+pub struct ReconnectReader {
+    addrs: Vec<SocketAddr>,
+    options: SocketOptions,
+    stream: TcpStream,
+    peer: SocketAddr,
+    reconnects: Arc<AtomicUsize>,
+}
+
+impl ReconnectReader {
+    pub fn new(stream: TcpStream, peer: SocketAddr, addrs: &[SocketAddr], options: &SocketOptions) -> (ReconnectReader, Arc<AtomicUsize>) {
+        let reconnects = Arc::new(AtomicUsize::new(0));
+        let reader = ReconnectReader {
+            addrs: addrs.to_vec(),
+            options: options.clone(),
+            stream,
+            peer,
+            reconnects: reconnects.clone(),
+        };
+        (reader, reconnects)
+    }
+
+    /// Replaces the lost connection, returning false if the server could
+    /// not be reached again.
+    fn reconnect(&mut self) -> bool {
+        print_err!("Lost the connection to {}, reconnecting", self.peer);
+        let options = &self.options;
+        match net::connect_retrying(&self.addrs, options, |err, retry| print_connect_retry(err, retry, options)) {
+            Ok((stream, peer)) => {
+                print_err!("Reconnected to {}", peer);
+                self.stream = stream;
+                self.peer = peer;
+                self.reconnects.fetch_add(1, Ordering::Relaxed);
+                true
+            },
+
+            Err(err) => {
+                print_err!("Could not reconnect to {}: {}", self.peer, err);
+                false
+            },
+        }
+    }
+}
+
+impl Read for ReconnectReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        loop {
+            match self.stream.read(buf) {
+                Ok(0) if !buf.is_empty() => {},
+                Err(ref err) if is_connection_lost(err) => {},
+                result => return result,
+            }
+            if !self.reconnect() {
+                return Ok(0);
+            }
+        }
+    }
+}
+
+/// Whether the error means the connection is gone, rather than that a read
+/// timed out or was interrupted.
+fn is_connection_lost(err: &io::Error) -> bool {
+    matches!(err.kind(), io::ErrorKind::ConnectionReset | io::ErrorKind::ConnectionAborted
+        | io::ErrorKind::BrokenPipe | io::ErrorKind::UnexpectedEof)
+}
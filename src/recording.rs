@@ -0,0 +1,215 @@
+//! Recording a stream along with when each part of it arrived, and replaying
+//! it with the same timing, so that one capture can be used again and again
+//! as a load source. A chunk log records only when each part arrived and how
+//! large it was, which is enough to study how bursty a stream is.
+//!
+//! Both start with a magic number, followed by every chunk that was read as
+//! the nanoseconds since the first chunk (u64) and the length of the chunk
+//! (u32), both little endian. A recording follows each of these with the data
+//! itself.
+
+use std::fs::File;
+use std::io::{self, BufReader, BufWriter, Read, Write};
+use std::thread;
+use std::time::{Duration, Instant};
+
+const RECORDING_MAGIC: &[u8; 8] = b"THRPREC1";
+const CHUNK_LOG_MAGIC: &[u8; 8] = b"THRPCHK1";
+
+/// Writes the chunks of a stream to a recording or a chunk log.
+pub struct Recorder {
+    writer: BufWriter<File>,
+
+    /// Whether the data of each chunk is written after it.
+    with_data: bool,
+
+    /// When the first chunk was recorded.
+    start: Option<Instant>,
+}
+
+impl Recorder {
+    pub fn create(path: &str) -> io::Result<Recorder> {
+        Recorder::create_with(path, RECORDING_MAGIC, true)
+    }
+
+    pub fn create_chunk_log(path: &str) -> io::Result<Recorder> {
+        Recorder::create_with(path, CHUNK_LOG_MAGIC, false)
+    }
+
+    fn create_with(path: &str, magic: &[u8], with_data: bool) -> io::Result<Recorder> {
+        let mut writer = BufWriter::new(File::create(path)?);
+        writer.write_all(magic)?;
+        Ok(Recorder { writer, with_data, start: None })
+    }
+
+    pub fn record(&mut self, data: &[u8]) -> io::Result<()> {
+        let now = Instant::now();
+        let offset = now.duration_since(*self.start.get_or_insert(now));
+        self.writer.write_all(&(offset.as_nanos() as u64).to_le_bytes())?;
+        self.writer.write_all(&(data.len() as u32).to_le_bytes())?;
+        if self.with_data {
+            self.writer.write_all(data)?;
+        }
+        Ok(())
+    }
+
+    pub fn flush(&mut self) -> io::Result<()> {
+        self.writer.flush()
+    }
+}
+
+/// When a chunk arrived, relative to the first one, and how large it was.
+#[derive(Clone, Copy, Debug)]
+pub struct Chunk {
+    pub offset: Duration,
+    pub len: u32,
+}
+
+/// Reads the chunks of a recording or a chunk log in order.
+pub struct ChunkReader {
+    reader: BufReader<File>,
+    has_data: bool,
+}
+
+impl ChunkReader {
+    pub fn open(path: &str) -> io::Result<ChunkReader> {
+        let mut reader = BufReader::new(File::open(path)?);
+        let mut magic = [0u8; 8];
+        let has_data = match reader.read_exact(&mut magic) {
+            Ok(()) if &magic == RECORDING_MAGIC => true,
+            Ok(()) if &magic == CHUNK_LOG_MAGIC => false,
+            _ => return Err(io::Error::new(io::ErrorKind::InvalidData, "not a throughput recording or chunk log")),
+        };
+        Ok(ChunkReader { reader, has_data })
+    }
+
+    /// Whether the data of each chunk was recorded.
+    pub fn has_data(&self) -> bool {
+        self.has_data
+    }
+
+    /// Reads the next chunk, and its data into `data` if it is given,
+    /// returning `None` at the end. A file that was cut short, such as by
+    /// the recorder being killed, ends at its last whole chunk.
+    pub fn next_chunk(&mut self, data: Option<&mut Vec<u8>>) -> io::Result<Option<Chunk>> {
+        match self.read_chunk(data) {
+            Ok(chunk) => Ok(Some(chunk)),
+            Err(ref err) if err.kind() == io::ErrorKind::UnexpectedEof => Ok(None),
+            Err(err) => Err(err),
+        }
+    }
+
+    fn read_chunk(&mut self, data: Option<&mut Vec<u8>>) -> io::Result<Chunk> {
+        let mut offset = [0u8; 8];
+        let mut len = [0u8; 4];
+        self.reader.read_exact(&mut offset)?;
+        self.reader.read_exact(&mut len)?;
+        let chunk = Chunk {
+            offset: Duration::from_nanos(u64::from_le_bytes(offset)),
+            len: u32::from_le_bytes(len),
+        };
+
+        if self.has_data {
+            match data {
+                Some(data) => {
+                    data.resize(chunk.len as usize, 0);
+                    if let Err(err) = self.reader.read_exact(data) {
+                        data.clear();
+                        return Err(err);
+                    }
+                },
+                None => {
+                    let skipped = io::copy(&mut (&mut self.reader).take(chunk.len as u64), &mut io::sink())?;
+                    if skipped < chunk.len as u64 {
+                        return Err(io::ErrorKind::UnexpectedEof.into());
+                    }
+                },
+            }
+        }
+        Ok(chunk)
+    }
+}
+
+/// Reads the data of a recording back, holding each chunk until the time it
+/// was recorded at divided by the speed, or handing it out right away when
+/// there is no speed.
+pub struct Replay {
+    chunks: ChunkReader,
+    speed: Option<f64>,
+
+    /// When the replay started, set once the first chunk is read.
+    start: Option<Instant>,
+
+    /// The chunk currently being handed out and how much of it has been read.
+    chunk: Vec<u8>,
+    position: usize,
+}
+
+impl Replay {
+    pub fn open(path: &str, speed: Option<f64>) -> io::Result<Replay> {
+        let chunks = ChunkReader::open(path)?;
+        if !chunks.has_data() {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "a chunk log holds no data to replay"));
+        }
+        Ok(Replay { chunks, speed, start: None, chunk: Vec::new(), position: 0 })
+    }
+
+    /// Reads the next chunk and waits until it is due, returning false at
+    /// the end of the recording.
+    fn next_chunk(&mut self) -> io::Result<bool> {
+        self.position = 0;
+        let chunk = match self.chunks.next_chunk(Some(&mut self.chunk))? {
+            Some(chunk) => chunk,
+            None => {
+                self.chunk.clear();
+                return Ok(false);
+            },
+        };
+
+        let start = *self.start.get_or_insert_with(Instant::now);
+        if let Some(speed) = self.speed {
+            let due = start + chunk.offset.div_f64(speed);
+            thread::sleep(due.saturating_duration_since(Instant::now()));
+        }
+        Ok(true)
+    }
+}
+
+impl Read for Replay {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        while self.position == self.chunk.len() {
+            if !self.next_chunk()? {
+                return Ok(0);
+            }
+        }
+
+        let count = buf.len().min(self.chunk.len() - self.position);
+        buf[..count].copy_from_slice(&self.chunk[self.position..self.position + count]);
+        self.position += count;
+        Ok(count)
+    }
+}
+
+/// Writes everything read from the reader to the writer, so that data can be
+/// measured as it is sent.
+pub struct Forwarder<R, W> {
+    reader: R,
+    writer: W,
+}
+
+impl<R, W> Forwarder<R, W> {
+    pub fn new(reader: R, writer: W) -> Forwarder<R, W> {
+        Forwarder { reader, writer }
+    }
+}
+
+impl<R: Read, W: Write> Read for Forwarder<R, W> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let count = self.reader.read(buf)?;
+        if count == 0 {
+            self.writer.flush()?;
+        }
+        self.writer.write_all(&buf[..count])?;
+        Ok(count)
+    }
+}
@@ -0,0 +1,64 @@
+//! Counting the records in a stream, such as the lines of a log or NDJSON
+//! stream, for when records rather than bytes are the interesting unit.
+
+/// Parses a delimiter given as a single character, an escape such as `\n`,
+/// `\t` or `\0`, or a byte value such as `10` or `0x0a`.
+pub fn parse_delimiter(value: &str) -> Option<u8> {
+    match value {
+        "\\n" => return Some(b'\n'),
+        "\\r" => return Some(b'\r'),
+        "\\t" => return Some(b'\t'),
+        "\\0" => return Some(0),
+        _ => {},
+    }
+
+    if value.len() == 1 {
+        return Some(value.as_bytes()[0]);
+    }
+    match value.strip_prefix("0x").or_else(|| value.strip_prefix("0X")) {
+        Some(hex) => u8::from_str_radix(hex, 16).ok(),
+        None => value.parse().ok(),
+    }
+}
+
+/// Counts the delimiters in the data read, each one ending a record.
+#[derive(Clone)]
+pub struct RecordCounter {
+    pub delimiter: u8,
+
+    /// Every record counted.
+    pub total: u64,
+
+    /// Records counted since the last measure.
+    pub last: u64,
+
+    /// The records per second during the last measure.
+    pub last_rate: f64,
+
+    /// The highest records per second of any measure.
+    pub peak_rate: f64,
+}
+
+impl RecordCounter {
+    pub fn new(delimiter: u8) -> RecordCounter {
+        RecordCounter { delimiter, total: 0, last: 0, last_rate: 0.0, peak_rate: 0.0 }
+    }
+
+    pub fn count(&mut self, data: &[u8]) {
+        let records = data.iter().filter(|&&byte| byte == self.delimiter).count() as u64;
+        self.total += records;
+        self.last += records;
+    }
+
+    /// Takes a measure of the records counted over the last `secs` seconds.
+    pub fn measure(&mut self, secs: f64) {
+        self.last_rate = if secs > 0.0 { self.last as f64 / secs } else { 0.0 };
+        self.peak_rate = self.peak_rate.max(self.last_rate);
+        self.last = 0;
+    }
+
+    /// The records per second over the whole transfer.
+    pub fn average_rate(&self, elapsed_secs: f64) -> f64 {
+        if elapsed_secs > 0.0 { self.total as f64 / elapsed_secs } else { 0.0 }
+    }
+}
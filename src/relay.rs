@@ -0,0 +1,128 @@
+//! Forwarding a connection on to another host and its replies back, so that
+//! throughput can sit between a client and a server as a proxy. Each
+//! direction is counted on its own, which shows which side is sending most of
+//! the data and how long each side spends waiting on the other.
+
+use std::io::{self, Read, Write};
+use std::net::{Shutdown, TcpStream};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+/// The shortest wait for data that is counted as the direction being idle,
+/// so that the gaps between the reads of a busy stream are not.
+const IDLE_THRESHOLD: Duration = Duration::from_millis(100);
+
+/// How much has gone one way through the relay.
+#[derive(Clone)]
+pub struct DirectionStats {
+    pub name: &'static str,
+    pub bytes: u64,
+
+    /// Bytes sent since the last measure.
+    pub last: u64,
+
+    /// The bytes per second during the last measure, and how many bytes it
+    /// was over.
+    pub last_rate: f64,
+    pub last_bytes: u64,
+
+    /// The time spent waiting for data, not counting any wait still going on.
+    idle: Duration,
+
+    /// When the read waiting for data started, while there is one.
+    waiting_since: Option<Instant>,
+}
+
+impl DirectionStats {
+    fn new(name: &'static str) -> DirectionStats {
+        DirectionStats { name, bytes: 0, last: 0, last_rate: 0.0, last_bytes: 0, idle: Duration::default(), waiting_since: None }
+    }
+
+    /// The time spent waiting for data, including the current wait if it has
+    /// gone on long enough to count.
+    pub fn idle(&self) -> Duration {
+        match self.waiting_since.map(|since| since.elapsed()) {
+            Some(waiting) if waiting >= IDLE_THRESHOLD => self.idle + waiting,
+            _ => self.idle,
+        }
+    }
+}
+
+/// The stats of both directions, client to server first.
+pub type Directions = Arc<Mutex<Vec<DirectionStats>>>;
+
+/// Reads one direction of the relay and writes what it reads on to the other
+/// side, so measuring the reader forwards the data.
+pub struct RelayReader {
+    from: TcpStream,
+    to: TcpStream,
+    index: usize,
+    directions: Directions,
+}
+
+/// Relays between the client and the server, returning a reader for each
+/// direction along with their stats.
+pub fn relay(client: TcpStream, server: TcpStream) -> io::Result<(Vec<RelayReader>, Directions)> {
+    let directions = Arc::new(Mutex::new(vec![
+        DirectionStats::new("client to server"),
+        DirectionStats::new("server to client"),
+    ]));
+    let readers = vec![
+        RelayReader { from: client.try_clone()?, to: server.try_clone()?, index: 0, directions: directions.clone() },
+        RelayReader { from: server, to: client, index: 1, directions: directions.clone() },
+    ];
+    Ok((readers, directions))
+}
+
+impl Read for RelayReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let started = Instant::now();
+        self.directions.lock().expect("direction stats lock was poisoned")[self.index].waiting_since = Some(started);
+        let result = self.from.read(buf);
+        let waited = started.elapsed();
+        {
+            let mut directions = self.directions.lock().expect("direction stats lock was poisoned");
+            let direction = &mut directions[self.index];
+            direction.waiting_since = None;
+            if waited >= IDLE_THRESHOLD {
+                direction.idle += waited;
+            }
+        }
+
+        let bytes_read = result?;
+        if bytes_read == 0 {
+            // The other side sees the end too, while the opposite direction
+            // carries on until it also ends.
+            let _ = self.to.shutdown(Shutdown::Write);
+            return Ok(0);
+        }
+        self.to.write_all(&buf[..bytes_read])?;
+
+        let mut directions = self.directions.lock().expect("direction stats lock was poisoned");
+        let direction = &mut directions[self.index];
+        direction.bytes += bytes_read as u64;
+        direction.last += bytes_read as u64;
+        Ok(bytes_read)
+    }
+}
+
+/// Takes a measure of each direction over the last `secs` seconds.
+pub fn measure(directions: &Directions, secs: f64) {
+    let mut directions = directions.lock().expect("direction stats lock was poisoned");
+    for direction in directions.iter_mut() {
+        direction.last_rate = if secs > 0.0 { direction.last as f64 / secs } else { 0.0 };
+        direction.last_bytes = direction.last;
+        direction.last = 0;
+    }
+}
+
+/// The direction that carried most of the bytes counted by `bytes`, with its
+/// share of them, or None if nothing was sent either way.
+pub fn dominant<F: Fn(&DirectionStats) -> u64>(directions: &[DirectionStats], bytes: F) -> Option<(&DirectionStats, f64)> {
+    let total: u64 = directions.iter().map(&bytes).sum();
+    if total == 0 {
+        return None;
+    }
+    directions.iter().max_by_key(|direction| bytes(direction))
+        .map(|direction| (direction, bytes(direction) as f64 / total as f64))
+}
@@ -0,0 +1,99 @@
+//! A Unix socket that other processes control the measurement through, for
+//! orchestration scripts that need to pause, reset or stop it without
+//! signals. Each line sent is a command, answered with a line of `ok` or of
+//! what was wrong with it:
+//!
+//! ```text
+//! $ echo pause | nc -U /run/throughput.sock
+//! ok
+//! ```
+
+use std::ffi::CString;
+use std::fs;
+use std::io::{self, BufRead, BufReader, Write};
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::fs::FileTypeExt;
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::path::Path;
+use std::sync::OnceLock;
+use std::thread;
+
+use controls::{Command, SharedControls};
+
+/// The commands as they are written on the socket.
+const COMMANDS: &[(&str, Command)] = &[
+    ("pause", Command::Pause),
+    ("resume", Command::Resume),
+    ("snapshot", Command::Snapshot),
+    ("reset", Command::Reset),
+    ("stop", Command::Stop),
+];
+
+static SOCKET_PATH: OnceLock<CString> = OnceLock::new();
+
+/// Listens for commands on a socket at the path, which is removed when the
+/// process exits. A socket left behind by a process that no longer runs is
+/// replaced.
+pub fn listen(path: &str, controls: SharedControls) -> io::Result<()> {
+    let listener = match UnixListener::bind(path) {
+        Err(ref err) if err.kind() == io::ErrorKind::AddrInUse && is_stale(path) => {
+            fs::remove_file(path)?;
+            UnixListener::bind(path)?
+        },
+        bound => bound?,
+    };
+    remove_at_exit(path);
+
+    thread::spawn(move || {
+        for stream in listener.incoming().flatten() {
+            let controls = controls.clone();
+            thread::spawn(move || {
+                // A client going away is not a problem of the measurement.
+                let _ = serve(stream, &controls);
+            });
+        }
+    });
+    Ok(())
+}
+
+/// Carries out the commands sent by one client until it disconnects.
+fn serve(stream: UnixStream, controls: &SharedControls) -> io::Result<()> {
+    let mut writer = stream.try_clone()?;
+    for line in BufReader::new(stream).lines() {
+        let line = line?;
+        let name = line.trim();
+        if name.is_empty() {
+            continue;
+        }
+        match COMMANDS.iter().find(|&&(command, _)| command.eq_ignore_ascii_case(name)) {
+            // Answered first, as stopping may end the process.
+            Some(&(_, command)) => {
+                writeln!(writer, "ok")?;
+                controls.send(command);
+            },
+            None => writeln!(writer, "error: unknown command {:?}, expected pause, resume, snapshot, reset or stop", name)?,
+        }
+    }
+    Ok(())
+}
+
+/// Whether the path is a socket that nothing is listening on any more.
+fn is_stale(path: &str) -> bool {
+    let is_socket = fs::symlink_metadata(path).is_ok_and(|metadata| metadata.file_type().is_socket());
+    is_socket && UnixStream::connect(path).is_err()
+}
+
+/// Removes the socket when the process exits, whether it returns from main
+/// or calls `exit`.
+fn remove_at_exit(path: &str) {
+    extern "C" fn remove_socket() {
+        if let Some(path) = SOCKET_PATH.get() {
+            unsafe { libc::unlink(path.as_ptr()) };
+        }
+    }
+
+    let path = CString::new(Path::new(path).as_os_str().as_bytes()).expect("paths from arguments have no NUL");
+    if SOCKET_PATH.set(path).is_ok() {
+        unsafe { libc::atexit(remove_socket) };
+    }
+}
@@ -0,0 +1,455 @@
+use std::fs::File;
+use std::io::{self, Write};
+use std::time::{Duration, UNIX_EPOCH};
+
+use serde_json::Value;
+
+use histogram::Histogram;
+use relay;
+use serial;
+use TransferInfo;
+
+/// Where the final machine readable report should be written.
+pub enum ReportTarget {
+    /// A file that will be created or truncated.
+    Path(String),
+
+    /// Stdout, given as `-`.
+    Stdout,
+
+    /// A file descriptor that was opened by the parent process.
+    #[cfg(unix)]
+    Fd(i32),
+}
+
+impl ReportTarget {
+    /// Parses `--report`. `-` is stdout, and a plain number is taken to be
+    /// a file descriptor on Unix-like platforms, which must already be open;
+    /// use `./3` to write to a file named `3`.
+    pub fn parse(value: &str) -> Result<ReportTarget, String> {
+        if value == "-" {
+            return Ok(ReportTarget::Stdout);
+        }
+        #[cfg(unix)]
+        {
+            if let Ok(fd) = value.parse::<i64>() {
+                if fd < 0 || fd > i64::from(i32::MAX) {
+                    return Err(format!("Report file descriptor {} is not valid. Use ./{} to write to a file with that name.", fd, fd));
+                }
+                let fd = fd as i32;
+                if unsafe { libc::fcntl(fd, libc::F_GETFD) } == -1 {
+                    return Err(format!("Report file descriptor {} is not open: {}", fd, io::Error::last_os_error()));
+                }
+                return Ok(ReportTarget::Fd(fd));
+            }
+        }
+        Ok(ReportTarget::Path(value.to_string()))
+    }
+}
+
+/// Builds the JSON summary of a finished transfer, including whether it met
+/// the configured thresholds.
+pub fn summary(transfer_info: &TransferInfo, thresholds_met: bool) -> Value {
+    let mut summary = transfer_summary(transfer_info);
+    summary["thresholds_met"] = json!(thresholds_met);
+    summary
+}
+
+/// Builds the JSON summary of the measurements of a finished transfer.
+pub fn transfer_summary(transfer_info: &TransferInfo) -> Value {
+    let mut summary = json!({
+        "total_bytes": transfer_info.total_bytes_transferred,
+        "elapsed_secs": transfer_info.elapsed.as_secs_f64(),
+        "measures": transfer_info.total_measures,
+        "average_bps": transfer_info.average_bps(),
+        "peak_bps": transfer_info.peak_bps,
+    });
+
+    let rates = &transfer_info.interval_rates;
+    if rates.count() > 0 {
+        summary["interval_rates"] = json!({
+            "measures": rates.count(),
+            "mean_bps": rates.mean(),
+            "stddev_bps": rates.stddev(),
+            "p50_bps": rates.percentile(50.0),
+            "p90_bps": rates.percentile(90.0),
+            "p99_bps": rates.percentile(99.0),
+        });
+    }
+
+    if let Some(ref records) = transfer_info.records {
+        summary["records"] = json!({
+            "delimiter": records.delimiter,
+            "total": records.total,
+            "average_per_sec": records.average_rate(transfer_info.elapsed.as_secs_f64()),
+            "peak_per_sec": records.peak_rate,
+        });
+    }
+
+    if let Some(ref frames) = transfer_info.frames {
+        summary["frames"] = json!({
+            "framing": frames.framing.name(),
+            "total": frames.total,
+            "average_per_sec": frames.average_rate(transfer_info.elapsed.as_secs_f64()),
+            "peak_per_sec": frames.peak_rate,
+            "p50_bytes": frames.sizes.percentile(50.0),
+            "p90_bytes": frames.sizes.percentile(90.0),
+            "p99_bytes": frames.sizes.percentile(99.0),
+            "max_bytes": frames.sizes.max(),
+        });
+    }
+
+    if let Some(ref wire) = transfer_info.wire {
+        let secs = transfer_info.elapsed.as_secs_f64();
+        summary["compression"] = json!({
+            "codec": wire.codec.name(),
+            "wire_bytes": wire.bytes,
+            "wire_average_bps": if secs > 0.0 { json!(wire.bytes as f64 / secs) } else { Value::Null },
+            "ratio": wire.ratio(transfer_info.total_bytes_transferred as u64),
+        });
+    }
+
+    if let Some(ref generation) = transfer_info.generation {
+        summary["payload_generation"] = json!({
+            "secs": generation.time.as_secs_f64(),
+            "bytes": generation.bytes,
+            "bps": generation.bytes_per_second(),
+            "prefilled": generation.prefilled,
+        });
+    }
+
+    if let Some(ref totals) = transfer_info.totals {
+        let since = totals.since.duration_since(UNIX_EPOCH).unwrap_or_default();
+        summary["totals"] = json!({
+            "session": {
+                "bytes": totals.session.bytes,
+                "elapsed_secs": totals.session.elapsed.as_secs_f64(),
+                "runs": totals.session.runs,
+            },
+            "lifetime": {
+                "bytes": totals.lifetime.bytes,
+                "elapsed_secs": totals.lifetime.elapsed.as_secs_f64(),
+                "runs": totals.lifetime.runs,
+                "since_secs": since.as_secs_f64(),
+            },
+        });
+    }
+
+    if let Some(ref close) = transfer_info.close {
+        summary["shutdown"] = json!({
+            "in_flight_bytes": close.in_flight,
+            "drained_bytes": close.drained,
+            "wait_secs": close.wait.as_secs_f64(),
+            "closed_by_peer": close.closed_by_peer,
+        });
+    }
+
+    if let Some(ref delay) = transfer_info.one_way_delay {
+        summary["one_way_delay"] = json!({
+            "average_secs": delay.average,
+            "min_secs": delay.min,
+            "max_secs": delay.max,
+            "samples": delay.samples,
+            "clock_offset_secs": delay.clock.offset,
+            "clock_error_secs": delay.clock.error(),
+        });
+    }
+
+    if let Some(ref stalls) = transfer_info.stalls {
+        summary["stalls"] = json!({
+            "threshold_secs": stalls.threshold.as_secs_f64(),
+            "total": stalls.total,
+            "peak_per_measure": stalls.peak,
+            "longest_wait_secs": stalls.longest.as_secs_f64(),
+        });
+    }
+
+    if let Some(baud) = transfer_info.baud {
+        let line_rate = serial::line_rate(baud);
+        summary["serial"] = json!({
+            "baud": baud,
+            "line_bps": line_rate,
+            "utilization": transfer_info.average_bps() / line_rate,
+        });
+    }
+
+    if transfer_info.timed_out {
+        summary["timed_out"] = json!(true);
+    }
+
+    let errors = transfer_info.read_errors;
+    if errors.fatal > 0 || errors.transient > 0 {
+        summary["read_errors"] = json!({
+            "failed": errors.fatal,
+            "retried": errors.transient,
+        });
+    }
+
+    if let Some(ref timing) = transfer_info.passthrough_timing {
+        summary["read_blocked_secs"] = json!(timing.read_time.as_secs_f64());
+        summary["write_blocked_secs"] = json!(timing.write_time.as_secs_f64());
+    }
+
+    if let Some(ref occupancy) = transfer_info.queue_occupancy {
+        summary["write_queue"] = json!({
+            "depth": occupancy.depth,
+            "average": occupancy.average(),
+            "peak": occupancy.peak,
+        });
+    }
+
+    if let Some(size) = transfer_info.tuned_buffer_size {
+        summary["buffer_size"] = json!(size);
+    }
+
+    if let Some(ref usage) = transfer_info.usage {
+        let cpu_secs = usage.cpu_time().as_secs_f64();
+        summary["cpu"] = json!({
+            "user_secs": usage.user.as_secs_f64(),
+            "system_secs": usage.system.as_secs_f64(),
+            "bytes_per_cpu_sec": if cpu_secs > 0.0 { json!(transfer_info.total_bytes_transferred as f64 / cpu_secs) } else { Value::Null },
+        });
+        if let Some(syscalls) = usage.syscalls {
+            let elapsed = transfer_info.elapsed.as_secs_f64();
+            summary["syscalls"] = json!({
+                "reads": syscalls.reads,
+                "writes": syscalls.writes,
+                "per_sec": if elapsed > 0.0 { json!((syscalls.reads + syscalls.writes) as f64 / elapsed) } else { Value::Null },
+            });
+        }
+    }
+
+    if let Some(ref stats) = transfer_info.sctp {
+        let elapsed = transfer_info.elapsed.as_secs_f64();
+        let streams: Vec<Value> = stats.streams.iter().map(|(stream, stream_stats)| json!({
+            "stream": stream,
+            "bytes": stream_stats.bytes,
+            "messages": stream_stats.messages,
+            "average_bps": if elapsed > 0.0 { json!(stream_stats.bytes as f64 / elapsed) } else { Value::Null },
+        })).collect();
+        summary["sctp"] = json!({
+            "messages": stats.messages(),
+            "average_message_size": stats.average_message_size(),
+            "streams": streams,
+        });
+    }
+
+    if let Some(ref stats) = transfer_info.tcp {
+        summary["tcp"] = json!({
+            "retransmits": stats.retransmits,
+            "rtt_secs": {
+                "average": stats.average_rtt().as_secs_f64(),
+                "min": stats.min_rtt.as_secs_f64(),
+                "max": stats.max_rtt.as_secs_f64(),
+            },
+            "cwnd_segments": stats.cwnd,
+            "max_cwnd_segments": stats.max_cwnd,
+            "mss": stats.mss,
+            "pacing_bps": stats.pacing_rate,
+            "max_pacing_bps": stats.max_pacing_rate,
+            "samples": stats.samples,
+        });
+    }
+
+    if let Some(ref stats) = transfer_info.udp {
+        summary["udp"] = json!({
+            "datagrams": stats.total_datagrams(),
+            "average_datagram_size": stats.average_datagram_size(),
+            "datagrams_per_sec": stats.datagrams_per_second(),
+            "active_secs": stats.active.as_secs_f64(),
+            "datagrams_per_thread": stats.datagrams,
+        });
+    }
+
+    if let Some(ref sources) = transfer_info.sources {
+        summary["sources"] = json!(sources.iter().map(|source| {
+            let secs = source.elapsed.as_secs_f64();
+            json!({
+                "path": source.path,
+                "bytes": source.bytes,
+                "elapsed_secs": secs,
+                "average_bps": if secs > 0.0 { json!(source.bytes as f64 / secs) } else { Value::Null },
+            })
+        }).collect::<Vec<Value>>());
+    }
+
+    if let Some(ref directions) = transfer_info.directions {
+        let secs = transfer_info.elapsed.as_secs_f64();
+        summary["directions"] = json!(directions.iter().map(|direction| json!({
+            "direction": direction.name,
+            "bytes": direction.bytes,
+            "average_bps": if secs > 0.0 { json!(direction.bytes as f64 / secs) } else { Value::Null },
+            "idle_secs": direction.idle().as_secs_f64(),
+        })).collect::<Vec<Value>>());
+        summary["dominant_direction"] = json!(relay::dominant(directions, |direction| direction.bytes)
+            .map(|(direction, _)| direction.name));
+    }
+
+    if let Some(ref command) = transfer_info.command {
+        summary["command"] = json!({
+            "command": command.command,
+            "exit_code": command.status.and_then(|status| status.code()),
+            "success": command.succeeded(),
+            "fed_bytes": command.fed,
+        });
+    }
+
+    if let Some(ref connections) = transfer_info.connections {
+        summary["connections"] = json!(connections.iter().map(|connection| {
+            let secs = connection.elapsed.as_secs_f64();
+            let opened = connection.opened.duration_since(UNIX_EPOCH).unwrap_or_default();
+            json!({
+                "label": connection.label,
+                "peer": connection.peer.to_string(),
+                "opened_secs": opened.as_secs_f64(),
+                "bytes": connection.bytes,
+                "elapsed_secs": secs,
+                "average_bps": if secs > 0.0 { json!(connection.bytes as f64 / secs) } else { Value::Null },
+            })
+        }).collect::<Vec<Value>>());
+        summary["connections_omitted"] = json!(transfer_info.connections_omitted);
+    }
+
+    if let Some(ref chaos) = transfer_info.chaos {
+        summary["chaos"] = json!({
+            "seed": chaos.spec.seed,
+            "dropped": chaos.dropped,
+            "duplicated": chaos.duplicated,
+            "delayed": chaos.delayed,
+            "bit_flipped": chaos.flipped,
+        });
+    }
+
+    if let Some(ref agents) = transfer_info.agents {
+        summary["agents"] = json!(agents.iter().map(|agent| json!({
+            "name": agent.name,
+            "bytes": agent.total_bytes,
+            "current_bps": agent.current_bps,
+            "average_bps": agent.average_bps,
+            "elapsed_secs": agent.elapsed.as_secs_f64(),
+            "state": agent.state.name(),
+        })).collect::<Vec<Value>>());
+    }
+
+    if let Some(reconnects) = transfer_info.reconnects {
+        summary["reconnects"] = json!(reconnects);
+    }
+
+    if let Some((algorithm, ref digest)) = transfer_info.digest {
+        summary["hash"] = json!({ "algorithm": algorithm.name(), "digest": digest });
+    }
+
+    if let Some(ref verifier) = transfer_info.verifier {
+        summary["verify"] = json!({
+            "ok": verifier.is_ok(),
+            "bytes_checked": verifier.bytes_checked(),
+            "corrupt_bytes": verifier.corrupt_bytes,
+            "corrupt_regions": verifier.corrupt_regions,
+            "first_corrupt_offsets": verifier.first_corrupt_offsets,
+        });
+    }
+
+    if let Some(ref stats) = transfer_info.read_stats {
+        summary["read_sizes"] = distribution(&stats.sizes);
+        summary["read_latency_ns"] = distribution(&stats.latencies);
+    }
+
+    summary
+}
+
+/// Rebuilds the totals of a transfer from its JSON summary, such as one sent
+/// by the peer of a coordinated test.
+pub fn transfer_info_from_summary(summary: &Value) -> Option<TransferInfo> {
+    let total_measures = summary["measures"].as_u64()? as usize;
+    let average_bps = summary["average_bps"].as_f64()?;
+
+    Some(TransferInfo {
+        total_bytes_transferred: summary["total_bytes"].as_u64()? as usize,
+        total_measures,
+        total_bps: average_bps * total_measures as f64,
+        peak_bps: summary["peak_bps"].as_f64().unwrap_or(0.0),
+        current_bps: average_bps,
+        elapsed: Duration::from_secs_f64(summary["elapsed_secs"].as_f64()?),
+        ..TransferInfo::default()
+    })
+}
+
+fn distribution(histogram: &Histogram) -> Value {
+    json!({
+        "count": histogram.count(),
+        "p50": histogram.percentile(50.0),
+        "p90": histogram.percentile(90.0),
+        "p99": histogram.percentile(99.0),
+        "max": histogram.max(),
+    })
+}
+
+/// Writes the summary as a single line of JSON to the target.
+pub fn write_report(target: &ReportTarget, summary: &Value) -> io::Result<()> {
+    match *target {
+        ReportTarget::Path(ref path) => write_line(File::create(path)?, summary),
+        ReportTarget::Stdout => write_line(io::stdout().lock(), summary),
+
+        #[cfg(unix)]
+        ReportTarget::Fd(fd) => {
+            use std::mem::ManuallyDrop;
+            use std::os::unix::io::FromRawFd;
+
+            let file = unsafe { File::from_raw_fd(fd) };
+            if fd <= 2 {
+                // Stdio is left open for whatever else the process prints.
+                write_line(&mut *ManuallyDrop::new(file), summary)
+            } else {
+                // The descriptor is owned by us from here on and is closed
+                // once the report has been written.
+                write_line(file, summary)
+            }
+        },
+    }
+}
+
+fn write_line<W: Write>(mut output: W, summary: &Value) -> io::Result<()> {
+    writeln!(output, "{}", summary)?;
+    output.flush()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn dash_is_stdout() {
+        assert!(matches!(ReportTarget::parse("-"), Ok(ReportTarget::Stdout)));
+    }
+
+    #[test]
+    fn plain_path_is_a_file() {
+        assert!(matches!(ReportTarget::parse("report.json"), Ok(ReportTarget::Path(ref path)) if path == "report.json"));
+        assert!(matches!(ReportTarget::parse("./3"), Ok(ReportTarget::Path(ref path)) if path == "./3"));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn open_fd_is_taken() {
+        use std::os::unix::io::AsRawFd;
+
+        let file = File::open("/dev/null").expect("/dev/null should open");
+        let fd = file.as_raw_fd();
+        assert!(matches!(ReportTarget::parse(&fd.to_string()), Ok(ReportTarget::Fd(parsed)) if parsed == fd));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn closed_fd_is_rejected() {
+        // Far above any descriptor the tests open.
+        let fd = i32::MAX;
+        assert!(ReportTarget::parse(&fd.to_string()).is_err());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn negative_or_huge_fd_is_rejected() {
+        assert!(ReportTarget::parse("-3").is_err());
+        assert!(ReportTarget::parse("4294967296").is_err());
+    }
+}
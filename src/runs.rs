@@ -0,0 +1,62 @@
+use serde_json::Value;
+
+use display::format_rate;
+use report;
+use TransferInfo;
+
+/// Statistics of the average transfer speed across repeated runs.
+pub struct RunStats {
+    pub count: usize,
+    pub mean_bps: f64,
+
+    /// The sample standard deviation, which is zero for a single run.
+    pub stddev_bps: f64,
+
+    pub min_bps: f64,
+    pub max_bps: f64,
+}
+
+impl RunStats {
+    pub fn new(runs: &[TransferInfo]) -> RunStats {
+        let rates: Vec<f64> = runs.iter().map(TransferInfo::average_bps).collect();
+        let count = rates.len();
+        let mean_bps = if count == 0 { 0.0 } else { rates.iter().sum::<f64>() / count as f64 };
+        let variance = if count < 2 {
+            0.0
+        } else {
+            rates.iter().map(|rate| (rate - mean_bps).powi(2)).sum::<f64>() / (count - 1) as f64
+        };
+
+        RunStats {
+            count,
+            mean_bps,
+            stddev_bps: variance.sqrt(),
+            min_bps: if count == 0 { 0.0 } else { rates.iter().cloned().fold(f64::INFINITY, f64::min) },
+            max_bps: rates.iter().cloned().fold(0.0, f64::max),
+        }
+    }
+
+    /// Returns the statistics and the summary of every run as JSON.
+    pub fn to_json(&self, runs: &[TransferInfo]) -> Value {
+        json!({
+            "count": self.count,
+            "mean_bps": self.mean_bps,
+            "stddev_bps": self.stddev_bps,
+            "min_bps": self.min_bps,
+            "max_bps": self.max_bps,
+            "results": runs.iter().map(report::transfer_summary).collect::<Vec<_>>(),
+        })
+    }
+
+    /// Prints the average speed of every run followed by the statistics
+    /// across all of them.
+    pub fn print(&self, runs: &[TransferInfo]) {
+        println!();
+        for (index, run) in runs.iter().enumerate() {
+            println!("Run {}: {}", index + 1, format_rate(run.average_bps()));
+        }
+        println!("Across {} runs: mean {}, stddev {}, min {}, max {}",
+            self.count, format_rate(self.mean_bps), format_rate(self.stddev_bps),
+            format_rate(self.min_bps), format_rate(self.max_bps));
+    }
+}
@@ -0,0 +1,122 @@
+//! Statistics that are kept in fixed memory however long a transfer runs, so
+//! that a measurement left running for a week does not grow without bound.
+
+use histogram::Histogram;
+
+/// The most samples kept of anything recorded over a transfer unless
+/// `--max-samples` says otherwise.
+pub const DEFAULT_MAX_SAMPLES: usize = 1000;
+
+/// The mean and variance of a series of values, updated as each one arrives
+/// without keeping them (Welford's algorithm).
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Welford {
+    count: u64,
+    mean: f64,
+
+    /// The sum of the squared differences from the mean.
+    m2: f64,
+}
+
+impl Welford {
+    pub fn add(&mut self, value: f64) {
+        self.count += 1;
+        let delta = value - self.mean;
+        self.mean += delta / self.count as f64;
+        self.m2 += delta * (value - self.mean);
+    }
+
+    pub fn count(&self) -> u64 {
+        self.count
+    }
+
+    pub fn mean(&self) -> f64 {
+        self.mean
+    }
+
+    /// The sample standard deviation, which is zero until there are two
+    /// values.
+    pub fn stddev(&self) -> f64 {
+        if self.count < 2 { return 0.0; }
+        (self.m2 / (self.count - 1) as f64).sqrt()
+    }
+}
+
+/// The distribution of the transfer speeds of every measure.
+#[derive(Clone, Default)]
+pub struct IntervalRates {
+    variance: Welford,
+    distribution: Histogram,
+}
+
+impl IntervalRates {
+    pub fn record(&mut self, bps: f64) {
+        self.variance.add(bps);
+        self.distribution.record(bps.max(0.0).round() as u64);
+    }
+
+    pub fn count(&self) -> u64 {
+        self.variance.count()
+    }
+
+    pub fn mean(&self) -> f64 {
+        self.variance.mean()
+    }
+
+    pub fn stddev(&self) -> f64 {
+        self.variance.stddev()
+    }
+
+    /// An upper bound for the speed at the given percentile (0-100), within
+    /// the precision of the histogram.
+    pub fn percentile(&self, percentile: f64) -> f64 {
+        self.distribution.percentile(percentile) as f64
+    }
+}
+
+/// A series of values, oldest first, that is never longer than its limit.
+/// Once full every pair of neighbouring samples is averaged into one, so that
+/// each sample covers twice as many values as before and the whole series
+/// is still covered evenly.
+#[derive(Clone, Debug)]
+pub struct History {
+    samples: Vec<f64>,
+    limit: usize,
+
+    /// How many values each sample is the average of.
+    span: usize,
+
+    /// The sum and count of the values that are not yet a whole sample.
+    pending: (f64, usize),
+}
+
+impl History {
+    /// Keeps at most `limit` samples, rounded up to an even number so that
+    /// every sample has a neighbour to be averaged with.
+    pub fn new(limit: usize) -> History {
+        History { samples: Vec::new(), limit: (limit.max(2) + 1) & !1, span: 1, pending: (0.0, 0) }
+    }
+
+    pub fn push(&mut self, value: f64) {
+        self.pending.0 += value;
+        self.pending.1 += 1;
+        if self.pending.1 < self.span { return; }
+
+        self.samples.push(self.pending.0 / self.span as f64);
+        self.pending = (0.0, 0);
+        if self.samples.len() == self.limit {
+            self.samples = self.samples.chunks(2).map(|pair| pair.iter().sum::<f64>() / pair.len() as f64).collect();
+            self.span *= 2;
+        }
+    }
+
+    /// The samples, ending with the average of any values that have not yet
+    /// made a whole sample.
+    pub fn samples(&self) -> Vec<f64> {
+        let mut samples = self.samples.clone();
+        if self.pending.1 > 0 {
+            samples.push(self.pending.0 / self.pending.1 as f64);
+        }
+        samples
+    }
+}
@@ -0,0 +1,117 @@
+//! Reading an SCTP association, keeping track of how the data was split
+//! over streams and into messages as well as how much arrived. SCTP can only
+//! be used on Linux.
+
+use std::collections::BTreeMap;
+#[cfg(target_os = "linux")]
+use std::io::{self, Read};
+#[cfg(target_os = "linux")]
+use std::mem;
+#[cfg(target_os = "linux")]
+use std::os::unix::io::AsRawFd;
+#[cfg(target_os = "linux")]
+use std::ptr;
+#[cfg(target_os = "linux")]
+use std::sync::{Arc, Mutex};
+
+#[cfg(target_os = "linux")]
+use socket2::Socket;
+
+/// The data received on one SCTP stream.
+#[derive(Clone, Copy, Default)]
+pub struct StreamStats {
+    pub bytes: u64,
+
+    /// Messages that were received completely.
+    pub messages: u64,
+}
+
+/// The data received on every stream of an association.
+#[derive(Clone, Default)]
+pub struct SctpStats {
+    /// Stats for each stream, by stream number.
+    pub streams: BTreeMap<u16, StreamStats>,
+}
+
+impl SctpStats {
+    /// The number of messages received completely on every stream.
+    pub fn messages(&self) -> u64 {
+        self.streams.values().map(|stream| stream.messages).sum()
+    }
+
+    /// The average size of a message in bytes.
+    pub fn average_message_size(&self) -> f64 {
+        let messages = self.messages();
+        if messages == 0 { return 0.0; }
+        self.streams.values().map(|stream| stream.bytes).sum::<u64>() as f64 / messages as f64
+    }
+}
+
+/// Reads an association with `recvmsg(2)`, recording the stream of every
+/// read and the end of every message into stats that are shared with
+/// whoever created the reader.
+#[cfg(target_os = "linux")]
+pub struct SctpReader {
+    socket: Socket,
+    control: Vec<u8>,
+    stats: Arc<Mutex<SctpStats>>,
+}
+
+#[cfg(target_os = "linux")]
+impl SctpReader {
+    /// Asks the kernel to say which stream every read came from.
+    pub fn new(socket: Socket) -> io::Result<(SctpReader, Arc<Mutex<SctpStats>>)> {
+        let enable: libc::c_int = 1;
+        let result = unsafe {
+            libc::setsockopt(socket.as_raw_fd(), libc::IPPROTO_SCTP, libc::SCTP_RECVRCVINFO,
+                &enable as *const libc::c_int as *const libc::c_void, mem::size_of::<libc::c_int>() as libc::socklen_t)
+        };
+        if result != 0 {
+            return Err(io::Error::last_os_error());
+        }
+
+        let control_len = unsafe { libc::CMSG_SPACE(mem::size_of::<libc::sctp_rcvinfo>() as u32) } as usize;
+        let stats = Arc::new(Mutex::new(SctpStats::default()));
+        Ok((SctpReader { socket, control: vec![0; control_len], stats: stats.clone() }, stats))
+    }
+}
+
+#[cfg(target_os = "linux")]
+impl Read for SctpReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let mut iov = libc::iovec { iov_base: buf.as_mut_ptr() as *mut libc::c_void, iov_len: buf.len() };
+        let mut msg: libc::msghdr = unsafe { mem::zeroed() };
+        msg.msg_iov = &mut iov;
+        msg.msg_iovlen = 1;
+        msg.msg_control = self.control.as_mut_ptr() as *mut libc::c_void;
+        msg.msg_controllen = self.control.len() as _;
+
+        let received = unsafe { libc::recvmsg(self.socket.as_raw_fd(), &mut msg, 0) };
+        if received < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        if received == 0 {
+            return Ok(0);
+        }
+
+        let mut stream = 0;
+        unsafe {
+            let mut cmsg = libc::CMSG_FIRSTHDR(&msg);
+            while !cmsg.is_null() {
+                if (*cmsg).cmsg_level == libc::IPPROTO_SCTP && (*cmsg).cmsg_type == libc::SCTP_RCVINFO {
+                    let info: libc::sctp_rcvinfo = ptr::read_unaligned(libc::CMSG_DATA(cmsg) as *const libc::sctp_rcvinfo);
+                    stream = info.rcv_sid;
+                }
+                cmsg = libc::CMSG_NXTHDR(&msg, cmsg);
+            }
+        }
+
+        let mut stats = self.stats.lock().expect("SCTP stats lock was poisoned");
+        let stream_stats = stats.streams.entry(stream).or_default();
+        stream_stats.bytes += received as u64;
+        if msg.msg_flags & libc::MSG_EOR != 0 {
+            stream_stats.messages += 1;
+        }
+        Ok(received as usize)
+    }
+}
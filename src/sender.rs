@@ -0,0 +1,140 @@
+use std::io::{self, Write};
+use std::net::UdpSocket;
+use std::sync::Mutex;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use pattern::Pattern;
+use shape::Shaper;
+use vectored;
+
+/// The data a sender writes.
+#[derive(Clone)]
+pub struct Payload {
+    pub pattern: Pattern,
+
+    /// Whether the buffers are filled once up front and then sent over and
+    /// over, so that generating the pattern costs nothing while sending. The
+    /// stream then only follows the pattern for the first buffers.
+    pub prefill: bool,
+}
+
+impl Payload {
+    /// The pattern generated afresh for every write.
+    pub fn generated(pattern: Pattern) -> Payload {
+        Payload { pattern, prefill: false }
+    }
+}
+
+/// How long a sender spent generating the payload and how much it generated,
+/// which shows whether generating it could hold back the send.
+#[derive(Clone, Copy, Default, Debug)]
+pub struct GenerationCost {
+    pub time: Duration,
+    pub bytes: u64,
+    pub prefilled: bool,
+}
+
+impl GenerationCost {
+    /// How quickly the payload was generated in bytes per second.
+    pub fn bytes_per_second(&self) -> f64 {
+        let secs = self.time.as_secs_f64();
+        if secs > 0.0 { self.bytes as f64 / secs } else { 0.0 }
+    }
+
+    /// Adds the cost of another sender of the same payload.
+    pub fn add(&mut self, other: &GenerationCost) {
+        self.time += other.time;
+        self.bytes += other.bytes;
+        self.prefilled |= other.prefilled;
+    }
+
+    /// Fills the buffers with the pattern from the offset and counts the time
+    /// it took.
+    fn fill<B: AsMut<[u8]>>(&mut self, pattern: &Pattern, mut offset: u64, buffers: &mut [B]) {
+        let start = Instant::now();
+        for buffer in buffers {
+            let buffer = buffer.as_mut();
+            pattern.fill(offset, buffer);
+            offset += buffer.len() as u64;
+            self.bytes += buffer.len() as u64;
+        }
+        self.time += start.elapsed();
+    }
+}
+
+/// Writes the payload to the stream until the duration has passed and
+/// returns the number of bytes written and what generating them cost. Each
+/// write covers `vecs` buffers, and waits for the shaper first if there is
+/// one.
+pub fn send_for<W: Write>(writer: &mut W, buffer_size: usize, vecs: usize, payload: Payload, duration: Duration, shaper: Option<&Mutex<Shaper>>) -> io::Result<(u64, GenerationCost)> {
+    let deadline = Instant::now() + duration;
+    let mut buffers = vec![vec![0; buffer_size]; vecs];
+    let mut cost = GenerationCost { prefilled: payload.prefill, ..GenerationCost::default() };
+    let mut offset = 0u64;
+
+    if payload.prefill {
+        cost.fill(&payload.pattern, 0, &mut buffers);
+    }
+    while Instant::now() < deadline {
+        if !payload.prefill {
+            cost.fill(&payload.pattern, offset, &mut buffers);
+        }
+        if let Some(shaper) = shaper {
+            let wait = shaper.lock().expect("shaper lock was poisoned").reserve(buffer_size * vecs);
+            thread::sleep(wait);
+            // Waiting for the next burst may have run past the end.
+            if Instant::now() >= deadline {
+                break;
+            }
+        }
+        vectored::write_all(writer, &buffers)?;
+        offset += (buffer_size * vecs) as u64;
+    }
+
+    writer.flush()?;
+    Ok((offset, cost))
+}
+
+/// Sends the payload as datagrams of `buffer_size` bytes until the duration
+/// has passed and returns the number of bytes sent and what generating them
+/// cost, waiting for the shaper before each one if there is one. With GSO set up on the socket each send
+/// is split into several datagrams by the kernel or NIC.
+///
+/// Datagrams that are refused because nothing is listening yet, or dropped
+/// because the send queue is full, are not counted.
+pub fn send_datagrams_for(socket: &UdpSocket, buffer_size: usize, payload: Payload, duration: Duration, shaper: Option<&Mutex<Shaper>>) -> io::Result<(u64, GenerationCost)> {
+    let deadline = Instant::now() + duration;
+    let mut buffer = [vec![0; buffer_size]];
+    let mut cost = GenerationCost { prefilled: payload.prefill, ..GenerationCost::default() };
+    let mut offset = 0u64;
+    let mut sent = 0u64;
+
+    if payload.prefill {
+        cost.fill(&payload.pattern, 0, &mut buffer);
+    }
+    while Instant::now() < deadline {
+        if !payload.prefill {
+            cost.fill(&payload.pattern, offset, &mut buffer);
+        }
+        offset += buffer_size as u64;
+        if let Some(shaper) = shaper {
+            let wait = shaper.lock().expect("shaper lock was poisoned").reserve(buffer_size);
+            thread::sleep(wait);
+        }
+        match socket.send(&buffer[0]) {
+            Ok(len) => sent += len as u64,
+            Err(ref err) if is_dropped(err) => {},
+            Err(err) => return Err(err),
+        }
+    }
+    Ok((sent, cost))
+}
+
+fn is_dropped(err: &io::Error) -> bool {
+    #[cfg(unix)]
+    {
+        if err.raw_os_error() == Some(libc::ENOBUFS) { return true; }
+    }
+    matches!(err.kind(), io::ErrorKind::ConnectionRefused | io::ErrorKind::WouldBlock | io::ErrorKind::Interrupted)
+}
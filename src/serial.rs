@@ -0,0 +1,97 @@
+//! Reading from a serial port, configured for raw 8N1 input at a given baud
+//! rate so that the link's effective throughput can be compared with what
+//! the baud rate allows. Serial ports can only be opened on Unix.
+
+use std::fs::File;
+use std::io;
+
+/// The bits sent on the line for every byte: a start bit, 8 data bits and a
+/// stop bit.
+const BITS_PER_BYTE: f64 = 10.0;
+
+/// The most bytes per second an 8N1 link at the baud rate can carry.
+pub fn line_rate(baud: u32) -> f64 {
+    baud as f64 / BITS_PER_BYTE
+}
+
+/// Opens the serial port and configures it for raw input at the baud rate,
+/// dropping anything received before it was opened.
+#[cfg(unix)]
+pub fn open(path: &str, baud: u32) -> io::Result<File> {
+    use std::fs::OpenOptions;
+    use std::os::unix::fs::OpenOptionsExt;
+    use std::os::unix::io::AsRawFd;
+
+    let speed = speed(baud).ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "unsupported baud rate"))?;
+    let port = OpenOptions::new().read(true).custom_flags(libc::O_NOCTTY).open(path)?;
+    let fd = port.as_raw_fd();
+
+    unsafe {
+        let mut termios: libc::termios = std::mem::zeroed();
+        if libc::tcgetattr(fd, &mut termios) != 0 {
+            return Err(io::Error::last_os_error());
+        }
+
+        libc::cfmakeraw(&mut termios);
+        termios.c_cflag |= libc::CLOCAL | libc::CREAD;
+        termios.c_cc[libc::VMIN] = 1;
+        termios.c_cc[libc::VTIME] = 0;
+        if libc::cfsetispeed(&mut termios, speed) != 0 || libc::cfsetospeed(&mut termios, speed) != 0 {
+            return Err(io::Error::last_os_error());
+        }
+        if libc::tcsetattr(fd, libc::TCSANOW, &termios) != 0 {
+            return Err(io::Error::last_os_error());
+        }
+        libc::tcflush(fd, libc::TCIFLUSH);
+    }
+    Ok(port)
+}
+
+#[cfg(not(unix))]
+pub fn open(_path: &str, _baud: u32) -> io::Result<File> {
+    Err(io::Error::new(io::ErrorKind::Unsupported, "serial ports are only available on Unix"))
+}
+
+/// Linux only accepts the rates it has constants for.
+#[cfg(target_os = "linux")]
+fn speed(baud: u32) -> Option<libc::speed_t> {
+    Some(match baud {
+        50 => libc::B50,
+        75 => libc::B75,
+        110 => libc::B110,
+        134 => libc::B134,
+        150 => libc::B150,
+        200 => libc::B200,
+        300 => libc::B300,
+        600 => libc::B600,
+        1200 => libc::B1200,
+        1800 => libc::B1800,
+        2400 => libc::B2400,
+        4800 => libc::B4800,
+        9600 => libc::B9600,
+        19200 => libc::B19200,
+        38400 => libc::B38400,
+        57600 => libc::B57600,
+        115200 => libc::B115200,
+        230400 => libc::B230400,
+        460800 => libc::B460800,
+        500000 => libc::B500000,
+        576000 => libc::B576000,
+        921600 => libc::B921600,
+        1000000 => libc::B1000000,
+        1152000 => libc::B1152000,
+        1500000 => libc::B1500000,
+        2000000 => libc::B2000000,
+        2500000 => libc::B2500000,
+        3000000 => libc::B3000000,
+        3500000 => libc::B3500000,
+        4000000 => libc::B4000000,
+        _ => return None,
+    })
+}
+
+/// The BSDs and macOS take the rate itself.
+#[cfg(all(unix, not(target_os = "linux")))]
+fn speed(baud: u32) -> Option<libc::speed_t> {
+    Some(baud as libc::speed_t)
+}
@@ -0,0 +1,158 @@
+//! Limiting how quickly data is sent or passed through according to a
+//! schedule of rates, so that a consumer can be tested against a producer
+//! whose rate changes in a reproducible way.
+
+use std::time::{Duration, Instant};
+
+use units;
+
+/// The most data that can be sent at once after a pause, in seconds of the
+/// current rate, so that an idle producer cannot build up a large burst.
+const MAX_BURST_SECS: f64 = 0.1;
+
+/// The rate that applies from `start` until `end`, or until the end of the
+/// transfer if there is no end.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct Segment {
+    pub start: Duration,
+    pub end: Option<Duration>,
+    pub rate: f64,
+}
+
+/// Data sent in bursts of `size` bytes, each starting `interval` after the
+/// one before, with nothing sent in between. A burst that takes longer than
+/// the interval runs straight into the next one.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct Bursts {
+    pub size: u64,
+    pub interval: Duration,
+}
+
+/// The rates that apply over a transfer. Outside of every segment the
+/// transfer is not limited.
+#[derive(Clone, PartialEq, Debug)]
+pub struct Schedule {
+    pub segments: Vec<Segment>,
+
+    /// The bursts that data is sent in, if it is not sent continuously.
+    pub bursts: Option<Bursts>,
+}
+
+impl Schedule {
+    /// Parses a comma separated list of segments such as
+    /// `0-30s:1MB/s,30-60s:10MB/s`, where the end of a segment can be left
+    /// out to keep its rate until the end. A rate on its own applies for the
+    /// whole transfer.
+    pub fn parse(value: &str) -> Option<Schedule> {
+        let mut segments = Vec::new();
+        for part in value.split(',').map(str::trim) {
+            let segment = match part.rfind(':') {
+                Some(colon) => {
+                    let (times, rate) = (&part[..colon], &part[colon + 1..]);
+                    let dash = times.find('-')?;
+                    let end = &times[dash + 1..];
+                    Segment {
+                        start: parse_offset(&times[..dash])?,
+                        end: if end.trim().is_empty() { None } else { Some(parse_offset(end)?) },
+                        rate: units::parse_rate(rate)?,
+                    }
+                },
+                None => Segment { start: Duration::default(), end: None, rate: units::parse_rate(part)? },
+            };
+            if segment.rate <= 0.0 || segment.end.is_some_and(|end| end <= segment.start) {
+                return None;
+            }
+            segments.push(segment);
+        }
+        Some(Schedule { segments, bursts: None })
+    }
+
+    /// The rate that applies at the given time into the transfer, if any.
+    pub fn rate_at(&self, elapsed: Duration) -> Option<f64> {
+        self.segments.iter()
+            .find(|segment| segment.start <= elapsed && segment.end.is_none_or(|end| elapsed < end))
+            .map(|segment| segment.rate)
+    }
+}
+
+/// A bare number is a number of seconds.
+fn parse_offset(value: &str) -> Option<Duration> {
+    let value = value.trim();
+    match value.parse::<f64>() {
+        Ok(secs) if secs >= 0.0 => Some(Duration::from_secs_f64(secs)),
+        Ok(_) => None,
+        Err(_) => units::parse_duration(value),
+    }
+}
+
+/// Keeps data to the rate of a schedule, started when the shaper is made.
+pub struct Shaper {
+    schedule: Schedule,
+    start: Instant,
+    last: Instant,
+
+    /// The bytes that can be sent right away, which is negative once more
+    /// has been sent than the rate allows so far.
+    credit: f64,
+
+    /// The number of the current burst and the bytes sent in it so far.
+    burst: u64,
+    burst_bytes: u64,
+}
+
+impl Shaper {
+    pub fn new(schedule: Schedule) -> Shaper {
+        let now = Instant::now();
+        Shaper { schedule, start: now, last: now, credit: 0.0, burst: 0, burst_bytes: 0 }
+    }
+
+    /// Takes `bytes` out of the allowance and returns how long to wait
+    /// before sending them. Callers sharing a shaper can wait without
+    /// holding on to it.
+    pub fn reserve(&mut self, bytes: usize) -> Duration {
+        let now = Instant::now();
+        let burst_wait = self.reserve_burst(now, bytes);
+        let rate = match self.schedule.rate_at(now - self.start) {
+            Some(rate) => rate,
+            None => {
+                self.last = now;
+                self.credit = 0.0;
+                return burst_wait;
+            },
+        };
+
+        let earned = (now - self.last).as_secs_f64() * rate;
+        self.credit = (self.credit + earned).min(rate * MAX_BURST_SECS) - bytes as f64;
+        self.last = now;
+        let rate_wait = if self.credit >= 0.0 {
+            Duration::default()
+        } else {
+            Duration::from_secs_f64(-self.credit / rate)
+        };
+        rate_wait.max(burst_wait)
+    }
+
+    /// Takes `bytes` out of the current burst, or the next one if it has
+    /// been sent in full, and returns how long to wait for that burst to
+    /// start.
+    fn reserve_burst(&mut self, now: Instant, bytes: usize) -> Duration {
+        let bursts = match self.schedule.bursts {
+            Some(bursts) => bursts,
+            None => return Duration::default(),
+        };
+
+        let due = ((now - self.start).as_nanos() / bursts.interval.as_nanos()) as u64;
+        if due > self.burst {
+            self.burst = due;
+            self.burst_bytes = 0;
+        }
+        if self.burst_bytes >= bursts.size {
+            self.burst += 1;
+            self.burst_bytes = 0;
+        }
+        self.burst_bytes += bytes as u64;
+
+        let burst_start = self.start + Duration::from_nanos((bursts.interval.as_nanos() * self.burst as u128) as u64);
+        burst_start.saturating_duration_since(now)
+    }
+}
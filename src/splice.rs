@@ -0,0 +1,68 @@
+//! Zero-copy passthrough on Linux. Data is moved from the input to stdout
+//! inside the kernel with `splice(2)` when either side is a pipe, or with
+//! `sendfile(2)` when the input is a regular file, instead of being copied
+//! through a userspace buffer.
+
+use std::io::{self, Read};
+use std::os::unix::io::RawFd;
+use std::ptr;
+
+#[derive(Clone, Copy)]
+enum Method {
+    Splice,
+    Sendfile,
+}
+
+/// A reader whose reads move data from the input to the output directly. The
+/// buffer passed to `read` is never written to; only the number of bytes
+/// that were moved is returned.
+pub struct SpliceReader {
+    input: RawFd,
+    output: RawFd,
+    method: Method,
+}
+
+impl SpliceReader {
+    /// Returns a reader if the kernel can move data between the descriptors.
+    /// The descriptors must stay open for as long as the reader is used.
+    pub fn new(input: RawFd, output: RawFd) -> Option<SpliceReader> {
+        let (input_mode, output_mode) = (file_mode(input)?, file_mode(output)?);
+        let is = |mode: libc::mode_t, kind: libc::mode_t| mode & libc::S_IFMT == kind;
+
+        let method = if is(input_mode, libc::S_IFIFO) || is(output_mode, libc::S_IFIFO) {
+            Method::Splice
+        } else if is(input_mode, libc::S_IFREG) {
+            Method::Sendfile
+        } else {
+            return None;
+        };
+        Some(SpliceReader { input, output, method })
+    }
+}
+
+impl Read for SpliceReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        loop {
+            let moved = unsafe {
+                match self.method {
+                    Method::Splice => libc::splice(self.input, ptr::null_mut(), self.output, ptr::null_mut(),
+                        buf.len(), libc::SPLICE_F_MOVE | libc::SPLICE_F_MORE),
+                    Method::Sendfile => libc::sendfile(self.output, self.input, ptr::null_mut(), buf.len()),
+                }
+            };
+            if moved >= 0 {
+                return Ok(moved as usize);
+            }
+
+            let err = io::Error::last_os_error();
+            if err.kind() != io::ErrorKind::Interrupted {
+                return Err(err);
+            }
+        }
+    }
+}
+
+fn file_mode(fd: RawFd) -> Option<libc::mode_t> {
+    let mut stat: libc::stat = unsafe { std::mem::zeroed() };
+    if unsafe { libc::fstat(fd, &mut stat) } == 0 { Some(stat.st_mode) } else { None }
+}
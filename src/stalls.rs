@@ -0,0 +1,47 @@
+//! Spotting producers that stop sending for a while, which an average rate
+//! hides, by timing how long each read has to wait for data.
+
+use std::time::Duration;
+
+/// Counts the reads that waited at least as long as the threshold.
+#[derive(Clone)]
+pub struct StallDetector {
+    pub threshold: Duration,
+
+    /// Every stall counted.
+    pub total: u64,
+
+    /// Stalls counted since the last measure.
+    pub current: u64,
+
+    /// The stalls counted during the last measure.
+    pub last: u64,
+
+    /// The most stalls counted during any measure.
+    pub peak: u64,
+
+    /// The longest any read waited, whether or not it was a stall.
+    pub longest: Duration,
+}
+
+impl StallDetector {
+    pub fn new(threshold: Duration) -> StallDetector {
+        StallDetector { threshold, total: 0, current: 0, last: 0, peak: 0, longest: Duration::default() }
+    }
+
+    /// Records how long a read that returned data waited for it.
+    pub fn record(&mut self, wait: Duration) {
+        self.longest = self.longest.max(wait);
+        if wait >= self.threshold {
+            self.total += 1;
+            self.current += 1;
+        }
+    }
+
+    /// Takes a measure of the stalls counted since the last one.
+    pub fn measure(&mut self) {
+        self.last = self.current;
+        self.peak = self.peak.max(self.last);
+        self.current = 0;
+    }
+}
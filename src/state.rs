@@ -0,0 +1,134 @@
+//! Keeping the totals of a long-running listener in a file so that they carry
+//! over when it is restarted, such as the bytes a collector box has taken in
+//! since it was set up. The totals of this process are the session, and those
+//! since the file was created are the lifetime.
+
+use std::fs;
+use std::io;
+use std::sync::Mutex;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use serde_json::Value;
+
+/// The format of the file, which is checked when it is read back.
+const STATE_VERSION: u64 = 1;
+
+/// How often the totals are written while a transfer is in progress.
+const CHECKPOINT_INTERVAL: Duration = Duration::from_secs(10);
+
+/// What was transferred over a number of runs.
+#[derive(Clone, Copy, Default, Debug)]
+pub struct Totals {
+    pub bytes: u64,
+    pub elapsed: Duration,
+    pub runs: u64,
+}
+
+impl Totals {
+    fn plus(self, other: Totals) -> Totals {
+        Totals {
+            bytes: self.bytes + other.bytes,
+            elapsed: self.elapsed + other.elapsed,
+            runs: self.runs + other.runs,
+        }
+    }
+}
+
+/// The totals of this process next to those since the file was created.
+#[derive(Clone, Copy, Debug)]
+pub struct StateTotals {
+    pub session: Totals,
+    pub lifetime: Totals,
+
+    /// When the file was created.
+    pub since: SystemTime,
+}
+
+/// The file that the totals are kept in.
+pub struct StateFile {
+    pub path: String,
+
+    /// The lifetime totals when this process started.
+    pub resumed: Totals,
+
+    pub since: SystemTime,
+
+    /// The totals of the runs this process has finished, and when they were
+    /// last written.
+    session: Mutex<(Totals, Instant)>,
+}
+
+impl StateFile {
+    /// Reads the totals from the file, or starts from nothing if it does not
+    /// exist yet.
+    pub fn load(path: &str) -> io::Result<StateFile> {
+        let (resumed, since) = match fs::read_to_string(path) {
+            Ok(content) => parse(&content)?,
+            Err(ref err) if err.kind() == io::ErrorKind::NotFound => (Totals::default(), SystemTime::now()),
+            Err(err) => return Err(err),
+        };
+        let state = StateFile {
+            path: path.to_string(),
+            resumed,
+            since,
+            session: Mutex::new((Totals::default(), Instant::now())),
+        };
+        // Finds out now rather than at the first checkpoint if the file
+        // cannot be written.
+        state.write(Totals::default())?;
+        Ok(state)
+    }
+
+    /// Writes the totals with what the run in progress has transferred so
+    /// far, if they have not been written for a while.
+    pub fn checkpoint(&self, bytes: u64, elapsed: Duration) -> io::Result<()> {
+        let mut session = self.session.lock().expect("state lock was poisoned");
+        if session.1.elapsed() < CHECKPOINT_INTERVAL {
+            return Ok(());
+        }
+        session.1 = Instant::now();
+        self.write(session.0.plus(Totals { bytes, elapsed, runs: 0 }))
+    }
+
+    /// Adds a finished run to the totals, writes them and returns them.
+    pub fn finish_run(&self, bytes: u64, elapsed: Duration) -> (StateTotals, io::Result<()>) {
+        let mut session = self.session.lock().expect("state lock was poisoned");
+        session.0 = session.0.plus(Totals { bytes, elapsed, runs: 1 });
+        session.1 = Instant::now();
+        let totals = StateTotals { session: session.0, lifetime: self.resumed.plus(session.0), since: self.since };
+        (totals, self.write(session.0))
+    }
+
+    /// Replaces the file with the lifetime totals including the session's,
+    /// through a temporary file so that a crash cannot leave it half written.
+    fn write(&self, session: Totals) -> io::Result<()> {
+        let lifetime = self.resumed.plus(session);
+        let since = self.since.duration_since(UNIX_EPOCH).unwrap_or_default();
+        let content = json!({
+            "version": STATE_VERSION,
+            "since_secs": since.as_secs_f64(),
+            "updated_secs": SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_secs_f64(),
+            "bytes": lifetime.bytes,
+            "elapsed_secs": lifetime.elapsed.as_secs_f64(),
+            "runs": lifetime.runs,
+        });
+        let temporary = format!("{}.tmp", self.path);
+        fs::write(&temporary, format!("{:#}\n", content))?;
+        fs::rename(&temporary, &self.path)
+    }
+}
+
+fn parse(content: &str) -> io::Result<(Totals, SystemTime)> {
+    let invalid = |message: &str| io::Error::new(io::ErrorKind::InvalidData, message.to_string());
+    let state: Value = serde_json::from_str(content).map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))?;
+    if state["version"].as_u64() != Some(STATE_VERSION) {
+        return Err(invalid("not a throughput state file, or one from an incompatible version"));
+    }
+    let secs = |key: &str| state[key].as_f64().filter(|secs| secs.is_finite() && *secs >= 0.0)
+        .map(Duration::from_secs_f64)
+        .ok_or_else(|| invalid(&format!("the state file has no valid {}", key)));
+    let count = |key: &str| state[key].as_u64().ok_or_else(|| invalid(&format!("the state file has no valid {}", key)));
+
+    let totals = Totals { bytes: count("bytes")?, elapsed: secs("elapsed_secs")?, runs: count("runs")? };
+    Ok((totals, UNIX_EPOCH + secs("since_secs")?))
+}
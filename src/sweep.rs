@@ -0,0 +1,158 @@
+//! Running the transfer once for each of a range of buffer sizes or numbers
+//! of parallel streams, such as to find the message size that gets the most
+//! through or the point where adding streams stops helping, and printing how
+//! fast each run was.
+
+use serde_json::Value;
+
+use display::format_rate;
+use units::{byte_to_mem_units, parse_size};
+use {MeasureOptions, TransferInfo};
+
+/// The most runs a sweep can make, which catches a step that was meant to
+/// be much larger.
+const MAX_SWEEP_RUNS: usize = 1000;
+
+/// How close to the fastest run a run must be to count as saturating.
+const SATURATION_FRACTION: f64 = 0.95;
+
+/// What a sweep changes from one run to the next.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum SweepKind {
+    BufferSize,
+    Parallel,
+}
+
+impl SweepKind {
+    fn heading(self) -> &'static str {
+        match self {
+            SweepKind::BufferSize => "Buffer Size",
+            SweepKind::Parallel => "Streams",
+        }
+    }
+
+    fn key(self) -> &'static str {
+        match self {
+            SweepKind::BufferSize => "buffer_size",
+            SweepKind::Parallel => "parallel",
+        }
+    }
+
+    fn parse_value(self, text: &str) -> Option<u64> {
+        match self {
+            SweepKind::BufferSize => parse_size(text),
+            SweepKind::Parallel => text.trim().parse().ok(),
+        }
+    }
+
+    fn describe(self, value: usize) -> String {
+        match self {
+            SweepKind::BufferSize => format_size(value),
+            SweepKind::Parallel if value == 1 => "1 stream".to_string(),
+            SweepKind::Parallel => format!("{} streams", value),
+        }
+    }
+}
+
+/// The values to run the transfer with, in order.
+pub struct Sweep {
+    pub kind: SweepKind,
+    pub values: Vec<usize>,
+}
+
+impl Sweep {
+    /// Parses `START:END:STEP`, where the step is a factor such as `x2` or an
+    /// amount added each time such as `+1K`. The end is included if the
+    /// steps land on it.
+    pub fn parse(kind: SweepKind, value: &str) -> Option<Sweep> {
+        let parts: Vec<&str> = value.split(':').collect();
+        if parts.len() != 3 {
+            return None;
+        }
+        let start = kind.parse_value(parts[0])? as usize;
+        let end = kind.parse_value(parts[1])? as usize;
+        if start == 0 || end < start {
+            return None;
+        }
+
+        let next: Box<dyn Fn(usize) -> usize> = if let Some(factor) = parts[2].strip_prefix('x') {
+            let factor: f64 = factor.parse().ok().filter(|factor: &f64| *factor > 1.0 && factor.is_finite())?;
+            Box::new(move |value| ((value as f64 * factor).round() as usize).max(value + 1))
+        } else if let Some(step) = parts[2].strip_prefix('+') {
+            let step = kind.parse_value(step).filter(|&step| step > 0)? as usize;
+            Box::new(move |value| value + step)
+        } else {
+            return None;
+        };
+
+        let mut values = Vec::new();
+        let mut value = start;
+        while value <= end {
+            if values.len() == MAX_SWEEP_RUNS {
+                return None;
+            }
+            values.push(value);
+            value = next(value);
+        }
+        Some(Sweep { kind, values })
+    }
+
+    /// The options to measure the run at the index with.
+    pub fn options_for(&self, run: usize, options: &MeasureOptions) -> MeasureOptions {
+        match self.kind {
+            SweepKind::BufferSize => MeasureOptions { buffer_size: self.values[run], ..options.clone() },
+            SweepKind::Parallel => MeasureOptions { parallel: self.values[run], ..options.clone() },
+        }
+    }
+
+    /// What the run at the index is made with, such as `64 KB` or `8 streams`.
+    pub fn describe(&self, run: usize) -> String {
+        self.kind.describe(self.values[run])
+    }
+
+    /// Prints the average and peak speed of each run and how it compares to
+    /// the first, then which run was fastest and where the speed stopped
+    /// growing. There are fewer results than values if the sweep was stopped
+    /// early.
+    pub fn print(&self, results: &[TransferInfo]) {
+        let first_bps = results.first().map_or(0.0, TransferInfo::average_bps);
+        println!();
+        println!("{:>12}  {:>20}  {:>20}  {:>8}", self.kind.heading(), "Average Speed", "Peak Speed", "Scaling");
+        for (&value, result) in self.values.iter().zip(results) {
+            let scaling = if first_bps > 0.0 { format!("x{:.2}", result.average_bps() / first_bps) } else { "-".to_string() };
+            println!("{:>12}  {:>20}  {:>20}  {:>8}", self.kind.describe(value), format_rate(result.average_bps()),
+                format_rate(result.peak_bps), scaling);
+        }
+
+        let fastest = self.values.iter().zip(results)
+            .max_by(|a, b| a.1.average_bps().total_cmp(&b.1.average_bps()));
+        if let Some((&value, best)) = fastest {
+            println!("Fastest with {}: {}", self.kind.describe(value), format_rate(best.average_bps()));
+            let saturated = self.values.iter().zip(results)
+                .find(|(_, result)| result.average_bps() >= best.average_bps() * SATURATION_FRACTION);
+            if let Some((&value, _)) = saturated {
+                println!("Within {:.0}% of the fastest from {}", (1.0 - SATURATION_FRACTION) * 100.0,
+                    self.kind.describe(value));
+            }
+        }
+    }
+
+    pub fn to_json(&self, results: &[TransferInfo]) -> Value {
+        json!(self.values.iter().zip(results).map(|(&value, result)| {
+            let mut run = json!({
+                "average_bps": result.average_bps(),
+                "peak_bps": result.peak_bps,
+                "total_bytes": result.total_bytes_transferred,
+            });
+            run[self.kind.key()] = json!(value);
+            run
+        }).collect::<Vec<_>>())
+    }
+}
+
+/// Formats a size without decimals when it is a whole number of its unit,
+/// such as `64 KB` or `1500 B`.
+fn format_size(size: usize) -> String {
+    let (mem, unit) = byte_to_mem_units(size as f64);
+    if mem.fract() == 0.0 { format!("{} {}", mem, unit) } else { format!("{:.2} {}", mem, unit) }
+}
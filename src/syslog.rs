@@ -0,0 +1,144 @@
+//! Logging every measure and the final summary to the system log on Unix,
+//! either to syslog through its local socket or to the systemd journal with
+//! its native protocol, so that a long running measurement can be followed
+//! with the rest of the logs of a host.
+//!
+//! Syslog records carry their values as `key=value` pairs after the event
+//! name. Journal records carry them as fields prefixed with `THROUGHPUT_`,
+//! which `journalctl` can filter and output as JSON.
+
+use std::io;
+use std::os::unix::net::UnixDatagram;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use serde_json::Value;
+
+use push::Sample;
+
+/// The name records are logged under.
+const IDENTIFIER: &str = "throughput";
+
+/// The sockets syslog listens on across Linux, macOS and the BSDs.
+const SYSLOG_PATHS: [&str; 3] = ["/dev/log", "/var/run/syslog", "/var/run/log"];
+
+/// The socket that journald receives native records on.
+const JOURNAL_PATH: &str = "/run/systemd/journal/socket";
+
+/// The user facility, which is what syslog(3) uses by default.
+const FACILITY_USER: u8 = 1;
+
+/// Informational severity.
+const SEVERITY_INFO: u8 = 6;
+
+/// Which system log records are written to.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum LogKind {
+    Syslog,
+    Journald,
+}
+
+/// A connection to the system log.
+pub struct SystemLog {
+        socket: UnixDatagram,
+    kind: LogKind,
+}
+
+impl SystemLog {
+    pub fn open(kind: LogKind) -> io::Result<SystemLog> {
+        let socket = UnixDatagram::unbound()?;
+        match kind {
+            LogKind::Syslog => {
+                let mut result = Err(io::Error::new(io::ErrorKind::NotFound, "no syslog socket was found"));
+                for path in &SYSLOG_PATHS {
+                    result = socket.connect(path);
+                    if result.is_ok() { break; }
+                }
+                result?;
+            },
+            LogKind::Journald => socket.connect(JOURNAL_PATH)?,
+        }
+        // A log that cannot keep up loses records rather than holding up
+        // measuring.
+        socket.set_nonblocking(true)?;
+        Ok(SystemLog { socket, kind })
+    }
+
+    pub fn log_measure(&self, sample: &Sample) -> io::Result<()> {
+        self.log("measure", &[
+            ("bytes", sample.bytes.to_string()),
+            ("total_bytes", sample.total_bytes.to_string()),
+            ("current_bps", format!("{:.0}", sample.current_bps)),
+            ("average_bps", format!("{:.0}", sample.average_bps)),
+            ("elapsed_secs", format!("{:.3}", sample.elapsed.as_secs_f64())),
+        ])
+    }
+
+    /// Logs every value of the summary, naming nested values by their path
+    /// such as `cpu_user_secs`.
+    pub fn log_summary(&self, summary: &Value) -> io::Result<()> {
+        let mut fields = Vec::new();
+        flatten("", summary, &mut fields);
+        let fields: Vec<(&str, String)> = fields.iter().map(|(key, value)| (key.as_str(), value.clone())).collect();
+        self.log("summary", &fields)
+    }
+
+    fn log(&self, event: &str, fields: &[(&str, String)]) -> io::Result<()> {
+        let pairs: Vec<String> = fields.iter().map(|&(key, ref value)| format!("{}={}", key, value)).collect();
+        let message = format!("{} {}", event, pairs.join(" "));
+        let record = match self.kind {
+            LogKind::Syslog => format!("<{}>{} {}[{}]: {}", FACILITY_USER * 8 + SEVERITY_INFO,
+                syslog_timestamp(SystemTime::now()), IDENTIFIER, std::process::id(), message),
+            LogKind::Journald => {
+                let mut record = format!("MESSAGE={}\nPRIORITY={}\nSYSLOG_FACILITY={}\nSYSLOG_IDENTIFIER={}\n\
+                    SYSLOG_PID={}\nTHROUGHPUT_EVENT={}\n", message, SEVERITY_INFO, FACILITY_USER, IDENTIFIER,
+                    std::process::id(), event);
+                for &(key, ref value) in fields {
+                    record.push_str(&format!("THROUGHPUT_{}={}\n", key.to_ascii_uppercase(), value));
+                }
+                record
+            },
+        };
+        self.send(record.as_bytes())
+    }
+
+    fn send(&self, record: &[u8]) -> io::Result<()> {
+        self.socket.send(record).map(drop)
+    }
+}
+
+/// Collects the scalar values of the JSON, joining the keys of nested
+/// objects with underscores. Arrays, such as the results of every run, are
+/// left out.
+fn flatten(prefix: &str, value: &Value, fields: &mut Vec<(String, String)>) {
+    match *value {
+        Value::Object(ref map) => {
+            for (key, value) in map {
+                let key = if prefix.is_empty() { key.clone() } else { format!("{}_{}", prefix, key) };
+                flatten(&key, value, fields);
+            }
+        },
+        Value::Array(_) | Value::Null => {},
+        // Strings are quoted so that ones with spaces stay one value.
+        Value::String(ref text) => fields.push((prefix.to_string(), format!("{:?}", text))),
+        ref value => fields.push((prefix.to_string(), value.to_string())),
+    }
+}
+
+/// Formats the time as syslog(3) does, such as `Oct  6 14:03:09`, in local
+/// time.
+fn syslog_timestamp(time: SystemTime) -> String {
+    const MONTHS: [&str; 12] = ["Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec"];
+
+    let secs = time.duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0);
+    let (month, day, hour, minute, second) = local_time(secs);
+    format!("{} {:>2} {:02}:{:02}:{:02}", MONTHS[month % 12], day, hour, minute, second)
+}
+
+/// The month from zero, day of the month, hour, minute and second in local
+/// time.
+fn local_time(secs: u64) -> (usize, u32, u32, u32, u32) {
+    let time = secs as libc::time_t;
+    let mut tm: libc::tm = unsafe { std::mem::zeroed() };
+    unsafe { libc::localtime_r(&time, &mut tm) };
+    (tm.tm_mon as usize, tm.tm_mday as u32, tm.tm_hour as u32, tm.tm_min as u32, tm.tm_sec as u32)
+}
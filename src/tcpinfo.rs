@@ -0,0 +1,163 @@
+//! The state of a TCP connection as the kernel sees it, sampled with
+//! `TCP_INFO` on Linux while the connection is measured. The retransmits,
+//! congestion window, round trip time and pacing rate explain why the
+//! throughput is what it is rather than only what it is.
+
+use std::net::TcpStream;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+/// How often the connection is sampled.
+const TCP_INFO_INTERVAL: Duration = Duration::from_millis(100);
+
+/// What the kernel reported about the connection at one moment.
+#[derive(Clone, Copy, Debug)]
+#[cfg_attr(not(target_os = "linux"), allow(dead_code))]
+struct TcpInfo {
+    rtt: Duration,
+    cwnd: u32,
+    mss: u32,
+
+    /// In bytes per second, or None if the connection is not paced.
+    pacing_rate: Option<u64>,
+
+    total_retransmits: u32,
+}
+
+/// The connection over every sample taken while it was measured.
+#[derive(Clone, Default, Debug)]
+pub struct TcpStats {
+    pub samples: u64,
+
+    /// The segments retransmitted over the life of the connection.
+    pub retransmits: u64,
+
+    pub min_rtt: Duration,
+    pub max_rtt: Duration,
+    rtt_total: Duration,
+
+    /// The congestion window in segments, when last sampled and at its
+    /// largest.
+    pub cwnd: u32,
+    pub max_cwnd: u32,
+
+    /// The largest segment the connection sends, for the window in bytes.
+    pub mss: u32,
+
+    /// In bytes per second when last sampled and at its fastest, if the
+    /// connection was paced.
+    pub pacing_rate: Option<u64>,
+    pub max_pacing_rate: Option<u64>,
+}
+
+impl TcpStats {
+    pub fn average_rtt(&self) -> Duration {
+        if self.samples == 0 { return Duration::ZERO; }
+        self.rtt_total / self.samples as u32
+    }
+
+    fn record(&mut self, info: TcpInfo) {
+        if self.samples == 0 {
+            self.min_rtt = info.rtt;
+        }
+        self.samples += 1;
+        self.retransmits = info.total_retransmits as u64;
+        self.min_rtt = self.min_rtt.min(info.rtt);
+        self.max_rtt = self.max_rtt.max(info.rtt);
+        self.rtt_total += info.rtt;
+        self.cwnd = info.cwnd;
+        self.max_cwnd = self.max_cwnd.max(info.cwnd);
+        self.mss = info.mss;
+        self.pacing_rate = info.pacing_rate;
+        self.max_pacing_rate = self.max_pacing_rate.max(info.pacing_rate);
+    }
+}
+
+/// Samples a connection from a thread of its own until it is finished. The
+/// connection must stay open until then.
+pub struct TcpInfoSampler {
+    #[cfg_attr(not(target_os = "linux"), allow(dead_code))]
+    fd: i32,
+    stats: Arc<Mutex<TcpStats>>,
+    finished: Arc<AtomicBool>,
+    thread: Mutex<Option<JoinHandle<()>>>,
+}
+
+impl TcpInfoSampler {
+    /// Starts sampling the connection, if the platform can tell anything
+    /// about it.
+    pub fn start(stream: &TcpStream) -> Option<Arc<TcpInfoSampler>> {
+        let fd = raw_fd(stream)?;
+        let first = query(fd)?;
+
+        let mut stats = TcpStats::default();
+        stats.record(first);
+        let stats = Arc::new(Mutex::new(stats));
+        let finished = Arc::new(AtomicBool::new(false));
+        let thread = {
+            let (stats, finished) = (stats.clone(), finished.clone());
+            thread::spawn(move || {
+                while !finished.load(Ordering::Relaxed) {
+                    thread::sleep(TCP_INFO_INTERVAL);
+                    match query(fd) {
+                        Some(info) => stats.lock().expect("TCP stats lock was poisoned").record(info),
+                        None => return,
+                    }
+                }
+            })
+        };
+        Some(Arc::new(TcpInfoSampler { fd, stats, finished, thread: Mutex::new(Some(thread)) }))
+    }
+
+    /// Stops sampling and returns the stats with a last sample taken.
+    pub fn finish(&self) -> TcpStats {
+        self.finished.store(true, Ordering::Relaxed);
+        if let Some(thread) = self.thread.lock().expect("TCP sampler lock was poisoned").take() {
+            let _ = thread.join();
+            if let Some(info) = query(self.fd) {
+                self.stats.lock().expect("TCP stats lock was poisoned").record(info);
+            }
+        }
+        self.stats.lock().expect("TCP stats lock was poisoned").clone()
+    }
+}
+
+#[cfg(target_os = "linux")]
+fn raw_fd(stream: &TcpStream) -> Option<i32> {
+    use std::os::unix::io::AsRawFd;
+    Some(stream.as_raw_fd())
+}
+
+#[cfg(not(target_os = "linux"))]
+fn raw_fd(_stream: &TcpStream) -> Option<i32> {
+    None
+}
+
+#[cfg(target_os = "linux")]
+fn query(fd: i32) -> Option<TcpInfo> {
+    let mut info: libc::tcp_info = unsafe { std::mem::zeroed() };
+    let mut len = std::mem::size_of::<libc::tcp_info>() as libc::socklen_t;
+    let result = unsafe {
+        libc::getsockopt(fd, libc::IPPROTO_TCP, libc::TCP_INFO, &mut info as *mut libc::tcp_info as *mut libc::c_void, &mut len)
+    };
+    if result != 0 {
+        return None;
+    }
+
+    Some(TcpInfo {
+        rtt: Duration::from_micros(info.tcpi_rtt as u64),
+        cwnd: info.tcpi_snd_cwnd,
+        mss: info.tcpi_snd_mss,
+        // Older kernels leave the rate out, and an unpaced connection reports
+        // the largest value.
+        pacing_rate: Some(info.tcpi_pacing_rate).filter(|&rate| rate != 0 && rate != u64::MAX),
+        total_retransmits: info.tcpi_total_retrans,
+    })
+}
+
+#[cfg(not(target_os = "linux"))]
+fn query(_fd: i32) -> Option<TcpInfo> {
+    None
+}
@@ -0,0 +1,135 @@
+//! Printing the stats of each measure as a line laid out by a template given
+//! with `--format`, so that the output can match what existing log parsers
+//! expect.
+//!
+//! Placeholders are names in braces such as `{rate}`, and `{{` and `}}` stand
+//! for literal braces. The sizes and rates are given without a unit in the
+//! unit that fits the current transfer speed, which `{units}` names, so that
+//! every value on a line is in the same unit.
+
+use std::time::SystemTime;
+
+use display::format_timestamp;
+use units::{format_elapsed, mem_unit, NumberFormat};
+use TransferInfo;
+
+/// A value that can be placed in a line.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+enum Field {
+    Total,
+    Rate,
+    Avg,
+    Peak,
+    Units,
+    Bytes,
+    Bps,
+    AvgBps,
+    PeakBps,
+    Elapsed,
+    Secs,
+    Measures,
+    Time,
+}
+
+impl Field {
+    fn parse(name: &str) -> Option<Field> {
+        match name {
+            "total" => Some(Field::Total),
+            "rate" => Some(Field::Rate),
+            "avg" => Some(Field::Avg),
+            "peak" => Some(Field::Peak),
+            "units" => Some(Field::Units),
+            "bytes" => Some(Field::Bytes),
+            "bps" => Some(Field::Bps),
+            "avg_bps" => Some(Field::AvgBps),
+            "peak_bps" => Some(Field::PeakBps),
+            "elapsed" => Some(Field::Elapsed),
+            "secs" => Some(Field::Secs),
+            "measures" => Some(Field::Measures),
+            "time" => Some(Field::Time),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+enum Part {
+    Text(String),
+    Field(Field),
+}
+
+/// The layout of the line printed for every measure.
+#[derive(Clone, Debug)]
+pub struct Template {
+    parts: Vec<Part>,
+}
+
+impl Template {
+    pub fn parse(text: &str) -> Result<Template, String> {
+        let mut parts = Vec::new();
+        let mut literal = String::new();
+        let mut chars = text.chars().peekable();
+        while let Some(c) = chars.next() {
+            match c {
+                '{' if chars.peek() == Some(&'{') => {
+                    chars.next();
+                    literal.push('{');
+                },
+                '}' if chars.peek() == Some(&'}') => {
+                    chars.next();
+                    literal.push('}');
+                },
+                '{' => {
+                    let mut name = String::new();
+                    loop {
+                        match chars.next() {
+                            Some('}') => break,
+                            Some(c) => name.push(c),
+                            None => return Err("unclosed {, write {{ for a literal brace".to_string()),
+                        }
+                    }
+                    let field = Field::parse(&name).ok_or_else(|| format!("unknown placeholder {{{}}}", name))?;
+                    if !literal.is_empty() {
+                        parts.push(Part::Text(literal.split_off(0)));
+                    }
+                    parts.push(Part::Field(field));
+                },
+                '}' => return Err("unmatched }, write }} for a literal brace".to_string()),
+                c => literal.push(c),
+            }
+        }
+        if !literal.is_empty() {
+            parts.push(Part::Text(literal));
+        }
+        Ok(Template { parts })
+    }
+
+    /// Lays out the stats of the latest measure, without a line ending.
+    pub fn render(&self, transfer_info: &TransferInfo, number_format: &NumberFormat) -> String {
+        let (unit_size, unit) = mem_unit(transfer_info.current_bps);
+        let scaled = |bytes: f64| number_format.format(bytes / unit_size);
+
+        let mut line = String::new();
+        for part in &self.parts {
+            match *part {
+                Part::Text(ref text) => line.push_str(text),
+                Part::Field(field) => line.push_str(&match field {
+                    Field::Total => scaled(transfer_info.total_bytes_transferred as f64),
+                    Field::Rate => scaled(transfer_info.current_bps),
+                    Field::Avg => scaled(transfer_info.average_bps()),
+                    Field::Peak => scaled(transfer_info.peak_bps),
+                    Field::Units => unit.to_string(),
+                    Field::Bytes => transfer_info.total_bytes_transferred.to_string(),
+                    Field::Bps => format!("{:.0}", transfer_info.current_bps),
+                    Field::AvgBps => format!("{:.0}", transfer_info.average_bps()),
+                    Field::PeakBps => format!("{:.0}", transfer_info.peak_bps),
+                    Field::Elapsed => format_elapsed(transfer_info.elapsed),
+                    Field::Secs => format!("{:.3}", transfer_info.elapsed.as_secs_f64()),
+                    Field::Measures => transfer_info.total_measures.to_string(),
+                    Field::Time => format_timestamp(transfer_info.last_measure_time.unwrap_or_else(SystemTime::now)),
+                }),
+            }
+        }
+        line
+    }
+}
@@ -0,0 +1,118 @@
+use std::collections::VecDeque;
+use std::io::{self, Write};
+use std::time::Duration;
+
+use units::{byte_to_mem_units, format_elapsed};
+use TransferInfo;
+
+/// The number of measures shown in the graph.
+pub const GRAPH_WIDTH: usize = 60;
+
+/// The number of rows used to draw the graph.
+const GRAPH_HEIGHT: usize = 8;
+
+/// Column width of the labels and graph axis.
+const LABEL_WIDTH: usize = 24;
+
+/// Partial blocks used for the top of each graph column, from empty to full.
+const BLOCKS: [char; 9] = [' ', '▁', '▂', '▃', '▄', '▅', '▆', '▇', '█'];
+
+/// A full screen view of the transfer with a scrolling graph of recent
+/// transfer speeds. The screen is redrawn in place after every measure.
+#[derive(Default)]
+pub struct Dashboard {
+    /// The most recent Bytes Per Second measures, oldest first.
+    history: VecDeque<f64>,
+}
+
+impl Dashboard {
+    pub fn new() -> Dashboard {
+        Dashboard::default()
+    }
+
+    /// Records the latest measure and redraws the whole dashboard.
+    pub fn render<W: Write>(&mut self, output: &mut W, transfer_info: &TransferInfo) -> io::Result<()> {
+        if self.history.len() == GRAPH_WIDTH {
+            self.history.pop_front();
+        }
+        self.history.push_back(transfer_info.last_bps);
+        self.redraw(output, transfer_info)
+    }
+
+    /// Redraws the whole dashboard without recording a measure, for a
+    /// transfer that is between measures.
+    pub fn redraw<W: Write>(&mut self, output: &mut W, transfer_info: &TransferInfo) -> io::Result<()> {
+        // Clear the screen the first time, afterwards just move to the top.
+        if transfer_info.total_measures <= 1 {
+            write!(output, "\x1b[2J")?;
+        }
+        write!(output, "\x1b[H")?;
+
+        writeln!(output, "Throughput{:>width$}\x1b[K", format!("elapsed {}", format_elapsed(transfer_info.elapsed)),
+            width = LABEL_WIDTH + GRAPH_WIDTH - "Throughput".len())?;
+        writeln!(output, "{}\x1b[K", "─".repeat(LABEL_WIDTH + GRAPH_WIDTH))?;
+
+        let (mem_total, unit_total) = byte_to_mem_units(transfer_info.total_bytes_transferred as f64);
+        writeln!(output, "{:<width$}{:.3} {} ({} cycles)\x1b[K", "Data Transferred:",
+            mem_total, unit_total, transfer_info.total_measures, width = LABEL_WIDTH)?;
+        self.render_rate(output, "Transfer Speed:", transfer_info.current_bps)?;
+        self.render_rate(output, "Average Transfer Speed:", transfer_info.average_bps())?;
+        self.render_rate(output, "Peak Transfer Speed:", transfer_info.peak_bps)?;
+        writeln!(output, "\x1b[K")?;
+
+        render_graph(output, self.history.make_contiguous(), "\x1b[K")?;
+        write!(output, "\x1b[J")?;
+        output.flush()
+    }
+
+    fn render_rate<W: Write>(&self, output: &mut W, label: &str, bps: f64) -> io::Result<()> {
+        let (mem, unit) = byte_to_mem_units(bps);
+        writeln!(output, "{:<width$}{:.3} {}/sec\x1b[K", label, mem, unit, width = LABEL_WIDTH)
+    }
+}
+
+/// Draws the rates as a bar graph scaled to the largest of them, ending every
+/// line with `line_end`.
+pub fn render_graph<W: Write>(output: &mut W, rates: &[f64], line_end: &str) -> io::Result<()> {
+    let max = rates.iter().cloned().fold(0.0, f64::max);
+    let (mem_max, unit_max) = byte_to_mem_units(max);
+    let top_label = format!("{:.1} {}/sec ", mem_max, unit_max);
+
+    for row in 0..GRAPH_HEIGHT {
+        let label = if row == 0 { top_label.as_str() } else { "" };
+        write!(output, "{:>width$}│", label, width = LABEL_WIDTH - 1)?;
+
+        // How many eighths of a row lie below the top of this row.
+        let row_floor = (GRAPH_HEIGHT - row - 1) * 8;
+        for &bps in rates {
+            let eighths = if max > 0.0 { (bps / max * (GRAPH_HEIGHT * 8) as f64).round() as usize } else { 0 };
+            let fill = eighths.saturating_sub(row_floor).min(8);
+            write!(output, "{}", BLOCKS[fill])?;
+        }
+        writeln!(output, "{}", line_end)?;
+    }
+
+    writeln!(output, "{:>width$}└{}{}", "0 Bytes/sec ", "─".repeat(GRAPH_WIDTH), line_end, width = LABEL_WIDTH - 1)
+}
+
+/// Draws the speed of every measure of a finished transfer, averaging
+/// neighbouring measures into one column when there are too many to fit.
+pub fn render_history<W: Write>(output: &mut W, history: &[f64], elapsed: Duration) -> io::Result<()> {
+    if history.is_empty() { return Ok(()); }
+
+    let columns: Vec<f64> = if history.len() <= GRAPH_WIDTH {
+        history.to_vec()
+    } else {
+        (0..GRAPH_WIDTH).map(|column| {
+            let group = &history[column * history.len() / GRAPH_WIDTH..(column + 1) * history.len() / GRAPH_WIDTH];
+            group.iter().sum::<f64>() / group.len() as f64
+        }).collect()
+    };
+
+    writeln!(output)?;
+    render_graph(output, &columns, "")?;
+    // The elapsed time ends under the last column when there is room for it.
+    let end_label = format_elapsed(elapsed);
+    writeln!(output, "{:>width$}{:>graph_width$}", "0s", end_label,
+        width = LABEL_WIDTH + 1, graph_width = (columns.len() - 1).max(end_label.len() + 1))
+}
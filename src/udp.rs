@@ -0,0 +1,240 @@
+//! Receiving UDP datagrams on a port with one or more threads, each reading
+//! its own socket. With several, the sockets share the port through
+//! SO_REUSEPORT and the kernel spreads datagrams over them by flow, so that
+//! packet rates a single receive loop cannot keep up with can be measured.
+//!
+//! The threads only count what they receive. The reader that is measured
+//! hands out how many bytes they counted rather than the datagrams, so their
+//! contents cannot be passed on. On Linux each thread receives a batch of
+//! datagrams per system call with `recvmmsg(2)`.
+
+use std::io::{self, Read};
+use std::net::UdpSocket;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// The largest payload a UDP datagram can carry.
+const MAX_DATAGRAM_SIZE: usize = 65535;
+
+/// How often the receiving threads check whether measuring has finished.
+const RECV_POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+/// How long the reader sleeps when nothing new was received.
+const READ_POLL_INTERVAL: Duration = Duration::from_millis(1);
+
+/// How many datagrams are received per system call by default where that
+/// is possible.
+pub const DEFAULT_BATCH_SIZE: usize = 32;
+
+/// The datagrams received by every thread.
+#[derive(Clone, Default)]
+pub struct UdpStats {
+    pub bytes: u64,
+
+    /// Datagrams received by each thread.
+    pub datagrams: Vec<u64>,
+
+    /// The time from the first datagram to the last, which leaves out the
+    /// wait for more that ends the transfer.
+    pub active: Duration,
+}
+
+impl UdpStats {
+    pub fn total_datagrams(&self) -> u64 {
+        self.datagrams.iter().sum()
+    }
+
+    /// The average size of a datagram in bytes.
+    pub fn average_datagram_size(&self) -> f64 {
+        let datagrams = self.total_datagrams();
+        if datagrams == 0 { return 0.0; }
+        self.bytes as f64 / datagrams as f64
+    }
+
+    pub fn datagrams_per_second(&self) -> f64 {
+        let secs = self.active.as_secs_f64();
+        if secs > 0.0 { self.total_datagrams() as f64 / secs } else { 0.0 }
+    }
+}
+
+/// Counts shared between the receiving threads and the reader.
+pub struct UdpCounters {
+    bytes: AtomicU64,
+
+    /// Bytes received that the reader has not handed out yet.
+    pending: AtomicU64,
+
+    datagrams: Vec<AtomicU64>,
+
+    /// When the first and last datagrams were received, in nanoseconds
+    /// since the threads started.
+    started: Instant,
+    first_received: AtomicU64,
+    last_received: AtomicU64,
+
+    /// Set once the reader has been dropped so the threads can exit.
+    finished: AtomicBool,
+}
+
+impl UdpCounters {
+    pub fn stats(&self) -> UdpStats {
+        UdpStats {
+            bytes: self.bytes.load(Ordering::Relaxed),
+            datagrams: self.datagrams.iter().map(|count| count.load(Ordering::Relaxed)).collect(),
+            active: Duration::from_nanos(self.last_received.load(Ordering::Relaxed)
+                .saturating_sub(self.first_received.load(Ordering::Relaxed))),
+        }
+    }
+
+    fn record(&self, index: usize, datagrams: u64, bytes: u64) {
+        self.bytes.fetch_add(bytes, Ordering::Relaxed);
+        self.datagrams[index].fetch_add(datagrams, Ordering::Relaxed);
+        self.pending.fetch_add(bytes, Ordering::AcqRel);
+
+        let now = self.started.elapsed().as_nanos() as u64;
+        self.first_received.fetch_min(now, Ordering::Relaxed);
+        self.last_received.fetch_max(now, Ordering::Relaxed);
+    }
+}
+
+/// Reads the bytes counted by the receiving threads. It reaches the end once
+/// no datagram has arrived for the idle timeout, as UDP has no end of stream.
+pub struct UdpReceiver {
+    counters: Arc<UdpCounters>,
+    idle_timeout: Duration,
+    last_received: Instant,
+}
+
+impl UdpReceiver {
+    /// Starts a thread receiving on each of the sockets, up to `batch`
+    /// datagrams at a time.
+    pub fn start(sockets: &[UdpSocket], idle_timeout: Duration, batch: usize) -> io::Result<(UdpReceiver, Arc<UdpCounters>)> {
+        let counters = Arc::new(UdpCounters {
+            bytes: AtomicU64::new(0),
+            pending: AtomicU64::new(0),
+            datagrams: sockets.iter().map(|_| AtomicU64::new(0)).collect(),
+            started: Instant::now(),
+            first_received: AtomicU64::new(u64::MAX),
+            last_received: AtomicU64::new(0),
+            finished: AtomicBool::new(false),
+        });
+        for (index, socket) in sockets.iter().enumerate() {
+            let socket = socket.try_clone()?;
+            socket.set_read_timeout(Some(RECV_POLL_INTERVAL))?;
+            let counters = counters.clone();
+            thread::spawn(move || receive(&socket, index, batch, &counters));
+        }
+        let receiver = UdpReceiver { counters: counters.clone(), idle_timeout, last_received: Instant::now() };
+        Ok((receiver, counters))
+    }
+
+    /// Waits for the first datagram, so that the time spent waiting for a
+    /// sender is not measured. Returns false if none arrives within the
+    /// timeout.
+    pub fn wait_for_first(&mut self, timeout: Option<Duration>) -> bool {
+        let start = Instant::now();
+        while self.counters.pending.load(Ordering::Acquire) == 0 {
+            if timeout.is_some_and(|timeout| start.elapsed() >= timeout) {
+                return false;
+            }
+            thread::sleep(READ_POLL_INTERVAL);
+        }
+        self.last_received = Instant::now();
+        true
+    }
+}
+
+impl Read for UdpReceiver {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        loop {
+            let pending = self.counters.pending.swap(0, Ordering::AcqRel);
+            if pending > 0 {
+                let count = pending.min(buf.len() as u64);
+                if pending > count {
+                    self.counters.pending.fetch_add(pending - count, Ordering::AcqRel);
+                }
+                self.last_received = Instant::now();
+                return Ok(count as usize);
+            }
+            if self.last_received.elapsed() >= self.idle_timeout {
+                return Ok(0);
+            }
+            thread::sleep(READ_POLL_INTERVAL);
+        }
+    }
+}
+
+impl Drop for UdpReceiver {
+    fn drop(&mut self) {
+        self.counters.finished.store(true, Ordering::Relaxed);
+    }
+}
+
+#[cfg(target_os = "linux")]
+fn receive(socket: &UdpSocket, index: usize, batch: usize, counters: &UdpCounters) {
+    if batch > 1 {
+        receive_batches(socket, index, batch, counters);
+    } else {
+        receive_each(socket, index, counters);
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+fn receive(socket: &UdpSocket, index: usize, _batch: usize, counters: &UdpCounters) {
+    receive_each(socket, index, counters);
+}
+
+fn receive_each(socket: &UdpSocket, index: usize, counters: &UdpCounters) {
+    let mut buf = vec![0; MAX_DATAGRAM_SIZE];
+    while !counters.finished.load(Ordering::Relaxed) {
+        match socket.recv(&mut buf) {
+            Ok(len) => counters.record(index, 1, len as u64),
+            Err(ref err) if is_retryable(err) => {},
+            Err(err) => {
+                print_err!("Error while receiving datagrams: {}", err);
+                return;
+            },
+        }
+    }
+}
+
+/// Receives with `recvmmsg(2)`, which waits for the first datagram and then
+/// takes whatever else is already queued, up to the batch size.
+#[cfg(target_os = "linux")]
+fn receive_batches(socket: &UdpSocket, index: usize, batch: usize, counters: &UdpCounters) {
+    use std::mem;
+    use std::os::unix::io::AsRawFd;
+    use std::ptr;
+
+    let mut bufs = vec![0u8; MAX_DATAGRAM_SIZE * batch];
+    let mut iovecs: Vec<libc::iovec> = bufs.chunks_mut(MAX_DATAGRAM_SIZE)
+        .map(|buf| libc::iovec { iov_base: buf.as_mut_ptr() as *mut libc::c_void, iov_len: buf.len() })
+        .collect();
+    let mut messages: Vec<libc::mmsghdr> = iovecs.iter_mut().map(|iovec| {
+        let mut message: libc::mmsghdr = unsafe { mem::zeroed() };
+        message.msg_hdr.msg_iov = iovec;
+        message.msg_hdr.msg_iovlen = 1;
+        message
+    }).collect();
+
+    while !counters.finished.load(Ordering::Relaxed) {
+        let received = unsafe {
+            libc::recvmmsg(socket.as_raw_fd(), messages.as_mut_ptr(), batch as libc::c_uint,
+                libc::MSG_WAITFORONE as _, ptr::null_mut())
+        };
+        if received < 0 {
+            let err = io::Error::last_os_error();
+            if is_retryable(&err) { continue; }
+            print_err!("Error while receiving datagrams: {}", err);
+            return;
+        }
+        let bytes: u64 = messages[..received as usize].iter().map(|message| message.msg_len as u64).sum();
+        counters.record(index, received as u64, bytes);
+    }
+}
+
+fn is_retryable(err: &io::Error) -> bool {
+    matches!(err.kind(), io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut | io::ErrorKind::Interrupted)
+}
@@ -0,0 +1,194 @@
+use std::env;
+use std::time::Duration;
+
+/// Parses a duration such as `10s`, `500ms`, `50us`, `2m` or `1.5h`. A number without
+/// a unit is taken to be a number of seconds.
+pub fn parse_duration(text: &str) -> Option<Duration> {
+    let text = text.trim();
+    let split = text.find(|c: char| !(c.is_ascii_digit() || c == '.')).unwrap_or(text.len());
+    let (number, unit) = text.split_at(split);
+
+    let value: f64 = number.parse().ok()?;
+    let seconds = match unit.trim() {
+        "" | "s" | "sec" | "secs" => value,
+        "ms" => value / 1000.0,
+        "us" | "µs" => value / 1_000_000.0,
+        "m" | "min" | "mins" => value * 60.0,
+        "h" | "hr" | "hrs" => value * 3600.0,
+        _ => return None,
+    };
+
+    if seconds.is_finite() && seconds >= 0.0 {
+        Some(Duration::from_nanos((seconds * 1_000_000_000.0) as u64))
+    } else {
+        None
+    }
+}
+
+/// Parses a byte count such as `4096`, `64K`, `1.5MB` or `10GiB`. Units are
+/// powers of 1024 to match how sizes are displayed.
+pub fn parse_size(text: &str) -> Option<u64> {
+    let text = text.trim();
+    let split = text.find(|c: char| !(c.is_ascii_digit() || c == '.')).unwrap_or(text.len());
+    let (number, unit) = text.split_at(split);
+
+    let value: f64 = number.parse().ok()?;
+    let multiplier = match unit.trim().to_ascii_uppercase().as_str() {
+        "" | "B" => 1u64,
+        "K" | "KB" | "KIB" => 1 << 10,
+        "M" | "MB" | "MIB" => 1 << 20,
+        "G" | "GB" | "GIB" => 1 << 30,
+        "T" | "TB" | "TIB" => 1 << 40,
+        _ => return None,
+    };
+
+    let bytes = value * multiplier as f64;
+    if bytes.is_finite() && bytes >= 0.0 {
+        Some(bytes as u64)
+    } else {
+        None
+    }
+}
+
+/// Parses a rate in bytes per second such as `50MB/s` or `1G`.
+pub fn parse_rate(text: &str) -> Option<f64> {
+    let text = text.trim();
+    let size = text.strip_suffix("/s")
+        .or_else(|| text.strip_suffix("/sec"))
+        .or_else(|| text.strip_suffix("ps"))
+        .unwrap_or(text);
+    parse_size(size).map(|bytes| bytes as f64)
+}
+
+pub fn byte_to_mem_units(bytes: f64) -> (f64, &'static str) {
+    let (size, unit) = mem_unit(bytes);
+    (bytes / size, unit)
+}
+
+/// The largest unit that fits the bytes, and its size in bytes.
+pub fn mem_unit(bytes: f64) -> (f64, &'static str) {
+    const KB: f64 = 1024.0;
+    const MB: f64 = KB * 1024.0;
+    const GB: f64 = MB * 1024.0;
+    const TB: f64 = GB * 1024.0;
+
+    if bytes >= TB { (TB, "TB") }
+    else if bytes >= GB { (GB, "GB") }
+    else if bytes >= MB { (MB, "MB") }
+    else if bytes >= KB { (KB, "KB") }
+    else { (1.0, "Bytes") }
+}
+
+/// How the numbers in the stats are written, so that they can be lined up
+/// in logs or match the conventions of a locale.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct NumberFormat {
+    /// The digits after the decimal point.
+    pub digits: usize,
+
+    /// The width that numbers are right aligned to, if any.
+    pub pad: Option<usize>,
+
+    /// Separates every group of three digits before the decimal point.
+    pub thousands: Option<char>,
+
+    pub decimal: char,
+}
+
+impl Default for NumberFormat {
+    fn default() -> NumberFormat {
+        NumberFormat { digits: 3, pad: None, thousands: None, decimal: '.' }
+    }
+}
+
+impl NumberFormat {
+    pub fn format(&self, value: f64) -> String {
+        let text = format!("{:.*}", self.digits, value.abs());
+        let (integer, fraction) = match text.find('.') {
+            Some(point) => (&text[..point], &text[point + 1..]),
+            None => (text.as_str(), ""),
+        };
+
+        let mut number = String::new();
+        if value < 0.0 && text.bytes().any(|digit| digit.is_ascii_digit() && digit != b'0') {
+            number.push('-');
+        }
+        for (index, digit) in integer.chars().enumerate() {
+            if index > 0 && (integer.len() - index) % 3 == 0 {
+                if let Some(separator) = self.thousands {
+                    number.push(separator);
+                }
+            }
+            number.push(digit);
+        }
+        if !fraction.is_empty() {
+            number.push(self.decimal);
+            number.push_str(fraction);
+        }
+
+        match self.pad {
+            Some(width) => format!("{:>width$}", number, width = width),
+            None => number,
+        }
+    }
+
+    /// The thousands separator and decimal point of the locale named by
+    /// `LC_ALL`, `LC_NUMERIC` or `LANG`, going by its language.
+    pub fn locale_separators() -> (char, char) {
+        let locale = ["LC_ALL", "LC_NUMERIC", "LANG"].iter()
+            .filter_map(|name| env::var(name).ok())
+            .find(|value| !value.is_empty())
+            .unwrap_or_default();
+        let language = locale.split(['_', '.', '-', '@']).next().unwrap_or("");
+        match language {
+            "de" | "es" | "it" | "nl" | "pt" | "da" | "id" | "tr" | "el" | "ro" | "hr" | "sl" | "sr" => ('.', ','),
+            "fr" | "ru" | "pl" | "cs" | "sk" | "sv" | "fi" | "nb" | "nn" | "no" | "uk" | "hu" | "bg" | "et"
+                | "lv" | "lt" => (' ', ','),
+            _ => (',', '.'),
+        }
+    }
+}
+
+pub fn bytes_per_second(bytes_read: usize, duration: Duration) -> f64 {
+    let duration_seconds = 
+        duration.as_secs() as f64 + 
+        duration.subsec_nanos() as f64 / 1000000000.0;
+    bytes_read as f64 / duration_seconds
+}
+
+/// Formats a duration as `HH:MM:SS`.
+pub fn format_elapsed(duration: Duration) -> String {
+    let secs = duration.as_secs();
+    format!("{:02}:{:02}:{:02}", secs / 3600, secs / 60 % 60, secs % 60)
+}
+
+/// Formats a duration in nanoseconds using the largest fitting unit.
+pub fn format_latency(nanos: u64) -> String {
+    let nanos = nanos as f64;
+    if nanos >= 1e9 { format!("{:.3} s", nanos / 1e9) }
+    else if nanos >= 1e6 { format!("{:.3} ms", nanos / 1e6) }
+    else if nanos >= 1e3 { format!("{:.3} µs", nanos / 1e3) }
+    else { format!("{} ns", nanos) }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rates_are_converted_to_the_largest_unit_that_fits() {
+        assert_eq!(byte_to_mem_units(1.5 * 1024.0 * 1024.0), (1.5, "MB"));
+        assert_eq!(byte_to_mem_units(1023.0), (1023.0, "Bytes"));
+        assert_eq!(byte_to_mem_units(1024.0), (1.0, "KB"));
+        assert_eq!(byte_to_mem_units(5.0 * 1024.0 * 1024.0 * 1024.0), (5.0, "GB"));
+        assert_eq!(byte_to_mem_units(2.0 * 1024.0 * 1024.0 * 1024.0 * 1024.0), (2.0, "TB"));
+    }
+
+    #[test]
+    fn sizes_and_rates_parse_in_binary_units() {
+        assert_eq!(parse_size("1K"), Some(1024));
+        assert_eq!(parse_size("1.5MB"), Some(1536 * 1024));
+        assert_eq!(parse_rate("50MB/s"), Some(50.0 * 1024.0 * 1024.0));
+        assert_eq!(parse_duration("500ms"), Some(Duration::from_millis(500)));
+    }
+}
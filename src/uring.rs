@@ -0,0 +1,142 @@
+//! An io_uring based reader for Linux that keeps several reads in flight at
+//! once, so that fast sources are not limited by one syscall per buffer.
+
+use std::io::{self, Read};
+use std::os::unix::io::RawFd;
+
+use io_uring::{opcode, types, IoUring};
+
+/// The offset that makes a read use and advance the file position, for
+/// streams that cannot be read at an offset.
+const CURRENT_POSITION: u64 = u64::MAX;
+
+/// A buffer that a read has been submitted for.
+struct Slot {
+    buffer: Vec<u8>,
+    offset: u64,
+
+    /// How much of the buffer earlier short reads have filled.
+    filled: usize,
+}
+
+/// A reader that measures a descriptor using io_uring. Completed reads are
+/// returned in whatever order they finish, so the buffer passed to `read` is
+/// never written to and only the number of bytes read is returned.
+pub struct UringReader {
+    ring: IoUring,
+    fd: RawFd,
+    slots: Vec<Slot>,
+
+    /// The offset of the next read for regular files, which are read at
+    /// explicit offsets so that reads in flight do not overlap.
+    next_offset: Option<u64>,
+
+    in_flight: usize,
+    finished: bool,
+}
+
+impl UringReader {
+    /// Starts `depth` reads of `buffer_size` bytes from the descriptor, which
+    /// must stay open for as long as the reader is used.
+    pub fn new(fd: RawFd, buffer_size: usize, depth: u32) -> io::Result<UringReader> {
+        let mut reader = UringReader {
+            ring: IoUring::new(depth)?,
+            fd,
+            slots: (0..depth).map(|_| Slot { buffer: vec![0; buffer_size], offset: 0, filled: 0 }).collect(),
+            next_offset: if is_regular_file(fd) { Some(0) } else { None },
+            in_flight: 0,
+            finished: false,
+        };
+
+        for index in 0..reader.slots.len() {
+            let offset = reader.advance();
+            reader.submit(index, offset, 0)?;
+        }
+        Ok(reader)
+    }
+
+    /// Returns the offset for the next full buffer.
+    fn advance(&mut self) -> u64 {
+        match self.next_offset {
+            Some(ref mut next) => {
+                let offset = *next;
+                *next += self.slots[0].buffer.len() as u64;
+                offset
+            },
+            None => CURRENT_POSITION,
+        }
+    }
+
+    /// Queues a read into the slot, starting `filled` bytes into its buffer.
+    fn submit(&mut self, index: usize, offset: u64, filled: usize) -> io::Result<()> {
+        let slot = &mut self.slots[index];
+        slot.offset = offset;
+        slot.filled = filled;
+
+        let buffer = &mut slot.buffer[filled..];
+        let read_offset = if offset == CURRENT_POSITION { offset } else { offset + filled as u64 };
+        let entry = opcode::Read::new(types::Fd(self.fd), buffer.as_mut_ptr(), buffer.len() as u32)
+            .offset(read_offset)
+            .build()
+            .user_data(index as u64);
+
+        // The buffer lives in the slot until its completion has been reaped.
+        unsafe {
+            self.ring.submission().push(&entry)
+                .map_err(|_| io::Error::other("io_uring submission queue is full"))?;
+        }
+        self.ring.submit()?;
+        self.in_flight += 1;
+        Ok(())
+    }
+}
+
+impl Read for UringReader {
+    fn read(&mut self, _buf: &mut [u8]) -> io::Result<usize> {
+        while self.in_flight > 0 {
+            if self.ring.completion().is_empty() {
+                self.ring.submit_and_wait(1)?;
+            }
+            let completion = match self.ring.completion().next() {
+                Some(completion) => completion,
+                None => continue,
+            };
+            self.in_flight -= 1;
+
+            let index = completion.user_data() as usize;
+            let result = completion.result();
+            let (offset, filled) = (self.slots[index].offset, self.slots[index].filled);
+            if result < 0 {
+                let err = io::Error::from_raw_os_error(-result);
+                if err.kind() == io::ErrorKind::Interrupted || err.kind() == io::ErrorKind::WouldBlock {
+                    self.submit(index, offset, filled)?;
+                    continue;
+                }
+                return Err(err);
+            }
+
+            let read = result as usize;
+            if read == 0 {
+                self.finished = true;
+                continue;
+            }
+
+            // A short read of a file is finished before the slot moves on so
+            // that no part of the file is skipped.
+            let filled = filled + read;
+            if offset != CURRENT_POSITION && filled < self.slots[index].buffer.len() && !self.finished {
+                self.submit(index, offset, filled)?;
+            } else if !self.finished {
+                let offset = self.advance();
+                self.submit(index, offset, 0)?;
+            }
+            return Ok(read);
+        }
+        Ok(0)
+    }
+}
+
+fn is_regular_file(fd: RawFd) -> bool {
+    let mut stat: libc::stat = unsafe { std::mem::zeroed() };
+    unsafe { libc::fstat(fd, &mut stat) == 0 && stat.st_mode & libc::S_IFMT == libc::S_IFREG }
+}
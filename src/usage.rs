@@ -0,0 +1,95 @@
+//! The CPU time and system calls used by the process, sampled at the start
+//! and end of a transfer to judge how efficiently it was measured.
+
+use std::time::Duration;
+
+/// Resources used by the whole process, including every thread.
+#[derive(Clone, Copy, Default)]
+pub struct Usage {
+    /// CPU time spent in user space.
+    pub user: Duration,
+
+    /// CPU time spent in the kernel.
+    pub system: Duration,
+
+    /// The number of read and write system calls, where the kernel counts
+    /// them.
+    pub syscalls: Option<Syscalls>,
+}
+
+/// Counts of the `read(2)` and `write(2)` family of system calls. The kernel
+/// does not include socket calls such as `recv(2)` or io_uring submissions, so
+/// the reads made while measuring are counted separately.
+#[derive(Clone, Copy, Default)]
+pub struct Syscalls {
+    pub reads: u64,
+    pub writes: u64,
+}
+
+impl Syscalls {
+    /// Makes up for the reads the kernel missed with the number of reads that
+    /// were made while measuring, which is all of them when reading a socket.
+    pub fn with_reads_made(self, reads: u64) -> Syscalls {
+        Syscalls { reads: self.reads.max(reads), ..self }
+    }
+}
+
+impl Usage {
+    /// Samples the resources used so far, if the platform can tell.
+    #[cfg(unix)]
+    pub fn now() -> Option<Usage> {
+        let mut usage: libc::rusage = unsafe { std::mem::zeroed() };
+        if unsafe { libc::getrusage(libc::RUSAGE_SELF, &mut usage) } != 0 {
+            return None;
+        }
+
+        let duration = |time: libc::timeval| Duration::new(time.tv_sec as u64, time.tv_usec as u32 * 1000);
+        Some(Usage {
+            user: duration(usage.ru_utime),
+            system: duration(usage.ru_stime),
+            syscalls: syscalls(),
+        })
+    }
+
+    #[cfg(not(unix))]
+    pub fn now() -> Option<Usage> {
+        None
+    }
+
+    /// The resources used between an earlier sample and this one.
+    pub fn since(&self, earlier: &Usage) -> Usage {
+        Usage {
+            user: self.user.saturating_sub(earlier.user),
+            system: self.system.saturating_sub(earlier.system),
+            syscalls: match (self.syscalls, earlier.syscalls) {
+                (Some(now), Some(then)) => Some(Syscalls {
+                    reads: now.reads.saturating_sub(then.reads),
+                    writes: now.writes.saturating_sub(then.writes),
+                }),
+                _ => None,
+            },
+        }
+    }
+
+    /// The total CPU time spent.
+    pub fn cpu_time(&self) -> Duration {
+        self.user + self.system
+    }
+}
+
+/// Reads the system call counts that Linux keeps in `/proc/self/io`.
+#[cfg(target_os = "linux")]
+fn syscalls() -> Option<Syscalls> {
+    let io = std::fs::read_to_string("/proc/self/io").ok()?;
+    let field = |name: &str| {
+        io.lines()
+            .find_map(|line| line.strip_prefix(name))
+            .and_then(|value| value.trim().parse().ok())
+    };
+    Some(Syscalls { reads: field("syscr:")?, writes: field("syscw:")? })
+}
+
+#[cfg(all(unix, not(target_os = "linux")))]
+fn syscalls() -> Option<Syscalls> {
+    None
+}
@@ -0,0 +1,143 @@
+//! Checking the values of the arguments before anything starts. Problems are
+//! collected rather than stopping at the first one, so that they can all be
+//! fixed at once, and each says what was given and, where it can be guessed,
+//! what was meant.
+
+use std::cell::RefCell;
+use std::fmt::Display;
+use std::mem;
+use std::str::FromStr;
+use std::time::Duration;
+
+use net::PortRange;
+use units;
+
+/// The units a duration can be given in, for pointing out a unit that is not
+/// one of them.
+const DURATION_UNITS: &str = "us, ms, s, m or h";
+
+/// The units a size can be given in.
+const SIZE_UNITS: &str = "K, M, G or T";
+
+/// The problems found with the arguments so far.
+#[derive(Default)]
+pub struct Diagnostics {
+    problems: RefCell<Vec<String>>,
+}
+
+impl Diagnostics {
+    /// Records a problem with the arguments.
+    pub fn error<S: Into<String>>(&self, problem: S) {
+        self.problems.borrow_mut().push(problem.into());
+    }
+
+    /// Parses the value of an argument if it was given, recording the problem
+    /// and returning nothing if it is invalid.
+    pub fn parse<T, F: FnOnce(&str) -> Result<T, String>>(&self, value: Option<&str>, parse: F) -> Option<T> {
+        match parse(value?) {
+            Ok(parsed) => Some(parsed),
+            Err(problem) => {
+                self.error(problem);
+                None
+            }
+        }
+    }
+
+    /// Takes the problems found so far, oldest first.
+    pub fn take(&self) -> Vec<String> {
+        mem::take(&mut *self.problems.borrow_mut())
+    }
+}
+
+/// Describes a value that was not what the argument expects, such as
+/// `Vecs must be at least 1 (got "0").`
+pub fn invalid(expected: &str, value: &str) -> String {
+    format!("{} (got \"{}\").", expected, value)
+}
+
+/// Parses a whole number of at least `min`. A size such as `64K` is pointed
+/// out along with the number of bytes it stands for.
+pub fn number<T: FromStr + PartialOrd + Display>(value: &str, what: &str, min: T) -> Result<T, String> {
+    match value.trim().parse::<T>() {
+        Ok(number) if number >= min => Ok(number),
+        Ok(_) => Err(invalid(&format!("{} must be at least {}", what, min), value)),
+        Err(_) if value.trim().parse::<u128>().is_ok() => Err(invalid(&format!("{} is too large", what), value)),
+        Err(_) => Err(match units::parse_size(value).filter(|_| !value.trim().chars().all(|c| c.is_ascii_digit())) {
+            Some(bytes) => format!("{} Did you mean {}?", invalid(&format!("{} must be a number", what), value), bytes),
+            None => invalid(&format!("{} must be a number", what), value),
+        }),
+    }
+}
+
+/// Parses a duration such as `500ms` or `10s`, which may be zero. A number
+/// is taken as seconds.
+pub fn duration(value: &str, what: &str, example: &str) -> Result<Duration, String> {
+    match units::parse_duration(value) {
+        Some(duration) => Ok(duration),
+        None => Err(unit_hint(invalid(&format!("{} must be a duration such as {}", what, example), value), value, DURATION_UNITS)),
+    }
+}
+
+/// Parses a duration that must be longer than zero.
+pub fn timeout(value: &str, what: &str, example: &str) -> Result<Duration, String> {
+    match duration(value, what, example)? {
+        duration if duration > Duration::from_secs(0) => Ok(duration),
+        _ => Err(invalid(&format!("{} must be longer than zero, such as {}", what, example), value)),
+    }
+}
+
+/// Parses a size in bytes such as `64K` or `1G` of at least `min` bytes.
+pub fn size(value: &str, what: &str, example: &str, min: u64) -> Result<u64, String> {
+    match units::parse_size(value) {
+        Some(size) if size >= min => Ok(size),
+        Some(_) => Err(invalid(&format!("{} must be at least {} bytes, such as {}", what, min, example), value)),
+        None => Err(unit_hint(invalid(&format!("{} must be a size such as {}", what, example), value), value, SIZE_UNITS)),
+    }
+}
+
+/// Parses a rate in bytes per second such as `50MB/s`.
+pub fn rate(value: &str, what: &str, example: &str) -> Result<f64, String> {
+    units::parse_rate(value).ok_or_else(|| invalid(&format!("{} must be a rate such as {}", what, example), value))
+}
+
+/// Parses a single port, pointing out numbers that are past the last port.
+pub fn port(value: &str) -> Result<u16, String> {
+    match value.trim().parse::<u64>() {
+        Ok(port) if port <= u16::MAX as u64 => Ok(port as u16),
+        Ok(_) => Err(invalid("Port is out of range, as ports go from 0 to 65535", value)),
+        Err(_) if PortRange::parse(value).is_some() => Err(invalid("Port must be a single port here, not a range", value)),
+        Err(_) => Err(invalid("Port must be a number from 0 to 65535", value)),
+    }
+}
+
+/// Parses a port or a range of ports such as `5000-5010`.
+pub fn port_range(value: &str) -> Result<PortRange, String> {
+    match PortRange::parse(value) {
+        Some(ports) => Ok(ports),
+        None if !value.contains('-') => port(value).map(|port| PortRange { first: port, last: port }),
+        None => Err(invalid("Port must be a range of ports from 1 to 65535 whose first is no higher than its last, \
+                             such as 5000-5010", value)),
+    }
+}
+
+/// Parses a host and port such as `db.internal:5432` or `[::1]:5432`.
+pub fn host_port(value: &str) -> Result<(String, u16), String> {
+    match value.rsplit_once(':') {
+        Some((host, port_value)) if !host.is_empty() => {
+            let host = host.strip_prefix('[').and_then(|host| host.strip_suffix(']')).unwrap_or(host);
+            port(port_value).map(|port| (host.to_string(), port))
+        },
+        _ => Err(invalid("Address must be a host and port such as localhost:5000", value)),
+    }
+}
+
+/// Adds the units that can be used to a problem with a value whose number
+/// is fine but whose unit is not.
+fn unit_hint(problem: String, value: &str, units: &str) -> String {
+    let value = value.trim();
+    let split = value.find(|c: char| !(c.is_ascii_digit() || c == '.')).unwrap_or(value.len());
+    match value[..split].parse::<f64>() {
+        Ok(_) if split < value.len() => format!("{} The unit must be one of {}.", problem, units),
+        _ => problem,
+    }
+}
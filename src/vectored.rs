@@ -0,0 +1,33 @@
+//! Reads and writes spread over several buffers with a single `readv(2)` or
+//! `writev(2)` call, which cuts the number of system calls on fast streams.
+//! Streams without vectored I/O fall back to using only the first buffer.
+
+use std::io::{self, IoSlice, IoSliceMut, Read, Write};
+
+/// Reads into the buffer as consecutive chunks of `chunk_size` bytes with one
+/// vectored read. The bytes read always start at the beginning of the buffer.
+pub fn read_chunks<R: Read>(reader: &mut R, buffer: &mut [u8], chunk_size: usize) -> io::Result<usize> {
+    if buffer.len() <= chunk_size {
+        return reader.read(buffer);
+    }
+
+    let mut slices: Vec<IoSliceMut> = buffer.chunks_mut(chunk_size).map(IoSliceMut::new).collect();
+    reader.read_vectored(&mut slices)
+}
+
+/// Writes every buffer with as few vectored writes as the writer allows.
+pub fn write_all<W: Write>(writer: &mut W, buffers: &[Vec<u8>]) -> io::Result<()> {
+    let mut slices: Vec<IoSlice> = buffers.iter().map(|buffer| IoSlice::new(buffer)).collect();
+    let mut remaining = &mut slices[..];
+    IoSlice::advance_slices(&mut remaining, 0);
+
+    while !remaining.is_empty() {
+        match writer.write_vectored(remaining) {
+            Ok(0) => return Err(io::Error::new(io::ErrorKind::WriteZero, "failed to write whole buffer")),
+            Ok(written) => IoSlice::advance_slices(&mut remaining, written),
+            Err(ref err) if err.kind() == io::ErrorKind::Interrupted => {},
+            Err(err) => return Err(err),
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,30 @@
+//! Listening and connecting over AF_VSOCK on Linux, which carries streams
+//! between a hypervisor host and its guests without virtual networking.
+
+use std::io;
+
+use socket2::{Domain, SockAddr, Socket, Type};
+
+/// Accepts connections from any CID when listening.
+pub const CID_ANY: u32 = libc::VMADDR_CID_ANY;
+
+pub fn listen(cid: u32, port: u32) -> io::Result<Socket> {
+    let socket = Socket::new(Domain::VSOCK, Type::STREAM, None)?;
+    socket.bind(&SockAddr::vsock(cid, port))?;
+    socket.listen(1)?;
+    Ok(socket)
+}
+
+pub fn connect(cid: u32, port: u32) -> io::Result<Socket> {
+    let socket = Socket::new(Domain::VSOCK, Type::STREAM, None)?;
+    socket.connect(&SockAddr::vsock(cid, port))?;
+    Ok(socket)
+}
+
+/// Describes a vsock address as its CID and port.
+pub fn describe(addr: &SockAddr) -> String {
+    match addr.as_vsock_address() {
+        Some((cid, port)) => format!("vsock CID {} port {}", cid, port),
+        None => "an unknown address".to_string(),
+    }
+}
@@ -0,0 +1,75 @@
+use std::io::{self, IoSliceMut, Read};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// Wraps a reader and invokes a callback from a background thread if no bytes
+/// are read from it for longer than the configured timeout. This works even
+/// when the reader is blocked inside of `read()` indefinitely.
+pub struct IdleWatchdog<R> {
+    inner: R,
+    state: Arc<WatchState>,
+}
+
+#[derive(Default)]
+struct WatchState {
+    bytes_read: AtomicUsize,
+
+    /// Set once the reader has been dropped so the thread can exit quietly.
+    finished: AtomicBool,
+}
+
+impl<R: Read> IdleWatchdog<R> {
+    /// Starts watching the reader. `on_idle` is called at most once, from the
+    /// watchdog thread, and is expected to end the process.
+    pub fn new<F>(inner: R, timeout: Duration, on_idle: F) -> IdleWatchdog<R>
+        where F: FnOnce(Duration) + Send + 'static
+    {
+        let state = Arc::new(WatchState::default());
+        let watched = state.clone();
+
+        thread::spawn(move || {
+            // Checking a few times per window keeps the overshoot small.
+            let check_interval = timeout / 4;
+            let mut last_seen = 0;
+            let mut last_activity = Instant::now();
+
+            loop {
+                thread::sleep(check_interval);
+                if watched.finished.load(Ordering::Relaxed) { return; }
+
+                let current = watched.bytes_read.load(Ordering::Relaxed);
+                if current != last_seen {
+                    last_seen = current;
+                    last_activity = Instant::now();
+                } else if last_activity.elapsed() >= timeout {
+                    on_idle(last_activity.elapsed());
+                    return;
+                }
+            }
+        });
+
+        IdleWatchdog { inner, state }
+    }
+}
+
+impl<R: Read> Read for IdleWatchdog<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let count = self.inner.read(buf)?;
+        self.state.bytes_read.fetch_add(count, Ordering::Relaxed);
+        Ok(count)
+    }
+
+    fn read_vectored(&mut self, bufs: &mut [IoSliceMut]) -> io::Result<usize> {
+        let count = self.inner.read_vectored(bufs)?;
+        self.state.bytes_read.fetch_add(count, Ordering::Relaxed);
+        Ok(count)
+    }
+}
+
+impl<R> Drop for IdleWatchdog<R> {
+    fn drop(&mut self) {
+        self.state.finished.store(true, Ordering::Relaxed);
+    }
+}